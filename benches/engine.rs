@@ -0,0 +1,68 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use std::path::PathBuf;
+use tx_accounts::{
+    generate::{generate, GenerateOptions},
+    records::{read_rows, read_rows_with, CsvDialect, InputFormat, InputRow},
+    rejects::{process_rows, process_rows_shared},
+    shared::SharedEngine,
+    transaction::Engine,
+};
+
+const TRANSACTIONS: u64 = 100_000;
+
+/// Writes the synthetic input once; every benchmark reads or applies the same rows.
+fn input() -> (PathBuf, Vec<InputRow>) {
+    let path = std::env::temp_dir().join("tx_accounts_bench.csv");
+    let options = GenerateOptions {
+        transactions: TRANSACTIONS,
+        seed: 1,
+        ..Default::default()
+    };
+    generate(std::fs::File::create(&path).unwrap(), &options).unwrap();
+    let rows = read_rows(&path, InputFormat::Csv).unwrap();
+
+    (path, rows)
+}
+
+fn benches(c: &mut Criterion) {
+    let (path, rows) = input();
+    let mut group = c.benchmark_group("engine");
+    group.throughput(Throughput::Elements(TRANSACTIONS));
+    group.sample_size(20);
+
+    group.bench_function("read_rows", |b| {
+        b.iter(|| read_rows(&path, InputFormat::Csv).unwrap())
+    });
+
+    let fast = CsvDialect {
+        fast_parse: true,
+        ..Default::default()
+    };
+    group.bench_function("read_rows_fast", |b| {
+        b.iter(|| read_rows_with(&path, InputFormat::Csv, &fast).unwrap())
+    });
+
+    group.bench_function("process_rows", |b| {
+        b.iter_batched(
+            || rows.clone(),
+            |rows| process_rows(&mut Engine::new(), rows),
+            BatchSize::LargeInput,
+        )
+    });
+
+    for threads in [2, 4] {
+        group.bench_function(format!("process_rows_shared/{threads}"), |b| {
+            b.iter_batched(
+                || rows.clone(),
+                |rows| process_rows_shared(&SharedEngine::new(threads), rows, threads),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+    std::fs::remove_file(&path).unwrap();
+}
+
+criterion_group!(engine, benches);
+criterion_main!(engine);
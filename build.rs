@@ -0,0 +1,14 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=proto/tx_accounts.proto");
+
+    // The gRPC service is generated from its proto with a bundled protoc, so that
+    // building it needs no system protobuf installation.
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        tonic_prost_build::compile_protos("proto/tx_accounts.proto")?;
+    }
+
+    Ok(())
+}
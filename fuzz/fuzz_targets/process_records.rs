@@ -0,0 +1,60 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use tx_accounts::{
+    amounts::Money,
+    integrity::{check_integrity, IntegrityPolicy},
+    records::{Record, TxType},
+    transaction::{process_records, ClientId, TxId},
+};
+
+#[derive(Debug, Arbitrary)]
+struct FuzzRecord {
+    r#type: u8,
+    client: u16,
+    tx: u32,
+    /// In quarters, which add up exactly in f32, so any finding is a real bug.
+    amount: Option<u16>,
+}
+
+impl From<FuzzRecord> for Record {
+    fn from(record: FuzzRecord) -> Self {
+        let r#type = match record.r#type % 8 {
+            0 => TxType::Deposit,
+            1 => TxType::Withdrawal,
+            2 => TxType::Dispute,
+            3 => TxType::Resolve,
+            4 => TxType::Chargeback,
+            5 => TxType::OpenAccount,
+            6 => TxType::Lock,
+            _ => TxType::Unlock,
+        };
+
+        Record {
+            r#type,
+            // Few clients and tx ids, so that records actually refer to each other.
+            client: ClientId(record.client % 16),
+            tx: TxId(record.tx % 256),
+            amount: record
+                .amount
+                .map(|quarters| Money::new(f32::from(quarters) / 4.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        }
+    }
+}
+
+fuzz_target!(|records: Vec<FuzzRecord>| {
+    let records = records.into_iter().map(Record::from).collect();
+    let mut accounts = process_records(records);
+
+    let findings = check_integrity(&mut accounts, IntegrityPolicy::Report);
+    assert!(findings.is_empty(), "{findings:?}");
+});
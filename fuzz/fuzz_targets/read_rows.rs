@@ -0,0 +1,31 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tx_accounts::{
+    records::{parse_record, read_rows_from, CsvDialect, InputFormat},
+    rejects::process_rows,
+    transaction::Engine,
+};
+
+// Arbitrary partner files must come back as rows or errors, never as a panic or a hang,
+// and whatever rows they yield must be safe to apply.
+fuzz_target!(|data: &[u8]| {
+    let headerless = CsvDialect {
+        has_headers: false,
+        ..Default::default()
+    };
+    let fast = CsvDialect {
+        fast_parse: true,
+        ..Default::default()
+    };
+
+    for format in [InputFormat::Csv, InputFormat::JsonLines] {
+        let _ = parse_record(data, format);
+
+        for dialect in [&CsvDialect::default(), &headerless, &fast] {
+            if let Ok(rows) = read_rows_from(data, format, dialect) {
+                process_rows(&mut Engine::new(), rows);
+            }
+        }
+    }
+});
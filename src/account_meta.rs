@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, error::Error, path::Path};
+
+use crate::{
+    amounts::Money,
+    credit::CreditLimits,
+    transaction::{Account, ClientId},
+};
+
+/// What an accounts file says about a client besides its transactions. Every column but
+/// `client` may be left blank.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct AccountMeta {
+    pub client: ClientId,
+    /// Name the client is shown with in the output.
+    pub name: Option<String>,
+    /// KYC tier of the client, passed through to the output as is.
+    pub kyc_tier: Option<String>,
+    /// Available funds in the base currency the account starts with.
+    pub opening_balance: Option<Money>,
+    /// Credit limit of the client, see [`CreditLimits`].
+    pub credit_limit: Option<Money>,
+    /// Whether the account starts locked.
+    pub locked: Option<bool>,
+}
+
+/// The metadata of clients, read from a CSV file with
+/// `client,name,kyc_tier,opening_balance,credit_limit,locked` columns:
+///
+/// ```text
+/// client,name,kyc_tier,opening_balance,credit_limit,locked
+/// 1,Acme Ltd,2,100.0,,
+/// 7,Jane Doe,1,,250.0,true
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AccountDirectory {
+    clients: BTreeMap<ClientId, AccountMeta>,
+}
+
+impl AccountDirectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+
+        let mut directory = Self::new();
+        for row in rdr.deserialize::<AccountMeta>() {
+            let mut meta = row.map_err(|e| format!("{}: {e}", path.display()))?;
+            let client = meta.client;
+            if meta
+                .opening_balance
+                .is_some_and(|balance| !balance.is_finite())
+                || meta
+                    .credit_limit
+                    .is_some_and(|limit| !limit.is_finite() || limit < 0.0)
+            {
+                return Err(
+                    format!("{}: invalid amounts for client {client}", path.display()).into(),
+                );
+            }
+            meta.name = meta.name.filter(|name| !name.is_empty());
+            meta.kyc_tier = meta.kyc_tier.filter(|tier| !tier.is_empty());
+            if directory.clients.insert(client, meta).is_some() {
+                return Err(format!("{}: client {client} is listed twice", path.display()).into());
+            }
+        }
+
+        Ok(directory)
+    }
+
+    pub fn get(&self, client: ClientId) -> Option<&AccountMeta> {
+        self.clients.get(&client)
+    }
+
+    /// The accounts of the listed clients as they are before their first transaction,
+    /// with their opening balances and locks.
+    pub fn opening_accounts(&self) -> impl Iterator<Item = Account> + '_ {
+        self.clients.values().map(|meta| {
+            let balance = meta.opening_balance.unwrap_or_default();
+            Account {
+                client: meta.client,
+                available: balance,
+                total: balance,
+                locked: meta.locked.unwrap_or_default(),
+                ..Default::default()
+            }
+        })
+    }
+
+    /// Sets the credit limits of the clients that have one, over those already in
+    /// `limits`.
+    pub fn add_credit_limits(&self, limits: &mut CreditLimits) {
+        for meta in self.clients.values() {
+            if let Some(limit) = meta.credit_limit {
+                limits.set(meta.client, limit);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        amounts::Precision,
+        config::EngineConfig,
+        output::{write_enriched_accounts, OutputFormat},
+        testing::Scenario,
+        transaction::{Engine, RejectionReason},
+    };
+
+    #[test]
+    fn accounts_files_seed_the_engine() {
+        let path = std::env::temp_dir().join("tx_accounts_accounts_meta.csv");
+        std::fs::write(
+            &path,
+            "client,name,kyc_tier,opening_balance,credit_limit,locked\n\
+             1, Acme Ltd ,2,100.0,,\n\
+             2,,,,50,\n\
+             3,\"Doe, Jane\",1,,,true\n",
+        )
+        .unwrap();
+        let directory = AccountDirectory::read(&path).unwrap();
+        assert_eq!(
+            directory.get(ClientId(1)).unwrap().name.as_deref(),
+            Some("Acme Ltd")
+        );
+        assert_eq!(directory.get(ClientId(2)).unwrap().name, None);
+
+        let mut credit_limits = CreditLimits::new();
+        directory.add_credit_limits(&mut credit_limits);
+        let mut engine = Engine::with_config(EngineConfig {
+            credit_limits,
+            ..Default::default()
+        });
+        for account in directory.opening_accounts() {
+            engine.restore_account(account);
+        }
+        let engine = Scenario::from_engine(engine)
+            .withdraw(1, 1, 60)
+            .expect_available(ClientId(1), 40)
+            .withdraw(2, 2, 50)
+            .expect_available(ClientId(2), -50)
+            .deposit(3, 3, 5)
+            .expect_rejected(RejectionReason::AccountLocked)
+            .into_engine();
+
+        let mut out = Vec::new();
+        let accounts = engine.into_accounts();
+        write_enriched_accounts(
+            &mut out,
+            &accounts,
+            OutputFormat::Csv,
+            Precision::default(),
+            &directory,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked,name,kyc_tier\n\
+             1,40.0000,0.0000,40.0000,false,Acme Ltd,2\n\
+             2,-50.0000,0.0000,-50.0000,false,,\n\
+             3,0.0000,0.0000,0.0000,true,\"Doe, Jane\",1\n"
+        );
+
+        std::fs::write(&path, "client,name\n1,a\n1,b\n").unwrap();
+        assert!(AccountDirectory::read(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign},
+    str::FromStr,
+};
+
+use crate::currency::Currency;
+
+/// Decimal places amounts are kept at, and written with unless a [`Precision`] says
+/// otherwise.
+pub const DECIMALS: u32 = 4;
+
+/// An amount of money, in the base currency of the ledger or in any other. Amounts are
+/// parsed rounded to [`DECIMALS`] places.
+#[derive(Debug, Serialize, Deserialize, PartialEq, PartialOrd, Default, Clone, Copy)]
+#[serde(transparent)]
+pub struct Money(f32);
+
+impl Money {
+    pub const ZERO: Self = Self(0.0);
+
+    pub const fn new(amount: f32) -> Self {
+        Self(amount)
+    }
+
+    pub const fn to_f32(self) -> f32 {
+        self.0
+    }
+
+    pub fn is_finite(self) -> bool {
+        self.0.is_finite()
+    }
+
+    pub fn abs(self) -> Self {
+        Self(self.0.abs())
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        Self(self.0.min(other.0))
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        Self(self.0.max(other.0))
+    }
+}
+
+impl From<f32> for Money {
+    fn from(amount: f32) -> Self {
+        Self(amount)
+    }
+}
+
+impl From<Money> for f64 {
+    fn from(amount: Money) -> Self {
+        f64::from(amount.0)
+    }
+}
+
+impl PartialEq<f32> for Money {
+    fn eq(&self, other: &f32) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialOrd<f32> for Money {
+    fn partial_cmp(&self, other: &f32) -> Option<std::cmp::Ordering> {
+        self.0.partial_cmp(other)
+    }
+}
+
+impl Add for Money {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(self.0 + other.0)
+    }
+}
+
+impl Sub for Money {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self(self.0 - other.0)
+    }
+}
+
+impl Neg for Money {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(-self.0)
+    }
+}
+
+/// Scales an amount by a rate or a factor.
+impl Mul<f32> for Money {
+    type Output = Self;
+
+    fn mul(self, factor: f32) -> Self {
+        Self(self.0 * factor)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Self) {
+        self.0 += other.0;
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, other: Self) {
+        self.0 -= other.0;
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        Self(iter.map(|amount| amount.0).sum())
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// How many decimal places the amounts of the accounts output are written with.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
+pub enum Precision {
+    /// The same places for every amount, at most [`DECIMALS`].
+    Fixed(u32),
+    /// The minor unit of each currency under ISO 4217, e.g. none for JPY and three for
+    /// BHD. Amounts in the base currency keep [`DECIMALS`] places.
+    Iso4217,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Self::Fixed(DECIMALS)
+    }
+}
+
+impl Precision {
+    /// Decimal places of an amount in `currency`, or in the base currency for `None`.
+    pub fn decimals(self, currency: Option<Currency>) -> u32 {
+        match self {
+            Self::Fixed(decimals) => decimals,
+            Self::Iso4217 => currency.map_or(DECIMALS, minor_units),
+        }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("iso4217") {
+            return Ok(Self::Iso4217);
+        }
+        match s.parse::<u32>() {
+            Ok(decimals) if decimals <= DECIMALS => Ok(Self::Fixed(decimals)),
+            _ => Err(format!(
+                "invalid precision '{s}', expected 0 to {DECIMALS} decimal places or iso4217"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fixed(decimals) => write!(f, "{decimals}"),
+            Self::Iso4217 => f.write_str("iso4217"),
+        }
+    }
+}
+
+impl TryFrom<String> for Precision {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Precision> for String {
+    fn from(precision: Precision) -> Self {
+        precision.to_string()
+    }
+}
+
+/// Decimal places of the minor unit of `currency` under ISO 4217. Codes without an entry
+/// here, including unknown ones, have the usual two.
+pub fn minor_units(currency: Currency) -> u32 {
+    match currency.as_str() {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX"
+        | "UYI" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        "CLF" | "UYW" => 4,
+        _ => 2,
+    }
+}
+
+/// Formats `amount` with `decimals` places, rounded half away from zero like amounts are
+/// when they are parsed.
+pub fn format_amount(amount: Money, decimals: u32) -> String {
+    let scale = 10_f32.powi(decimals.min(DECIMALS) as i32);
+    let rounded = (amount.0 * scale).round() / scale;
+    format!("{rounded:.*}", decimals as usize)
+}
+
+/// The nearest `f64` to `amount` as [`format_amount`] writes it, for outputs that hold
+/// numbers rather than text.
+pub fn amount_to_f64(amount: Money, decimals: u32) -> f64 {
+    format_amount(amount, decimals).parse().unwrap_or(f64::NAN)
+}
+
+pub(crate) fn serialize_4dp<S>(value: &Money, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&format_amount(*value, DECIMALS))
+}
+
+pub(crate) fn deserialize_4dp<'de, D>(deserializer: D) -> Result<Money, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    crate::records::trim_and_parse_4dp(deserializer)?
+        .ok_or_else(|| serde::de::Error::custom("missing amount"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_follow_the_precision() {
+        let jpy = "JPY".parse().ok();
+        let bhd = "BHD".parse().ok();
+        let eur = "EUR".parse().ok();
+
+        let iso = Precision::Iso4217;
+        let places: Vec<u32> = [None, jpy, bhd, eur]
+            .into_iter()
+            .map(|currency| iso.decimals(currency))
+            .collect();
+        assert_eq!(places, vec![4, 0, 3, 2]);
+        assert_eq!(Precision::Fixed(2).decimals(jpy), 2);
+
+        assert_eq!(format_amount(Money::new(1234.5678), 0), "1235");
+        assert_eq!(format_amount(Money::new(0.125), 2), "0.13");
+        assert_eq!(format_amount(Money::new(-0.125), 2), "-0.13");
+        assert_eq!(format_amount(Money::new(1.5), DECIMALS), "1.5000");
+
+        assert_eq!("ISO4217".parse(), Ok(Precision::Iso4217));
+        assert_eq!("2".parse(), Ok(Precision::Fixed(2)));
+        assert!("5".parse::<Precision>().is_err());
+        assert_eq!(String::from(Precision::Fixed(0)), "0");
+    }
+}
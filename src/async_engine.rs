@@ -0,0 +1,186 @@
+use std::{io, thread};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, Lines},
+    sync::{mpsc, oneshot},
+};
+
+use crate::{
+    records::{parse_record, InputFormat, InputRow, Record},
+    rejects::{malformed, record_fields, Reject},
+    transaction::{Account, ClientId, Engine, RejectionReason},
+};
+
+type Job = Box<dyn FnOnce(&mut Engine) + Send>;
+
+/// Handle to an engine owned by a dedicated thread, for use from async code. Records are
+/// applied one at a time in the order they are submitted, without blocking the runtime's
+/// worker threads while they wait for the engine. Clones share the same engine, which
+/// stops once every handle is dropped.
+#[derive(Debug, Clone)]
+pub struct AsyncEngine {
+    jobs: mpsc::UnboundedSender<Job>,
+}
+
+impl AsyncEngine {
+    pub fn new(mut engine: Engine) -> Self {
+        let (jobs, mut rx) = mpsc::unbounded_channel::<Job>();
+        thread::spawn(move || {
+            while let Some(job) = rx.blocking_recv() {
+                job(&mut engine);
+            }
+        });
+
+        Self { jobs }
+    }
+
+    /// Runs `f` on the engine once the jobs submitted before it are done.
+    pub async fn with<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Engine) -> T + Send + 'static,
+    ) -> T {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::new(move |engine| {
+            let _ = tx.send(f(engine));
+        });
+        self.jobs.send(job).expect("engine thread stopped");
+
+        rx.await.expect("engine thread stopped")
+    }
+
+    pub async fn apply(&self, record: Record) -> Result<(), RejectionReason> {
+        self.with(|engine| engine.apply(record)).await
+    }
+
+    pub async fn account(&self, client: ClientId) -> Option<Account> {
+        self.with(move |engine| engine.accounts().get(&client).cloned())
+            .await
+    }
+
+    /// All accounts, sorted by client.
+    pub async fn accounts(&self) -> Vec<Account> {
+        self.with(|engine| {
+            let mut accounts: Vec<_> = engine.accounts().values().cloned().collect();
+            accounts.sort_by_key(|account| account.client);
+            accounts
+        })
+        .await
+    }
+}
+
+/// Reads rows from an async source such as a socket, one row per line. CSV sources start
+/// with a header row; rows may not span lines.
+#[derive(Debug)]
+pub struct AsyncRowReader<R> {
+    lines: Lines<R>,
+    format: InputFormat,
+    headers: Option<csv::StringRecord>,
+    line: u64,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRowReader<R> {
+    pub fn new(reader: R, format: InputFormat) -> Self {
+        Self {
+            lines: reader.lines(),
+            format,
+            headers: None,
+            line: 0,
+        }
+    }
+
+    /// The next row, or `None` at the end of the input. Blank lines are skipped.
+    pub async fn next_row(&mut self) -> io::Result<Option<InputRow>> {
+        while let Some(line) = self.lines.next_line().await? {
+            self.line += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let record = match (self.format, &self.headers) {
+                (InputFormat::JsonLines, _) => parse_record(line.as_bytes(), self.format),
+                (InputFormat::Csv, None) => {
+                    self.headers = Some(csv_row(&line)?.iter().map(str::trim).collect());
+                    continue;
+                }
+                (InputFormat::Csv, Some(headers)) => csv_row(&line)
+                    .and_then(|row| row.deserialize(Some(headers)).map_err(Into::into))
+                    .map_err(Into::into),
+            };
+
+            return Ok(Some(InputRow {
+                line: self.line,
+                record: record.map_err(|e| e.to_string()),
+            }));
+        }
+
+        Ok(None)
+    }
+}
+
+fn csv_row(line: &str) -> io::Result<csv::StringRecord> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(line.as_bytes());
+    let mut row = csv::StringRecord::new();
+    rdr.read_record(&mut row)?;
+
+    Ok(row)
+}
+
+/// Async counterpart of [`crate::rejects::process_rows`]: applies every row of `rows` as
+/// it arrives and collects the rows that were skipped. Only I/O errors abort.
+pub async fn process_rows_async<R: AsyncBufRead + Unpin>(
+    engine: &AsyncEngine,
+    rows: &mut AsyncRowReader<R>,
+) -> io::Result<Vec<Reject>> {
+    let mut rejects = Vec::new();
+    while let Some(row) = rows.next_row().await? {
+        match row.record {
+            Ok(record) => {
+                let rejected = record_fields(row.line, &record);
+                if let Err(reason) = engine.apply(record).await {
+                    rejects.push(rejected(reason));
+                }
+            }
+            Err(detail) => rejects.push(malformed(row.line, detail)),
+        }
+    }
+
+    Ok(rejects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amounts::Money;
+
+    #[tokio::test]
+    async fn apply_from_async_source() {
+        let engine = AsyncEngine::new(Engine::new());
+        let input = "type, client, tx, amount\n\ndeposit,1,1,10.0\nwithdrawal,1,2,20.0\nre-fund,1,3,1.0\ndispute,1,1,\n";
+        let mut rows = AsyncRowReader::new(input.as_bytes(), InputFormat::Csv);
+
+        let rejects = process_rows_async(&engine, &mut rows).await.unwrap();
+        let reasons: Vec<_> = rejects.iter().map(|r| (r.line, r.reason)).collect();
+        assert_eq!(
+            reasons,
+            [
+                (4, RejectionReason::InsufficientFunds),
+                (5, RejectionReason::Malformed)
+            ]
+        );
+
+        let account = engine.account(ClientId(1)).await.unwrap();
+        assert_eq!(
+            (account.available, account.held),
+            (Money::ZERO, Money::new(10.0))
+        );
+
+        let input = r#"{"type":"deposit","client":2,"tx":4,"amount":1.5}"#;
+        let mut rows = AsyncRowReader::new(input.as_bytes(), InputFormat::JsonLines);
+        assert!(process_rows_async(&engine, &mut rows)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(engine.accounts().await.len(), 2);
+    }
+}
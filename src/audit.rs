@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    error::Error,
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    events::{AccountEvent, Event, TxOutcome},
+    observer::EngineObserver,
+    records::Record,
+};
+
+/// Hash the first entry of an audit log is chained to.
+const GENESIS: [u8; 32] = [0; 32];
+
+/// Append-only, tamper-evident log of every applied record. Every line holds the
+/// `tx_applied` event of a record and the SHA-256 hash of the hash on the line before
+/// it followed by the event, so that altering, inserting or removing a line breaks the
+/// chain from there on, see [`verify`].
+///
+/// Clones append to the same file, like those of a [`Journal`](crate::journal::Journal).
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    inner: Arc<Mutex<Chain>>,
+}
+
+#[derive(Debug)]
+struct Chain {
+    writer: BufWriter<File>,
+    seq: u64,
+    last: [u8; 32],
+}
+
+/// A line of an audit log.
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditEntry {
+    seq: u64,
+    hash: String,
+    applied: Event<TxOutcome>,
+}
+
+/// The last entry of an intact audit log. Only a head recorded elsewhere shows that no
+/// entries were cut off the end of the log.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AuditHead {
+    pub entries: u64,
+    pub hash: String,
+}
+
+impl AuditLog {
+    /// Opens `path` for appending, creating it if needed. The entries it already holds
+    /// are verified first, so that new entries are never chained to an altered log.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let (seq, last) = match path.exists() {
+            true => chain(path).map_err(|e| format!("{}: {e}", path.display()))?,
+            false => (0, GENESIS),
+        };
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Chain {
+                writer: BufWriter::new(file),
+                seq,
+                last,
+            })),
+        })
+    }
+
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error>> {
+        let applied = Event::new(TxOutcome::new(record, Ok(())));
+        let mut chain = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let hash = link(&chain.last, &applied)?;
+        let entry = AuditEntry {
+            seq: chain.seq + 1,
+            hash: hex(&hash),
+            applied,
+        };
+        writeln!(chain.writer, "{}", serde_json::to_string(&entry)?)?;
+        chain.writer.flush()?;
+
+        chain.seq = entry.seq;
+        chain.last = hash;
+        Ok(())
+    }
+}
+
+/// An applied record cannot be reported as rejected when it fails to be audited, so
+/// audit errors abort the process instead.
+impl EngineObserver for AuditLog {
+    fn on_applied(&mut self, record: &Record, event: &AccountEvent) {
+        if matches!(event, AccountEvent::Updated { currency, .. } if *currency == record.currency) {
+            self.append(record)
+                .expect("writing to the audit log failed");
+        }
+    }
+}
+
+/// Recomputes the hash chain of the audit log at `path` and returns its head, or the
+/// first line whose entry was altered, inserted or removed.
+pub fn verify<P: AsRef<Path>>(path: P) -> Result<AuditHead, Box<dyn Error>> {
+    let (entries, last) = chain(path.as_ref())?;
+    Ok(AuditHead {
+        entries,
+        hash: hex(&last),
+    })
+}
+
+fn chain(path: &Path) -> Result<(u64, [u8; 32]), Box<dyn Error>> {
+    let mut seq = 0;
+    let mut last = GENESIS;
+
+    for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let broken = |problem: &str| format!("audit line {}: {problem}", index + 1);
+        let entry: AuditEntry = serde_json::from_str(&line).map_err(|e| broken(&e.to_string()))?;
+        if entry.seq != seq + 1 {
+            return Err(broken(&format!("entry {} follows entry {seq}", entry.seq)).into());
+        }
+        let hash = link(&last, &entry.applied)?;
+        if entry.hash != hex(&hash) {
+            return Err(broken("the hash does not match, the log was altered").into());
+        }
+
+        seq = entry.seq;
+        last = hash;
+    }
+
+    Ok((seq, last))
+}
+
+/// Hash of the entry holding `applied` after the entry hashed to `previous`.
+fn link(previous: &[u8; 32], applied: &Event<TxOutcome>) -> Result<[u8; 32], Box<dyn Error>> {
+    let mut hasher = Sha256::new();
+    hasher.update(previous);
+    hasher.update(applied.to_json()?.as_bytes());
+    Ok(hasher.finalize().into())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::Scenario, transaction::Engine};
+
+    #[test]
+    fn altered_audit_logs_fail_verification() {
+        let path = std::env::temp_dir().join("tx_accounts_audit.ndjson");
+        let _ = std::fs::remove_file(&path);
+
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(AuditLog::open(&path).unwrap()));
+        let engine = Scenario::from_engine(engine)
+            .deposit(1, 1, 10)
+            .deposit(2, 2, 5)
+            .withdraw(2, 3, 50)
+            .into_engine();
+        drop(engine);
+
+        // Reopening continues the chain.
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(AuditLog::open(&path).unwrap()));
+        Scenario::from_engine(engine).deposit(3, 4, 2.5);
+        let head = verify(&path).unwrap();
+        assert_eq!(head.entries, 3);
+        assert_eq!(head.hash.len(), 64);
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let altered = log.replacen(r#""amount":"5.0000""#, r#""amount":"50.0000""#, 1);
+        std::fs::write(&path, altered).unwrap();
+        let error = verify(&path).unwrap_err().to_string();
+        assert!(error.starts_with("audit line 2: the hash"), "{error}");
+
+        let removed: Vec<&str> = log
+            .lines()
+            .filter(|line| !line.contains(r#""seq":2"#))
+            .collect();
+        std::fs::write(&path, removed.join("\n")).unwrap();
+        let error = verify(&path).unwrap_err().to_string();
+        assert!(error.contains("entry 3 follows entry 1"), "{error}");
+        assert!(AuditLog::open(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+use std::{collections::HashSet, error::Error, path::Path};
+
+use crate::transaction::ClientId;
+
+/// Reads a blocklist with one client id per line. Blank lines and lines starting with `#`
+/// are skipped.
+pub fn read_blocklist<P: AsRef<Path>>(path: P) -> Result<HashSet<ClientId>, Box<dyn Error>> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+    let mut clients = HashSet::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let client = line
+            .parse()
+            .map_err(|_| format!("{}:{}: invalid client '{line}'", path.display(), index + 1))?;
+        clients.insert(client);
+    }
+
+    Ok(clients)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::EngineConfig,
+        testing::Scenario,
+        transaction::{Engine, RejectionReason},
+    };
+
+    #[test]
+    fn blocked_clients_are_rejected() {
+        let path = std::env::temp_dir().join("tx_accounts_blocklist.txt");
+        std::fs::write(&path, "# frozen by compliance\n2\n\n 3 \n").unwrap();
+        let blocklist = read_blocklist(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(blocklist, HashSet::from([ClientId(2), ClientId(3)]));
+
+        let engine = Engine::with_config(EngineConfig {
+            blocklist,
+            ..Default::default()
+        });
+        Scenario::from_engine(engine)
+            .deposit(1, 1, 10)
+            .deposit(3, 2, 10)
+            .expect_rejected(RejectionReason::ClientBlocked)
+            .expect_no_account(ClientId(3));
+
+        let mut engine = Scenario::new()
+            .deposit(1, 1, 10)
+            .deposit(2, 2, 10)
+            .into_engine();
+        engine.block(ClientId(2));
+        let mut engine = Scenario::from_engine(engine)
+            .deposit(2, 3, 5)
+            .expect_rejected(RejectionReason::ClientBlocked)
+            .dispute(2, 2)
+            .expect_rejected(RejectionReason::ClientBlocked)
+            .deposit(1, 4, 5)
+            .expect_available(ClientId(2), 10)
+            .into_engine();
+        assert!(engine.is_blocked(ClientId(2)));
+        assert_eq!(engine.accounts().len(), 2);
+
+        engine.unblock(ClientId(2));
+        Scenario::from_engine(engine)
+            .deposit(2, 6, 5)
+            .expect_available(ClientId(2), 15);
+    }
+}
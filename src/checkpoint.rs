@@ -0,0 +1,207 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{BufReader, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    records::InputOffset,
+    rejects::{read_rejects, write_rejects, Reject},
+    state::{HistoryDates, StateDir},
+    transaction::Engine,
+};
+
+/// How often a batch run saves a checkpoint it can be resumed from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckpointConfig {
+    pub dir: PathBuf,
+    /// Rows read between checkpoints.
+    pub rows: u64,
+    /// Seconds between checkpoints.
+    pub secs: u64,
+}
+
+/// How far a run got through its inputs, along with the rows it rejected so far.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Inputs of the run; a run over other inputs cannot resume from the checkpoint.
+    pub inputs: Vec<PathBuf>,
+    /// Index into `inputs` of the input being read.
+    pub input: usize,
+    /// Where the next row of that input starts.
+    pub offset: InputOffset,
+    /// Last line of the inputs before it, which its line numbers continue from.
+    pub line_offset: u64,
+    /// Line of the last row read, counted across inputs.
+    pub last_line: u64,
+    pub rows_read: u64,
+    pub rows_sampled: u64,
+    #[serde(skip)]
+    pub rejects: Vec<Reject>,
+}
+
+impl Checkpoint {
+    /// The start of a run over `inputs`.
+    pub fn start(inputs: Vec<PathBuf>) -> Self {
+        Self {
+            inputs,
+            input: 0,
+            offset: InputOffset::default(),
+            line_offset: 0,
+            last_line: 0,
+            rows_read: 0,
+            rows_sampled: 0,
+            rejects: Vec::new(),
+        }
+    }
+}
+
+/// Checkpoints of a run, laid out as:
+///
+/// - `current/`: the last checkpoint, a [`StateDir`] of the engine plus `rejects.csv` and
+///   `position.json`
+/// - `previous/`: the checkpoint before it, only left behind by a crash while saving
+///
+/// A checkpoint is written to `next/` first and then renamed into place, so a crash while
+/// saving never leaves a partial checkpoint behind.
+#[derive(Debug, Clone)]
+pub struct CheckpointDir {
+    root: PathBuf,
+}
+
+impl CheckpointDir {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn save(&self, engine: &Engine, checkpoint: &Checkpoint) -> Result<(), Box<dyn Error>> {
+        let next = self.root.join("next");
+        remove_dir(&next)?;
+
+        let state = StateDir::new(&next);
+        // The run date only matters to the dispute lookback of --state-dir, which
+        // checkpoints cannot be combined with.
+        state.save_history(engine, &HistoryDates::new(), NaiveDate::default())?;
+        state.save_accounts(engine.accounts())?;
+        write_rejects(File::create(next.join("rejects.csv"))?, &checkpoint.rejects)?;
+        fs::write(
+            next.join("position.json"),
+            serde_json::to_string(checkpoint)?,
+        )?;
+
+        let current = self.root.join("current");
+        let previous = self.root.join("previous");
+        if current.exists() {
+            remove_dir(&previous)?;
+            fs::rename(&current, &previous)?;
+        }
+        fs::rename(next, current)?;
+        remove_dir(&previous)
+    }
+
+    /// Restores the last checkpoint into `engine`, if there is one.
+    pub fn load(&self, engine: &mut Engine) -> Result<Option<Checkpoint>, Box<dyn Error>> {
+        let Some(dir) = ["current", "previous"]
+            .into_iter()
+            .map(|name| self.root.join(name))
+            .find(|dir| dir.exists())
+        else {
+            return Ok(None);
+        };
+
+        let mut checkpoint: Checkpoint =
+            serde_json::from_str(&fs::read_to_string(dir.join("position.json"))?)?;
+        checkpoint.rejects = read_rejects(BufReader::new(File::open(dir.join("rejects.csv"))?))?;
+        StateDir::new(dir).restore(engine, NaiveDate::default(), None)?;
+
+        Ok(Some(checkpoint))
+    }
+
+    /// Removes all checkpoints once the run completed.
+    pub fn remove(&self) -> Result<(), Box<dyn Error>> {
+        remove_dir(&self.root)
+    }
+}
+
+fn remove_dir(path: &Path) -> Result<(), Box<dyn Error>> {
+    match fs::remove_dir_all(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        records::{for_each_row_at, CsvDialect, InputFormat},
+        rejects::process_row,
+        transaction::ClientId,
+    };
+
+    #[test]
+    fn runs_resume_from_their_last_checkpoint() {
+        let root = std::env::temp_dir().join("tx_accounts_checkpoint");
+        let input = std::env::temp_dir().join("tx_accounts_checkpointed.csv");
+        fs::write(
+            &input,
+            "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,50\ndispute,1,1,\ndeposit,2,3,5\n",
+        )
+        .unwrap();
+        let dir = CheckpointDir::new(&root);
+        let dialect = CsvDialect::default();
+
+        // The run stops after the dispute, checkpointing after every row.
+        let mut engine = Engine::new();
+        let mut checkpoint = Checkpoint::start(vec![input.clone()]);
+        let stopped = for_each_row_at(
+            &input,
+            InputFormat::Csv,
+            &dialect,
+            checkpoint.offset,
+            |row, offset| {
+                let line = row.line;
+                if let Err(reject) = process_row(&mut engine, row) {
+                    checkpoint.rejects.push(reject);
+                }
+                checkpoint.offset = offset;
+                checkpoint.last_line = line;
+                dir.save(&engine, &checkpoint)?;
+                match line {
+                    4 => Err("crashed".into()),
+                    _ => Ok(()),
+                }
+            },
+        );
+        assert!(stopped.is_err());
+
+        let mut engine = Engine::new();
+        let checkpoint = dir.load(&mut engine).unwrap().unwrap();
+        assert_eq!(checkpoint.last_line, 4);
+        assert_eq!(checkpoint.rejects.len(), 1);
+        assert_eq!(checkpoint.rejects[0].line, 3);
+        let mut lines = Vec::new();
+        for_each_row_at(
+            &input,
+            InputFormat::Csv,
+            &dialect,
+            checkpoint.offset,
+            |row, _| {
+                lines.push(row.line);
+                process_row(&mut engine, row).map_err(|reject| reject.reason.to_string().into())
+            },
+        )
+        .unwrap();
+        assert_eq!(lines, vec![5]);
+
+        let accounts = engine.accounts();
+        assert_eq!(accounts[&ClientId(1)].held, 10.0);
+        assert_eq!(accounts[&ClientId(2)].available, 5.0);
+
+        dir.remove().unwrap();
+        assert!(dir.load(&mut Engine::new()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,787 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    account_meta::AccountDirectory,
+    amounts::{Money, Precision},
+    checkpoint::CheckpointConfig,
+    credit::CreditLimits,
+    fx::{FxRounding, RateTable},
+    integrity::IntegrityPolicy,
+    ledger::LedgerFormat,
+    output::OutputFormat,
+    pipeline::PipelineConfig,
+    records::{CsvDialect, InputFormat},
+    rules::RuleSet,
+    sample::SampleRate,
+    sanity::SanityChecks,
+    transaction::ClientId,
+};
+
+/// What happens when a disputed transaction is charged back but the account holds less
+/// than the disputed amount.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChargebackPolicy {
+    /// Leave the account and the dispute untouched and reject the chargeback.
+    #[default]
+    Reject,
+    /// Charge back the full amount, even if held funds become negative.
+    AllowNegative,
+    /// Charge back only what is still held.
+    Partial,
+}
+
+impl FromStr for ChargebackPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(Self::Reject),
+            "allow-negative" => Ok(Self::AllowNegative),
+            "partial" => Ok(Self::Partial),
+            _ => Err(format!(
+                "unknown chargeback policy '{s}', expected one of reject, allow-negative, partial"
+            )),
+        }
+    }
+}
+
+/// How accounts come into existence.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccountPolicy {
+    /// The first deposit of an unknown client opens its account.
+    #[default]
+    AutoCreate,
+    /// Like `AutoCreate`, but every auto-created account is reported.
+    Report,
+    /// Accounts must be opened by an `open_account` record before any other activity.
+    Strict,
+}
+
+impl FromStr for AccountPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto-create" => Ok(Self::AutoCreate),
+            "report" => Ok(Self::Report),
+            "strict" => Ok(Self::Strict),
+            _ => Err(format!(
+                "unknown account policy '{s}', expected one of auto-create, report, strict"
+            )),
+        }
+    }
+}
+
+/// Which transactions a deposit or withdrawal tx id has to be unique among.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateScope {
+    /// Unique across all clients.
+    #[default]
+    Global,
+    /// Unique per client only, for partners that number transactions per account.
+    PerClient,
+}
+
+impl FromStr for DuplicateScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "global" => Ok(Self::Global),
+            "per-client" => Ok(Self::PerClient),
+            _ => Err(format!(
+                "unknown duplicate scope '{s}', expected one of global, per-client"
+            )),
+        }
+    }
+}
+
+/// Which records still apply to a locked account.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum LockedAccountPolicy {
+    /// Reject everything but an unlock.
+    #[default]
+    Freeze,
+    /// Keep applying disputes, resolves and chargebacks, so that the follow-up events of
+    /// other disputes can settle after a first chargeback. Deposits and withdrawals are
+    /// still rejected.
+    AllowDisputes,
+}
+
+impl FromStr for LockedAccountPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "freeze" => Ok(Self::Freeze),
+            "allow-disputes" => Ok(Self::AllowDisputes),
+            _ => Err(format!(
+                "unknown locked account policy '{s}', expected one of freeze, allow-disputes"
+            )),
+        }
+    }
+}
+
+/// What happens when a dispute would hold more than the account has available, e.g. when
+/// a deposit is disputed after the client spent it. Disputed withdrawals hold the funds
+/// provisionally returned to the client and never reduce the available funds.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisputeHoldPolicy {
+    /// Hold the full amount, even if the available funds become negative.
+    #[default]
+    AllowNegative,
+    /// Reject the dispute unless the available funds cover the amount.
+    RequireFunds,
+}
+
+impl FromStr for DisputeHoldPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "allow-negative" => Ok(Self::AllowNegative),
+            "require-funds" => Ok(Self::RequireFunds),
+            _ => Err(format!(
+                "unknown dispute hold policy '{s}', expected one of allow-negative, require-funds"
+            )),
+        }
+    }
+}
+
+/// What a `representment` does to the account its chargeback locked.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RepresentmentPolicy {
+    /// Restore the funds only; the account stays locked until an unlock.
+    #[default]
+    KeepLocked,
+    /// Restore the funds and unlock the account.
+    Unlock,
+}
+
+impl FromStr for RepresentmentPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "keep-locked" => Ok(Self::KeepLocked),
+            "unlock" => Ok(Self::Unlock),
+            _ => Err(format!(
+                "unknown representment policy '{s}', expected one of keep-locked, unlock"
+            )),
+        }
+    }
+}
+
+/// When [`Engine::apply_batch`](crate::transaction::Engine::apply_batch) rolls a batch
+/// back.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum BatchPolicy {
+    /// Roll back the whole batch as soon as one of its records is rejected.
+    #[default]
+    AllOrNothing,
+    /// Keep the applied records of a batch, like records applied one by one.
+    BestEffort,
+}
+
+impl FromStr for BatchPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "all-or-nothing" => Ok(Self::AllOrNothing),
+            "best-effort" => Ok(Self::BestEffort),
+            _ => Err(format!(
+                "unknown batch policy '{s}', expected one of all-or-nothing, best-effort"
+            )),
+        }
+    }
+}
+
+/// How many times the same deposit or withdrawal may be disputed, counting disputes that
+/// were resolved since.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(try_from = "String", into = "String")]
+pub enum RedisputePolicy {
+    #[default]
+    AllowAlways,
+    /// At most this many disputes; `allow-once` is one.
+    AllowN(u32),
+}
+
+impl RedisputePolicy {
+    /// Whether a transaction that was disputed `disputes` times may be disputed again.
+    pub fn allows(self, disputes: u32) -> bool {
+        match self {
+            Self::AllowAlways => true,
+            Self::AllowN(max) => disputes < max,
+        }
+    }
+}
+
+impl FromStr for RedisputePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+        match s.as_str() {
+            "allow-always" => Ok(Self::AllowAlways),
+            "allow-once" => Ok(Self::AllowN(1)),
+            _ => match s.strip_prefix("allow-").map(str::parse) {
+                Some(Ok(max)) if max > 0 => Ok(Self::AllowN(max)),
+                _ => Err(format!(
+                    "unknown re-dispute policy '{s}', expected one of allow-once, allow-N, allow-always"
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for RedisputePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AllowAlways => f.write_str("allow-always"),
+            Self::AllowN(1) => f.write_str("allow-once"),
+            Self::AllowN(max) => write!(f, "allow-{max}"),
+        }
+    }
+}
+
+impl TryFrom<String> for RedisputePolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<RedisputePolicy> for String {
+    fn from(policy: RedisputePolicy) -> Self {
+        policy.to_string()
+    }
+}
+
+/// Policies of the engine for edge cases that partners handle differently. A `--config`
+/// file sets them in TOML, with the field names as keys; policies it leaves out keep their
+/// defaults.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    pub chargeback_policy: ChargebackPolicy,
+    pub account_policy: AccountPolicy,
+    pub duplicate_scope: DuplicateScope,
+    pub locked_account_policy: LockedAccountPolicy,
+    pub dispute_hold_policy: DisputeHoldPolicy,
+    pub redispute_policy: RedisputePolicy,
+    pub representment_policy: RepresentmentPolicy,
+    pub batch_policy: BatchPolicy,
+    pub fx_rounding: FxRounding,
+    /// Fee charged to the account after every successful chargeback, in the currency of
+    /// the chargeback and as far as the available funds cover it.
+    pub chargeback_fee: Option<Money>,
+    /// Hours after which a hold that was neither captured nor released is released by the
+    /// engine, counted from the timestamp of its `hold` record. Holds never expire when
+    /// not set, nor do holds placed by records without a timestamp.
+    pub hold_expiry: Option<u32>,
+    /// Records dated after this are deferred rather than applied, see
+    /// [`Engine::set_clock`](crate::transaction::Engine::set_clock).
+    pub as_of: Option<DateTime<Utc>>,
+    /// Rates for `convert` records, read from the file in [`Config::rates`].
+    #[serde(skip)]
+    pub rates: RateTable,
+    /// Limits on deposits and withdrawals, read from the file in [`Config::rules`].
+    #[serde(skip)]
+    pub rules: RuleSet,
+    /// How far withdrawals may take accounts below zero, read from the file in
+    /// [`Config::limits`].
+    #[serde(skip)]
+    pub credit_limits: CreditLimits,
+    /// Clients whose records are all rejected, read from the file in [`Config::blocklist`].
+    #[serde(skip)]
+    pub blocklist: HashSet<ClientId>,
+}
+
+impl EngineConfig {
+    pub fn builder() -> EngineConfigBuilder {
+        EngineConfigBuilder::default()
+    }
+
+    /// Reads the policies of a `--config` file.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?)
+    }
+}
+
+/// Builds an [`EngineConfig`] one policy at a time, starting from the defaults or from an
+/// existing configuration, e.g. one read from a `--config` file that flags override.
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfigBuilder {
+    config: EngineConfig,
+}
+
+impl From<EngineConfig> for EngineConfigBuilder {
+    fn from(config: EngineConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl EngineConfigBuilder {
+    pub fn chargeback_policy(mut self, policy: ChargebackPolicy) -> Self {
+        self.config.chargeback_policy = policy;
+        self
+    }
+
+    pub fn account_policy(mut self, policy: AccountPolicy) -> Self {
+        self.config.account_policy = policy;
+        self
+    }
+
+    pub fn duplicate_scope(mut self, scope: DuplicateScope) -> Self {
+        self.config.duplicate_scope = scope;
+        self
+    }
+
+    pub fn locked_account_policy(mut self, policy: LockedAccountPolicy) -> Self {
+        self.config.locked_account_policy = policy;
+        self
+    }
+
+    pub fn dispute_hold_policy(mut self, policy: DisputeHoldPolicy) -> Self {
+        self.config.dispute_hold_policy = policy;
+        self
+    }
+
+    pub fn redispute_policy(mut self, policy: RedisputePolicy) -> Self {
+        self.config.redispute_policy = policy;
+        self
+    }
+
+    pub fn representment_policy(mut self, policy: RepresentmentPolicy) -> Self {
+        self.config.representment_policy = policy;
+        self
+    }
+
+    pub fn batch_policy(mut self, policy: BatchPolicy) -> Self {
+        self.config.batch_policy = policy;
+        self
+    }
+
+    pub fn fx_rounding(mut self, rounding: FxRounding) -> Self {
+        self.config.fx_rounding = rounding;
+        self
+    }
+
+    pub fn chargeback_fee(mut self, fee: Money) -> Self {
+        self.config.chargeback_fee = Some(fee);
+        self
+    }
+
+    pub fn hold_expiry(mut self, hours: u32) -> Self {
+        self.config.hold_expiry = Some(hours);
+        self
+    }
+
+    pub fn as_of(mut self, as_of: DateTime<Utc>) -> Self {
+        self.config.as_of = Some(as_of);
+        self
+    }
+
+    pub fn rates(mut self, rates: RateTable) -> Self {
+        self.config.rates = rates;
+        self
+    }
+
+    pub fn rules(mut self, rules: RuleSet) -> Self {
+        self.config.rules = rules;
+        self
+    }
+
+    pub fn credit_limits(mut self, limits: CreditLimits) -> Self {
+        self.config.credit_limits = limits;
+        self
+    }
+
+    pub fn blocklist(mut self, blocklist: HashSet<ClientId>) -> Self {
+        self.config.blocklist = blocklist;
+        self
+    }
+
+    pub fn build(self) -> EngineConfig {
+        self.config
+    }
+}
+
+/// Database the engine state is kept in besides memory, given as a URL such as
+/// `sqlite://accounts.db` or `postgres://user@host/accounts`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub enum BackendUrl {
+    Sqlite(PathBuf),
+    /// Connection URL as accepted by libpq.
+    Postgres(String),
+}
+
+impl FromStr for BackendUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once("://") {
+            Some(("sqlite", path)) if !path.is_empty() => Ok(Self::Sqlite(path.into())),
+            Some(("postgres" | "postgresql", _)) => Ok(Self::Postgres(s.to_owned())),
+            _ => Err(format!(
+                "unknown backend '{s}', expected e.g. sqlite://accounts.db or postgres://localhost/accounts"
+            )),
+        }
+    }
+}
+
+/// Passwords are left out, since the configuration is echoed and kept in run manifests.
+/// Such a URL still connects with the password from `PGPASSWORD`.
+impl fmt::Display for BackendUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sqlite(path) => write!(f, "sqlite://{}", path.display()),
+            Self::Postgres(url) => {
+                let Some((scheme, rest)) = url.split_once("://") else {
+                    return f.write_str(url);
+                };
+                match rest.split_once('@') {
+                    Some((user_info, host)) => match user_info.split_once(':') {
+                        Some((user, _)) => write!(f, "{scheme}://{user}@{host}"),
+                        None => f.write_str(url),
+                    },
+                    None => f.write_str(url),
+                }
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for BackendUrl {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<BackendUrl> for String {
+    fn from(url: BackendUrl) -> Self {
+        url.to_string()
+    }
+}
+
+/// Fully resolved settings of a run. Serializes to JSON and back so that a run can be
+/// reproduced exactly from its echoed configuration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    /// Inputs applied one after another as a single stream, after glob expansion.
+    #[serde(alias = "input", deserialize_with = "one_or_many")]
+    pub inputs: Vec<PathBuf>,
+    pub input_format: InputFormat,
+    #[serde(default)]
+    pub csv_dialect: CsvDialect,
+    /// Apply transactions in timestamp order rather than input order.
+    #[serde(default)]
+    pub sort_by_time: bool,
+    pub output_format: OutputFormat,
+    /// Decimal places of the amounts in the accounts output.
+    #[serde(default)]
+    pub precision: Precision,
+    /// Where the accounts are written; stdout when not set.
+    pub output: Option<PathBuf>,
+    pub integrity_policy: IntegrityPolicy,
+    pub corrections: Option<PathBuf>,
+    pub rejects: Option<PathBuf>,
+    pub reject_stats: Option<PathBuf>,
+    pub reject_stats_per_client: bool,
+    pub manifest: Option<PathBuf>,
+    /// Business date of the run recorded in the manifest; today when not set.
+    pub run_date: Option<NaiveDate>,
+    pub engine: EngineConfig,
+    /// File every applied transaction is appended to.
+    pub journal: Option<PathBuf>,
+    /// Tamper-evident log every applied transaction is appended to.
+    #[serde(default)]
+    pub audit_log: Option<PathBuf>,
+    /// Directory that applied transactions are spilled to once `max_memory` is used up.
+    pub tx_store: Option<PathBuf>,
+    /// Memory budget for resident transactions in MiB.
+    pub max_memory: Option<u64>,
+    /// Only process this share of clients and extrapolate the figures of the run.
+    pub sample: Option<SampleRate>,
+    /// Directory the accounts and dispute history are restored from and saved to.
+    pub state_dir: Option<PathBuf>,
+    /// Days of history that can still be disputed.
+    pub dispute_lookback: Option<u32>,
+    /// Fail the run on the first malformed or rejected row.
+    pub strict: bool,
+    pub threads: usize,
+    /// Apply rows while the inputs are still being read, with these channel sizes.
+    #[serde(default)]
+    pub pipeline: Option<PipelineConfig>,
+    /// Where and how often a serial run saves checkpoints it can be resumed from.
+    #[serde(default)]
+    pub checkpoint: Option<CheckpointConfig>,
+    /// Continue from the last checkpoint instead of the start of the inputs.
+    #[serde(default)]
+    pub resume: bool,
+    /// Database the accounts, transactions and open disputes are kept in.
+    #[serde(default)]
+    pub backend: Option<BackendUrl>,
+    /// File the Prometheus metrics of the run are written to.
+    #[serde(default)]
+    pub metrics_file: Option<PathBuf>,
+    /// File the exchange rates for `convert` records are read from.
+    #[serde(default)]
+    pub rates: Option<PathBuf>,
+    /// File the records deferred by [`EngineConfig::as_of`] are written to.
+    #[serde(default)]
+    pub deferred: Option<PathBuf>,
+    /// File every applied record is exported to as double-entry transactions.
+    #[serde(default)]
+    pub ledger: Option<PathBuf>,
+    #[serde(default)]
+    pub ledger_format: LedgerFormat,
+    /// Commodity amounts in the base currency are exported as, `XXX` when not set.
+    #[serde(default)]
+    pub ledger_currency: Option<String>,
+    /// File the outcome of every record is written to.
+    #[serde(default)]
+    pub tx_status: Option<PathBuf>,
+    /// File the disputes still open at the end of the run are written to.
+    #[serde(default)]
+    pub open_disputes: Option<PathBuf>,
+    /// Only write the accounts that changed since the state the run started from.
+    #[serde(default)]
+    pub changed_only: bool,
+    /// File the limits on deposits and withdrawals are read from.
+    #[serde(default)]
+    pub rules: Option<PathBuf>,
+    /// File the credit limits of clients are read from.
+    #[serde(default)]
+    pub limits: Option<PathBuf>,
+    /// File the blocked clients are read from.
+    #[serde(default)]
+    pub blocklist: Option<PathBuf>,
+    /// File the names, KYC tiers, opening balances, credit limits and locks of clients
+    /// are read from.
+    #[serde(default)]
+    pub accounts: Option<PathBuf>,
+    /// Metadata of clients, read from the file in [`Config::accounts`].
+    #[serde(skip)]
+    pub directory: AccountDirectory,
+    /// Checks every row has to pass before it is applied.
+    #[serde(default)]
+    pub sanity: SanityChecks,
+}
+
+impl Config {
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// Accepts the single `input` path recorded by older builds as well as a list of inputs.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_urls() {
+        let url: BackendUrl = "postgres://app:secret@db:5432/accounts".parse().unwrap();
+        assert_eq!(
+            url,
+            BackendUrl::Postgres("postgres://app:secret@db:5432/accounts".to_owned())
+        );
+        assert_eq!(url.to_string(), "postgres://app@db:5432/accounts");
+        assert_eq!(
+            "sqlite://accounts.db".parse(),
+            Ok(BackendUrl::Sqlite("accounts.db".into()))
+        );
+        assert!("sqlite://".parse::<BackendUrl>().is_err());
+        assert!("mysql://db/accounts".parse::<BackendUrl>().is_err());
+    }
+
+    #[test]
+    fn engine_config_from_toml() {
+        let path = std::env::temp_dir().join("tx_accounts_engine.toml");
+        std::fs::write(
+            &path,
+            "chargeback_policy = \"partial\"\nredispute_policy = \"allow-once\"\nhold_expiry = 24\n",
+        )
+        .unwrap();
+
+        let config = EngineConfigBuilder::from(EngineConfig::read(&path).unwrap())
+            .duplicate_scope(DuplicateScope::PerClient)
+            .build();
+        assert_eq!(
+            config,
+            EngineConfig::builder()
+                .chargeback_policy(ChargebackPolicy::Partial)
+                .redispute_policy(RedisputePolicy::AllowN(1))
+                .hold_expiry(24)
+                .duplicate_scope(DuplicateScope::PerClient)
+                .build()
+        );
+
+        // A misspelled policy is an error rather than silently left at its default.
+        std::fs::write(&path, "chargeback_polcy = \"partial\"\n").unwrap();
+        assert!(EngineConfig::read(&path).is_err());
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let config = Config {
+            inputs: vec!["a.jsonl".into(), "b.jsonl".into()],
+            input_format: InputFormat::JsonLines,
+            csv_dialect: CsvDialect {
+                delimiter: ';',
+                has_headers: false,
+                fast_parse: true,
+                ..Default::default()
+            },
+            sort_by_time: true,
+            output_format: OutputFormat::Ndjson,
+            precision: Precision::Iso4217,
+            output: Some(PathBuf::from("accounts.ndjson")),
+            integrity_policy: IntegrityPolicy::Correct,
+            corrections: Some(PathBuf::from("corrections.csv")),
+            rejects: None,
+            reject_stats: None,
+            reject_stats_per_client: false,
+            manifest: Some(PathBuf::from("runs/2024-01-02.json")),
+            run_date: Some(NaiveDate::from_ymd_opt(2024, 1, 2).unwrap()),
+            engine: EngineConfig {
+                chargeback_policy: ChargebackPolicy::Partial,
+                account_policy: AccountPolicy::Strict,
+                duplicate_scope: DuplicateScope::PerClient,
+                locked_account_policy: LockedAccountPolicy::AllowDisputes,
+                dispute_hold_policy: DisputeHoldPolicy::RequireFunds,
+                redispute_policy: RedisputePolicy::AllowN(3),
+                representment_policy: RepresentmentPolicy::Unlock,
+                fx_rounding: FxRounding::Down,
+                chargeback_fee: Some(Money::new(15.0)),
+                hold_expiry: Some(168),
+                as_of: "2024-01-02T23:59:59Z".parse().ok(),
+                ..Default::default()
+            },
+            journal: Some("journal.ndjson".into()),
+            audit_log: Some("audit.ndjson".into()),
+            tx_store: Some("/var/tmp/tx-store".into()),
+            max_memory: Some(512),
+            sample: Some("5%".parse().unwrap()),
+            state_dir: Some("/var/lib/tx-accounts".into()),
+            dispute_lookback: Some(90),
+            strict: true,
+            threads: 4,
+            pipeline: Some(PipelineConfig {
+                batch_size: 512,
+                queue_depth: 8,
+            }),
+            checkpoint: Some(CheckpointConfig {
+                dir: "/var/tmp/checkpoints".into(),
+                rows: 100_000,
+                secs: 60,
+            }),
+            resume: true,
+            backend: Some("sqlite:///var/lib/tx-accounts/state.db".parse().unwrap()),
+            metrics_file: Some("metrics.prom".into()),
+            rates: Some("rates.csv".into()),
+            deferred: Some("deferred.csv".into()),
+            ledger: Some("ledger.beancount".into()),
+            ledger_format: LedgerFormat::Beancount,
+            ledger_currency: Some("USD".into()),
+            tx_status: Some("tx_status.csv".into()),
+            open_disputes: Some("open_disputes.csv".into()),
+            changed_only: true,
+            rules: Some("rules.toml".into()),
+            limits: Some("limits.csv".into()),
+            blocklist: Some("blocklist.txt".into()),
+            accounts: Some("accounts_meta.csv".into()),
+            directory: AccountDirectory::new(),
+            sanity: SanityChecks {
+                max_amount: Some(Money::new(1_000_000.0)),
+                max_decimal_places: Some(2),
+                reject_zero_client: true,
+            },
+        };
+
+        let json = config.to_json().unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"inputs":["a.jsonl","b.jsonl"],"input_format":"json-lines","#,
+                r#""csv_dialect":{"delimiter":";","has_headers":false,"columns":{},"fast_parse":true},"#,
+                r#""sort_by_time":true,"#,
+                r#""output_format":"ndjson","precision":"iso4217","#,
+                r#""output":"accounts.ndjson","#,
+                r#""integrity_policy":"correct","corrections":"corrections.csv","rejects":null,"#,
+                r#""reject_stats":null,"reject_stats_per_client":false,"#,
+                r#""manifest":"runs/2024-01-02.json","run_date":"2024-01-02","#,
+                r#""engine":{"chargeback_policy":"partial","account_policy":"strict","#,
+                r#""duplicate_scope":"per-client","locked_account_policy":"allow-disputes","#,
+                r#""dispute_hold_policy":"require-funds","redispute_policy":"allow-3","representment_policy":"unlock","#,
+                r#""batch_policy":"all-or-nothing","fx_rounding":"down","chargeback_fee":15.0,"hold_expiry":168,"#,
+                r#""as_of":"2024-01-02T23:59:59Z"},"#,
+                r#""journal":"journal.ndjson","audit_log":"audit.ndjson","#,
+                r#""tx_store":"/var/tmp/tx-store","max_memory":512,"sample":0.05,"#,
+                r#""state_dir":"/var/lib/tx-accounts","dispute_lookback":90,"strict":true,"#,
+                r#""threads":4,"pipeline":{"batch_size":512,"queue_depth":8},"#,
+                r#""checkpoint":{"dir":"/var/tmp/checkpoints","rows":100000,"secs":60},"resume":true,"#,
+                r#""backend":"sqlite:///var/lib/tx-accounts/state.db","#,
+                r#""metrics_file":"metrics.prom","rates":"rates.csv","deferred":"deferred.csv","#,
+                r#""ledger":"ledger.beancount","ledger_format":"beancount","ledger_currency":"USD","#,
+                r#""tx_status":"tx_status.csv","open_disputes":"open_disputes.csv","changed_only":true,"#,
+                r#""rules":"rules.toml","limits":"limits.csv","#,
+                r#""blocklist":"blocklist.txt","accounts":"accounts_meta.csv","#,
+                r#""sanity":{"max_amount":1000000.0,"max_decimal_places":2,"reject_zero_client":true}}"#
+            )
+        );
+        assert_eq!(Config::from_json(&json).unwrap(), config);
+
+        // Manifests written before multiple inputs were supported name a single input.
+        let json = json.replace(r#""inputs":["a.jsonl","b.jsonl"]"#, r#""input":"a.jsonl""#);
+        assert_eq!(
+            Config::from_json(&json).unwrap().inputs,
+            vec![PathBuf::from("a.jsonl")]
+        );
+    }
+}
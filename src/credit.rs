@@ -0,0 +1,107 @@
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error, path::Path};
+
+use crate::{amounts::Money, transaction::ClientId};
+
+/// How far withdrawals may take the available funds of a client in the base currency below
+/// zero. Clients without a limit of their own get the default one, which is zero unless
+/// set.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CreditLimits {
+    default: Money,
+    clients: HashMap<ClientId, Money>,
+}
+
+/// A row of a limits file; a blank client sets the default limit.
+#[derive(Debug, Deserialize)]
+struct LimitRow {
+    client: Option<ClientId>,
+    limit: Money,
+}
+
+impl CreditLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a CSV file with `client,limit` columns, e.g. `7,500.0`.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+
+        let mut limits = Self::new();
+        for row in rdr.deserialize::<LimitRow>() {
+            let row = row?;
+            if !row.limit.is_finite() || row.limit < 0.0 {
+                return Err(format!("{}: invalid limit {}", path.display(), row.limit).into());
+            }
+
+            match row.client {
+                Some(client) => limits.set(client, row.limit),
+                None => limits.set_default(row.limit),
+            }
+        }
+
+        Ok(limits)
+    }
+
+    pub fn set(&mut self, client: ClientId, limit: Money) {
+        self.clients.insert(client, limit);
+    }
+
+    pub fn set_default(&mut self, limit: Money) {
+        self.default = limit;
+    }
+
+    pub fn limit(&self, client: ClientId) -> Money {
+        self.clients.get(&client).copied().unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::EngineConfig,
+        testing::Scenario,
+        transaction::{Engine, RejectionReason},
+    };
+
+    #[test]
+    fn withdrawals_use_credit_up_to_the_limit() {
+        let path = std::env::temp_dir().join("tx_accounts_limits.csv");
+        std::fs::write(&path, "client,limit\n,10\n2,50.5\n3,0\n").unwrap();
+        let credit_limits = CreditLimits::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(credit_limits.limit(ClientId(1)), 10.0);
+        assert_eq!(credit_limits.limit(ClientId(2)), 50.5);
+
+        let engine = Engine::with_config(EngineConfig {
+            credit_limits,
+            ..Default::default()
+        });
+        let engine = Scenario::from_engine(engine)
+            .deposit(1, 1, 5)
+            .withdraw(1, 2, 16)
+            .expect_rejected(RejectionReason::CreditLimitExceeded)
+            .withdraw(1, 3, 15)
+            .expect_available(ClientId(1), -10)
+            .deposit(2, 4, 1)
+            .withdraw(2, 5, 51.5)
+            .expect_available(ClientId(2), -50.5)
+            .deposit(3, 6, 1)
+            .withdraw(3, 7, 2)
+            .expect_rejected(RejectionReason::InsufficientFunds)
+            .in_currency(Some("EUR"))
+            .deposit(1, 8, 1)
+            .withdraw(1, 9, 2)
+            .expect_rejected(RejectionReason::InsufficientFunds)
+            .into_engine();
+
+        assert_eq!(engine.account(ClientId(1)).unwrap().credit_used(), 10.0);
+        assert_eq!(engine.account(ClientId(3)).unwrap().credit_used(), 0.0);
+    }
+}
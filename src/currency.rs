@@ -0,0 +1,80 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+/// A three-letter currency code such as `EUR`, stored uppercase. Records without a
+/// currency are in the base currency of the ledger.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        // Only ASCII letters are ever stored.
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+
+    pub fn to_bytes(self) -> [u8; 3] {
+        self.0
+    }
+
+    pub fn from_bytes(bytes: [u8; 3]) -> Option<Self> {
+        bytes
+            .iter()
+            .all(u8::is_ascii_uppercase)
+            .then_some(Self(bytes))
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes: [u8; 3] = s
+            .as_bytes()
+            .try_into()
+            .ok()
+            .filter(|bytes: &[u8; 3]| bytes.iter().all(u8::is_ascii_alphabetic))
+            .ok_or_else(|| format!("invalid currency '{s}', expected a code like EUR"))?;
+
+        Ok(Self(bytes.map(|b| b.to_ascii_uppercase())))
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Currency({self})")
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.trim().parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_currency() {
+        let eur: Currency = "eur".parse().unwrap();
+        assert_eq!(eur.to_string(), "EUR");
+        assert_eq!(Currency::from_bytes(eur.to_bytes()), Some(eur));
+        assert_eq!(Currency::from_bytes([0; 3]), None);
+        assert!("EURO".parse::<Currency>().is_err());
+        assert!("E1R".parse::<Currency>().is_err());
+    }
+}
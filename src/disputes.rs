@@ -0,0 +1,85 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use std::{error::Error, io::Write};
+
+use crate::{
+    amounts::{serialize_4dp, Money},
+    currency::Currency,
+    transaction::{ClientId, OpenDispute, TxId},
+};
+
+/// A dispute still open at the end of a run, as listed in the open-disputes report.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct OpenDisputeRow {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub currency: Option<Currency>,
+    #[serde(serialize_with = "serialize_4dp")]
+    pub amount: Money,
+    pub opened: Option<DateTime<Utc>>,
+    /// Whole days from the day the dispute was opened to the run date; blank for disputes
+    /// without a timestamp.
+    pub age_days: Option<i64>,
+}
+
+impl OpenDisputeRow {
+    pub fn new(dispute: &OpenDispute, run_date: NaiveDate) -> Self {
+        Self {
+            client: dispute.client,
+            tx: dispute.tx,
+            currency: dispute.currency,
+            amount: dispute.amount,
+            opened: dispute.opened,
+            age_days: dispute
+                .opened
+                .map(|opened| (run_date - opened.date_naive()).num_days().max(0)),
+        }
+    }
+}
+
+/// Writes the open disputes as the queue operations work through, in the order given.
+pub fn write_open_disputes<W: Write>(
+    writer: W,
+    disputes: &[OpenDispute],
+    run_date: NaiveDate,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for dispute in disputes {
+        wtr.serialize(OpenDisputeRow::new(dispute, run_date))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        records::{parse_record, InputFormat},
+        testing::Scenario,
+    };
+
+    #[test]
+    fn open_disputes_are_listed_with_their_age() {
+        let record = |csv: &str| parse_record(csv.as_bytes(), InputFormat::Csv).unwrap();
+        let engine = Scenario::new()
+            .deposit(1, 1, 10)
+            .deposit(1, 2, 5)
+            .deposit(2, 3, 7.5)
+            .apply(record("dispute,1,1,,2024-01-02T10:00:00Z"))
+            .apply(record("dispute,1,2,,2024-01-05T23:00:00Z"))
+            .apply(record("resolve,1,2,,2024-01-06T09:00:00Z"))
+            .dispute(2, 3)
+            .into_engine();
+
+        let mut out = Vec::new();
+        let run_date = NaiveDate::from_ymd_opt(2024, 1, 9).unwrap();
+        write_open_disputes(&mut out, &engine.open_disputes(), run_date).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,tx,currency,amount,opened,age_days\n\
+             1,1,,10.0000,2024-01-02T10:00:00Z,7\n\
+             2,3,,7.5000,,\n"
+        );
+    }
+}
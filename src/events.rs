@@ -0,0 +1,464 @@
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use std::error::Error;
+
+use crate::{
+    amounts::{deserialize_4dp, serialize_4dp, Money},
+    currency::Currency,
+    integrity::{Finding, IntegrityIssue},
+    records::{Record, TxType},
+    transaction::{Account, ClientId, RejectionReason, TxId},
+};
+
+/// Version of the event schema written by this build.
+///
+/// Rules for evolving the schema:
+/// - Adding an event kind or a field keeps the version. New fields must have a default so
+///   that events written by older builds still decode.
+/// - Removing or renaming a field or event kind, or changing the type or meaning of a
+///   field, bumps the version.
+/// - Readers ignore fields they do not know and refuse events with a newer version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// An event as emitted to a sink: the schema version next to the fields of the event,
+/// e.g. `{"version":1,"event":"account_locked","client":1,"tx":7}`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Event<T> {
+    pub version: u32,
+    #[serde(flatten)]
+    pub body: T,
+}
+
+impl<T> Event<T> {
+    pub fn new(body: T) -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            body,
+        }
+    }
+}
+
+impl<T: Serialize> Event<T> {
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+impl<T: DeserializeOwned> Event<T> {
+    pub fn from_json(json: &str) -> Result<Self, Box<dyn Error>> {
+        let event: Self = serde_json::from_str(json)?;
+        if event.version > SCHEMA_VERSION {
+            return Err(format!(
+                "event schema version {} is newer than the supported version {SCHEMA_VERSION}",
+                event.version
+            )
+            .into());
+        }
+
+        Ok(event)
+    }
+}
+
+/// A change to an account.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(tag = "event")]
+pub enum AccountEvent {
+    #[serde(rename = "account_opened")]
+    Opened { client: ClientId, tx: TxId },
+    /// The balances of the account after a transaction was applied, in the currency of
+    /// the transaction.
+    #[serde(rename = "account_updated")]
+    Updated {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+        available: Money,
+        #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+        held: Money,
+        #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+        total: Money,
+        locked: bool,
+    },
+    /// The account was locked by the chargeback `tx`.
+    #[serde(rename = "account_locked")]
+    Locked { client: ClientId, tx: TxId },
+}
+
+impl AccountEvent {
+    /// The balances of `account` in `currency`, or in the base currency for `None`.
+    pub fn updated(account: &Account, currency: Option<Currency>) -> Self {
+        let balance = account.balance(currency);
+        Self::Updated {
+            client: account.client,
+            currency,
+            available: balance.available,
+            held: balance.held,
+            total: balance.total,
+            locked: account.locked,
+        }
+    }
+}
+
+impl From<&Account> for AccountEvent {
+    fn from(account: &Account) -> Self {
+        Self::updated(account, None)
+    }
+}
+
+/// The balances of an account after the transaction `tx` was applied, in the currency of
+/// the transaction, as pushed to the WebSocket subscribers of `serve`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(tag = "event", rename = "balance_updated")]
+pub struct BalanceUpdate {
+    pub r#type: TxType,
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+    pub available: Money,
+    #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+    pub held: Money,
+    #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+    pub total: Money,
+    pub locked: bool,
+}
+
+impl BalanceUpdate {
+    /// The update `record` caused, or `None` for events other than
+    /// [`AccountEvent::Updated`].
+    pub fn new(record: &Record, event: &AccountEvent) -> Option<Self> {
+        let AccountEvent::Updated {
+            client,
+            currency,
+            available,
+            held,
+            total,
+            locked,
+        } = *event
+        else {
+            return None;
+        };
+
+        Some(Self {
+            r#type: record.r#type,
+            client,
+            tx: record.tx,
+            currency,
+            available,
+            held,
+            total,
+            locked,
+        })
+    }
+}
+
+/// Whether a transaction was applied, and if not, why.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(tag = "event")]
+pub enum TxOutcome {
+    #[serde(rename = "tx_applied")]
+    Applied {
+        r#type: TxType,
+        client: ClientId,
+        tx: TxId,
+        #[serde(
+            default,
+            serialize_with = "serialize_amount",
+            deserialize_with = "deserialize_amount"
+        )]
+        amount: Option<Money>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to_currency: Option<Currency>,
+        /// Reason code of a dispute.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason_code: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        memo: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reference: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        merchant_id: Option<String>,
+    },
+    #[serde(rename = "tx_rejected")]
+    Rejected {
+        r#type: TxType,
+        client: ClientId,
+        tx: TxId,
+        #[serde(
+            default,
+            serialize_with = "serialize_amount",
+            deserialize_with = "deserialize_amount"
+        )]
+        amount: Option<Money>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to_currency: Option<Currency>,
+        /// Reason code of a dispute.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason_code: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        memo: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reference: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        merchant_id: Option<String>,
+        reason: RejectionReason,
+    },
+}
+
+impl TxOutcome {
+    pub fn new(record: &Record, result: Result<(), RejectionReason>) -> Self {
+        let (r#type, client, tx, amount) = (record.r#type, record.client, record.tx, record.amount);
+        let (currency, to_currency) = (record.currency, record.to_currency);
+        let reason_code = record.reason_code.clone();
+        let (memo, reference, merchant_id) = (
+            record.memo.clone(),
+            record.reference.clone(),
+            record.merchant_id.clone(),
+        );
+        match result {
+            Ok(()) => Self::Applied {
+                r#type,
+                client,
+                tx,
+                amount,
+                currency,
+                to_currency,
+                reason_code,
+                memo,
+                reference,
+                merchant_id,
+            },
+            Err(reason) => Self::Rejected {
+                r#type,
+                client,
+                tx,
+                amount,
+                currency,
+                to_currency,
+                reason_code,
+                memo,
+                reference,
+                merchant_id,
+                reason,
+            },
+        }
+    }
+}
+
+/// Something that needs attention beyond the transaction at hand.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AlertEvent {
+    Chargeback {
+        client: ClientId,
+        tx: TxId,
+        #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+        amount: Money,
+    },
+    /// The available funds went below zero, e.g. after a chargeback under the
+    /// allow-negative policy.
+    NegativeBalance {
+        client: ClientId,
+        #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+        available: Money,
+    },
+    IntegrityViolation {
+        client: ClientId,
+        issue: IntegrityIssue,
+        corrected: bool,
+    },
+}
+
+impl From<&Finding> for AlertEvent {
+    fn from(finding: &Finding) -> Self {
+        Self::IntegrityViolation {
+            client: finding.client,
+            issue: finding.issue,
+            corrected: finding.corrected,
+        }
+    }
+}
+
+pub(crate) fn serialize_amount<S>(amount: &Option<Money>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match amount {
+        Some(amount) => serialize_4dp(amount, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub(crate) fn deserialize_amount<'de, D>(deserializer: D) -> Result<Option<Money>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    crate::records::trim_and_parse_4dp(deserializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T>(body: T, json: &str)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug + Clone,
+    {
+        let event = Event::new(body);
+        assert_eq!(event.to_json().unwrap(), json);
+        assert_eq!(Event::from_json(json).unwrap(), event);
+    }
+
+    #[test]
+    fn account_events() {
+        let account = Account {
+            client: ClientId(1),
+            available: Money::new(1.5),
+            held: Money::new(0.25),
+            total: Money::new(1.75),
+            locked: false,
+            ..Default::default()
+        };
+
+        round_trip(
+            AccountEvent::from(&account),
+            r#"{"version":1,"event":"account_updated","client":1,"available":"1.5000","held":"0.2500","total":"1.7500","locked":false}"#,
+        );
+        round_trip(
+            AccountEvent::updated(&account, "EUR".parse().ok()),
+            r#"{"version":1,"event":"account_updated","client":1,"currency":"EUR","available":"0.0000","held":"0.0000","total":"0.0000","locked":false}"#,
+        );
+        round_trip(
+            AccountEvent::Locked {
+                client: ClientId(1),
+                tx: TxId(7),
+            },
+            r#"{"version":1,"event":"account_locked","client":1,"tx":7}"#,
+        );
+        round_trip(
+            BalanceUpdate {
+                r#type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(3),
+                currency: None,
+                available: Money::new(1.5),
+                held: Money::new(0.25),
+                total: Money::new(1.75),
+                locked: false,
+            },
+            r#"{"version":1,"event":"balance_updated","type":"deposit","client":1,"tx":3,"available":"1.5000","held":"0.2500","total":"1.7500","locked":false}"#,
+        );
+    }
+
+    #[test]
+    fn tx_outcomes() {
+        let record = Record {
+            r#type: TxType::Withdrawal,
+            client: ClientId(2),
+            tx: TxId(3),
+            amount: Some(Money::new(5.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        };
+
+        round_trip(
+            TxOutcome::new(&record, Ok(())),
+            r#"{"version":1,"event":"tx_applied","type":"withdrawal","client":2,"tx":3,"amount":"5.0000"}"#,
+        );
+        round_trip(
+            TxOutcome::new(&record, Err(RejectionReason::InsufficientFunds)),
+            r#"{"version":1,"event":"tx_rejected","type":"withdrawal","client":2,"tx":3,"amount":"5.0000","reason":"insufficient_funds"}"#,
+        );
+        round_trip(
+            TxOutcome::Applied {
+                r#type: TxType::Dispute,
+                client: ClientId(2),
+                tx: TxId(3),
+                amount: None,
+                currency: None,
+                to_currency: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
+            },
+            r#"{"version":1,"event":"tx_applied","type":"dispute","client":2,"tx":3,"amount":null}"#,
+        );
+        round_trip(
+            TxOutcome::Applied {
+                r#type: TxType::Dispute,
+                client: ClientId(2),
+                tx: TxId(3),
+                amount: Some(Money::new(1.5)),
+                currency: None,
+                to_currency: None,
+                reason_code: Some("10.4".to_string()),
+                memo: None,
+                reference: Some("INV-88".to_string()),
+                merchant_id: None,
+            },
+            r#"{"version":1,"event":"tx_applied","type":"dispute","client":2,"tx":3,"amount":"1.5000","reason_code":"10.4","reference":"INV-88"}"#,
+        );
+    }
+
+    #[test]
+    fn alert_events() {
+        round_trip(
+            AlertEvent::NegativeBalance {
+                client: ClientId(4),
+                available: Money::new(-2.0),
+            },
+            r#"{"version":1,"event":"negative_balance","client":4,"available":"-2.0000"}"#,
+        );
+        round_trip(
+            AlertEvent::IntegrityViolation {
+                client: ClientId(4),
+                issue: IntegrityIssue::NegativeHeld,
+                corrected: true,
+            },
+            r#"{"version":1,"event":"integrity_violation","client":4,"issue":"negative_held","corrected":true}"#,
+        );
+    }
+
+    #[test]
+    fn schema_evolution() {
+        // Fields added by a later build of the same version are ignored.
+        let event: Event<AccountEvent> = Event::from_json(
+            r#"{"version":1,"event":"account_opened","client":1,"tx":2,"channel":"web"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            event.body,
+            AccountEvent::Opened {
+                client: ClientId(1),
+                tx: TxId(2)
+            }
+        );
+
+        // A missing optional field decodes to its default.
+        let event: Event<TxOutcome> = Event::from_json(
+            r#"{"version":1,"event":"tx_applied","type":"resolve","client":1,"tx":2}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            event.body,
+            TxOutcome::Applied { amount: None, .. }
+        ));
+
+        assert!(Event::<AccountEvent>::from_json(
+            r#"{"version":2,"event":"account_opened","client":1,"tx":2}"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,347 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    io::{Read, Write},
+};
+
+use crate::{
+    amounts::Money,
+    currency::Currency,
+    events::{deserialize_amount, serialize_amount},
+    records::{Record, TxType},
+    state::{HistoryDates, IndexedTx, SourceOffset},
+    transaction::{Account, ClientId, Engine, Hold, TxId},
+};
+
+/// Version of the state files written by `export-state`. Files of older versions are
+/// migrated when they are read.
+pub const STATE_VERSION: u32 = 1;
+
+/// The whole state of an engine as one JSON document, written by `export-state` and read
+/// by `import-state`. Fields it does not know are ignored and fields it misses keep their
+/// defaults, so that files written by other versions of the engine still load.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+#[serde(default)]
+pub struct StateExport {
+    pub version: u32,
+    pub accounts: Vec<Account>,
+    /// Deposits and withdrawals that can still be disputed, with their disputes.
+    pub transactions: Vec<ExportedTx>,
+    /// Client, id and amount of every deposit and withdrawal ever applied, so that one
+    /// sent again is still a duplicate after it left `transactions`.
+    pub tx_index: Vec<IndexedTx>,
+    pub holds: Vec<Hold>,
+    pub offsets: Vec<SourceOffset>,
+    /// The last tx id the engine generated, e.g. for a chargeback fee.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_generated_tx: Option<TxId>,
+}
+
+/// A deposit or withdrawal in a [`StateExport`].
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ExportedTx {
+    pub r#type: TxType,
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(
+        default,
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub amount: Option<Money>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    /// Date of the run that applied it, from which the dispute lookback counts. Today
+    /// when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub disputed: bool,
+    /// How many times it was disputed, including an open dispute.
+    #[serde(default)]
+    pub disputes: u32,
+    /// Amount of an open dispute over part of it.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub disputed_amount: Option<Money>,
+    /// Amount of a chargeback a representment may still reverse.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_amount",
+        deserialize_with = "deserialize_amount"
+    )]
+    pub charged_back: Option<Money>,
+    /// When an open dispute on it was opened, if its record had a timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dispute_opened: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merchant_id: Option<String>,
+}
+
+impl StateExport {
+    /// The state of `engine`, sorted by client and tx id. Transactions are dated with
+    /// their run date in `dates`, if they have one.
+    pub fn new(engine: &Engine, dates: &HistoryDates) -> Self {
+        let mut transactions: Vec<ExportedTx> = engine
+            .dispute_history()
+            .map(|(record, disputed)| ExportedTx {
+                r#type: record.r#type,
+                client: record.client,
+                tx: record.tx,
+                amount: record.amount,
+                currency: record.currency,
+                run_date: dates.get(&(record.client, record.tx)).copied(),
+                disputed,
+                disputes: engine.dispute_count(record.client, record.tx),
+                disputed_amount: engine.disputed_amount(record.client, record.tx),
+                charged_back: engine.charged_back(record.client, record.tx),
+                dispute_opened: engine.dispute_opened(record.client, record.tx),
+                memo: record.memo,
+                reference: record.reference,
+                merchant_id: record.merchant_id,
+            })
+            .collect();
+        transactions.sort_by_key(|tx| (tx.client, tx.tx));
+
+        let mut tx_index: Vec<IndexedTx> = engine
+            .tx_index()
+            .map(|(client, tx, amount)| IndexedTx { client, tx, amount })
+            .collect();
+        tx_index.sort_by_key(|indexed| (indexed.client, indexed.tx));
+
+        let mut holds = engine.holds();
+        holds.sort_by_key(|hold| (hold.client, hold.tx));
+
+        Self {
+            version: STATE_VERSION,
+            accounts: engine.accounts_iter().cloned().collect(),
+            transactions,
+            tx_index,
+            holds,
+            offsets: engine
+                .source_offsets()
+                .into_iter()
+                .map(|(source, offset)| SourceOffset { source, offset })
+                .collect(),
+            last_generated_tx: engine.last_generated_tx(),
+        }
+    }
+
+    pub fn read<R: Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let export: Self = serde_json::from_reader(reader)?;
+        if export.version == 0 {
+            return Err("not a state export: the version is missing".into());
+        }
+
+        Ok(export.migrate())
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Merges the states of runs over disjoint sets of clients, e.g. the shards of an
+    /// input split by client, into one. Fails if a client is in more than one of them.
+    /// A source read by several runs keeps the lowest offset, so that no row is skipped,
+    /// and generated tx ids carry on after the highest one of any run.
+    pub fn merge(shards: Vec<Self>) -> Result<Self, Box<dyn Error>> {
+        let mut owners = HashMap::new();
+        for (shard, export) in shards.iter().enumerate() {
+            let clients = export
+                .accounts
+                .iter()
+                .map(|account| account.client)
+                .chain(export.tx_index.iter().map(|indexed| indexed.client))
+                .collect::<HashSet<_>>();
+            for client in clients {
+                if let Some(other) = owners.insert(client, shard) {
+                    return Err(format!(
+                        "client {client} is in both state {} and state {}",
+                        other + 1,
+                        shard + 1
+                    )
+                    .into());
+                }
+            }
+        }
+
+        let mut merged = Self {
+            version: STATE_VERSION,
+            ..Self::default()
+        };
+        let mut offsets: HashMap<String, u64> = HashMap::new();
+        for export in shards {
+            merged.accounts.extend(export.accounts);
+            merged.transactions.extend(export.transactions);
+            merged.tx_index.extend(export.tx_index);
+            merged.holds.extend(export.holds);
+            merged.last_generated_tx = merged.last_generated_tx.max(export.last_generated_tx);
+            for stored in export.offsets {
+                offsets
+                    .entry(stored.source)
+                    .and_modify(|offset| *offset = (*offset).min(stored.offset))
+                    .or_insert(stored.offset);
+            }
+        }
+
+        merged.accounts.sort_by_key(|account| account.client);
+        merged.transactions.sort_by_key(|tx| (tx.client, tx.tx));
+        merged
+            .tx_index
+            .sort_by_key(|indexed| (indexed.client, indexed.tx));
+        merged.holds.sort_by_key(|hold| (hold.client, hold.tx));
+        merged.offsets = offsets
+            .into_iter()
+            .map(|(source, offset)| SourceOffset { source, offset })
+            .collect();
+        merged.offsets.sort_by(|a, b| a.source.cmp(&b.source));
+        Ok(merged)
+    }
+
+    /// Brings the state of a file written by an older version up to [`STATE_VERSION`].
+    /// Newer files are taken as they are, without the fields this version does not know.
+    fn migrate(self) -> Self {
+        Self {
+            version: STATE_VERSION,
+            ..self
+        }
+    }
+
+    /// Restores the state into `engine`, and returns the run date of every transaction
+    /// that has one.
+    pub fn restore(self, engine: &mut Engine) -> HistoryDates {
+        for account in self.accounts {
+            engine.restore_account(account);
+        }
+
+        let mut dates = HistoryDates::new();
+        let mut disputable = HashSet::new();
+        for exported in self.transactions {
+            let (client, tx) = (exported.client, exported.tx);
+            disputable.insert((client, tx));
+            if let Some(run_date) = exported.run_date {
+                dates.insert((client, tx), run_date);
+            }
+
+            engine.restore_transaction(
+                Record {
+                    r#type: exported.r#type,
+                    client,
+                    tx,
+                    amount: exported.amount,
+                    timestamp: None,
+                    currency: exported.currency,
+                    to_currency: None,
+                    total: None,
+                    reason_code: None,
+                    memo: exported.memo,
+                    reference: exported.reference,
+                    merchant_id: exported.merchant_id,
+                },
+                exported.disputed,
+            );
+            engine.restore_dispute_count(client, tx, exported.disputes);
+            if let Some(amount) = exported.disputed_amount {
+                engine.restore_disputed_amount(client, tx, amount);
+            }
+            if let Some(amount) = exported.charged_back {
+                engine.restore_chargeback(client, tx, amount);
+            }
+            if let Some(opened) = exported.dispute_opened {
+                engine.restore_dispute_opened(client, tx, opened);
+            }
+        }
+
+        for indexed in self.tx_index {
+            if !disputable.contains(&(indexed.client, indexed.tx)) {
+                engine.retire_transaction(indexed.client, indexed.tx, indexed.amount);
+            }
+        }
+
+        for hold in self.holds {
+            engine.restore_hold(hold);
+        }
+
+        for stored in self.offsets {
+            engine.set_source_offset(&stored.source, Some(stored.offset));
+        }
+
+        if let Some(last) = self.last_generated_tx {
+            engine.restore_last_generated_tx(last);
+        }
+
+        dates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scenario;
+
+    #[test]
+    fn state_survives_an_export() {
+        let scenario = Scenario::new()
+            .deposit(1, 1, 10)
+            .deposit(1, 2, 5)
+            .dispute_part(1, 1, 4)
+            .deposit(2, 4, 8)
+            .hold(2, 5, 3);
+        let engine = scenario.engine();
+        let dates = HistoryDates::from([((ClientId(1), TxId(2)), "2024-01-02".parse().unwrap())]);
+
+        let mut json = Vec::new();
+        StateExport::new(engine, &dates).write(&mut json).unwrap();
+        let export = StateExport::read(json.as_slice()).unwrap();
+        assert_eq!(export.transactions.len(), 3);
+
+        let mut restored = Engine::new();
+        assert_eq!(export.restore(&mut restored), dates);
+        assert_eq!(restored.accounts(), engine.accounts());
+        assert_eq!(restored.open_disputes(), engine.open_disputes());
+        assert_eq!(restored.holds(), engine.holds());
+
+        // Files of newer versions load without the fields this version does not know.
+        let newer = r#"{"version":7,"accounts":[{"client":3,"available":"1.5","held":"0",
+            "total":"1.5","locked":false,"tier":"gold"}],"audit":[]}"#;
+        let export = StateExport::read(newer.as_bytes()).unwrap();
+        assert_eq!(export.version, STATE_VERSION);
+        assert_eq!(export.accounts[0].available, 1.5);
+
+        assert!(StateExport::read(r#"{"accounts":[]}"#.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn shards_merge_unless_they_share_a_client() {
+        let shard = |scenario: Scenario| StateExport::new(scenario.engine(), &HistoryDates::new());
+        let first = Scenario::new().deposit(1, 1, 10).dispute(1, 1);
+        let second = Scenario::new().deposit(2, 2, 5).withdraw(2, 3, 1);
+
+        let merged = StateExport::merge(vec![shard(first), shard(second)]).unwrap();
+        let mut engine = Engine::new();
+        merged.restore(&mut engine);
+        let accounts = engine.accounts();
+        assert_eq!(accounts[&ClientId(1)].held, 10.0);
+        assert_eq!(accounts[&ClientId(2)].available, 4.0);
+        assert_eq!(engine.open_disputes().len(), 1);
+
+        let overlapping = vec![
+            shard(Scenario::new().deposit(1, 1, 10)),
+            shard(Scenario::new().deposit(2, 2, 5).deposit(1, 3, 5)),
+        ];
+        let error = StateExport::merge(overlapping).unwrap_err().to_string();
+        assert_eq!(error, "client 1 is in both state 1 and state 2");
+    }
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error, path::Path, str::FromStr};
+
+use crate::{amounts::Money, currency::Currency};
+
+/// How a converted amount is rounded to the 4 decimal places amounts are kept at.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum FxRounding {
+    /// To the nearest value, halves away from zero.
+    #[default]
+    Nearest,
+    /// Towards zero, so the client never receives more than the rate gives.
+    Down,
+    Up,
+}
+
+impl FromStr for FxRounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nearest" => Ok(Self::Nearest),
+            "down" => Ok(Self::Down),
+            "up" => Ok(Self::Up),
+            _ => Err(format!(
+                "unknown fx rounding '{s}', expected one of nearest, down, up"
+            )),
+        }
+    }
+}
+
+/// Exchange rates between currencies, where `None` is the base currency. A rate from
+/// one currency to another also converts the other way round at its inverse.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RateTable {
+    rates: HashMap<(Option<Currency>, Option<Currency>), f64>,
+}
+
+/// A row of a rates file; a blank currency is the base currency.
+#[derive(Debug, Deserialize)]
+struct RateRow {
+    from: Option<Currency>,
+    to: Option<Currency>,
+    rate: f64,
+}
+
+impl RateTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a CSV file with `from,to,rate` columns, e.g. `EUR,USD,1.08`.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+
+        let mut table = Self::new();
+        for row in rdr.deserialize::<RateRow>() {
+            let row = row?;
+            if !row.rate.is_finite() || row.rate <= 0.0 {
+                return Err(format!("{}: invalid rate {}", path.display(), row.rate).into());
+            }
+
+            table.insert(row.from, row.to, row.rate);
+        }
+
+        Ok(table)
+    }
+
+    pub fn insert(&mut self, from: Option<Currency>, to: Option<Currency>, rate: f64) {
+        self.rates.insert((from, to), rate);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+
+    /// The rate from `from` to `to`, if the table has it either way round.
+    pub fn rate(&self, from: Option<Currency>, to: Option<Currency>) -> Option<f64> {
+        if from == to {
+            return None;
+        }
+
+        self.rates
+            .get(&(from, to))
+            .copied()
+            .or_else(|| self.rates.get(&(to, from)).map(|rate| 1.0 / rate))
+    }
+
+    /// `amount` in `from` converted to `to` and rounded to 4 decimal places.
+    pub fn convert(
+        &self,
+        amount: Money,
+        from: Option<Currency>,
+        to: Option<Currency>,
+        rounding: FxRounding,
+    ) -> Option<Money> {
+        let scaled = f64::from(amount) * self.rate(from, to)? * 10_000.0;
+        let rounded = match rounding {
+            FxRounding::Nearest => scaled.round(),
+            FxRounding::Down => scaled.floor(),
+            FxRounding::Up => scaled.ceil(),
+        };
+
+        Some(Money::new((rounded / 10_000.0) as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_with_rounding() {
+        let path = std::env::temp_dir().join("tx_accounts_rates.csv");
+        std::fs::write(&path, "from,to,rate\nEUR,USD,1.08335\n,EUR,0.5\n").unwrap();
+        let rates = RateTable::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (eur, usd) = ("EUR".parse().ok(), "USD".parse().ok());
+        assert_eq!(
+            rates.convert(Money::new(1.0), eur, usd, FxRounding::Nearest),
+            Some(Money::new(1.0834))
+        );
+        assert_eq!(
+            rates.convert(Money::new(1.0), eur, usd, FxRounding::Down),
+            Some(Money::new(1.0833))
+        );
+        assert_eq!(
+            rates.convert(Money::new(10.0), None, eur, FxRounding::Up),
+            Some(Money::new(5.0))
+        );
+        assert_eq!(
+            rates.convert(Money::new(5.0), eur, None, FxRounding::Nearest),
+            Some(Money::new(10.0))
+        );
+        assert_eq!(rates.rate(usd, None), None);
+        assert_eq!(rates.rate(eur, eur), None);
+
+        assert_eq!("down".parse(), Ok(FxRounding::Down));
+        assert!("bankers".parse::<FxRounding>().is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use serde::Serialize;
+use std::{error::Error, io::Write};
+
+use crate::{
+    records::TxType,
+    sample::mix,
+    transaction::{ClientId, RawClientId, TxId},
+};
+
+/// Shape of a synthetic input. The same options always produce the same file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerateOptions {
+    pub clients: u16,
+    pub transactions: u64,
+    /// Share of rows that dispute, resolve or charge back an earlier deposit.
+    pub dispute_rate: f64,
+    /// Share of rows that reuse the tx id of an earlier deposit or withdrawal.
+    pub duplicate_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            clients: 1000,
+            transactions: 100_000,
+            dispute_rate: 0.01,
+            duplicate_rate: 0.001,
+            seed: 0,
+        }
+    }
+}
+
+/// Writes a synthetic CSV input, e.g. for benchmarks. Most rows are deposits and
+/// withdrawals of random clients; disputes refer to earlier deposits of the same client
+/// and are later resolved or charged back.
+pub fn generate<W: Write>(writer: W, options: &GenerateOptions) -> Result<(), Box<dyn Error>> {
+    let mut rng = Rng::new(options.seed);
+    let mut wtr = csv::Writer::from_writer(writer);
+
+    let mut applied: Vec<(ClientId, TxId)> = Vec::new();
+    let mut deposits: Vec<(ClientId, TxId)> = Vec::new();
+    let mut disputes: Vec<(ClientId, TxId)> = Vec::new();
+    let mut next_tx = 1;
+
+    for _ in 0..options.transactions {
+        let roll = rng.next_f64();
+        if roll < options.dispute_rate && !deposits.is_empty() {
+            // Settle an open dispute half of the time, so disputes do not pile up.
+            let (r#type, (client, tx)) = if !disputes.is_empty() && rng.next_f64() < 0.5 {
+                let (client, tx) = disputes.swap_remove(rng.below(disputes.len()));
+                let r#type = if rng.next_f64() < 0.8 {
+                    TxType::Resolve
+                } else {
+                    TxType::Chargeback
+                };
+                (r#type, (client, tx))
+            } else {
+                let deposit = deposits[rng.below(deposits.len())];
+                disputes.push(deposit);
+                (TxType::Dispute, deposit)
+            };
+            wtr.serialize(Row {
+                r#type,
+                client,
+                tx,
+                amount: None,
+            })?;
+            continue;
+        }
+
+        let client = ClientId(rng.below(usize::from(options.clients.max(1))) as RawClientId + 1);
+        let duplicate = roll < options.dispute_rate + options.duplicate_rate && !applied.is_empty();
+        let tx = if duplicate {
+            applied[rng.below(applied.len())].1
+        } else {
+            next_tx += 1;
+            TxId(next_tx - 1)
+        };
+        let r#type = if rng.next_f64() < 0.6 {
+            TxType::Deposit
+        } else {
+            TxType::Withdrawal
+        };
+        if !duplicate {
+            applied.push((client, tx));
+            if r#type == TxType::Deposit {
+                deposits.push((client, tx));
+            }
+        }
+
+        let amount = rng.below(10_000_000) as f64 / 10_000.0;
+        wtr.serialize(Row {
+            r#type,
+            client,
+            tx,
+            amount: Some(format!("{amount:.4}")),
+        })?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Row {
+    r#type: TxType,
+    client: ClientId,
+    tx: TxId,
+    amount: Option<String>,
+}
+
+/// Counter based generator on the same mixing function as sampling, so that files are
+/// reproducible without depending on the stream of an external RNG crate.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(mix(seed))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(1);
+        mix(self.0)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        records::{read_rows, InputFormat},
+        stats::BatchSummary,
+    };
+
+    fn generated(options: &GenerateOptions) -> String {
+        let mut out = Vec::new();
+        generate(&mut out, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn generate_is_reproducible() {
+        let options = GenerateOptions {
+            clients: 10,
+            transactions: 2000,
+            dispute_rate: 0.1,
+            duplicate_rate: 0.05,
+            seed: 7,
+        };
+        let csv = generated(&options);
+        assert_eq!(csv, generated(&options));
+        assert_ne!(
+            csv,
+            generated(&GenerateOptions {
+                seed: 8,
+                ..options.clone()
+            })
+        );
+
+        let path = std::env::temp_dir().join("tx_accounts_generated.csv");
+        std::fs::write(&path, &csv).unwrap();
+        let rows = read_rows(&path, InputFormat::Csv).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let summary = BatchSummary::new(rows);
+        assert_eq!(summary.rows, 2000);
+        assert_eq!(summary.malformed, 0);
+        assert_eq!(summary.clients, 10);
+        assert!(summary.counts[&TxType::Dispute] > 100, "{summary}");
+        assert!(summary.disputes_opened > 0, "{summary}");
+        assert!(summary.charged_back > 0, "{summary}");
+    }
+}
@@ -0,0 +1,328 @@
+use std::{error::Error, net::SocketAddr, pin::Pin, str::FromStr, sync::Arc};
+use tokio::sync::broadcast;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    Stream, StreamExt,
+};
+use tonic::{Request, Response, Status};
+
+use crate::{
+    currency::Currency,
+    events::AccountEvent,
+    observer::EngineObserver,
+    records::{parse_amount, parse_currency, parse_timestamp, Record, TxType},
+    shared::SharedEngine,
+    transaction::{Balance, ClientId, OpenDispute, TxId},
+};
+
+use proto::{
+    accounts_server::{Accounts, AccountsServer},
+    Account, AccountUpdate, GetAccountRequest, OpenDisputesRequest, OpenDisputesResponse,
+    StreamAccountUpdatesRequest, SubmitTransactionResponse, Transaction,
+};
+
+/// Messages and service generated from `proto/tx_accounts.proto`.
+pub mod proto {
+    tonic::include_proto!("tx_accounts.v1");
+}
+
+/// Updates buffered per stream; a client falling further behind loses its stream.
+const UPDATE_BUFFER: usize = 1024;
+
+/// Publishes every balance change to the open `StreamAccountUpdates` streams.
+#[derive(Debug, Clone)]
+struct AccountUpdates(broadcast::Sender<AccountUpdate>);
+
+impl EngineObserver for AccountUpdates {
+    fn on_applied(&mut self, record: &Record, event: &AccountEvent) {
+        let AccountEvent::Updated {
+            client,
+            currency,
+            available,
+            held,
+            total,
+            locked,
+        } = *event
+        else {
+            return;
+        };
+
+        // Sending only fails while no stream is open.
+        let _ = self.0.send(AccountUpdate {
+            client: client.0.into(),
+            tx: record.tx.to_u64(),
+            locked,
+            balance: Some(balance(
+                currency,
+                Balance {
+                    available,
+                    held,
+                    total,
+                },
+            )),
+        });
+    }
+}
+
+/// The `Accounts` service of `proto/tx_accounts.proto` on top of an engine shared between
+/// all requests.
+#[derive(Debug)]
+pub struct AccountsService {
+    engine: Arc<SharedEngine>,
+    updates: broadcast::Sender<AccountUpdate>,
+}
+
+pub fn service(engine: Arc<SharedEngine>) -> AccountsServer<AccountsService> {
+    let (updates, _) = broadcast::channel(UPDATE_BUFFER);
+    engine.add_observer(AccountUpdates(updates.clone()));
+
+    AccountsServer::new(AccountsService { engine, updates })
+}
+
+/// Serves the gRPC API on `addr` until the process is stopped.
+pub fn serve(addr: SocketAddr, engine: SharedEngine) -> Result<(), Box<dyn Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        tonic::transport::Server::builder()
+            .add_service(service(Arc::new(engine)))
+            .serve(addr)
+            .await?;
+
+        Ok(())
+    })
+}
+
+#[tonic::async_trait]
+impl Accounts for AccountsService {
+    async fn submit_transaction(
+        &self,
+        request: Request<Transaction>,
+    ) -> Result<Response<SubmitTransactionResponse>, Status> {
+        let record = to_record(request.into_inner()).map_err(Status::invalid_argument)?;
+
+        let response = match self.engine.apply(record) {
+            Ok(()) => SubmitTransactionResponse {
+                applied: true,
+                reason: None,
+            },
+            Err(reason) => SubmitTransactionResponse {
+                applied: false,
+                reason: Some(reason.to_string()),
+            },
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<GetAccountRequest>,
+    ) -> Result<Response<Account>, Status> {
+        let client = request.into_inner().client;
+        let account = ClientId::from_u64(client)
+            .and_then(|client| self.engine.account(client))
+            .ok_or_else(|| Status::not_found(format!("no account of client {client}")))?;
+
+        let mut balances = vec![balance(None, account.balance(None))];
+        for (currency, amounts) in &account.currencies {
+            balances.push(balance(Some(*currency), *amounts));
+        }
+        Ok(Response::new(Account {
+            client,
+            locked: account.locked,
+            balances,
+        }))
+    }
+
+    type StreamAccountUpdatesStream =
+        Pin<Box<dyn Stream<Item = Result<AccountUpdate, Status>> + Send>>;
+
+    async fn stream_account_updates(
+        &self,
+        request: Request<StreamAccountUpdatesRequest>,
+    ) -> Result<Response<Self::StreamAccountUpdatesStream>, Status> {
+        let client = request.into_inner().client;
+        // tonic ends the stream with the first error, so a client that fell behind learns
+        // to query the accounts again instead of silently missing updates.
+        let updates =
+            BroadcastStream::new(self.updates.subscribe()).filter_map(move |update| match update {
+                Ok(update) if client.is_none_or(|client| client == update.client) => {
+                    Some(Ok(update))
+                }
+                Ok(_) => None,
+                Err(BroadcastStreamRecvError::Lagged(missed)) => Some(Err(Status::data_loss(
+                    format!("the stream fell behind by {missed} update(s)"),
+                ))),
+            });
+
+        Ok(Response::new(Box::pin(updates)))
+    }
+
+    async fn open_disputes(
+        &self,
+        request: Request<OpenDisputesRequest>,
+    ) -> Result<Response<OpenDisputesResponse>, Status> {
+        let disputes = match request.into_inner().client {
+            None => self.engine.open_disputes(),
+            Some(client) => match ClientId::from_u64(client) {
+                Some(client) => self.engine.open_disputes_of(client),
+                None => Vec::new(),
+            },
+        };
+
+        Ok(Response::new(OpenDisputesResponse {
+            disputes: disputes.into_iter().map(dispute).collect(),
+        }))
+    }
+}
+
+fn to_record(transaction: Transaction) -> Result<Record, String> {
+    let text = |field: &Option<String>| field.as_deref().unwrap_or_default().trim().to_owned();
+
+    Ok(Record {
+        r#type: TxType::from_str(transaction.r#type.trim())?,
+        client: ClientId::from_u64(transaction.client)
+            .ok_or_else(|| format!("client {} is out of range", transaction.client))?,
+        tx: TxId::from_u64(transaction.tx)
+            .ok_or_else(|| format!("tx {} is out of range", transaction.tx))?,
+        amount: parse_amount(&text(&transaction.amount))?,
+        timestamp: parse_timestamp(&text(&transaction.timestamp))?,
+        currency: parse_currency(&text(&transaction.currency))?,
+        to_currency: parse_currency(&text(&transaction.to_currency))?,
+        total: parse_amount(&text(&transaction.total))?,
+        reason_code: Some(text(&transaction.reason_code)).filter(|code| !code.is_empty()),
+        memo: None,
+        reference: None,
+        merchant_id: None,
+    })
+}
+
+fn balance(currency: Option<Currency>, balance: Balance) -> proto::Balance {
+    proto::Balance {
+        currency: currency.map(|currency| currency.to_string()),
+        available: format!("{:.4}", balance.available),
+        held: format!("{:.4}", balance.held),
+        total: format!("{:.4}", balance.total),
+    }
+}
+
+fn dispute(dispute: OpenDispute) -> proto::Dispute {
+    proto::Dispute {
+        client: dispute.client.0.into(),
+        tx: dispute.tx.to_u64(),
+        currency: dispute.currency.map(|currency| currency.to_string()),
+        amount: format!("{:.4}", dispute.amount),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::{accounts_client::AccountsClient, Dispute};
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::{transport::Channel, Code};
+
+    fn transaction(r#type: &str, client: u64, tx: u64, amount: Option<&str>) -> Transaction {
+        Transaction {
+            r#type: r#type.to_owned(),
+            client,
+            tx,
+            amount: amount.map(str::to_owned),
+            ..Default::default()
+        }
+    }
+
+    async fn submit(
+        client: &mut AccountsClient<Channel>,
+        transaction: Transaction,
+    ) -> Option<String> {
+        let response = client
+            .submit_transaction(transaction)
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.applied, response.reason.is_none());
+        response.reason
+    }
+
+    #[tokio::test]
+    async fn submit_query_and_stream_accounts() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service(Arc::new(SharedEngine::default())))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let mut client = AccountsClient::connect(format!("http://{addr}"))
+            .await
+            .unwrap();
+
+        let mut updates = client
+            .stream_account_updates(StreamAccountUpdatesRequest { client: Some(1) })
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(
+            submit(&mut client, transaction("deposit", 1, 1, Some("10"))).await,
+            None
+        );
+        assert_eq!(
+            submit(&mut client, transaction("deposit", 2, 2, Some("5"))).await,
+            None
+        );
+        assert_eq!(
+            submit(&mut client, transaction("withdrawal", 1, 3, Some("20"))).await,
+            Some("insufficient_funds".to_owned())
+        );
+        assert_eq!(
+            submit(&mut client, transaction("dispute", 1, 1, Some("4"))).await,
+            None
+        );
+
+        let error = client
+            .submit_transaction(transaction("re-fund", 1, 4, None))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::InvalidArgument);
+
+        let account = client
+            .get_account(GetAccountRequest { client: 1 })
+            .await
+            .unwrap()
+            .into_inner();
+        let balance = &account.balances[0];
+        assert_eq!(
+            (balance.available.as_str(), balance.held.as_str()),
+            ("6.0000", "4.0000")
+        );
+        let error = client
+            .get_account(GetAccountRequest { client: 9 })
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::NotFound);
+
+        let disputes = client
+            .open_disputes(OpenDisputesRequest { client: None })
+            .await
+            .unwrap()
+            .into_inner()
+            .disputes;
+        assert_eq!(
+            disputes,
+            vec![Dispute {
+                client: 1,
+                tx: 1,
+                currency: None,
+                amount: "4.0000".to_owned(),
+            }]
+        );
+
+        // Only the two updates of client 1 were streamed.
+        for (tx, available) in [(1, "10.0000"), (1, "6.0000")] {
+            let update = updates.message().await.unwrap().unwrap();
+            assert_eq!((update.client, update.tx), (1, tx));
+            assert_eq!(update.balance.unwrap().available, available);
+        }
+    }
+}
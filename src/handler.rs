@@ -0,0 +1,108 @@
+use std::fmt;
+
+use crate::{
+    records::Record,
+    transaction::{Account, RejectionReason},
+    tx_store::TxLookup,
+};
+
+/// Applies the records of a type the engine does not know itself, e.g. proprietary
+/// loyalty adjustments, in the same pass as the built-in types. Registered with
+/// [`Engine::register_handler`] under the name of the type.
+///
+/// [`Engine::register_handler`]: crate::transaction::Engine::register_handler
+pub trait TxHandler: fmt::Debug + Send {
+    /// Applies `record` to `account`, the account of its client, through the methods of
+    /// [`Account`], which keep its total in step. `transactions` holds the deposits and
+    /// withdrawals applied so far, e.g. to look up one the record refers to. Changes to
+    /// `account` are only kept when this returns `Ok`.
+    fn apply(
+        &mut self,
+        record: &Record,
+        account: &mut Account,
+        transactions: &dyn TxLookup,
+    ) -> Result<(), RejectionReason>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        records::{read_rows, InputFormat, TxType},
+        testing::Scenario,
+        transaction::{ClientId, Engine},
+    };
+
+    /// Credits loyalty points as funds, refusing more points than the deposit named by
+    /// `tx` was worth.
+    #[derive(Debug)]
+    struct LoyaltyAdjustment;
+
+    impl TxHandler for LoyaltyAdjustment {
+        fn apply(
+            &mut self,
+            record: &Record,
+            account: &mut Account,
+            transactions: &dyn TxLookup,
+        ) -> Result<(), RejectionReason> {
+            let points = record.amount.ok_or(RejectionReason::InvalidAmount)?;
+            let earned = transactions
+                .find(record.client, record.tx)
+                .and_then(|deposit| deposit.amount)
+                .ok_or(RejectionReason::TxNeverSeen)?;
+            account.deposit(record.currency, points.min(earned))?;
+            match points > earned {
+                true => Err(RejectionReason::InvalidAmount),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn custom_types_go_to_their_handler() {
+        let path = std::env::temp_dir().join("tx_accounts_custom_types.csv");
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\n\
+             deposit,1,1,10\n\
+             Loyalty_Adjustment,1,1,2\n\
+             loyalty_adjustment,1,1,20\n\
+             loyalty_adjustment,1,9,1\n\
+             cashback,1,1,1\n",
+        )
+        .unwrap();
+        let rows = read_rows(&path, InputFormat::Csv).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<Record> = rows.into_iter().map(|row| row.record.unwrap()).collect();
+        assert_eq!(records[1].r#type.as_str(), "loyalty_adjustment");
+        assert_eq!(
+            serde_json::to_string(&records[1].r#type).unwrap(),
+            r#""loyalty_adjustment""#
+        );
+
+        let mut engine = Engine::new();
+        assert!(engine
+            .register_handler("deposit", Box::new(LoyaltyAdjustment))
+            .is_err());
+        engine
+            .register_handler("loyalty_adjustment", Box::new(LoyaltyAdjustment))
+            .unwrap();
+        let mut records = records.into_iter();
+        let mut next = || records.next().unwrap();
+        Scenario::from_engine(engine)
+            .apply(next())
+            .apply(next())
+            .expect_available(ClientId(1), 12)
+            // A rejected record leaves the account as it was.
+            .apply(next())
+            .expect_rejected(RejectionReason::InvalidAmount)
+            .apply(next())
+            .expect_rejected(RejectionReason::TxNeverSeen)
+            .apply(next())
+            .expect_rejected(RejectionReason::UnsupportedType)
+            .expect_available(ClientId(1), 12);
+
+        assert!(matches!("cashback".parse(), Ok(TxType::Custom(_))));
+        assert!("2fast".parse::<TxType>().is_err());
+    }
+}
@@ -0,0 +1,13 @@
+use std::collections::{HashMap, HashSet};
+
+/// Hasher of the engine's internal indexes, which are keyed by client and tx ids and hit
+/// for every record. With the `ahash` feature this is aHash, which is much faster than the
+/// default SipHash on small keys; both are randomly seeded, so iteration order is
+/// unspecified either way.
+#[cfg(feature = "ahash")]
+pub type RandomState = ahash::RandomState;
+#[cfg(not(feature = "ahash"))]
+pub type RandomState = std::collections::hash_map::RandomState;
+
+pub type FastMap<K, V> = HashMap<K, V, RandomState>;
+pub type FastSet<T> = HashSet<T, RandomState>;
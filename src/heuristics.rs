@@ -0,0 +1,328 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    io::Write,
+    path::Path,
+};
+
+use crate::{
+    amounts::Money,
+    records::{Record, TxType},
+    transaction::{ClientId, Engine, TxId},
+};
+
+/// Thresholds of the suspicious-activity heuristics, read from a TOML file whose keys
+/// override the defaults:
+///
+/// ```toml
+/// structuring_threshold = 5000.0
+/// dispute_rate = 0.2
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct Thresholds {
+    /// Minutes after a deposit within which a withdrawal cycles it.
+    pub cycle_minutes: i64,
+    /// Share of the deposit such a withdrawal takes at least.
+    pub cycle_share: f32,
+    /// Amount from which deposits must be reported, which structuring stays under.
+    pub structuring_threshold: Money,
+    /// How far under the threshold, as a share of it, a deposit counts towards structuring.
+    pub structuring_margin: f32,
+    /// Deposits just under the threshold that make a client structuring.
+    pub structuring_count: usize,
+    /// Share of its deposits a client may dispute.
+    pub dispute_rate: f32,
+    /// Deposits a client needs before its dispute rate is judged.
+    pub dispute_min_deposits: usize,
+    /// Deposits of the same amount that make a client suspicious.
+    pub duplicate_count: usize,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            cycle_minutes: 60,
+            cycle_share: 0.9,
+            structuring_threshold: Money::new(10_000.0),
+            structuring_margin: 0.1,
+            structuring_count: 3,
+            dispute_rate: 0.1,
+            dispute_min_deposits: 5,
+            duplicate_count: 5,
+        }
+    }
+}
+
+impl Thresholds {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let thresholds: Self =
+            toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+
+        let shares = [
+            thresholds.cycle_share,
+            thresholds.structuring_margin,
+            thresholds.dispute_rate,
+        ];
+        if thresholds.cycle_minutes < 0
+            || !thresholds.structuring_threshold.is_finite()
+            || shares.iter().any(|share| !(0.0..=1.0).contains(share))
+        {
+            return Err(format!("{}: invalid thresholds", path.display()).into());
+        }
+
+        Ok(thresholds)
+    }
+}
+
+/// What a client is flagged for.
+#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum Heuristic {
+    /// Deposits withdrawn again shortly after, nearly in full.
+    RapidCycle,
+    /// Several deposits just under the reporting threshold.
+    Structuring,
+    /// More of its deposits disputed than the dispute rate allows.
+    DisputeRate,
+    /// Many deposits of the same amount.
+    DuplicateAmounts,
+}
+
+/// A client one heuristic flagged, with the transactions that made it.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct Flag {
+    pub client: ClientId,
+    pub rule: Heuristic,
+    #[serde(serialize_with = "serialize_txs")]
+    pub transactions: Vec<TxId>,
+}
+
+/// Deposits, withdrawals and disputes of a client that were applied.
+#[derive(Debug, Default)]
+struct Activity {
+    deposits: Vec<(TxId, Money, Option<DateTime<Utc>>)>,
+    withdrawals: Vec<(TxId, Money, Option<DateTime<Utc>>)>,
+    disputed: Vec<TxId>,
+}
+
+/// Applies `records` to a throwaway engine and flags the clients whose applied records
+/// match a heuristic, sorted by client and heuristic. Only records in the base currency
+/// are looked at, and rapid cycles only between records with a timestamp.
+pub fn screen<I>(records: I, thresholds: &Thresholds) -> Vec<Flag>
+where
+    I: IntoIterator<Item = Record>,
+{
+    let mut engine = Engine::new();
+    let mut clients: BTreeMap<ClientId, Activity> = BTreeMap::new();
+    for record in records {
+        let (r#type, client, tx) = (record.r#type, record.client, record.tx);
+        let entry = (tx, record.amount.unwrap_or_default(), record.timestamp);
+        if record.currency.is_some() || engine.apply(record).is_err() {
+            continue;
+        }
+
+        let activity = clients.entry(client).or_default();
+        match r#type {
+            TxType::Deposit => activity.deposits.push(entry),
+            TxType::Withdrawal => activity.withdrawals.push(entry),
+            TxType::Dispute => activity.disputed.push(tx),
+            _ => {}
+        }
+    }
+
+    let mut flags = Vec::new();
+    for (client, activity) in clients {
+        let checks = [
+            (Heuristic::RapidCycle, rapid_cycles(&activity, thresholds)),
+            (Heuristic::Structuring, structuring(&activity, thresholds)),
+            (Heuristic::DisputeRate, dispute_rate(&activity, thresholds)),
+            (
+                Heuristic::DuplicateAmounts,
+                duplicates(&activity, thresholds),
+            ),
+        ];
+        for (rule, transactions) in checks {
+            if !transactions.is_empty() {
+                flags.push(Flag {
+                    client,
+                    rule,
+                    transactions,
+                });
+            }
+        }
+    }
+    flags
+}
+
+fn rapid_cycles(activity: &Activity, thresholds: &Thresholds) -> Vec<TxId> {
+    let window = Duration::minutes(thresholds.cycle_minutes);
+    let mut cycled = Vec::new();
+    for &(withdrawal, amount, withdrawn) in &activity.withdrawals {
+        let Some(withdrawn) = withdrawn else {
+            continue;
+        };
+        let deposits = activity
+            .deposits
+            .iter()
+            .filter(|&&(_, deposit, deposited)| {
+                deposited.is_some_and(|deposited| {
+                    deposited <= withdrawn && withdrawn - deposited <= window
+                }) && amount >= deposit * thresholds.cycle_share
+            });
+        for &(deposit, ..) in deposits {
+            cycled.extend([deposit, withdrawal]);
+        }
+    }
+    dedup(cycled)
+}
+
+fn structuring(activity: &Activity, thresholds: &Thresholds) -> Vec<TxId> {
+    let threshold = thresholds.structuring_threshold;
+    let floor = threshold * (1.0 - thresholds.structuring_margin);
+    let under: Vec<TxId> = activity
+        .deposits
+        .iter()
+        .filter(|&&(_, amount, _)| amount >= floor && amount < threshold)
+        .map(|&(tx, ..)| tx)
+        .collect();
+    match under.len() >= thresholds.structuring_count {
+        true => under,
+        false => Vec::new(),
+    }
+}
+
+fn dispute_rate(activity: &Activity, thresholds: &Thresholds) -> Vec<TxId> {
+    let deposits = activity.deposits.len();
+    let disputed = dedup(activity.disputed.clone());
+    let rate = disputed.len() as f32 / deposits.max(1) as f32;
+    match deposits >= thresholds.dispute_min_deposits && rate > thresholds.dispute_rate {
+        true => disputed,
+        false => Vec::new(),
+    }
+}
+
+fn duplicates(activity: &Activity, thresholds: &Thresholds) -> Vec<TxId> {
+    let mut amounts: HashMap<u32, Vec<TxId>> = HashMap::new();
+    for &(tx, amount, _) in &activity.deposits {
+        amounts
+            .entry(amount.to_f32().to_bits())
+            .or_default()
+            .push(tx);
+    }
+    let mut repeated: Vec<TxId> = amounts
+        .into_values()
+        .filter(|txs| txs.len() >= thresholds.duplicate_count)
+        .flatten()
+        .collect();
+    repeated.sort();
+    repeated
+}
+
+/// `txs` without repeats, in the order they first appear.
+fn dedup(txs: Vec<TxId>) -> Vec<TxId> {
+    let mut seen = HashSet::new();
+    txs.into_iter().filter(|tx| seen.insert(*tx)).collect()
+}
+
+fn serialize_txs<S>(txs: &[TxId], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let txs: Vec<String> = txs.iter().map(TxId::to_string).collect();
+    serializer.serialize_str(&txs.join(" "))
+}
+
+pub fn write_flags<W: Write>(writer: W, flags: &[Flag]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for flag in flags {
+        wtr.serialize(flag)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{RawClientId, RawTxId};
+
+    fn record(
+        r#type: TxType,
+        client: RawClientId,
+        tx: RawTxId,
+        amount: f32,
+        minute: u32,
+    ) -> Record {
+        Record {
+            r#type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: (amount > 0.0).then_some(Money::new(amount)),
+            timestamp: format!("2024-01-02T10:{minute:02}:00Z").parse().ok(),
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        }
+    }
+
+    #[test]
+    fn clients_are_flagged_per_heuristic() {
+        use TxType::{Deposit, Dispute, Withdrawal};
+        let mut records = vec![
+            // Client 1 withdraws a deposit within the hour, and a later one after it.
+            record(Deposit, 1, 1, 100.0, 0),
+            record(Withdrawal, 1, 2, 95.0, 30),
+            record(Deposit, 1, 3, 100.0, 31),
+            record(Withdrawal, 1, 4, 50.0, 40),
+        ];
+        // Client 2 deposits just under 10000 three times, once over it.
+        for (tx, amount) in [(10, 9_500.0), (11, 9_900.0), (12, 10_000.0), (13, 9_000.0)] {
+            records.push(record(Deposit, 2, tx, amount, 0));
+        }
+        // Client 3 disputes two of its five deposits, all of the same amount.
+        for tx in 20..25 {
+            records.push(record(Deposit, 3, tx, 20.0, 0));
+        }
+        records.extend([
+            record(Dispute, 3, 20, 0.0, 1),
+            record(Dispute, 3, 21, 0.0, 1),
+        ]);
+
+        let flags: Vec<(ClientId, Heuristic, Vec<TxId>)> = screen(records, &Thresholds::default())
+            .into_iter()
+            .map(|flag| (flag.client, flag.rule, flag.transactions))
+            .collect();
+        let txs = |txs: &[RawTxId]| txs.iter().copied().map(TxId).collect::<Vec<_>>();
+        assert_eq!(
+            flags,
+            vec![
+                (ClientId(1), Heuristic::RapidCycle, txs(&[1, 2])),
+                (ClientId(2), Heuristic::Structuring, txs(&[10, 11, 13])),
+                (ClientId(3), Heuristic::DisputeRate, txs(&[20, 21])),
+                (
+                    ClientId(3),
+                    Heuristic::DuplicateAmounts,
+                    txs(&[20, 21, 22, 23, 24])
+                ),
+            ]
+        );
+
+        let path = std::env::temp_dir().join("tx_accounts_thresholds.toml");
+        std::fs::write(&path, "dispute_rate = 0.5\nduplicate_count = 6\n").unwrap();
+        let thresholds = Thresholds::read(&path).unwrap();
+        assert_eq!(thresholds.dispute_rate, 0.5);
+        assert_eq!(thresholds.cycle_minutes, 60);
+        std::fs::write(&path, "dispute_rate = 2.0\n").unwrap();
+        assert!(Thresholds::read(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
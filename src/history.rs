@@ -0,0 +1,151 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{error::Error, io::Write};
+
+use crate::{
+    amounts::{serialize_4dp, Money},
+    currency::Currency,
+    events::serialize_amount,
+    records::{Record, TxType},
+    transaction::{Account, ClientId, TxId},
+};
+
+/// A record applied to an account, with the balance it left in the currency of the
+/// record. Only kept after
+/// [`Engine::keep_history`](crate::transaction::Engine::keep_history).
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct AppliedTx {
+    pub client: ClientId,
+    pub r#type: TxType,
+    pub tx: TxId,
+    #[serde(serialize_with = "serialize_amount")]
+    pub amount: Option<Money>,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub currency: Option<Currency>,
+    #[serde(serialize_with = "serialize_4dp")]
+    pub available: Money,
+    #[serde(serialize_with = "serialize_4dp")]
+    pub held: Money,
+    #[serde(serialize_with = "serialize_4dp")]
+    pub total: Money,
+    pub locked: bool,
+    pub memo: Option<String>,
+    pub reference: Option<String>,
+    pub merchant_id: Option<String>,
+}
+
+impl AppliedTx {
+    pub fn new(record: &Record, account: &Account) -> Self {
+        let balance = account.balance(record.currency);
+        Self {
+            client: record.client,
+            r#type: record.r#type,
+            tx: record.tx,
+            amount: record.amount,
+            timestamp: record.timestamp,
+            currency: record.currency,
+            available: balance.available,
+            held: balance.held,
+            total: balance.total,
+            locked: account.locked,
+            memo: record.memo.clone(),
+            reference: record.reference.clone(),
+            merchant_id: record.merchant_id.clone(),
+        }
+    }
+}
+
+/// Which entries of a client history to show. Entries without a timestamp never match
+/// a time range.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HistoryFilter {
+    /// All types when empty.
+    pub types: Vec<TxType>,
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive.
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl HistoryFilter {
+    pub fn matches(&self, entry: &AppliedTx) -> bool {
+        if !self.types.is_empty() && !self.types.contains(&entry.r#type) {
+            return false;
+        }
+
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+
+        entry.timestamp.is_some_and(|timestamp| {
+            self.from.is_none_or(|from| timestamp >= from)
+                && self.to.is_none_or(|to| timestamp < to)
+        })
+    }
+}
+
+pub fn write_history<'a, W: Write>(
+    writer: W,
+    entries: impl IntoIterator<Item = &'a AppliedTx>,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for entry in entries {
+        wtr.serialize(entry)?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::Scenario, transaction::Engine};
+
+    #[test]
+    fn history_per_client() {
+        let mut engine = Engine::new();
+        engine.keep_history();
+        let engine = Scenario::from_engine(engine)
+            .at("2024-01-01T10:00:00Z")
+            .deposit(1, 1, 10)
+            .deposit(2, 2, 7)
+            .withdraw(1, 3, 50)
+            .at("2024-01-02T10:00:00Z")
+            .withdraw(1, 4, 3)
+            .dispute(1, 1)
+            .into_engine();
+
+        let history: Vec<&AppliedTx> = engine.history(ClientId(1)).collect();
+        let txs: Vec<(TxType, TxId)> = history.iter().map(|e| (e.r#type, e.tx)).collect();
+        assert_eq!(
+            txs,
+            vec![
+                (TxType::Deposit, TxId(1)),
+                (TxType::Withdrawal, TxId(4)),
+                (TxType::Dispute, TxId(1))
+            ]
+        );
+        assert_eq!(
+            (history[2].available, history[2].held),
+            (Money::new(-3.0), Money::new(10.0))
+        );
+        assert_eq!(engine.history(ClientId(3)).count(), 0);
+
+        let filter = HistoryFilter {
+            types: vec![TxType::Deposit, TxType::Dispute],
+            from: "2024-01-02T00:00:00Z".parse().ok(),
+            to: None,
+        };
+        let mut out = Vec::new();
+        write_history(
+            &mut out,
+            engine.history(ClientId(1)).filter(|e| filter.matches(e)),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,type,tx,amount,timestamp,currency,available,held,total,locked,memo,reference,merchant_id\n\
+             1,dispute,1,,2024-01-02T10:00:00Z,,-3.0000,10.0000,7.0000,false,,,\n"
+        );
+    }
+}
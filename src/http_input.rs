@@ -0,0 +1,210 @@
+use std::{
+    error::Error,
+    io::{self, Read},
+    thread,
+    time::Duration,
+};
+use ureq::{Agent, BodyReader};
+
+/// Attempts per request, and per read of the body, before a transient failure is given up
+/// on.
+const ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry, doubled for every further one.
+const BACKOFF: Duration = Duration::from_millis(500);
+
+/// Streams the body of an `http://` or `https://` URL, e.g. a presigned URL of a partner
+/// file. Connection failures, timeouts, `429` and server errors are retried with
+/// exponential backoff. A download that breaks off resumes where it stopped, with a
+/// `Range` request when the server supports one.
+pub struct HttpInput {
+    agent: Agent,
+    url: String,
+    backoff: Duration,
+    body: Option<BodyReader<'static>>,
+    /// Bytes of the body read so far.
+    offset: u64,
+}
+
+impl HttpInput {
+    pub fn open(url: &str) -> Result<Self, Box<dyn Error>> {
+        Self::open_with_backoff(url, BACKOFF)
+    }
+
+    fn open_with_backoff(url: &str, backoff: Duration) -> Result<Self, Box<dyn Error>> {
+        let agent = Agent::new_with_config(
+            Agent::config_builder()
+                .timeout_connect(Some(Duration::from_secs(30)))
+                .timeout_recv_response(Some(Duration::from_secs(60)))
+                .build(),
+        );
+
+        let mut input = Self {
+            agent,
+            url: url.to_owned(),
+            backoff,
+            body: None,
+            offset: 0,
+        };
+        // Requesting the body up front reports a missing file or an expired URL right away.
+        input.request()?;
+        Ok(input)
+    }
+
+    fn request(&mut self) -> io::Result<()> {
+        let mut backoff = self.backoff;
+        for attempt in 1..=ATTEMPTS {
+            // Without content encoding, offsets into the body are offsets into the file.
+            let mut request = self
+                .agent
+                .get(&self.url)
+                .header("Accept-Encoding", "identity");
+            if self.offset > 0 {
+                request = request.header("Range", format!("bytes={}-", self.offset));
+            }
+
+            let error = match request.call() {
+                Ok(response) => {
+                    let resumed = response.status() == 206;
+                    let mut body = response.into_body().into_reader();
+                    if self.offset > 0 && !resumed {
+                        // The server sent the whole body again.
+                        io::copy(&mut (&mut body).take(self.offset), &mut io::sink())?;
+                    }
+                    self.body = Some(body);
+                    return Ok(());
+                }
+                Err(error) => error,
+            };
+            if !is_transient(&error) || attempt == ATTEMPTS {
+                return Err(io::Error::other(format!(
+                    "{} failed after {attempt} attempt(s): {error}",
+                    self.url
+                )));
+            }
+
+            thread::sleep(backoff);
+            backoff *= 2;
+        }
+
+        unreachable!("the last attempt returns")
+    }
+}
+
+impl Read for HttpInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut failures = 0;
+        loop {
+            if self.body.is_none() {
+                self.request()?;
+            }
+            let Some(body) = &mut self.body else {
+                unreachable!("a successful request sets the body")
+            };
+
+            match body.read(buf) {
+                Ok(read) => {
+                    self.offset += read as u64;
+                    return Ok(read);
+                }
+                Err(error) if failures + 1 < ATTEMPTS => {
+                    failures += 1;
+                    eprintln!(
+                        "Warning: resuming {} at byte {}: {error}",
+                        self.url, self.offset
+                    );
+                    self.body = None;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+fn is_transient(error: &ureq::Error) -> bool {
+    match error {
+        ureq::Error::StatusCode(status) => matches!(status, 408 | 429 | 500..),
+        ureq::Error::Io(_) | ureq::Error::Timeout(_) | ureq::Error::ConnectionFailed => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    /// Answers the first request with a server error, cuts the second response short
+    /// after `cut` bytes of `body`, and serves the rest of it for the `Range` of the third.
+    fn flaky_server(
+        listener: TcpListener,
+        body: &'static str,
+        cut: usize,
+    ) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            for (index, stream) in listener.incoming().take(3).enumerate() {
+                let mut stream = stream.unwrap();
+                let mut range = None;
+                for line in BufReader::new(stream.try_clone().unwrap()).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.to_lowercase().strip_prefix("range: bytes=") {
+                        range = value.trim_end_matches('-').parse::<usize>().ok();
+                    }
+                }
+
+                let (status, length, sent) = match (index, range) {
+                    (0, _) => ("503 Service Unavailable", 0, ""),
+                    (1, _) => ("200 OK", body.len(), &body[..cut]),
+                    (_, Some(start)) => ("206 Partial Content", body.len() - start, &body[start..]),
+                    (_, None) => panic!("the download did not resume with a range"),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\ncontent-length: {length}\r\nconnection: close\r\n\r\n{sent}"
+                )
+                .unwrap();
+            }
+        })
+    }
+
+    #[test]
+    fn downloads_retry_and_resume() {
+        let body = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,2,5\n";
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/daily.csv?signature=abc",
+            listener.local_addr().unwrap()
+        );
+        let server = flaky_server(listener, body, 30);
+
+        let mut input = HttpInput::open_with_backoff(&url, Duration::from_millis(1)).unwrap();
+        let mut read = String::new();
+        input.read_to_string(&mut read).unwrap();
+        server.join().unwrap();
+        assert_eq!(read, body);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/missing.csv", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream.try_clone().unwrap())
+                .read_line(&mut line)
+                .unwrap();
+            stream
+                .write_all(b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+        });
+        // Client errors are not retried.
+        let Err(error) = HttpInput::open_with_backoff(&url, Duration::from_millis(1)) else {
+            panic!("a missing file was opened");
+        };
+        assert!(error.to_string().contains("after 1 attempt(s)"), "{error}");
+    }
+}
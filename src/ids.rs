@@ -0,0 +1,237 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::transaction::{RawTxId, TxId};
+
+/// Hands out tx ids for transactions generated by the engine itself (interest, fees,
+/// expanded standing orders, ...), so that they never collide with partner-supplied ids.
+pub trait IdAllocator: fmt::Debug + Send {
+    /// Returns the next unused id, or `None` once the allocator is exhausted.
+    fn next_id(&mut self) -> Option<TxId>;
+
+    /// Whether `tx` belongs to the ids handed out by this allocator. Partner transactions
+    /// using such an id are rejected.
+    fn is_reserved(&self, tx: TxId) -> bool;
+
+    /// The last id handed out, if any. It is persisted with the engine state, so that the
+    /// next run carries on after it with [`IdAllocator::skip_past`].
+    fn last_id(&self) -> Option<TxId>;
+
+    /// Never hands out `last` or an id before it from now on.
+    fn skip_past(&mut self, last: TxId);
+}
+
+impl Default for Box<dyn IdAllocator> {
+    fn default() -> Self {
+        Box::new(HighBitAllocator::default())
+    }
+}
+
+/// Allocates ids with the highest bit set, leaving the lower half of the id space to
+/// partners.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HighBitAllocator {
+    next: TxId,
+}
+
+impl HighBitAllocator {
+    const HIGH_BIT: TxId = TxId(1 << (RawTxId::BITS - 1));
+}
+
+impl Default for HighBitAllocator {
+    fn default() -> Self {
+        Self {
+            next: Self::HIGH_BIT,
+        }
+    }
+}
+
+impl IdAllocator for HighBitAllocator {
+    fn next_id(&mut self) -> Option<TxId> {
+        let id = self.next;
+        // Wrapping around to 0 means every id with the high bit set was handed out.
+        if id < Self::HIGH_BIT {
+            return None;
+        }
+
+        self.next = TxId(id.0.wrapping_add(1));
+        Some(id)
+    }
+
+    fn is_reserved(&self, tx: TxId) -> bool {
+        tx >= Self::HIGH_BIT
+    }
+
+    fn last_id(&self) -> Option<TxId> {
+        (self.next != Self::HIGH_BIT).then(|| TxId(self.next.0.wrapping_sub(1)))
+    }
+
+    fn skip_past(&mut self, last: TxId) {
+        if self.next >= Self::HIGH_BIT && last >= self.next {
+            self.next = TxId(last.0.wrapping_add(1));
+        }
+    }
+}
+
+/// An allocator used by several engines at once, e.g. the shards of a
+/// [`SharedEngine`](crate::shared::SharedEngine), so that the ids they generate never
+/// collide. Clones hand out ids from the same allocator.
+#[derive(Debug, Clone, Default)]
+pub struct SharedAllocator(Arc<Mutex<Box<dyn IdAllocator>>>);
+
+impl SharedAllocator {
+    pub fn new(allocator: Box<dyn IdAllocator>) -> Self {
+        Self(Arc::new(Mutex::new(allocator)))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Box<dyn IdAllocator>> {
+        // Allocators never panic halfway through handing out an id.
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl IdAllocator for SharedAllocator {
+    fn next_id(&mut self) -> Option<TxId> {
+        self.lock().next_id()
+    }
+
+    fn is_reserved(&self, tx: TxId) -> bool {
+        self.lock().is_reserved(tx)
+    }
+
+    fn last_id(&self) -> Option<TxId> {
+        self.lock().last_id()
+    }
+
+    fn skip_past(&mut self, last: TxId) {
+        self.lock().skip_past(last);
+    }
+}
+
+/// Allocates ids from a fixed, inclusive range agreed on for a run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RangeAllocator {
+    start: TxId,
+    end: TxId,
+    next: Option<TxId>,
+}
+
+impl RangeAllocator {
+    pub fn new(start: TxId, end: TxId) -> Self {
+        Self {
+            start,
+            end,
+            next: (start <= end).then_some(start),
+        }
+    }
+}
+
+impl IdAllocator for RangeAllocator {
+    fn next_id(&mut self) -> Option<TxId> {
+        let id = self.next?;
+        self.next =
+            id.0.checked_add(1)
+                .map(TxId)
+                .filter(|&next| next <= self.end);
+        Some(id)
+    }
+
+    fn is_reserved(&self, tx: TxId) -> bool {
+        (self.start..=self.end).contains(&tx)
+    }
+
+    fn last_id(&self) -> Option<TxId> {
+        match self.next {
+            Some(next) if next == self.start => None,
+            Some(next) => Some(TxId(next.0 - 1)),
+            None => (self.start <= self.end).then_some(self.end),
+        }
+    }
+
+    fn skip_past(&mut self, last: TxId) {
+        if self.next.is_some_and(|next| last >= next) {
+            self.next = last
+                .0
+                .checked_add(1)
+                .map(TxId)
+                .filter(|&next| next <= self.end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn high_bit_allocator() {
+        let mut allocator = HighBitAllocator::default();
+
+        let high_bit = HighBitAllocator::HIGH_BIT.0;
+        assert_eq!(allocator.next_id(), Some(TxId(high_bit)));
+        assert_eq!(allocator.next_id(), Some(TxId(high_bit + 1)));
+        assert!(allocator.is_reserved(TxId(high_bit)));
+        assert!(!allocator.is_reserved(TxId(high_bit - 1)));
+
+        let mut allocator = HighBitAllocator { next: TxId::MAX };
+        assert_eq!(allocator.next_id(), Some(TxId::MAX));
+        assert_eq!(allocator.next_id(), None);
+        assert_eq!(allocator.last_id(), Some(TxId::MAX));
+    }
+
+    #[test]
+    fn allocators_carry_on_after_the_last_id() {
+        let high_bit = HighBitAllocator::HIGH_BIT.0;
+        let mut allocator = HighBitAllocator::default();
+        assert_eq!(allocator.last_id(), None);
+        allocator.skip_past(TxId(high_bit - 1));
+        allocator.skip_past(TxId(high_bit + 4));
+        assert_eq!(allocator.next_id(), Some(TxId(high_bit + 5)));
+        assert_eq!(allocator.last_id(), Some(TxId(high_bit + 5)));
+
+        let mut allocator = RangeAllocator::new(TxId(10), TxId(12));
+        assert_eq!(allocator.last_id(), None);
+        allocator.skip_past(TxId(11));
+        assert_eq!(allocator.last_id(), Some(TxId(11)));
+        assert_eq!(allocator.next_id(), Some(TxId(12)));
+        assert_eq!(allocator.next_id(), None);
+        assert_eq!(allocator.last_id(), Some(TxId(12)));
+    }
+
+    #[test]
+    fn range_allocator() {
+        let mut allocator = RangeAllocator::new(TxId(10), TxId(11));
+
+        assert_eq!(allocator.next_id(), Some(TxId(10)));
+        assert_eq!(allocator.next_id(), Some(TxId(11)));
+        assert_eq!(allocator.next_id(), None);
+        assert!(allocator.is_reserved(TxId(11)));
+        assert!(!allocator.is_reserved(TxId(12)));
+    }
+
+    #[test]
+    fn shared_allocator_clones_hand_out_distinct_ids() {
+        let mut a = SharedAllocator::new(Box::new(RangeAllocator::new(TxId(1), TxId(3))));
+        let mut b = a.clone();
+
+        assert_eq!(a.next_id(), Some(TxId(1)));
+        assert_eq!(b.next_id(), Some(TxId(2)));
+        assert_eq!(a.next_id(), Some(TxId(3)));
+        assert_eq!(b.next_id(), None);
+        assert!(b.is_reserved(TxId(1)));
+    }
+
+    #[test]
+    fn allocator_state_round_trip() {
+        let mut allocator = RangeAllocator::new(TxId(100), TxId(200));
+        allocator.next_id();
+
+        let json = serde_json::to_string(&allocator).unwrap();
+        let mut restored: RangeAllocator = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.next_id(), Some(TxId(101)));
+    }
+}
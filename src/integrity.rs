@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error, io::Write, str::FromStr};
+
+use crate::{
+    amounts::Money,
+    transaction::{Account, ClientId},
+};
+
+// Amounts are only meaningful up to 4 decimal places.
+const TOLERANCE: f32 = 0.00005;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum IntegrityPolicy {
+    /// Only list impossible states in the corrections report.
+    #[default]
+    Report,
+    /// Fix impossible states and record what was changed in the corrections report.
+    Correct,
+}
+
+impl FromStr for IntegrityPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "report" => Ok(Self::Report),
+            "correct" => Ok(Self::Correct),
+            _ => Err(format!(
+                "unknown integrity policy '{s}', expected one of report, correct"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrityIssue {
+    NegativeHeld,
+    TotalMismatch,
+}
+
+/// A single impossible state found in an account, with the balances before and after
+/// the (optional) correction.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct Finding {
+    pub client: ClientId,
+    pub issue: IntegrityIssue,
+    pub available_before: Money,
+    pub held_before: Money,
+    pub total_before: Money,
+    pub available_after: Money,
+    pub held_after: Money,
+    pub total_after: Money,
+    pub corrected: bool,
+}
+
+/// Detects impossible account states, e.g. after loading or merging state that was not
+/// produced by the engine itself, and fixes them when the policy says so.
+pub fn check_integrity(
+    accounts: &mut HashMap<ClientId, Account>,
+    policy: IntegrityPolicy,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    for account in accounts.values_mut() {
+        if account.held < -TOLERANCE {
+            let before = (account.available, account.held, account.total);
+            if policy == IntegrityPolicy::Correct {
+                account.held = Money::ZERO;
+                account.total = account.available + account.held;
+            }
+
+            findings.push(finding(
+                account,
+                IntegrityIssue::NegativeHeld,
+                before,
+                policy,
+            ));
+        }
+
+        if (account.total - (account.available + account.held)).abs() > TOLERANCE {
+            let before = (account.available, account.held, account.total);
+            if policy == IntegrityPolicy::Correct {
+                account.total = account.available + account.held;
+            }
+
+            findings.push(finding(
+                account,
+                IntegrityIssue::TotalMismatch,
+                before,
+                policy,
+            ));
+        }
+    }
+
+    findings.sort_by_key(|finding| finding.client);
+    findings
+}
+
+pub fn write_corrections_report<W: Write>(
+    writer: W,
+    findings: &[Finding],
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::WriterBuilder::new().from_writer(writer);
+    for finding in findings {
+        wtr.serialize(finding)?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+fn finding(
+    account: &Account,
+    issue: IntegrityIssue,
+    (available_before, held_before, total_before): (Money, Money, Money),
+    policy: IntegrityPolicy,
+) -> Finding {
+    Finding {
+        client: account.client,
+        issue,
+        available_before,
+        held_before,
+        total_before,
+        available_after: account.available,
+        held_after: account.held,
+        total_after: account.total,
+        corrected: policy == IntegrityPolicy::Correct,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accounts() -> HashMap<ClientId, Account> {
+        let mut accounts = HashMap::new();
+        accounts.insert(
+            ClientId(1),
+            Account {
+                client: ClientId(1),
+                available: Money::new(10.0),
+                held: Money::ZERO,
+                total: Money::new(10.0),
+                locked: false,
+                ..Default::default()
+            },
+        );
+        accounts.insert(
+            ClientId(2),
+            Account {
+                client: ClientId(2),
+                available: Money::new(10.0),
+                held: Money::new(-5.0),
+                total: Money::new(5.0),
+                locked: false,
+                ..Default::default()
+            },
+        );
+        accounts.insert(
+            ClientId(3),
+            Account {
+                client: ClientId(3),
+                available: Money::new(10.0),
+                held: Money::new(5.0),
+                total: Money::new(20.0),
+                locked: false,
+                ..Default::default()
+            },
+        );
+
+        accounts
+    }
+
+    #[test]
+    fn report_does_not_modify_accounts() {
+        let mut accounts = accounts();
+
+        let findings = check_integrity(&mut accounts, IntegrityPolicy::Report);
+
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].client, ClientId(2));
+        assert_eq!(findings[0].issue, IntegrityIssue::NegativeHeld);
+        assert!(!findings[0].corrected);
+        assert_eq!(findings[1].client, ClientId(3));
+        assert_eq!(findings[1].issue, IntegrityIssue::TotalMismatch);
+        assert_eq!(accounts[&ClientId(2)].held, -5.0);
+        assert_eq!(accounts[&ClientId(3)].total, 20.0);
+    }
+
+    #[test]
+    fn correct_fixes_accounts() {
+        let mut accounts = accounts();
+
+        let findings = check_integrity(&mut accounts, IntegrityPolicy::Correct);
+
+        assert_eq!(findings.len(), 2);
+        assert!(findings.iter().all(|finding| finding.corrected));
+        assert_eq!(findings[0].held_before, -5.0);
+        assert_eq!(findings[0].held_after, 0.0);
+
+        assert_eq!(accounts[&ClientId(2)].held, 0.0);
+        assert_eq!(accounts[&ClientId(2)].total, 10.0);
+        assert_eq!(accounts[&ClientId(3)].total, 15.0);
+        assert!(check_integrity(&mut accounts, IntegrityPolicy::Report).is_empty());
+    }
+}
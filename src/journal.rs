@@ -0,0 +1,184 @@
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    events::{AccountEvent, Event, TxOutcome},
+    observer::EngineObserver,
+    records::{Record, TxType},
+    transaction::RejectionReason,
+};
+
+/// Append-only log of every applied record, one `tx_applied` event per line. Applying
+/// the logged records in order to an empty engine with the same policies rebuilds its
+/// state, see [`replay`].
+///
+/// Clones append to the same file, so one journal can observe all shards of a
+/// [`SharedEngine`](crate::shared::SharedEngine). Records of one client are logged in the
+/// order they were applied.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    writer: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl Journal {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: Arc::new(Mutex::new(BufWriter::new(file))),
+        })
+    }
+
+    fn append(&self, record: &Record) -> Result<(), Box<dyn Error>> {
+        let line = Event::new(TxOutcome::new(record, Ok(()))).to_json()?;
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        writeln!(writer, "{line}")?;
+        // Every entry is flushed so that a crash loses at most the record being applied.
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// An applied record cannot be reported as rejected when it fails to be journaled, so
+/// journal errors abort the process instead.
+impl EngineObserver for Journal {
+    fn on_applied(&mut self, record: &Record, event: &AccountEvent) {
+        // Exactly one update in the currency of the record is reported per applied record;
+        // a conversion reports a second one in the target currency.
+        if matches!(event, AccountEvent::Updated { currency, .. } if *currency == record.currency) {
+            self.append(record).expect("writing to the journal failed");
+        }
+    }
+}
+
+/// Applies the records logged in the journal at `path` in order and returns how many were
+/// applied. Since only applied records are logged, a record that is rejected now means the
+/// journal does not match the policies or the state it is replayed onto.
+pub fn replay<P: AsRef<Path>>(
+    path: P,
+    mut apply: impl FnMut(Record) -> Result<(), RejectionReason>,
+) -> Result<u64, Box<dyn Error>> {
+    let mut applied = 0;
+
+    for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let event: Event<TxOutcome> =
+            Event::from_json(&line).map_err(|e| format!("journal line {}: {e}", index + 1))?;
+        let TxOutcome::Applied {
+            r#type,
+            client,
+            tx,
+            amount,
+            currency,
+            to_currency,
+            reason_code,
+            memo,
+            reference,
+            merchant_id,
+        } = event.body
+        else {
+            continue;
+        };
+        // Assertions were checked when the journal was written and change nothing.
+        if r#type == TxType::Assert {
+            continue;
+        }
+
+        let record = Record {
+            r#type,
+            client,
+            tx,
+            amount,
+            timestamp: None,
+            currency,
+            to_currency,
+            total: None,
+            reason_code,
+            memo,
+            reference,
+            merchant_id,
+        };
+        apply(record).map_err(|reason| {
+            format!(
+                "journal line {}: tx {tx} of client {client} no longer applies: {reason}",
+                index + 1
+            )
+        })?;
+        applied += 1;
+    }
+
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        amounts::Money,
+        shared::SharedEngine,
+        testing::Scenario,
+        transaction::{ClientId, Engine},
+    };
+
+    #[test]
+    fn replay_rebuilds_accounts() {
+        let path = std::env::temp_dir().join("tx_accounts_journal.ndjson");
+        let _ = std::fs::remove_file(&path);
+
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(Journal::open(&path).unwrap()));
+        let engine = Scenario::from_engine(engine)
+            .deposit(1, 1, 10)
+            .deposit(2, 2, 5)
+            .withdraw(2, 3, 50)
+            .withdraw(1, 4, 2.5)
+            .dispute(1, 1)
+            .into_engine();
+
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert_eq!(lines, 4);
+
+        let mut replayed = Engine::new();
+        assert_eq!(replay(&path, |record| replayed.apply(record)).unwrap(), 4);
+        assert_eq!(replayed.accounts(), engine.accounts());
+
+        // The open dispute was rebuilt as well.
+        Scenario::from_engine(replayed)
+            .resolve(1, 1)
+            .expect_available(ClientId(1), 7.5);
+
+        let shared = SharedEngine::new(4);
+        assert_eq!(replay(&path, |record| shared.apply(record)).unwrap(), 4);
+        assert_eq!(
+            shared.account(ClientId(2)).map(|account| account.total),
+            Some(Money::new(5.0))
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replay_rejects_inconsistent_journal() {
+        let path = std::env::temp_dir().join("tx_accounts_journal_inconsistent.ndjson");
+        std::fs::write(
+            &path,
+            r#"{"version":1,"event":"tx_applied","type":"withdrawal","client":1,"tx":1,"amount":"1.0000"}"#,
+        )
+        .unwrap();
+
+        let mut engine = Engine::new();
+        let error = replay(&path, |record| engine.apply(record)).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(error.to_string().contains("unknown_account"), "{error}");
+    }
+}
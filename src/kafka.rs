@@ -0,0 +1,155 @@
+use chrono::NaiveDate;
+use rdkafka::{
+    config::ClientConfig,
+    consumer::{BaseConsumer, CommitMode, Consumer},
+    error::KafkaError,
+    types::RDKafkaErrorCode,
+    Message,
+};
+use std::{
+    error::Error,
+    fs::{self, File},
+    io,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    amounts::Precision,
+    output::{write_accounts_with, OutputFormat},
+    records::{parse_record, InputFormat},
+    state::{HistoryDates, StateDir},
+    transaction::Engine,
+};
+
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct KafkaOptions {
+    pub brokers: String,
+    pub topic: String,
+    pub group_id: String,
+    pub payload_format: InputFormat,
+    pub snapshot_interval: Duration,
+    /// Where account snapshots are written; stdout when not set.
+    pub snapshot: Option<PathBuf>,
+    pub output_format: OutputFormat,
+    pub precision: Precision,
+    /// Directory the engine is restored from and saved to with every snapshot.
+    pub state: Option<StateDir>,
+}
+
+/// Consumes records from a Kafka topic and applies them to the engine until the process
+/// is stopped. Offsets are only committed once a message has been applied, which gives
+/// at-least-once semantics: after a crash, uncommitted messages are delivered again and
+/// replayed deposits and withdrawals are rejected as duplicates.
+///
+/// With a state directory, the engine is restored from it on startup and saved to it with
+/// every snapshot, along with the offset each partition was applied up to, and offsets are
+/// only committed once saved. Messages delivered again after a crash are skipped up to the
+/// saved offsets, so that each one is applied effectively once.
+pub fn consume(engine: &mut Engine, options: &KafkaOptions) -> Result<(), Box<dyn Error>> {
+    let mut dates = match &options.state {
+        Some(state) => state.restore(engine, today(), None)?,
+        None => HistoryDates::new(),
+    };
+
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &options.brokers)
+        .set("group.id", &options.group_id)
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "earliest")
+        .create()?;
+    consumer.subscribe(&[&options.topic])?;
+
+    let mut last_snapshot = Instant::now();
+    loop {
+        if let Some(message) = consumer.poll(POLL_TIMEOUT) {
+            let message = message?;
+            let source = format!("{}/{}", message.topic(), message.partition());
+            // Offsets of delivered messages are never negative.
+            let offset = message.offset() as u64;
+            if engine
+                .source_offset(&source)
+                .is_none_or(|applied| offset > applied)
+            {
+                match message
+                    .payload()
+                    .map(|p| parse_record(p, options.payload_format))
+                {
+                    Some(Ok(record)) => {
+                        // Rejected transactions are counted in the engine metrics.
+                        let _ = engine.apply(record);
+                    }
+                    Some(Err(e)) => eprintln!(
+                        "Warning: skipping malformed message at partition {} offset {}: {e}",
+                        message.partition(),
+                        message.offset()
+                    ),
+                    None => {}
+                }
+                engine.set_source_offset(&source, Some(offset));
+            }
+
+            if options.state.is_none() {
+                consumer.commit_message(&message, CommitMode::Async)?;
+            }
+        }
+
+        if last_snapshot.elapsed() >= options.snapshot_interval {
+            write_snapshot(engine, options)?;
+            if let Some(state) = &options.state {
+                save_state(engine, state, &mut dates)?;
+                match consumer.commit_consumer_state(CommitMode::Async) {
+                    // Nothing was consumed since the last commit.
+                    Ok(()) | Err(KafkaError::ConsumerCommit(RDKafkaErrorCode::NoOffset)) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            last_snapshot = Instant::now();
+        }
+    }
+}
+
+fn save_state(
+    engine: &Engine,
+    state: &StateDir,
+    dates: &mut HistoryDates,
+) -> Result<(), Box<dyn Error>> {
+    let run_date = today();
+    state.save_accounts(engine.accounts())?;
+    state.save_history(engine, dates, run_date)?;
+    for (record, _) in engine.dispute_history() {
+        dates.entry((record.client, record.tx)).or_insert(run_date);
+    }
+
+    Ok(())
+}
+
+fn today() -> NaiveDate {
+    chrono::Local::now().date_naive()
+}
+
+fn write_snapshot(engine: &Engine, options: &KafkaOptions) -> Result<(), Box<dyn Error>> {
+    match &options.snapshot {
+        Some(path) => {
+            // Readers of the snapshot file must never see a partially written one.
+            let tmp_path = path.with_extension("tmp");
+            write_accounts_with(
+                File::create(&tmp_path)?,
+                engine.accounts(),
+                options.output_format,
+                options.precision,
+            )?;
+            fs::rename(tmp_path, path)?;
+        }
+        None => write_accounts_with(
+            io::stdout(),
+            engine.accounts(),
+            options.output_format,
+            options.precision,
+        )?,
+    }
+
+    Ok(())
+}
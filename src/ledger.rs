@@ -0,0 +1,301 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    currency::Currency,
+    events::AccountEvent,
+    observer::EngineObserver,
+    records::{Record, TxType},
+    transaction::{Balance, ClientId, Engine},
+};
+
+/// Syntax of the exported ledger.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum LedgerFormat {
+    /// Plain text accounting as read by ledger-cli and hledger.
+    #[default]
+    Ledger,
+    Beancount,
+}
+
+impl FromStr for LedgerFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ledger" => Ok(Self::Ledger),
+            "beancount" => Ok(Self::Beancount),
+            _ => Err(format!(
+                "unknown ledger format '{s}', expected ledger or beancount"
+            )),
+        }
+    }
+}
+
+/// Exports every applied record as a balanced double-entry transaction, e.g. to
+/// reconcile the engine against the general ledger. Client funds are liabilities, split
+/// into `Liabilities:Clients:<client>:Available` and `...:Held`, and each change to them
+/// is balanced against a counter account that depends on the type of the record:
+///
+/// - `Assets:Clearing` for deposits, withdrawals, captures and chargebacks
+/// - `Assets:Suspense` for what disputes and resolves add to or take from the total, as
+///   for disputed withdrawals
+/// - `Income:Fees` for fees
+/// - `Equity:Conversion` for conversions, balanced per currency
+///
+/// Moves between available and held funds, as for disputes and holds, balance between the
+/// two client accounts. Records that do not change any balance are left out.
+///
+/// Clones write to the same file, so one export can observe all shards of a
+/// [`SharedEngine`](crate::shared::SharedEngine).
+#[derive(Debug, Clone)]
+pub struct LedgerExport {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    writer: BufWriter<File>,
+    format: LedgerFormat,
+    /// Commodity the base currency is written as.
+    base_currency: String,
+    /// Date of records without a timestamp.
+    date: NaiveDate,
+    /// Last known balances, to turn the balances of updates into changes.
+    balances: HashMap<(ClientId, Option<Currency>), Balance>,
+}
+
+impl LedgerExport {
+    /// Creates the export at `path`. Records without a timestamp are dated `date`, and
+    /// amounts in the base currency are written as `base_currency`, e.g. `XXX`.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        format: LedgerFormat,
+        base_currency: &str,
+        date: NaiveDate,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        if format == LedgerFormat::Beancount {
+            writeln!(writer, "plugin \"beancount.plugins.auto_accounts\"")?;
+            writeln!(writer)?;
+        }
+
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Inner {
+                writer,
+                format,
+                base_currency: base_currency.to_owned(),
+                date,
+                balances: HashMap::new(),
+            })),
+        })
+    }
+
+    /// Takes the balances of `engine` as the starting point, e.g. after restoring the state
+    /// of an earlier run, so that only the changes of this run are exported.
+    pub fn set_state(&self, engine: &Engine) {
+        let mut inner = self.lock();
+        for account in engine.accounts().values() {
+            inner
+                .balances
+                .insert((account.client, None), account.balance(None));
+            for (&currency, &balance) in &account.currencies {
+                inner
+                    .balances
+                    .insert((account.client, Some(currency)), balance);
+            }
+        }
+    }
+
+    /// Writes out what is still buffered.
+    pub fn finish(&self) -> Result<(), Box<dyn Error>> {
+        Ok(self.lock().writer.flush()?)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Inner {
+    fn write(
+        &mut self,
+        record: &Record,
+        client: ClientId,
+        currency: Option<Currency>,
+        balance: Balance,
+    ) -> Result<(), Box<dyn Error>> {
+        let before = self
+            .balances
+            .insert((client, currency), balance)
+            .unwrap_or_default();
+        let available = balance.available - before.available;
+        let held = balance.held - before.held;
+
+        // Client funds are liabilities, so money credited to a client is a negative posting.
+        let mut postings = Vec::new();
+        if available != 0.0 {
+            postings.push((
+                format!("Liabilities:Clients:{client}:Available"),
+                -available,
+            ));
+        }
+        if held != 0.0 {
+            postings.push((format!("Liabilities:Clients:{client}:Held"), -held));
+        }
+        let counter = available + held;
+        if counter != 0.0 {
+            postings.push((counter_account(record.r#type).to_owned(), counter));
+        }
+        if postings.is_empty() {
+            return Ok(());
+        }
+
+        let date = record
+            .timestamp
+            .map_or(self.date, |timestamp| timestamp.date_naive());
+        let description = format!(
+            "{} client {client} tx {}",
+            record.r#type.as_str(),
+            record.tx
+        );
+        let commodity = match &currency {
+            Some(currency) => currency.as_str(),
+            None => &self.base_currency,
+        };
+        let (header, indent) = match self.format {
+            LedgerFormat::Ledger => (format!("{date} {description}"), "    "),
+            LedgerFormat::Beancount => (format!("{date} * \"{description}\""), "  "),
+        };
+
+        writeln!(self.writer, "{header}")?;
+        for (account, amount) in postings {
+            writeln!(self.writer, "{indent}{account}  {amount:.4} {commodity}")?;
+        }
+        writeln!(self.writer)?;
+        Ok(())
+    }
+}
+
+fn counter_account(r#type: TxType) -> &'static str {
+    match r#type {
+        TxType::Dispute | TxType::Resolve => "Assets:Suspense",
+        TxType::Fee => "Income:Fees",
+        TxType::Convert => "Equity:Conversion",
+        _ => "Assets:Clearing",
+    }
+}
+
+/// Like the journal, a record that failed to be exported cannot be reported as rejected
+/// anymore, so write errors abort the process.
+impl EngineObserver for LedgerExport {
+    fn on_applied(&mut self, record: &Record, event: &AccountEvent) {
+        let AccountEvent::Updated {
+            client,
+            currency,
+            available,
+            held,
+            total,
+            ..
+        } = *event
+        else {
+            return;
+        };
+
+        let balance = Balance {
+            available,
+            held,
+            total,
+        };
+        self.lock()
+            .write(record, client, currency, balance)
+            .expect("writing the ledger export failed");
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        amounts::Money,
+        config::EngineConfig,
+        testing::Scenario,
+        transaction::{RawTxId, TxId},
+    };
+
+    #[test]
+    fn exports_balanced_transactions() {
+        let path = std::env::temp_dir().join("tx_accounts_ledger.ledger");
+        let date = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        let ledger = LedgerExport::create(&path, LedgerFormat::Ledger, "XXX", date).unwrap();
+
+        let mut engine = Engine::with_config(EngineConfig {
+            chargeback_fee: Some(Money::new(1.0)),
+            ..Default::default()
+        });
+        engine.add_observer(Box::new(ledger.clone()));
+        Scenario::from_engine(engine)
+            .deposit(1, 1, 10)
+            .deposit(1, 2, 5)
+            .dispute(1, 2)
+            .chargeback(1, 2)
+            .unlock(ClientId(1))
+            .at("2024-01-03T10:00:00Z")
+            .withdraw(1, 3, 4);
+        ledger.finish().unwrap();
+
+        let exported = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // The fee gets the first id reserved for engine-generated transactions.
+        let fee = TxId(1 << (RawTxId::BITS - 1));
+        assert_eq!(
+            exported,
+            format!(
+                "2024-01-02 deposit client 1 tx 1\n\
+             \x20   Liabilities:Clients:1:Available  -10.0000 XXX\n\
+             \x20   Assets:Clearing  10.0000 XXX\n\
+             \n\
+             2024-01-02 deposit client 1 tx 2\n\
+             \x20   Liabilities:Clients:1:Available  -5.0000 XXX\n\
+             \x20   Assets:Clearing  5.0000 XXX\n\
+             \n\
+             2024-01-02 dispute client 1 tx 2\n\
+             \x20   Liabilities:Clients:1:Available  5.0000 XXX\n\
+             \x20   Liabilities:Clients:1:Held  -5.0000 XXX\n\
+             \n\
+             2024-01-02 chargeback client 1 tx 2\n\
+             \x20   Liabilities:Clients:1:Held  5.0000 XXX\n\
+             \x20   Assets:Clearing  -5.0000 XXX\n\
+             \n\
+             2024-01-02 fee client 1 tx {fee}\n\
+             \x20   Liabilities:Clients:1:Available  1.0000 XXX\n\
+             \x20   Income:Fees  -1.0000 XXX\n\
+             \n\
+             2024-01-03 withdrawal client 1 tx 3\n\
+             \x20   Liabilities:Clients:1:Available  4.0000 XXX\n\
+             \x20   Assets:Clearing  -4.0000 XXX\n\
+             \n"
+            )
+        );
+    }
+
+    #[test]
+    fn parse_ledger_format() {
+        assert_eq!("Beancount".parse(), Ok(LedgerFormat::Beancount));
+        assert!("gnucash".parse::<LedgerFormat>().is_err());
+    }
+}
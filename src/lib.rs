@@ -0,0 +1,69 @@
+pub mod account_meta;
+pub mod amounts;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "async")]
+pub mod async_engine;
+pub mod audit;
+pub mod blocklist;
+pub mod checkpoint;
+pub mod config;
+pub mod credit;
+pub mod currency;
+pub mod disputes;
+pub mod events;
+pub mod export;
+pub mod fx;
+pub mod generate;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod handler;
+pub mod hash;
+pub mod heuristics;
+pub mod history;
+#[cfg(feature = "https")]
+pub mod http_input;
+pub mod ids;
+pub mod integrity;
+pub mod journal;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod ledger;
+pub mod metrics;
+pub mod observer;
+pub mod output;
+pub mod pipeline;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod prometheus;
+pub mod reconcile;
+pub mod records;
+pub mod rejects;
+pub mod report;
+pub mod rules;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod sample;
+pub mod sanity;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod shared;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod state;
+pub mod stats;
+pub mod storage;
+pub mod transaction;
+pub mod tx_status;
+pub mod tx_store;
+pub mod validate;
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(feature = "webhooks")]
+pub mod webhook;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
@@ -1,40 +1,2355 @@
-use records::read_csv;
-use std::{env, error::Error};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs::{self, File},
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-mod records;
-mod transaction;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use tx_accounts::{
+    account_meta::AccountDirectory,
+    amounts::{Money, Precision},
+    audit::{self, AuditLog},
+    blocklist::read_blocklist,
+    checkpoint::{Checkpoint, CheckpointConfig, CheckpointDir},
+    config::{
+        AccountPolicy, BackendUrl, ChargebackPolicy, Config, DisputeHoldPolicy, DuplicateScope,
+        EngineConfig, EngineConfigBuilder, LockedAccountPolicy, RedisputePolicy,
+        RepresentmentPolicy,
+    },
+    credit::CreditLimits,
+    disputes::write_open_disputes,
+    export::StateExport,
+    fx::{FxRounding, RateTable},
+    generate::{self, GenerateOptions},
+    heuristics::{screen, write_flags, Thresholds},
+    history::{write_history, HistoryFilter},
+    integrity::{check_integrity, write_corrections_report, IntegrityPolicy},
+    journal::{self, Journal},
+    ledger::{LedgerExport, LedgerFormat},
+    metrics::{write_rejection_metrics, RejectionMetrics},
+    output::{
+        read_accounts, sqlite_output_path, write_accounts_with, write_enriched_accounts,
+        written_alike, OutputFormat,
+    },
+    pipeline::{process_pipelined, PipelineConfig},
+    prometheus::PrometheusMetrics,
+    reconcile::{reconcile_within, write_deltas},
+    records::{
+        expand_inputs, for_each_input_row, for_each_row_at, is_http_url, is_s3_url, read_inputs,
+        read_rows_with, sort_by_time, write_records, ColumnMapping, CsvDialect, InputFormat,
+        InputOffset, InputRow, Record, TxType,
+    },
+    rejects::{process_row, process_rows, process_rows_shared, write_rejects, Reject},
+    report::{consolidate, write_quarterly_totals, RunManifest},
+    rules::{annotate_rejects, RuleSet, RuleViolation},
+    sample::{SampleEstimate, SampleRate},
+    sanity::SanityChecks,
+    shared::SharedEngine,
+    snapshot::{read_state_file, write_snapshot},
+    state::{HistorySnapshot, StateDir},
+    stats::{BatchSummary, TopReport},
+    transaction::{
+        Account, AutoCreatedAccount, ClientActivity, ClientId, Engine, FailedAssertion,
+        OpenDispute, RejectionReason,
+    },
+    tx_status::TxStatusLog,
+    tx_store::TxStore,
+    validate::{validate_file, write_issues},
+};
 
-use transaction::process_records;
+#[cfg(feature = "tx-store")]
+const DEFAULT_MAX_MEMORY_MIB: u64 = 256;
+
+/// Applies a file of transactions to client accounts and writes the resulting balances.
+///
+/// Without a subcommand the input is processed, so `tx-accounts transactions.csv` is the
+/// same as `tx-accounts process transactions.csv`.
+#[derive(Debug, Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    process: Option<ProcessArgs>,
+}
+
+#[derive(Debug, Args)]
+struct GlobalArgs {
+    /// Write the output to this file or s3:// object instead of stdout; processing also
+    /// takes a sqlite:// database
+    #[arg(long, global = true, alias = "out")]
+    output: Option<PathBuf>,
+
+    /// Format of the accounts output: csv, json, ndjson, arrow or xlsx
+    #[arg(long, global = true, alias = "output-format", default_value = "csv")]
+    format: OutputFormat,
+
+    /// Decimal places of the amounts in the accounts output, 0 to 4, or iso4217 for the
+    /// minor unit of each currency
+    #[arg(long, global = true, default_value = "4")]
+    precision: Precision,
+
+    /// Fail on the first malformed or rejected row instead of skipping it
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Number of worker threads used to apply transactions
+    #[arg(long, global = true, default_value_t = 1)]
+    threads: usize,
+
+    /// Field delimiter of CSV inputs, a single ASCII character or "tab"
+    #[arg(long, global = true, default_value = ",", value_parser = parse_delimiter)]
+    delimiter: char,
+
+    /// CSV inputs have no header row; fields are read in type,client,tx,amount,timestamp order
+    #[arg(long, global = true, conflicts_with = "column")]
+    no_header: bool,
+
+    /// Header name a CSV input uses for a field, e.g. type=transaction_type; repeatable
+    #[arg(long, global = true)]
+    column: Vec<ColumnMapping>,
+
+    /// Parse CSV inputs from raw bytes instead of through serde, for very large inputs
+    #[arg(long, global = true)]
+    fast_parse: bool,
+
+    /// Report progress details, e.g. the throughput of each pipeline stage
+    #[arg(short, long, global = true)]
+    verbose: bool,
+}
+
+impl GlobalArgs {
+    fn csv_dialect(&self) -> CsvDialect {
+        CsvDialect {
+            delimiter: self.delimiter,
+            has_headers: !self.no_header,
+            columns: self
+                .column
+                .iter()
+                .map(|mapping| (mapping.field.clone(), mapping.column.clone()))
+                .collect(),
+            fast_parse: self.fast_parse,
+        }
+    }
+}
+
+fn parse_delimiter(s: &str) -> Result<char, String> {
+    match s {
+        "tab" | "\\t" => Ok('\t'),
+        _ => match s.chars().collect::<Vec<_>>()[..] {
+            [c] if c.is_ascii() => Ok(c),
+            _ => Err(format!(
+                "invalid delimiter '{s}', expected a single ASCII character"
+            )),
+        },
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Apply the transactions of one or more input files and write the resulting accounts
+    Process(Box<ProcessArgs>),
+    /// Check an input file for malformed rows and suspicious transactions without applying it
+    Validate(InputArgs),
+    /// Summarise an input file with a dry run that keeps no state
+    Stats(InputArgs),
+    /// Flag clients whose activity matches a suspicious-activity heuristic, for AML review
+    Suspicious(SuspiciousArgs),
+    /// Rebuild the accounts from a journal written with --journal
+    Replay(ReplayArgs),
+    /// Apply input files and compare the resulting accounts with the expected balances
+    Reconcile(ReconcileArgs),
+    /// Keep applying the input files dropped into a directory
+    Watch(WatchArgs),
+    /// Write a synthetic input file, e.g. for benchmarks
+    Generate(GenerateArgs),
+    /// Apply input files with the default policies and show the account of one client
+    Query(QueryArgs),
+    /// Run as a long-lived service
+    Serve(ServeArgs),
+    /// Rank the clients and transactions of an input, or report over run manifests
+    Report(ReportArgs),
+    /// Compare two account files, e.g. expected and actual output, and fail if they differ
+    Diff(DiffArgs),
+    /// Write the state kept in a directory as one versioned JSON document
+    ExportState(ExportStateArgs),
+    /// Replace the state kept in a directory with a document written by export-state
+    ImportState(ImportStateArgs),
+    /// Merge the states of runs over disjoint sets of clients, e.g. the shards of a run
+    Merge(MergeArgs),
+    /// Check that an audit log written with --audit-log was not altered
+    VerifyAudit(VerifyAuditArgs),
+}
+
+#[derive(Debug, Args)]
+struct InputArgs {
+    /// Input file (.csv, .json, .jsonl or .ndjson)
+    input: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct SuspiciousArgs {
+    /// Input file (.csv, .json, .jsonl or .ndjson)
+    input: PathBuf,
+
+    /// Thresholds of the heuristics, a TOML file overriding the defaults
+    #[arg(long)]
+    thresholds: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct ProcessArgs {
+    /// Input files (.csv, .json, .jsonl or .ndjson), s3:// objects, http(s):// URLs or glob
+    /// patterns, applied in order as one stream
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Apply the transactions in timestamp order instead of input order, with the tx id
+    /// breaking ties
+    #[arg(long)]
+    sort_by_time: bool,
+
+    /// What to do about accounts whose balances do not add up: report or correct
+    #[arg(long, default_value = "report")]
+    integrity_policy: IntegrityPolicy,
+
+    /// Write the account integrity findings to this file
+    #[arg(long)]
+    corrections: Option<PathBuf>,
+
+    /// Write skipped rows and the reason they were skipped to this file
+    #[arg(long)]
+    rejects: Option<PathBuf>,
+
+    /// Write rejection counts per transaction type and reason to this file
+    #[arg(long)]
+    reject_stats: Option<PathBuf>,
+
+    /// Break the rejection counts down per client
+    #[arg(long, requires = "reject_stats")]
+    reject_stats_per_client: bool,
+
+    /// Write a manifest of the run to this file
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+
+    /// Business date of the run, recorded in the manifest and the dispute history; today
+    /// when not set
+    #[arg(long)]
+    run_date: Option<NaiveDate>,
+
+    /// Read the engine policies from this TOML file; policy flags override it
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    // The policy flags are repeated in PolicyArgs: clap does not track the presence of
+    // flattened args nested in the optional ProcessArgs, which the bare form relies on.
+    /// What happens when a chargeback finds too little held: reject, allow-negative or partial;
+    /// reject by default
+    #[arg(long)]
+    chargeback_policy: Option<ChargebackPolicy>,
+
+    /// How accounts are opened: auto-create, report or strict; auto-create by default
+    #[arg(long)]
+    account_policy: Option<AccountPolicy>,
+
+    /// Whether tx ids must be unique globally or per client: global or per-client;
+    /// global by default
+    #[arg(long)]
+    duplicate_scope: Option<DuplicateScope>,
+
+    /// Which records still apply to a locked account: freeze or allow-disputes;
+    /// freeze by default
+    #[arg(long)]
+    locked_account_policy: Option<LockedAccountPolicy>,
+
+    /// What happens when a dispute would hold more than is available: allow-negative or
+    /// require-funds; allow-negative by default
+    #[arg(long)]
+    dispute_hold_policy: Option<DisputeHoldPolicy>,
+
+    /// How often the same transaction may be disputed, counting resolved disputes:
+    /// allow-once, allow-N or allow-always; allow-always by default
+    #[arg(long)]
+    redispute_policy: Option<RedisputePolicy>,
+
+    /// Whether a representment unlocks the account its chargeback locked: keep-locked or
+    /// unlock; keep-locked by default
+    #[arg(long)]
+    representment_policy: Option<RepresentmentPolicy>,
+
+    /// Fee charged to an account after every successful chargeback, as far as its
+    /// available funds cover it
+    #[arg(long)]
+    chargeback_fee: Option<f32>,
+
+    /// Hours after which holds that were neither captured nor released are released,
+    /// counted from the timestamp of the hold
+    #[arg(long)]
+    hold_expiry: Option<u32>,
+
+    /// Only apply records dated up to this time, e.g. 2024-01-02T23:59:59Z; later ones are
+    /// deferred
+    #[arg(long)]
+    as_of: Option<DateTime<Utc>>,
+
+    /// Write the records deferred by --as-of to this CSV file, to be fed to a later run
+    #[arg(long, requires = "as_of")]
+    deferred: Option<PathBuf>,
+
+    /// Exchange rates for convert records, a CSV file with from,to,rate columns
+    #[arg(long)]
+    rates: Option<PathBuf>,
+
+    /// How converted amounts are rounded to 4 decimal places: nearest, down or up;
+    /// nearest by default
+    #[arg(long)]
+    fx_rounding: Option<FxRounding>,
+
+    /// Limits on deposits and withdrawals, a TOML file with a [[rules]] table per rule
+    #[arg(long)]
+    rules: Option<PathBuf>,
+
+    /// Credit limits that let withdrawals take accounts below zero, a CSV file with
+    /// client,limit columns; a blank client sets the default
+    #[arg(long)]
+    limits: Option<PathBuf>,
+
+    /// Reject every transaction of the clients listed in this file, one id per line
+    #[arg(long)]
+    blocklist: Option<PathBuf>,
+
+    /// Metadata of clients, a CSV file with client,name,kyc_tier,opening_balance,
+    /// credit_limit,locked columns; opens their accounts and adds name and kyc_tier to the
+    /// output
+    #[arg(long)]
+    accounts: Option<PathBuf>,
+
+    /// Reject rows with a larger amount as malformed, e.g. to catch a fat-fingered 1e30
+    #[arg(long)]
+    max_amount: Option<f32>,
+
+    /// Reject rows whose amount has more decimal places as malformed, 0 to 4
+    #[arg(long, value_parser = clap::value_parser!(u32).range(0..=4))]
+    max_decimal_places: Option<u32>,
+
+    /// Reject rows of client 0 as malformed
+    #[arg(long)]
+    reject_zero_client: bool,
+
+    /// Append every applied transaction to this journal
+    #[arg(long)]
+    journal: Option<PathBuf>,
+
+    /// Append every applied transaction to this tamper-evident audit log
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+
+    /// Spill applied transactions to an on-disk store in this directory
+    #[arg(long)]
+    tx_store: Option<PathBuf>,
+
+    /// Memory budget for applied transactions in MiB before they are spilled
+    #[arg(long, requires = "tx_store")]
+    max_memory: Option<u64>,
+
+    /// Only process this share of clients, e.g. 1%, and extrapolate the figures
+    #[arg(long, conflicts_with = "state_dir")]
+    sample: Option<SampleRate>,
+
+    /// Continue from the accounts and dispute history in this directory and save them back
+    #[arg(long)]
+    state_dir: Option<PathBuf>,
+
+    /// Days of earlier runs whose transactions can still be disputed; all when not set
+    #[arg(long, requires = "state_dir")]
+    dispute_lookback: Option<u32>,
+
+    /// Keep the accounts, transactions and open disputes in this database and continue
+    /// from it, e.g. sqlite://accounts.db
+    #[arg(long)]
+    backend: Option<BackendUrl>,
+
+    /// Apply rows while the inputs are still being read, on --threads processor threads
+    #[arg(long)]
+    pipeline: bool,
+
+    /// Rows handed from the reader to a processor at a time
+    #[arg(long, requires = "pipeline")]
+    batch_size: Option<usize>,
+
+    /// Batches that may wait in front of each processor before the reader blocks
+    #[arg(long, requires = "pipeline")]
+    queue_depth: Option<usize>,
+
+    /// Save a checkpoint of the run to this directory every --checkpoint-rows rows or
+    /// --checkpoint-secs seconds, whichever comes first
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// Rows read between checkpoints
+    #[arg(long, requires = "checkpoint", default_value_t = 1_000_000)]
+    checkpoint_rows: u64,
+
+    /// Seconds between checkpoints
+    #[arg(long, requires = "checkpoint", default_value_t = 300)]
+    checkpoint_secs: u64,
+
+    /// Continue from the last checkpoint in --checkpoint, e.g. after a crash
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+
+    /// Write Prometheus metrics of the run to this file
+    #[arg(long)]
+    metrics_file: Option<PathBuf>,
+
+    /// Export every applied transaction to this file as double-entry journal entries
+    #[arg(long)]
+    ledger: Option<PathBuf>,
+
+    /// Syntax of the --ledger export: ledger (ledger-cli, hledger) or beancount
+    #[arg(long, requires = "ledger", default_value = "ledger")]
+    ledger_format: LedgerFormat,
+
+    /// Commodity amounts in the base currency are exported as, e.g. USD
+    #[arg(long, requires = "ledger")]
+    ledger_currency: Option<String>,
+
+    /// Write the outcome of every transaction and the funds of its client after it to
+    /// this CSV file
+    #[arg(long = "emit-tx-status")]
+    tx_status: Option<PathBuf>,
+
+    /// Write the disputes still open at the end of the run, with their age in days, to
+    /// this CSV file
+    #[arg(long)]
+    open_disputes: Option<PathBuf>,
+
+    /// Only write the accounts whose balances or lock changed in this run, compared to
+    /// the ones restored from --state-dir or --backend
+    #[arg(long)]
+    changed_only: bool,
+
+    /// Write every account even with --changed-only, e.g. for a periodic full resync
+    #[arg(long)]
+    full_dump: bool,
+}
+
+#[derive(Debug, Args)]
+struct ReplayArgs {
+    /// Journal written by process or serve with --journal; pass the policies it was
+    /// written with
+    journal: PathBuf,
+
+    // Fees charged after chargebacks and releases of expired holds are in the journal as
+    // records of their own, so replays never charge or release them.
+    #[command(flatten)]
+    policies: PolicyArgs,
+}
+
+#[derive(Debug, Args)]
+struct ReconcileArgs {
+    /// Input files (.csv, .json, .jsonl or .ndjson) or glob patterns, applied in order
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Balances the accounts should end up with, in the CSV format process writes
+    #[arg(long)]
+    expected: PathBuf,
+
+    #[command(flatten)]
+    policies: PolicyArgs,
+}
+
+/// The policies of commands that apply records without the other options of process.
+#[derive(Debug, Args)]
+struct PolicyArgs {
+    /// Read the engine policies from this TOML file; policy flags override it
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// What happens when a chargeback finds too little held: reject, allow-negative or partial;
+    /// reject by default
+    #[arg(long)]
+    chargeback_policy: Option<ChargebackPolicy>,
+
+    /// How accounts are opened: auto-create, report or strict; auto-create by default
+    #[arg(long)]
+    account_policy: Option<AccountPolicy>,
+
+    /// Whether tx ids must be unique globally or per client: global or per-client;
+    /// global by default
+    #[arg(long)]
+    duplicate_scope: Option<DuplicateScope>,
+
+    /// Which records still apply to a locked account: freeze or allow-disputes;
+    /// freeze by default
+    #[arg(long)]
+    locked_account_policy: Option<LockedAccountPolicy>,
+
+    /// What happens when a dispute would hold more than is available: allow-negative or
+    /// require-funds; allow-negative by default
+    #[arg(long)]
+    dispute_hold_policy: Option<DisputeHoldPolicy>,
+
+    /// How often the same transaction may be disputed, counting resolved disputes:
+    /// allow-once, allow-N or allow-always; allow-always by default
+    #[arg(long)]
+    redispute_policy: Option<RedisputePolicy>,
+
+    /// Whether a representment unlocks the account its chargeback locked: keep-locked or
+    /// unlock; keep-locked by default
+    #[arg(long)]
+    representment_policy: Option<RepresentmentPolicy>,
+
+    /// Exchange rates for convert records, a CSV file with from,to,rate columns
+    #[arg(long)]
+    rates: Option<PathBuf>,
+
+    /// How converted amounts are rounded to 4 decimal places: nearest, down or up;
+    /// nearest by default
+    #[arg(long)]
+    fx_rounding: Option<FxRounding>,
+}
+
+impl PolicyArgs {
+    fn engine_config(&self) -> Result<EngineConfig, Box<dyn Error>> {
+        let flags = PolicyFlags {
+            chargeback_policy: self.chargeback_policy,
+            account_policy: self.account_policy,
+            duplicate_scope: self.duplicate_scope,
+            locked_account_policy: self.locked_account_policy,
+            dispute_hold_policy: self.dispute_hold_policy,
+            redispute_policy: self.redispute_policy,
+            representment_policy: self.representment_policy,
+            fx_rounding: self.fx_rounding,
+        };
+        Ok(engine_builder(self.config.as_deref(), flags)?
+            .rates(read_rates(self.rates.as_deref())?)
+            .build())
+    }
+}
+
+#[derive(Debug, Args)]
+struct WatchArgs {
+    /// Directory to watch; applied files are moved to its processed/ subdirectory
+    dir: PathBuf,
+
+    /// Continue from the accounts and dispute history in this directory and save them
+    /// back after every file
+    #[arg(long)]
+    state_dir: PathBuf,
+
+    /// Days of earlier runs whose transactions can still be disputed; all when not set
+    #[arg(long)]
+    dispute_lookback: Option<u32>,
+
+    #[command(flatten)]
+    policies: PolicyArgs,
+}
+
+#[derive(Debug, Args)]
+struct QueryArgs {
+    /// Input files (.csv, .json, .jsonl or .ndjson) or glob patterns, applied in order
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    #[arg(long)]
+    client: ClientId,
+
+    /// Print every transaction applied to the client with the balance it left, instead
+    /// of the account
+    #[arg(long)]
+    history: bool,
+
+    /// Only show transactions of this type; may be given several times
+    #[arg(long = "type", requires = "history")]
+    types: Vec<TxType>,
+
+    /// Only show transactions dated at or after this time
+    #[arg(long, requires = "history")]
+    from: Option<DateTime<Utc>>,
+
+    /// Only show transactions dated before this time
+    #[arg(long, requires = "history")]
+    to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Args)]
+struct GenerateArgs {
+    #[arg(long, default_value_t = 1000)]
+    clients: u16,
+
+    #[arg(long, default_value_t = 100_000)]
+    transactions: u64,
+
+    /// Share of rows that dispute, resolve or charge back an earlier deposit
+    #[arg(long, default_value_t = 0.01)]
+    dispute_rate: f64,
+
+    /// Share of rows that reuse an earlier tx id
+    #[arg(long, default_value_t = 0.001)]
+    duplicate_rate: f64,
+
+    /// Seed of the generator; the same seed and options always give the same file
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Debug, Args)]
+struct ServeArgs {
+    /// Address the HTTP or gRPC API listens on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    /// Only serve queries over the state in --state-dir, never accept transactions
+    #[arg(long, requires = "state_dir", conflicts_with = "kafka")]
+    read_only: bool,
+
+    /// Directory with the persisted state served by --read-only, or that --kafka restores
+    /// and saves together with the offsets it applied
+    #[arg(long, conflicts_with = "journal")]
+    state_dir: Option<PathBuf>,
+
+    /// Replay this journal on startup, then append every applied transaction to it
+    #[arg(long, conflicts_with = "read_only")]
+    journal: Option<PathBuf>,
+
+    /// Post chargebacks, account locks and large rejections to the webhooks in this TOML
+    /// file
+    #[arg(long, conflicts_with_all = ["read_only", "kafka"])]
+    webhooks: Option<PathBuf>,
+
+    /// Serve the gRPC API of proto/tx_accounts.proto instead of the HTTP API
+    #[arg(long, conflicts_with_all = ["read_only", "kafka"])]
+    grpc: bool,
+
+    /// Consume transactions from Kafka instead of serving an HTTP API
+    #[arg(long)]
+    kafka: bool,
+
+    #[arg(long, default_value = "localhost:9092", requires = "kafka")]
+    brokers: String,
+
+    #[arg(long, default_value = "transactions", requires = "kafka")]
+    topic: String,
+
+    #[arg(long = "group", default_value = "tx-accounts", requires = "kafka")]
+    group_id: String,
+
+    /// Format of the message payloads: json or csv
+    #[arg(long, default_value = "json", requires = "kafka")]
+    payload_format: InputFormat,
+
+    /// Seconds between account snapshots
+    #[arg(long, default_value_t = 60, requires = "kafka")]
+    snapshot_interval: u64,
+
+    /// File the account snapshots are written to; --output or stdout when not set
+    #[arg(long, requires = "kafka")]
+    snapshot: Option<PathBuf>,
+
+    #[command(flatten)]
+    policies: PolicyArgs,
+}
+
+#[derive(Debug, Args)]
+struct DiffArgs {
+    /// Accounts in the CSV format process writes
+    expected: PathBuf,
+
+    /// Accounts to compare with them
+    actual: PathBuf,
+
+    /// Largest difference between two amounts that still counts as a match
+    #[arg(long, default_value_t = 0.0)]
+    tolerance: f32,
+}
+
+#[derive(Debug, Args)]
+struct ExportStateArgs {
+    /// Directory the accounts and dispute history are kept in, e.g. by --state-dir
+    #[arg(long)]
+    state_dir: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct ImportStateArgs {
+    /// State file written by export-state, a JSON document or a .state snapshot
+    input: PathBuf,
+
+    /// Directory to write the accounts and dispute history to, replacing what it holds
+    #[arg(long)]
+    state_dir: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct MergeArgs {
+    /// State files written by export-state, JSON documents or .state snapshots
+    #[arg(required = true, num_args = 2..)]
+    inputs: Vec<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct VerifyAuditArgs {
+    /// Audit log written by process with --audit-log
+    log: PathBuf,
+}
+
+#[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands = true)]
+struct ReportArgs {
+    #[command(subcommand)]
+    command: Option<ReportCommand>,
+
+    #[command(flatten)]
+    top: Option<TopArgs>,
+}
+
+#[derive(Debug, Args)]
+struct TopArgs {
+    input: PathBuf,
+
+    /// Entries in each ranking
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+}
+
+#[derive(Debug, Subcommand)]
+enum ReportCommand {
+    /// Sum the activity of run manifests per quarter and client
+    Consolidate {
+        #[arg(long)]
+        runs_dir: PathBuf,
+        #[arg(long)]
+        from: NaiveDate,
+        #[arg(long)]
+        to: NaiveDate,
+    },
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let file_path = get_file_path_from_args()?;
-    let records = read_csv(file_path)?;
-    let processed_records = process_records(records);
+    let cli = Cli::parse();
+    match (cli.command, cli.process) {
+        (Some(Command::Process(args)), _) => process(&cli.global, *args),
+        (None, Some(args)) => process(&cli.global, args),
+        (Some(Command::Validate(args)), _) => validate(&cli.global, &args),
+        (Some(Command::Stats(args)), _) => stats(&cli.global, &args),
+        (Some(Command::Suspicious(args)), _) => suspicious(&cli.global, &args),
+        (Some(Command::Replay(args)), _) => replay(&cli.global, &args),
+        (Some(Command::Reconcile(args)), _) => reconcile(&cli.global, &args),
+        (Some(Command::Watch(args)), _) => watch(&cli.global, args),
+        (Some(Command::Generate(args)), _) => generate(&cli.global, &args),
+        (Some(Command::Query(args)), _) => query(&cli.global, &args),
+        (Some(Command::Serve(args)), _) => serve(&cli.global, args),
+        (Some(Command::Report(args)), _) => report(&cli.global, args),
+        (Some(Command::Diff(args)), _) => diff(&cli.global, &args),
+        (Some(Command::ExportState(args)), _) => export_state(&cli.global, &args),
+        (Some(Command::ImportState(args)), _) => import_state(&args),
+        (Some(Command::Merge(args)), _) => merge(&cli.global, &args),
+        (Some(Command::VerifyAudit(args)), _) => verify_audit(&args),
+        (None, None) => {
+            Cli::command().print_help()?;
+            std::process::exit(2);
+        }
+    }
+}
+
+fn process(global: &GlobalArgs, args: ProcessArgs) -> Result<(), Box<dyn Error>> {
+    let config = get_config(global, args)?;
+    // Echo the effective configuration so that support can reproduce the run exactly.
+    eprintln!("{}", config.to_json()?);
+
+    let metrics = Arc::new(if config.reject_stats_per_client {
+        RejectionMetrics::with_per_client()
+    } else {
+        RejectionMetrics::new()
+    });
+    let prometheus = config
+        .metrics_file
+        .as_ref()
+        .map(|_| PrometheusMetrics::new());
+    let ledger = config
+        .ledger
+        .as_ref()
+        .map(|path| {
+            let currency = config.ledger_currency.as_deref().unwrap_or("XXX");
+            LedgerExport::create(path, config.ledger_format, currency, run_date(&config))
+        })
+        .transpose()?;
+    let tx_status = config
+        .tx_status
+        .as_ref()
+        .map(TxStatusLog::create)
+        .transpose()?;
+    let (mut run, rows_read, rows_sampled) = match (config.pipeline, &config.checkpoint) {
+        (_, Some(checkpoint)) => apply_checkpointed(
+            &config,
+            checkpoint,
+            Arc::clone(&metrics),
+            prometheus.as_ref(),
+        )?,
+        (Some(pipeline), None) => apply_pipelined(
+            &config,
+            pipeline,
+            Arc::clone(&metrics),
+            prometheus.as_ref(),
+            ledger.as_ref(),
+            tx_status.as_ref(),
+            global.verbose,
+        )?,
+        (None, None) => {
+            let (rows, rows_read, rows_sampled) = load_rows(&config)?;
+            let run = if config.threads > 1 {
+                apply_shared(
+                    &config,
+                    rows,
+                    Arc::clone(&metrics),
+                    prometheus.as_ref(),
+                    ledger.as_ref(),
+                    tx_status.as_ref(),
+                )?
+            } else {
+                apply_serial(
+                    &config,
+                    rows,
+                    Arc::clone(&metrics),
+                    prometheus.as_ref(),
+                    ledger.as_ref(),
+                    tx_status.as_ref(),
+                )?
+            };
+            (run, rows_read, rows_sampled)
+        }
+    };
+
+    annotate_rejects(&mut run.rejects, &run.rule_violations);
+    if config.strict {
+        if let Some(reject) = run.rejects.first() {
+            return Err(format!("line {}: rejected: {}", reject.line, reject.reason).into());
+        }
+        if let Some(failed) = run.failed_assertions.first() {
+            return Err(failed.to_string().into());
+        }
+    }
+
+    if let Some(path) = &config.rejects {
+        write_rejects(File::create(path)?, &run.rejects)?;
+    }
+
+    if let Some(path) = &config.open_disputes {
+        write_open_disputes(File::create(path)?, &run.open_disputes, run_date(&config))?;
+    }
+
+    if let Some(path) = &config.deferred {
+        write_records(File::create(path)?, &run.deferred)?;
+    } else if !run.deferred.is_empty() {
+        eprintln!(
+            "Notice: {} record(s) dated after --as-of deferred, use --deferred to keep them",
+            run.deferred.len()
+        );
+    }
+
+    if let Some(rate) = config.sample {
+        let estimate = SampleEstimate::new(
+            rate,
+            rows_read,
+            rows_sampled,
+            &run.activity,
+            run.rejects.len(),
+        );
+        eprintln!("{estimate}");
+    }
 
-    let mut wtr = csv::WriterBuilder::new().from_writer(std::io::stdout());
-    for record in processed_records {
-        wtr.serialize(record.1)?;
+    for failed in &run.failed_assertions {
+        eprintln!("Warning: {failed}");
     }
 
-    wtr.flush()?;
+    for created in &run.auto_created {
+        eprintln!(
+            "Notice: account of client {} auto-created by deposit tx {}",
+            created.client, created.tx
+        );
+    }
 
+    if let Some(path) = &config.reject_stats {
+        write_rejection_metrics(File::create(path)?, &metrics)?;
+    }
+
+    if let (Some(path), Some(prometheus)) = (&config.metrics_file, &prometheus) {
+        prometheus.write(path)?;
+    }
+
+    if let Some(ledger) = &ledger {
+        ledger.finish()?;
+    }
+
+    if let Some(tx_status) = &tx_status {
+        tx_status.finish()?;
+    }
+
+    if let Some(path) = &config.manifest {
+        let manifest = RunManifest {
+            run_date: run_date(&config),
+            config: config.clone(),
+            activity: run.activity,
+        };
+        manifest.write(path)?;
+    }
+
+    let mut accounts = run.accounts;
+    let findings = check_integrity(&mut accounts, config.integrity_policy);
+    if let Some(path) = &config.corrections {
+        write_corrections_report(File::create(path)?, &findings)?;
+    } else if !findings.is_empty() {
+        eprintln!(
+            "Warning: {} account integrity issue(s) found, use --corrections to list them",
+            findings.len()
+        );
+    }
+
+    let changed: Option<HashMap<ClientId, Account>> = run.before.as_ref().map(|before| {
+        accounts
+            .iter()
+            .filter(|&(client, account)| {
+                !before
+                    .get(client)
+                    .is_some_and(|before| written_alike(before, account))
+            })
+            .map(|(&client, account)| (client, account.clone()))
+            .collect()
+    });
+    let written = changed.as_ref().unwrap_or(&accounts);
+
+    if let Some(path) = config.output.as_deref().and_then(sqlite_output_path) {
+        let run_date = run_date(&config);
+        sqlite_output(path, written, &run.rejects, &run.open_disputes, run_date)?;
+    } else if config.output_format == OutputFormat::Xlsx {
+        let mut out = output_writer(config.output.as_deref())?;
+        xlsx_report(&mut out, &config, written, &run.rejects, &run.open_disputes)?;
+        out.finish()?;
+    } else {
+        let mut out = output_writer(config.output.as_deref())?;
+        match config.accounts {
+            Some(_) => write_enriched_accounts(
+                &mut out,
+                written,
+                config.output_format,
+                config.precision,
+                &config.directory,
+            )?,
+            None => write_accounts_with(&mut out, written, config.output_format, config.precision)?,
+        }
+        out.finish()?;
+    }
+
+    // Saved last, so that a run failing on the way, e.g. with --strict, leaves the state
+    // as it was and can be run again.
+    if let Some(dir) = &config.state_dir {
+        let history = run.history.unwrap_or_default();
+        StateDir::new(dir).save(&history, &accounts)?;
+    }
+
+    // Run again, the inputs are applied from the start.
+    if let Some(checkpoint) = &config.checkpoint {
+        CheckpointDir::new(&checkpoint.dir).remove()?;
+    }
     Ok(())
 }
 
-fn get_file_path_from_args() -> Result<String, Box<dyn Error>> {
-    const CSV_EXTENSION: &str = ".csv";
+/// Reads, samples and orders the rows of a run, along with the number of rows read and
+/// kept by the sample.
+fn load_rows(config: &Config) -> Result<(Vec<InputRow>, u64, u64), Box<dyn Error>> {
+    let mut rows = read_inputs(&config.inputs, config.input_format, &config.csv_dialect)?;
+    let rows_read = rows.len() as u64;
+    for row in &mut rows {
+        config.sanity.apply(row);
+    }
+    if let Some(rate) = config.sample {
+        rows.retain(|row| sampled(rate, row));
+    }
+    let rows_sampled = rows.len() as u64;
+    if config.sort_by_time {
+        sort_by_time(&mut rows);
+    }
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <file.csv>", args[0]);
-        std::process::exit(1);
+    if config.rejects.is_none() || config.strict {
+        // Without a rejects report there is nowhere to account for malformed rows, so the
+        // whole run fails instead.
+        if let Some(row) = rows.iter().find(|row| row.record.is_err()) {
+            let message = row.record.as_ref().err().cloned().unwrap_or_default();
+            return Err(format!("line {}: {message}", row.line).into());
+        }
     }
 
-    let file_path = &args[1];
-    if !file_path.ends_with(CSV_EXTENSION) {
-        eprintln!("Error: The file must have a .csv extension");
+    Ok((rows, rows_read, rows_sampled))
+}
+
+fn sampled(rate: SampleRate, row: &InputRow) -> bool {
+    match &row.record {
+        Ok(record) => rate.includes_client(record.client),
+        Err(_) => rate.includes_line(row.line),
+    }
+}
+
+/// Opens the accounts of the clients in `directory` that have none yet, e.g. restored
+/// from the state directory.
+fn seed_accounts(engine: &mut Engine, directory: &AccountDirectory) {
+    for account in directory.opening_accounts() {
+        if engine.account(account.client).is_none() {
+            engine.restore_account(account);
+        }
+    }
+}
+
+/// Everything a batch run produces, regardless of how many threads applied it.
+struct Run {
+    rejects: Vec<Reject>,
+    accounts: HashMap<ClientId, Account>,
+    activity: Vec<ClientActivity>,
+    auto_created: Vec<AutoCreatedAccount>,
+    failed_assertions: Vec<FailedAssertion>,
+    rule_violations: Vec<RuleViolation>,
+    deferred: Vec<Record>,
+    open_disputes: Vec<OpenDispute>,
+    /// The accounts restored or seeded at the start of the run, kept for `--changed-only`.
+    before: Option<HashMap<ClientId, Account>>,
+    /// What the run leaves in `--state-dir` besides the accounts, saved once it succeeded.
+    history: Option<HistorySnapshot>,
+}
+
+fn apply_serial(
+    config: &Config,
+    rows: Vec<InputRow>,
+    metrics: Arc<RejectionMetrics>,
+    prometheus: Option<&PrometheusMetrics>,
+    ledger: Option<&LedgerExport>,
+    tx_status: Option<&TxStatusLog>,
+) -> Result<Run, Box<dyn Error>> {
+    let mut engine = Engine::with_config(config.engine.clone());
+    engine.set_metrics(metrics);
+    if let Some(path) = &config.tx_store {
+        engine.set_tx_store(open_tx_store(path, config.max_memory)?);
+    }
+    if let Some(path) = &config.journal {
+        engine.add_observer(Box::new(Journal::open(path)?));
+    }
+    if let Some(path) = &config.audit_log {
+        engine.add_observer(Box::new(AuditLog::open(path)?));
+    }
+
+    let run_date = run_date(config);
+    let state = config.state_dir.as_ref().map(StateDir::new);
+    let mut dates = HashMap::new();
+    if let Some(state) = &state {
+        dates = state.restore(&mut engine, run_date, config.dispute_lookback)?;
+    }
+    if let Some(backend) = &config.backend {
+        attach_backend(&mut engine, backend)?;
+    }
+    seed_accounts(&mut engine, &config.directory);
+    let before = config.changed_only.then(|| engine.accounts().clone());
+    if let Some(prometheus) = prometheus {
+        prometheus.set_state(&engine);
+        engine.add_observer(Box::new(prometheus.clone()));
+    }
+    if let Some(ledger) = ledger {
+        ledger.set_state(&engine);
+        engine.add_observer(Box::new(ledger.clone()));
+    }
+    if let Some(tx_status) = tx_status {
+        tx_status.set_state(&engine);
+        engine.add_observer(Box::new(tx_status.clone()));
+    }
+
+    let rejects = process_rows(&mut engine, rows);
+    engine.flush_observers()?;
+    let history = state
+        .is_some()
+        .then(|| HistorySnapshot::new(&engine, &dates, run_date));
+
+    Ok(Run {
+        rejects,
+        activity: engine.activity(),
+        auto_created: engine.auto_created().to_vec(),
+        failed_assertions: engine.failed_assertions().to_vec(),
+        rule_violations: engine.rule_violations().to_vec(),
+        deferred: engine.deferred().to_vec(),
+        open_disputes: engine.open_disputes(),
+        before,
+        history,
+        accounts: engine.into_accounts(),
+    })
+}
+
+fn apply_shared(
+    config: &Config,
+    rows: Vec<InputRow>,
+    metrics: Arc<RejectionMetrics>,
+    prometheus: Option<&PrometheusMetrics>,
+    ledger: Option<&LedgerExport>,
+    tx_status: Option<&TxStatusLog>,
+) -> Result<Run, Box<dyn Error>> {
+    let engine = SharedEngine::with_config(config.threads, config.engine.clone(), metrics);
+    for account in config.directory.opening_accounts() {
+        engine.restore_account(account);
+    }
+    if let Some(path) = &config.journal {
+        engine.add_observer(Journal::open(path)?);
+    }
+    if let Some(path) = &config.audit_log {
+        engine.add_observer(AuditLog::open(path)?);
+    }
+    if let Some(prometheus) = prometheus {
+        engine.add_observer(prometheus.clone());
+    }
+    if let Some(ledger) = ledger {
+        engine.add_observer(ledger.clone());
+    }
+    if let Some(tx_status) = tx_status {
+        engine.add_observer(tx_status.clone());
+    }
+
+    let rejects = process_rows_shared(&engine, rows, config.threads);
+    Ok(Run {
+        rejects,
+        activity: engine.activity(),
+        auto_created: engine.auto_created(),
+        failed_assertions: engine.failed_assertions(),
+        rule_violations: engine.rule_violations(),
+        deferred: engine.deferred(),
+        open_disputes: engine.open_disputes(),
+        before: None,
+        history: None,
+        accounts: engine.into_accounts(),
+    })
+}
+
+/// Applies the rows while they are read. Malformed rows can only be found once they have
+/// been read, so a run without a rejects report fails after applying the rows before them
+/// rather than up front.
+fn apply_pipelined(
+    config: &Config,
+    pipeline: PipelineConfig,
+    metrics: Arc<RejectionMetrics>,
+    prometheus: Option<&PrometheusMetrics>,
+    ledger: Option<&LedgerExport>,
+    tx_status: Option<&TxStatusLog>,
+    verbose: bool,
+) -> Result<(Run, u64, u64), Box<dyn Error>> {
+    let engine = SharedEngine::with_config(config.threads, config.engine.clone(), metrics);
+    for account in config.directory.opening_accounts() {
+        engine.restore_account(account);
+    }
+    if let Some(path) = &config.journal {
+        engine.add_observer(Journal::open(path)?);
+    }
+    if let Some(path) = &config.audit_log {
+        engine.add_observer(AuditLog::open(path)?);
+    }
+    if let Some(prometheus) = prometheus {
+        engine.add_observer(prometheus.clone());
+    }
+    if let Some(ledger) = ledger {
+        engine.add_observer(ledger.clone());
+    }
+    if let Some(tx_status) = tx_status {
+        engine.add_observer(tx_status.clone());
+    }
+
+    let (mut rows_read, mut rows_sampled) = (0, 0);
+    let pipelined = process_pipelined(&engine, config.threads, pipeline, |f| {
+        for_each_input_row(
+            &config.inputs,
+            config.input_format,
+            &config.csv_dialect,
+            |mut row| {
+                rows_read += 1;
+                config.sanity.apply(&mut row);
+                if config.sample.is_none_or(|rate| sampled(rate, &row)) {
+                    rows_sampled += 1;
+                    f(row);
+                }
+            },
+        )
+    })?;
+    if verbose {
+        eprint!("{}", pipelined.stats);
+    }
+
+    if config.rejects.is_none() || config.strict {
+        if let Some(reject) = pipelined
+            .rejects
+            .iter()
+            .find(|reject| reject.reason == RejectionReason::Malformed)
+        {
+            return Err(format!("line {}: {}", reject.line, reject.detail).into());
+        }
+    }
+
+    let run = Run {
+        rejects: pipelined.rejects,
+        activity: engine.activity(),
+        auto_created: engine.auto_created(),
+        failed_assertions: engine.failed_assertions(),
+        rule_violations: engine.rule_violations(),
+        deferred: engine.deferred(),
+        open_disputes: engine.open_disputes(),
+        before: None,
+        history: None,
+        accounts: engine.into_accounts(),
+    };
+    Ok((run, rows_read, rows_sampled))
+}
+
+/// Applies the rows while they are read, like [`apply_pipelined`], on a single thread that
+/// saves a checkpoint every so often, which a run with `--resume` continues from. The
+/// activity, auto-created accounts, failed assertions, rule violations and metrics of a
+/// resumed run only cover the rows applied since it was resumed.
+fn apply_checkpointed(
+    config: &Config,
+    every: &CheckpointConfig,
+    metrics: Arc<RejectionMetrics>,
+    prometheus: Option<&PrometheusMetrics>,
+) -> Result<(Run, u64, u64), Box<dyn Error>> {
+    let mut engine = Engine::with_config(config.engine.clone());
+    engine.set_metrics(metrics);
+
+    let dir = CheckpointDir::new(&every.dir);
+    let mut checkpoint = Checkpoint::start(config.inputs.clone());
+    if config.resume {
+        let Some(saved) = dir.load(&mut engine)? else {
+            return Err(format!("{}: no checkpoint to resume from", every.dir.display()).into());
+        };
+        if saved.inputs != checkpoint.inputs {
+            return Err("the inputs are not the ones of the checkpointed run".into());
+        }
+        checkpoint = saved;
+    }
+    seed_accounts(&mut engine, &config.directory);
+    if let Some(prometheus) = prometheus {
+        prometheus.set_state(&engine);
+        engine.add_observer(Box::new(prometheus.clone()));
+    }
+
+    let fail_malformed = config.rejects.is_none() || config.strict;
+    let interval = Duration::from_secs(every.secs);
+    let (mut saved_rows, mut saved_at) = (checkpoint.rows_read, Instant::now());
+    while let Some(path) = config.inputs.get(checkpoint.input) {
+        let start = checkpoint.offset;
+        for_each_row_at(
+            path,
+            config.input_format,
+            &config.csv_dialect,
+            start,
+            |mut row, offset| {
+                row.line += checkpoint.line_offset;
+                config.sanity.apply(&mut row);
+                checkpoint.last_line = row.line;
+                checkpoint.offset = offset;
+                checkpoint.rows_read += 1;
+                if config.sample.is_none_or(|rate| sampled(rate, &row)) {
+                    checkpoint.rows_sampled += 1;
+                    if let Err(reject) = process_row(&mut engine, row) {
+                        if fail_malformed && reject.reason == RejectionReason::Malformed {
+                            return Err(format!("line {}: {}", reject.line, reject.detail).into());
+                        }
+                        checkpoint.rejects.push(reject);
+                    }
+                }
+
+                if checkpoint.rows_read - saved_rows >= every.rows || saved_at.elapsed() >= interval
+                {
+                    dir.save(&engine, &checkpoint)?;
+                    (saved_rows, saved_at) = (checkpoint.rows_read, Instant::now());
+                }
+                Ok(())
+            },
+        )
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+
+        checkpoint.input += 1;
+        checkpoint.offset = InputOffset::default();
+        checkpoint.line_offset = checkpoint.last_line;
+    }
+    engine.flush_observers()?;
+
+    let run = Run {
+        rejects: checkpoint.rejects,
+        activity: engine.activity(),
+        auto_created: engine.auto_created().to_vec(),
+        failed_assertions: engine.failed_assertions().to_vec(),
+        rule_violations: engine.rule_violations().to_vec(),
+        deferred: engine.deferred().to_vec(),
+        open_disputes: engine.open_disputes(),
+        before: None,
+        history: None,
+        accounts: engine.into_accounts(),
+    };
+    Ok((run, checkpoint.rows_read, checkpoint.rows_sampled))
+}
+
+fn get_config(global: &GlobalArgs, args: ProcessArgs) -> Result<Config, Box<dyn Error>> {
+    let inputs = expand_inputs(&args.inputs)?;
+    let input_format = input_format(&inputs[0]);
+    if let Some(path) = inputs
+        .iter()
+        .find(|path| self::input_format(path) != input_format)
+    {
+        return Err(format!(
+            "{} is not in the same format as {}",
+            path.display(),
+            inputs[0].display()
+        )
+        .into());
+    }
+
+    if global.threads > 1 && args.tx_store.is_some() {
+        return Err("--tx-store cannot be combined with --threads".into());
+    }
+
+    if global.threads > 1 && args.state_dir.is_some() {
+        return Err("--state-dir cannot be combined with --threads".into());
+    }
+
+    if args.pipeline && (args.tx_store.is_some() || args.state_dir.is_some() || args.sort_by_time) {
+        return Err(
+            "--pipeline cannot be combined with --tx-store, --state-dir or --sort-by-time".into(),
+        );
+    }
+
+    // Only the serial path attaches the backend.
+    if args.backend.is_some() && (global.threads > 1 || args.pipeline) {
+        return Err("--backend cannot be combined with --threads or --pipeline".into());
+    }
+
+    if args.changed_only && args.state_dir.is_none() && args.backend.is_none() {
+        return Err("--changed-only needs --state-dir or --backend to compare with".into());
+    }
+
+    if args.changed_only && (global.threads > 1 || args.pipeline) {
+        return Err("--changed-only cannot be combined with --threads or --pipeline".into());
+    }
+
+    // Checked before anything is applied, rather than failing once the journal, rejects
+    // and other reports of the run have been written.
+    if global.format == OutputFormat::Arrow && !cfg!(feature = "arrow") {
+        return Err("--format arrow requires building with the `arrow` feature".into());
+    }
+    if global.format == OutputFormat::Xlsx && !cfg!(feature = "xlsx") {
+        return Err("--format xlsx requires building with the `xlsx` feature".into());
+    }
+    let sqlite_output = global.output.as_deref().and_then(sqlite_output_path);
+    if sqlite_output.is_some() && !cfg!(feature = "sqlite") {
+        return Err("sqlite:// outputs require building with the `sqlite` feature".into());
+    }
+
+    if args
+        .max_amount
+        .is_some_and(|max| !max.is_finite() || max < 0.0)
+    {
+        return Err("--max-amount must be a finite amount of at least 0".into());
+    }
+
+    let directory = args
+        .accounts
+        .as_deref()
+        .map_or_else(|| Ok(AccountDirectory::new()), AccountDirectory::read)?;
+    let mut credit_limits = args
+        .limits
+        .as_deref()
+        .map_or_else(|| Ok(CreditLimits::new()), CreditLimits::read)?;
+    directory.add_credit_limits(&mut credit_limits);
+
+    let mut engine = engine_builder(
+        args.config.as_deref(),
+        PolicyFlags {
+            chargeback_policy: args.chargeback_policy,
+            account_policy: args.account_policy,
+            duplicate_scope: args.duplicate_scope,
+            locked_account_policy: args.locked_account_policy,
+            dispute_hold_policy: args.dispute_hold_policy,
+            redispute_policy: args.redispute_policy,
+            representment_policy: args.representment_policy,
+            fx_rounding: args.fx_rounding,
+        },
+    )?
+    .rates(read_rates(args.rates.as_deref())?)
+    .rules(
+        args.rules
+            .as_deref()
+            .map_or_else(|| Ok(RuleSet::new()), RuleSet::read)?,
+    )
+    .credit_limits(credit_limits)
+    .blocklist(
+        args.blocklist
+            .as_deref()
+            .map_or_else(|| Ok(HashSet::new()), read_blocklist)?,
+    );
+    if let Some(fee) = args.chargeback_fee {
+        engine = engine.chargeback_fee(Money::new(fee));
+    }
+    if let Some(hours) = args.hold_expiry {
+        engine = engine.hold_expiry(hours);
+    }
+    if let Some(as_of) = args.as_of {
+        engine = engine.as_of(as_of);
+    }
+    let engine = engine.build();
+
+    if args.checkpoint.is_some() {
+        if global.threads > 1 || args.pipeline || args.sort_by_time {
+            return Err(
+                "--checkpoint cannot be combined with --threads, --pipeline or --sort-by-time"
+                    .into(),
+            );
+        }
+        // What these keep of the rows after the last checkpoint would be kept twice once
+        // the run is resumed, and records deferred by --as-of are not checkpointed.
+        if args.tx_store.is_some()
+            || args.state_dir.is_some()
+            || args.backend.is_some()
+            || args.journal.is_some()
+            || args.audit_log.is_some()
+            || args.ledger.is_some()
+            || args.tx_status.is_some()
+            || engine.as_of.is_some()
+        {
+            return Err("--checkpoint cannot be combined with --tx-store, --state-dir, --backend, --journal, --audit-log, --ledger, --emit-tx-status or --as-of".into());
+        }
+        if let Some(url) = inputs
+            .iter()
+            .find(|path| is_s3_url(path) || is_http_url(path))
+        {
+            return Err(format!("--checkpoint needs local inputs, not {}", url.display()).into());
+        }
+    }
+
+    Ok(Config {
+        inputs,
+        input_format,
+        csv_dialect: global.csv_dialect(),
+        sort_by_time: args.sort_by_time,
+        output_format: global.format,
+        precision: global.precision,
+        output: global.output.clone(),
+        integrity_policy: args.integrity_policy,
+        corrections: args.corrections,
+        rejects: args.rejects,
+        reject_stats: args.reject_stats,
+        reject_stats_per_client: args.reject_stats_per_client,
+        manifest: args.manifest,
+        run_date: args.run_date,
+        engine,
+        journal: args.journal,
+        audit_log: args.audit_log,
+        tx_store: args.tx_store,
+        max_memory: args.max_memory,
+        sample: args.sample,
+        state_dir: args.state_dir,
+        dispute_lookback: args.dispute_lookback,
+        strict: global.strict,
+        threads: global.threads.max(1),
+        pipeline: args.pipeline.then(|| {
+            let defaults = PipelineConfig::default();
+            PipelineConfig {
+                batch_size: args.batch_size.unwrap_or(defaults.batch_size),
+                queue_depth: args.queue_depth.unwrap_or(defaults.queue_depth),
+            }
+        }),
+        checkpoint: args.checkpoint.map(|dir| CheckpointConfig {
+            dir,
+            rows: args.checkpoint_rows,
+            secs: args.checkpoint_secs,
+        }),
+        resume: args.resume,
+        backend: args.backend,
+        metrics_file: args.metrics_file,
+        rates: args.rates,
+        rules: args.rules,
+        limits: args.limits,
+        blocklist: args.blocklist,
+        accounts: args.accounts,
+        directory,
+        sanity: SanityChecks {
+            max_amount: args.max_amount.map(Money::new),
+            max_decimal_places: args.max_decimal_places,
+            reject_zero_client: args.reject_zero_client,
+        },
+        deferred: args.deferred,
+        ledger: args.ledger,
+        ledger_format: args.ledger_format,
+        ledger_currency: args.ledger_currency,
+        tx_status: args.tx_status,
+        open_disputes: args.open_disputes,
+        changed_only: args.changed_only && !args.full_dump,
+    })
+}
+
+/// Policy flags of the commands that apply records. Those not given keep the policy of
+/// `--config`, or the default one.
+#[derive(Debug, Default)]
+struct PolicyFlags {
+    chargeback_policy: Option<ChargebackPolicy>,
+    account_policy: Option<AccountPolicy>,
+    duplicate_scope: Option<DuplicateScope>,
+    locked_account_policy: Option<LockedAccountPolicy>,
+    dispute_hold_policy: Option<DisputeHoldPolicy>,
+    redispute_policy: Option<RedisputePolicy>,
+    representment_policy: Option<RepresentmentPolicy>,
+    fx_rounding: Option<FxRounding>,
+}
+
+fn engine_builder(
+    config: Option<&Path>,
+    flags: PolicyFlags,
+) -> Result<EngineConfigBuilder, Box<dyn Error>> {
+    let config = config.map_or_else(|| Ok(EngineConfig::default()), EngineConfig::read)?;
+    let mut builder = EngineConfigBuilder::from(config);
+    if let Some(policy) = flags.chargeback_policy {
+        builder = builder.chargeback_policy(policy);
+    }
+    if let Some(policy) = flags.account_policy {
+        builder = builder.account_policy(policy);
+    }
+    if let Some(scope) = flags.duplicate_scope {
+        builder = builder.duplicate_scope(scope);
+    }
+    if let Some(policy) = flags.locked_account_policy {
+        builder = builder.locked_account_policy(policy);
+    }
+    if let Some(policy) = flags.dispute_hold_policy {
+        builder = builder.dispute_hold_policy(policy);
+    }
+    if let Some(policy) = flags.redispute_policy {
+        builder = builder.redispute_policy(policy);
+    }
+    if let Some(policy) = flags.representment_policy {
+        builder = builder.representment_policy(policy);
+    }
+    if let Some(rounding) = flags.fx_rounding {
+        builder = builder.fx_rounding(rounding);
+    }
+
+    Ok(builder)
+}
+
+fn read_rates(path: Option<&Path>) -> Result<RateTable, Box<dyn Error>> {
+    path.map_or_else(|| Ok(RateTable::new()), RateTable::read)
+}
+
+fn run_date(config: &Config) -> NaiveDate {
+    config
+        .run_date
+        .unwrap_or_else(|| chrono::Local::now().date_naive())
+}
+
+fn input_format(path: &Path) -> InputFormat {
+    let Some(format) = InputFormat::from_path(path) else {
+        eprintln!("Error: The file must have a .csv, .json, .jsonl or .ndjson extension");
         std::process::exit(1);
+    };
+
+    format
+}
+
+fn output_writer(path: Option<&Path>) -> Result<Output, Box<dyn Error>> {
+    if path.and_then(sqlite_output_path).is_some() {
+        return Err("sqlite:// outputs only take the results of processing".into());
     }
+    Ok(match path {
+        Some(path) if is_s3_url(path) => s3_output(path)?,
+        Some(path) => Output::File(TmpOutput::create(path)?),
+        None => Output::Stdout(io::BufWriter::new(io::stdout())),
+    })
+}
+
+#[cfg(feature = "s3")]
+fn s3_output(url: &Path) -> Result<Output, Box<dyn Error>> {
+    let url = url.to_str().unwrap_or_default();
+    let writer = tx_accounts::s3::S3Object::from_url(url)?.writer()?;
+    Ok(Output::S3(io::BufWriter::new(writer)))
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_output(
+    path: &Path,
+    accounts: &HashMap<ClientId, Account>,
+    rejects: &[Reject],
+    open_disputes: &[OpenDispute],
+    run_date: NaiveDate,
+) -> Result<(), Box<dyn Error>> {
+    tx_accounts::sqlite::write_results(path, accounts, rejects, open_disputes, run_date)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn sqlite_output(
+    _path: &Path,
+    _accounts: &HashMap<ClientId, Account>,
+    _rejects: &[Reject],
+    _open_disputes: &[OpenDispute],
+    _run_date: NaiveDate,
+) -> Result<(), Box<dyn Error>> {
+    Err("sqlite:// outputs require building with the `sqlite` feature".into())
+}
+
+/// With `--format xlsx` the accounts come with the rejects and open disputes of the run
+/// on sheets of their own.
+#[cfg(feature = "xlsx")]
+fn xlsx_report(
+    out: &mut Output,
+    config: &Config,
+    accounts: &HashMap<ClientId, Account>,
+    rejects: &[Reject],
+    open_disputes: &[OpenDispute],
+) -> Result<(), Box<dyn Error>> {
+    let mut sorted: Vec<&Account> = accounts.values().collect();
+    sorted.sort_by_key(|account| account.client);
+    tx_accounts::xlsx::write_report(
+        out,
+        &sorted,
+        config.precision,
+        config.accounts.as_ref().map(|_| &config.directory),
+        rejects,
+        open_disputes,
+        run_date(config),
+    )
+}
+
+#[cfg(not(feature = "xlsx"))]
+fn xlsx_report(
+    _out: &mut Output,
+    _config: &Config,
+    _accounts: &HashMap<ClientId, Account>,
+    _rejects: &[Reject],
+    _open_disputes: &[OpenDispute],
+) -> Result<(), Box<dyn Error>> {
+    Err("xlsx output requires building with the `xlsx` feature".into())
+}
+
+#[cfg(not(feature = "s3"))]
+fn s3_output(_url: &Path) -> Result<Output, Box<dyn Error>> {
+    Err("s3:// outputs require building with the `s3` feature".into())
+}
+
+/// Where a command writes its result. A file is written under a temporary name and only
+/// renamed to the `--output` path by [`Output::finish`], so that a failed or crashed run
+/// never leaves a partial report behind that downstream jobs take for a complete one. An
+/// `s3://` object likewise only appears once its upload was completed.
+enum Output {
+    Stdout(io::BufWriter<io::Stdout>),
+    File(TmpOutput),
+    #[cfg(feature = "s3")]
+    S3(io::BufWriter<tx_accounts::s3::ObjectWriter>),
+}
+
+impl Output {
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Output::Stdout(mut stdout) => stdout.flush()?,
+            Output::File(mut tmp) => {
+                tmp.file.flush()?;
+                tmp.file.get_ref().sync_all()?;
+                fs::rename(&tmp.tmp_path, &tmp.path)?;
+            }
+            #[cfg(feature = "s3")]
+            Output::S3(object) => object.into_inner()?.finish()?,
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(stdout) => stdout.write(buf),
+            Output::File(tmp) => tmp.file.write(buf),
+            #[cfg(feature = "s3")]
+            Output::S3(object) => object.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(stdout) => stdout.flush(),
+            Output::File(tmp) => tmp.file.flush(),
+            #[cfg(feature = "s3")]
+            Output::S3(object) => object.flush(),
+        }
+    }
+}
+
+/// An `--output` file being written under a hidden name next to it, unique to the process
+/// and the output, so that it never is the output itself or the file of another output or
+/// run. It is removed when dropped, unless [`Output::finish`] renamed it into place.
+struct TmpOutput {
+    file: io::BufWriter<File>,
+    tmp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl TmpOutput {
+    fn create(path: &Path) -> Result<Self, Box<dyn Error>> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let Some(name) = path.file_name() else {
+            return Err(format!("{}: not a file name", path.display()).into());
+        };
+        let tmp_name = format!(
+            ".{}.{}.{}.tmp",
+            name.to_string_lossy(),
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        let tmp_path = path.with_file_name(tmp_name);
+        let file = File::create(&tmp_path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+        Ok(Self {
+            file: io::BufWriter::new(file),
+            tmp_path,
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl Drop for TmpOutput {
+    fn drop(&mut self) {
+        // Already gone once it was renamed into place.
+        let _ = fs::remove_file(&self.tmp_path);
+    }
+}
+
+#[cfg(feature = "tx-store")]
+fn open_tx_store(path: &Path, max_memory: Option<u64>) -> Result<TxStore, Box<dyn Error>> {
+    let max_memory = max_memory.unwrap_or(DEFAULT_MAX_MEMORY_MIB) * 1024 * 1024;
+    TxStore::open(path, usize::try_from(max_memory)?)
+}
+
+#[cfg(not(feature = "tx-store"))]
+fn open_tx_store(_path: &Path, _max_memory: Option<u64>) -> Result<TxStore, Box<dyn Error>> {
+    Err("--tx-store requires building with the `tx-store` feature".into())
+}
+
+/// Restores the state kept in `backend` into `engine` and keeps writing to it from now on.
+fn attach_backend(engine: &mut Engine, backend: &BackendUrl) -> Result<(), Box<dyn Error>> {
+    match backend {
+        BackendUrl::Sqlite(path) => attach_sqlite(engine, path),
+        BackendUrl::Postgres(url) => attach_postgres(engine, url),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn attach_sqlite(engine: &mut Engine, path: &Path) -> Result<(), Box<dyn Error>> {
+    use tx_accounts::sqlite::SqliteBackend;
+
+    let sqlite = SqliteBackend::open(path)?;
+    sqlite.restore(engine)?;
+    engine.add_observer(Box::new(sqlite));
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn attach_sqlite(_engine: &mut Engine, _path: &Path) -> Result<(), Box<dyn Error>> {
+    Err("--backend sqlite:// requires building with the `sqlite` feature".into())
+}
+
+#[cfg(feature = "postgres")]
+fn attach_postgres(engine: &mut Engine, url: &str) -> Result<(), Box<dyn Error>> {
+    use tx_accounts::postgres::PostgresBackend;
+
+    let mut postgres = PostgresBackend::connect(url)?;
+    postgres.restore(engine)?;
+    engine.add_observer(Box::new(postgres));
+    Ok(())
+}
+
+#[cfg(not(feature = "postgres"))]
+fn attach_postgres(_engine: &mut Engine, _url: &str) -> Result<(), Box<dyn Error>> {
+    Err("--backend postgres:// requires building with the `postgres` feature".into())
+}
+
+fn validate(global: &GlobalArgs, args: &InputArgs) -> Result<(), Box<dyn Error>> {
+    let issues = validate_file(
+        &args.input,
+        input_format(&args.input),
+        &global.csv_dialect(),
+    )?;
+    let mut out = output_writer(global.output.as_deref())?;
+    write_issues(&mut out, &issues)?;
+    out.finish()?;
+
+    if !issues.is_empty() {
+        return Err(format!("{} issue(s) found", issues.len()).into());
+    }
+
+    eprintln!("No issues found");
+    Ok(())
+}
 
-    Ok(file_path.to_owned())
+fn stats(global: &GlobalArgs, args: &InputArgs) -> Result<(), Box<dyn Error>> {
+    let rows = read_rows_with(
+        &args.input,
+        input_format(&args.input),
+        &global.csv_dialect(),
+    )?;
+
+    let mut out = output_writer(global.output.as_deref())?;
+    write!(out, "{}", BatchSummary::new(rows))?;
+    out.finish()
+}
+
+fn suspicious(global: &GlobalArgs, args: &SuspiciousArgs) -> Result<(), Box<dyn Error>> {
+    let thresholds = match &args.thresholds {
+        Some(path) => Thresholds::read(path)?,
+        None => Thresholds::default(),
+    };
+    let rows = read_rows_with(
+        &args.input,
+        input_format(&args.input),
+        &global.csv_dialect(),
+    )?;
+
+    let flags = screen(
+        rows.into_iter().filter_map(|row| row.record.ok()),
+        &thresholds,
+    );
+    let mut out = output_writer(global.output.as_deref())?;
+    write_flags(&mut out, &flags)?;
+    out.finish()?;
+
+    eprintln!("{} flag(s) raised", flags.len());
+    Ok(())
+}
+
+fn query(global: &GlobalArgs, args: &QueryArgs) -> Result<(), Box<dyn Error>> {
+    let inputs = expand_inputs(&args.inputs)?;
+    let rows = read_inputs(&inputs, input_format(&inputs[0]), &global.csv_dialect())?;
+
+    let mut engine = Engine::new();
+    if args.history {
+        engine.keep_history();
+    }
+    for record in rows.into_iter().filter_map(|row| row.record.ok()) {
+        let _ = engine.apply(record);
+    }
+
+    let mut out = output_writer(global.output.as_deref())?;
+    if args.history {
+        let filter = HistoryFilter {
+            types: args.types.clone(),
+            from: args.from,
+            to: args.to,
+        };
+        let entries = engine
+            .history(args.client)
+            .filter(|entry| filter.matches(entry));
+        write_history(&mut out, entries)?;
+    } else {
+        let Some(account) = engine.account(args.client) else {
+            return Err(format!("client {} has no account", args.client).into());
+        };
+        let accounts = HashMap::from([(args.client, account.clone())]);
+        write_accounts_with(&mut out, &accounts, global.format, global.precision)?;
+    }
+    out.finish()
+}
+
+fn report(global: &GlobalArgs, args: ReportArgs) -> Result<(), Box<dyn Error>> {
+    match (args.command, args.top) {
+        (Some(ReportCommand::Consolidate { runs_dir, from, to }), _) => {
+            let totals = consolidate(runs_dir, from, to)?;
+            let mut out = output_writer(global.output.as_deref())?;
+            write_quarterly_totals(&mut out, &totals)?;
+            out.finish()
+        }
+        (None, Some(args)) => {
+            let rows = read_rows_with(
+                &args.input,
+                input_format(&args.input),
+                &global.csv_dialect(),
+            )?;
+            let mut out = output_writer(global.output.as_deref())?;
+            write!(out, "{}", TopReport::new(rows, args.top))?;
+            out.finish()
+        }
+        (None, None) => Err("report needs an input file or a subcommand".into()),
+    }
+}
+
+fn diff(global: &GlobalArgs, args: &DiffArgs) -> Result<(), Box<dyn Error>> {
+    let read = |path: &Path| {
+        File::open(path)
+            .map_err(|e| format!("{}: {e}", path.display()).into())
+            .and_then(read_accounts)
+    };
+    let expected = read(&args.expected)?;
+    let actual: HashMap<ClientId, Account> = read(&args.actual)?
+        .into_iter()
+        .map(|account| (account.client, account))
+        .collect();
+
+    let deltas = reconcile_within(&actual, &expected, Money::new(args.tolerance));
+    let mut out = output_writer(global.output.as_deref())?;
+    write_deltas(&mut out, &deltas)?;
+    out.finish()?;
+
+    if !deltas.is_empty() {
+        return Err(format!("{} balance(s) differ", deltas.len()).into());
+    }
+
+    eprintln!("No differences");
+    Ok(())
+}
+
+fn export_state(global: &GlobalArgs, args: &ExportStateArgs) -> Result<(), Box<dyn Error>> {
+    let mut engine = Engine::new();
+    let today = chrono::Local::now().date_naive();
+    let dates = StateDir::new(&args.state_dir).restore(&mut engine, today, None)?;
+
+    let export = StateExport::new(&engine, &dates);
+    let mut out = output_writer(global.output.as_deref())?;
+    match global.output.as_ref().and_then(|path| path.extension()) {
+        Some(extension) if extension == "state" => write_snapshot(&mut out, &export)?,
+        _ => export.write(&mut out)?,
+    }
+    out.finish()
+}
+
+fn import_state(args: &ImportStateArgs) -> Result<(), Box<dyn Error>> {
+    let export = read_state_file(&args.input)?;
+
+    let mut engine = Engine::new();
+    let dates = export.restore(&mut engine);
+    let state = StateDir::new(&args.state_dir);
+    state.save_history(&engine, &dates, chrono::Local::now().date_naive())?;
+    state.save_accounts(engine.accounts())?;
+
+    eprintln!(
+        "Imported {} account(s) into {}",
+        engine.accounts().len(),
+        args.state_dir.display()
+    );
+    Ok(())
+}
+
+/// Writes the accounts of the merged states, or the merged state itself as a snapshot
+/// when the output is a `.state` file.
+fn merge(global: &GlobalArgs, args: &MergeArgs) -> Result<(), Box<dyn Error>> {
+    let shards = args
+        .inputs
+        .iter()
+        .map(read_state_file)
+        .collect::<Result<Vec<_>, _>>()?;
+    let merged = StateExport::merge(shards)?;
+
+    let mut out = output_writer(global.output.as_deref())?;
+    match global.output.as_ref().and_then(|path| path.extension()) {
+        Some(extension) if extension == "state" => write_snapshot(&mut out, &merged)?,
+        _ => {
+            let mut engine = Engine::new();
+            merged.restore(&mut engine);
+            write_accounts_with(&mut out, engine.accounts(), global.format, global.precision)?;
+        }
+    }
+    out.finish()
+}
+
+fn verify_audit(args: &VerifyAuditArgs) -> Result<(), Box<dyn Error>> {
+    let head = audit::verify(&args.log).map_err(|e| format!("{}: {e}", args.log.display()))?;
+    println!("{} entries, last hash {}", head.entries, head.hash);
+    eprintln!("The audit log is intact");
+    Ok(())
+}
+
+fn reconcile(global: &GlobalArgs, args: &ReconcileArgs) -> Result<(), Box<dyn Error>> {
+    let inputs = expand_inputs(&args.inputs)?;
+    let rows = read_inputs(&inputs, input_format(&inputs[0]), &global.csv_dialect())?;
+    let expected = read_accounts(File::open(&args.expected)?)?;
+
+    let mut engine = Engine::with_config(args.policies.engine_config()?);
+    let rejects = process_rows(&mut engine, rows);
+    if !rejects.is_empty() {
+        eprintln!("Notice: {} row(s) rejected", rejects.len());
+    }
+
+    let deltas = tx_accounts::reconcile::reconcile(engine.accounts(), &expected);
+    let mut out = output_writer(global.output.as_deref())?;
+    write_deltas(&mut out, &deltas)?;
+    out.finish()?;
+
+    if !deltas.is_empty() {
+        return Err(format!("{} balance(s) do not match", deltas.len()).into());
+    }
+
+    eprintln!("All balances match");
+    Ok(())
+}
+
+fn replay(global: &GlobalArgs, args: &ReplayArgs) -> Result<(), Box<dyn Error>> {
+    let mut engine = Engine::with_config(args.policies.engine_config()?);
+    let applied = journal::replay(&args.journal, |record| engine.apply(record))?;
+    eprintln!("Replayed {applied} transaction(s)");
+
+    let mut out = output_writer(global.output.as_deref())?;
+    write_accounts_with(&mut out, engine.accounts(), global.format, global.precision)?;
+    out.finish()
+}
+
+#[cfg(feature = "watch")]
+fn watch(global: &GlobalArgs, args: WatchArgs) -> Result<(), Box<dyn Error>> {
+    use tx_accounts::watch::Inbox;
+
+    let engine = Engine::with_config(args.policies.engine_config()?);
+    let mut inbox = Inbox::new(
+        args.dir,
+        engine,
+        StateDir::new(args.state_dir),
+        args.dispute_lookback,
+        global.csv_dialect(),
+    )?;
+
+    tx_accounts::watch::watch(&mut inbox)
+}
+
+#[cfg(not(feature = "watch"))]
+fn watch(_global: &GlobalArgs, _args: WatchArgs) -> Result<(), Box<dyn Error>> {
+    Err("watch requires building with the `watch` feature".into())
+}
+
+fn generate(global: &GlobalArgs, args: &GenerateArgs) -> Result<(), Box<dyn Error>> {
+    let options = GenerateOptions {
+        clients: args.clients,
+        transactions: args.transactions,
+        dispute_rate: args.dispute_rate,
+        duplicate_rate: args.duplicate_rate,
+        seed: args.seed,
+    };
+
+    let mut out = output_writer(global.output.as_deref())?;
+    generate::generate(&mut out, &options)?;
+    out.finish()
+}
+
+fn serve(global: &GlobalArgs, args: ServeArgs) -> Result<(), Box<dyn Error>> {
+    if args.state_dir.is_some() && !args.read_only && !args.kafka {
+        return Err("--state-dir requires --read-only or --kafka".into());
+    }
+
+    if args.kafka {
+        serve_kafka(global, args)
+    } else {
+        serve_http(args)
+    }
+}
+
+#[cfg(feature = "server")]
+fn serve_http(args: ServeArgs) -> Result<(), Box<dyn Error>> {
+    use tx_accounts::shared::DEFAULT_SHARDS;
+
+    let listen = args.listen;
+    let Some(state_dir) = args.state_dir.filter(|_| args.read_only) else {
+        let config = args.policies.engine_config()?;
+        let engine = SharedEngine::with_config(DEFAULT_SHARDS, config, Arc::default());
+        if let Some(path) = &args.journal {
+            if path.exists() {
+                let applied = journal::replay(path, |record| engine.apply(record))?;
+                eprintln!("Replayed {applied} transaction(s) from {}", path.display());
+            }
+            engine.add_observer(Journal::open(path)?);
+        }
+        if let Some(path) = &args.webhooks {
+            attach_webhooks(&engine, path)?;
+        }
+        if args.grpc {
+            return serve_grpc(listen, engine);
+        }
+
+        eprintln!("Listening on http://{listen}");
+        return tx_accounts::server::serve(listen, engine);
+    };
+
+    eprintln!(
+        "Listening on http://{listen} (read-only, state in {})",
+        state_dir.display()
+    );
+    tx_accounts::server::serve_read_only(listen, StateDir::new(state_dir))
+}
+
+#[cfg(not(feature = "server"))]
+fn serve_http(_args: ServeArgs) -> Result<(), Box<dyn Error>> {
+    Err("serve requires building with the `server` feature".into())
+}
+
+#[cfg(feature = "webhooks")]
+fn attach_webhooks(engine: &SharedEngine, path: &Path) -> Result<(), Box<dyn Error>> {
+    use tx_accounts::webhook::{WebhookConfig, WebhookNotifier};
+
+    let config = WebhookConfig::read(path)?;
+    eprintln!("Posting events to {} webhook(s)", config.webhooks.len());
+    engine.add_observer(WebhookNotifier::new(config));
+    Ok(())
+}
+
+#[cfg(all(feature = "server", not(feature = "webhooks")))]
+fn attach_webhooks(_engine: &SharedEngine, _path: &Path) -> Result<(), Box<dyn Error>> {
+    Err("--webhooks requires building with the `webhooks` feature".into())
+}
+
+#[cfg(feature = "grpc")]
+fn serve_grpc(listen: SocketAddr, engine: SharedEngine) -> Result<(), Box<dyn Error>> {
+    eprintln!("Serving gRPC on {listen}");
+    tx_accounts::grpc::serve(listen, engine)
+}
+
+#[cfg(all(feature = "server", not(feature = "grpc")))]
+fn serve_grpc(_listen: SocketAddr, _engine: SharedEngine) -> Result<(), Box<dyn Error>> {
+    Err("serve --grpc requires building with the `grpc` feature".into())
+}
+
+#[cfg(feature = "kafka")]
+fn serve_kafka(global: &GlobalArgs, args: ServeArgs) -> Result<(), Box<dyn Error>> {
+    use std::time::Duration;
+    use tx_accounts::kafka::KafkaOptions;
+
+    let mut engine = Engine::with_config(args.policies.engine_config()?);
+    let options = KafkaOptions {
+        brokers: args.brokers,
+        topic: args.topic,
+        group_id: args.group_id,
+        payload_format: args.payload_format,
+        snapshot_interval: Duration::from_secs(args.snapshot_interval),
+        snapshot: args.snapshot.or_else(|| global.output.clone()),
+        output_format: global.format,
+        precision: global.precision,
+        state: args.state_dir.map(StateDir::new),
+    };
+
+    if let Some(path) = &args.journal {
+        if path.exists() {
+            let applied = journal::replay(path, |record| engine.apply(record))?;
+            eprintln!("Replayed {applied} transaction(s) from {}", path.display());
+        }
+        engine.add_observer(Box::new(Journal::open(path)?));
+    }
+
+    tx_accounts::kafka::consume(&mut engine, &options)
+}
+
+#[cfg(not(feature = "kafka"))]
+fn serve_kafka(_global: &GlobalArgs, _args: ServeArgs) -> Result<(), Box<dyn Error>> {
+    Err("serve --kafka requires building with the `kafka` feature".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn bare_input_is_processed() {
+        let cli = Cli::try_parse_from(["tx-accounts", "transactions.csv"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(
+            cli.process.unwrap().inputs,
+            vec![PathBuf::from("transactions.csv")]
+        );
+
+        let cli = Cli::try_parse_from(["tx-accounts", "a.csv", "b.csv", "incoming/*.csv"]).unwrap();
+        assert_eq!(cli.process.unwrap().inputs.len(), 3);
+
+        let cli = Cli::try_parse_from([
+            "tx-accounts",
+            "process",
+            "--format",
+            "json",
+            "--threads",
+            "4",
+            "transactions.csv",
+        ])
+        .unwrap();
+        assert_eq!(cli.global.format, OutputFormat::Json);
+        assert_eq!(cli.global.threads, 4);
+        assert!(matches!(cli.command, Some(Command::Process(_))));
+    }
+
+    #[test]
+    fn global_flags_after_subcommand() {
+        let cli = Cli::try_parse_from(["tx-accounts", "stats", "in.csv", "--output", "stats.txt"])
+            .unwrap();
+        assert_eq!(cli.global.output, Some(PathBuf::from("stats.txt")));
+        assert!(!cli.global.strict);
+    }
+
+    #[test]
+    fn output_appears_once_finished() {
+        let root = std::env::temp_dir().join("tx_accounts_output");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let files = || fs::read_dir(&root).unwrap().count();
+
+        // Outputs differing only in their extension, or named like a temporary file, do
+        // not share a temporary file with each other.
+        let paths = ["out.csv", "out.json", "out.tmp"].map(|name| root.join(name));
+        let mut outs: Vec<Output> = paths
+            .iter()
+            .map(|path| output_writer(Some(path)).unwrap())
+            .collect();
+        for (out, path) in outs.iter_mut().zip(&paths) {
+            writeln!(out, "{}", path.display()).unwrap();
+            out.flush().unwrap();
+        }
+        assert!(paths.iter().all(|path| !path.exists()));
+        assert_eq!(files(), 3);
+
+        for out in outs {
+            out.finish().unwrap();
+        }
+        for path in &paths {
+            assert_eq!(
+                fs::read_to_string(path).unwrap(),
+                format!("{}\n", path.display())
+            );
+        }
+        assert_eq!(files(), 3);
+
+        // An output that is never finished leaves nothing behind.
+        let unfinished = root.join("unfinished.csv");
+        let mut out = output_writer(Some(&unfinished)).unwrap();
+        writeln!(out, "client,available,held,total,locked").unwrap();
+        drop(out);
+        assert!(!unfinished.exists());
+        assert_eq!(files(), 3);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn backends_need_the_serial_path() {
+        let config = |args: &[&str]| {
+            let cli = Cli::try_parse_from(["tx-accounts", "process"].iter().chain(args)).unwrap();
+            let Some(Command::Process(args)) = cli.command else {
+                unreachable!();
+            };
+            get_config(&cli.global, *args)
+        };
+        let backend = ["in.csv", "--backend", "sqlite://accounts.db"];
+        assert!(config(&backend).is_ok());
+        assert!(config(&[&backend[..], &["--threads", "2"]].concat()).is_err());
+        assert!(config(&[&backend[..], &["--pipeline"]].concat()).is_err());
+    }
+
+    #[test]
+    fn serve_takes_the_engine_policies() {
+        let args = [
+            "tx-accounts",
+            "serve",
+            "--kafka",
+            "--chargeback-policy",
+            "partial",
+        ];
+        let Some(Command::Serve(args)) = Cli::try_parse_from(args).unwrap().command else {
+            unreachable!();
+        };
+        let config = args.policies.engine_config().unwrap();
+        assert_eq!(config.chargeback_policy, ChargebackPolicy::Partial);
+    }
+
+    fn run_process(args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let cli = Cli::try_parse_from(["tx-accounts", "process"].iter().chain(args)).unwrap();
+        let Some(Command::Process(args)) = cli.command else {
+            unreachable!();
+        };
+        process(&cli.global, *args)
+    }
+
+    #[test]
+    fn failed_runs_leave_the_state_dir_untouched() {
+        let root = std::env::temp_dir().join("tx_accounts_failed_run");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let input = root.join("in.csv");
+        fs::write(
+            &input,
+            "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,50\n",
+        )
+        .unwrap();
+        let (state, output) = (root.join("state"), root.join("out.csv"));
+        let [input, state, output] = [&input, &state, &output].map(|path| path.to_str().unwrap());
+
+        assert!(run_process(&[input, "--state-dir", state, "--strict"]).is_err());
+        assert!(!Path::new(state).exists());
+
+        // Run again without --strict, the deposit is applied rather than a duplicate.
+        run_process(&[input, "--state-dir", state, "--output", output]).unwrap();
+        assert_eq!(
+            fs::read_to_string(output).unwrap(),
+            "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n"
+        );
+        assert!(Path::new(state).join("tx_index.csv").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn changed_only_writes_the_accounts_a_run_changed() {
+        let root = std::env::temp_dir().join("tx_accounts_changed_only");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let (state, output) = (root.join("state"), root.join("out.csv"));
+        let [state, output] = [&state, &output].map(|path| path.to_str().unwrap());
+        let run = |name: &str, rows: &str, flags: &[&str]| {
+            let input = root.join(name);
+            fs::write(&input, format!("type,client,tx,amount\n{rows}")).unwrap();
+            let input = input.to_str().unwrap();
+            let args = [
+                &[input, "--state-dir", state, "--output", output][..],
+                flags,
+            ]
+            .concat();
+            run_process(&args).unwrap();
+            fs::read_to_string(output).unwrap()
+        };
+
+        run(
+            "day1.csv",
+            "deposit,1,1,10\ndeposit,2,2,5\ndeposit,3,3,2.2\ndeposit,3,7,1.1\n",
+            &[],
+        );
+        // Client 3 is disputed and resolved, which leaves its account as it was, even
+        // though 3.3 - 1.1 + 1.1 does not give back the same float.
+        let changed = run(
+            "day2.csv",
+            "deposit,2,4,1\ndeposit,4,5,2\ndispute,3,7,\nresolve,3,7,\n",
+            &["--changed-only"],
+        );
+        assert_eq!(
+            changed,
+            "client,available,held,total,locked\n\
+             2,6.0000,0.0000,6.0000,false\n\
+             4,2.0000,0.0000,2.0000,false\n"
+        );
+        let full = run(
+            "day3.csv",
+            "deposit,1,6,1\n",
+            &["--changed-only", "--full-dump"],
+        );
+        assert_eq!(full.lines().count(), 5);
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -0,0 +1,194 @@
+use serde::Serialize;
+use std::{collections::HashMap, error::Error, io::Write, sync::Mutex};
+
+use crate::{
+    records::TxType,
+    transaction::{ClientId, RejectionReason},
+};
+
+/// Counts every ignored operation by transaction type and rejection reason, e.g.
+/// "withdrawals skipped: insufficient funds". The counters can be shared between
+/// threads, and a per-client breakdown is only kept when requested.
+#[derive(Debug, Default)]
+pub struct RejectionMetrics {
+    per_client: bool,
+    totals: Mutex<HashMap<(TxType, RejectionReason), u64>>,
+    clients: Mutex<HashMap<(ClientId, TxType, RejectionReason), u64>>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct RejectionCount {
+    pub client: Option<ClientId>,
+    pub r#type: TxType,
+    pub reason: RejectionReason,
+    pub count: u64,
+}
+
+impl RejectionMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_per_client() -> Self {
+        Self {
+            per_client: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn record(&self, r#type: TxType, client: ClientId, reason: RejectionReason) {
+        *lock(&self.totals).entry((r#type, reason)).or_default() += 1;
+
+        if self.per_client {
+            *lock(&self.clients)
+                .entry((client, r#type, reason))
+                .or_default() += 1;
+        }
+    }
+
+    pub fn count(&self, r#type: TxType, reason: RejectionReason) -> u64 {
+        lock(&self.totals)
+            .get(&(r#type, reason))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn client_count(&self, client: ClientId, r#type: TxType, reason: RejectionReason) -> u64 {
+        lock(&self.clients)
+            .get(&(client, r#type, reason))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// All non-zero counters, sorted. Overall totals come first (without a client), followed
+    /// by the per-client breakdown if it is enabled.
+    pub fn counts(&self) -> Vec<RejectionCount> {
+        let mut totals: Vec<RejectionCount> = lock(&self.totals)
+            .iter()
+            .map(|(&(r#type, reason), &count)| RejectionCount {
+                client: None,
+                r#type,
+                reason,
+                count,
+            })
+            .collect();
+        totals.sort_by_key(|c| (c.r#type, c.reason));
+
+        let mut clients: Vec<RejectionCount> = lock(&self.clients)
+            .iter()
+            .map(|(&(client, r#type, reason), &count)| RejectionCount {
+                client: Some(client),
+                r#type,
+                reason,
+                count,
+            })
+            .collect();
+        clients.sort_by_key(|c| (c.client, c.r#type, c.reason));
+
+        totals.extend(clients);
+        totals
+    }
+}
+
+pub fn write_rejection_metrics<W: Write>(
+    writer: W,
+    metrics: &RejectionMetrics,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::WriterBuilder::new().from_writer(writer);
+    for count in metrics.counts() {
+        wtr.serialize(count)?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    // A panic while holding the lock cannot leave a counter map half-updated, so a
+    // poisoned lock is still safe to use.
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn record_from_multiple_threads() {
+        let metrics = Arc::new(RejectionMetrics::with_per_client());
+
+        let handles: Vec<_> = (0..4)
+            .map(|client| {
+                let metrics = Arc::clone(&metrics);
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        metrics.record(
+                            TxType::Withdrawal,
+                            ClientId(client),
+                            RejectionReason::InsufficientFunds,
+                        );
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(
+            metrics.count(TxType::Withdrawal, RejectionReason::InsufficientFunds),
+            400
+        );
+        assert_eq!(
+            metrics.client_count(
+                ClientId(2),
+                TxType::Withdrawal,
+                RejectionReason::InsufficientFunds
+            ),
+            100
+        );
+        assert_eq!(
+            metrics.count(TxType::Deposit, RejectionReason::InsufficientFunds),
+            0
+        );
+    }
+
+    #[test]
+    fn per_client_breakdown_only_on_request() {
+        let metrics = RejectionMetrics::new();
+        metrics.record(TxType::Dispute, ClientId(7), RejectionReason::TxNeverSeen);
+
+        assert_eq!(
+            metrics.count(TxType::Dispute, RejectionReason::TxNeverSeen),
+            1
+        );
+        assert_eq!(
+            metrics.client_count(ClientId(7), TxType::Dispute, RejectionReason::TxNeverSeen),
+            0
+        );
+    }
+
+    #[test]
+    fn write_counts_csv() {
+        let metrics = RejectionMetrics::with_per_client();
+        metrics.record(
+            TxType::Withdrawal,
+            ClientId(2),
+            RejectionReason::InsufficientFunds,
+        );
+        metrics.record(TxType::Deposit, ClientId(1), RejectionReason::DuplicateTx);
+        metrics.record(TxType::Deposit, ClientId(1), RejectionReason::DuplicateTx);
+
+        let mut out = Vec::new();
+        write_rejection_metrics(&mut out, &metrics).unwrap();
+
+        let expected = "client,type,reason,count\n\
+                        ,deposit,duplicate_tx,2\n\
+                        ,withdrawal,insufficient_funds,1\n\
+                        1,deposit,duplicate_tx,2\n\
+                        2,withdrawal,insufficient_funds,1\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{events::AccountEvent, records::Record, transaction::RejectionReason};
+
+/// Receives every state change of an engine, e.g. to feed an audit pipeline. Observers
+/// are called synchronously while the record is applied, in the order they were added.
+pub trait EngineObserver: fmt::Debug + Send {
+    /// Called for each change caused by an applied record: `Opened` when the record
+    /// opened the account, then `Updated` with the balances afterwards, then `Locked`
+    /// when the record locked the account.
+    fn on_applied(&mut self, _record: &Record, _event: &AccountEvent) {}
+
+    fn on_rejected(&mut self, _record: &Record, _reason: RejectionReason) {}
+
+    /// Called once the record was applied or rejected, with the time the engine took to
+    /// apply it.
+    fn on_processed(&mut self, _record: &Record, _elapsed: Duration) {}
+
+    /// Writes out changes the observer buffers, see [`Engine::flush_observers`].
+    ///
+    /// [`Engine::flush_observers`]: crate::transaction::Engine::flush_observers
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// A call to the observers of an engine, held back while a batch may still be rolled back.
+#[derive(Debug, Clone)]
+pub(crate) enum ObserverCall {
+    Applied(Record, AccountEvent),
+    Rejected(Record, RejectionReason),
+    Processed(Record, Duration),
+}
+
+impl ObserverCall {
+    pub(crate) fn replay(&self, observer: &mut dyn EngineObserver) {
+        match self {
+            Self::Applied(record, event) => observer.on_applied(record, event),
+            Self::Rejected(record, reason) => observer.on_rejected(record, *reason),
+            Self::Processed(record, elapsed) => observer.on_processed(record, *elapsed),
+        }
+    }
+}
+
+/// Stands in for the observers of an engine during a batch, keeping their calls until the
+/// batch is committed.
+#[derive(Debug, Default)]
+pub(crate) struct HeldBack(pub(crate) Arc<Mutex<Vec<ObserverCall>>>);
+
+impl HeldBack {
+    fn push(&self, call: ObserverCall) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).push(call);
+    }
+}
+
+impl EngineObserver for HeldBack {
+    fn on_applied(&mut self, record: &Record, event: &AccountEvent) {
+        self.push(ObserverCall::Applied(record.clone(), event.clone()));
+    }
+
+    fn on_rejected(&mut self, record: &Record, reason: RejectionReason) {
+        self.push(ObserverCall::Rejected(record.clone(), reason));
+    }
+
+    fn on_processed(&mut self, record: &Record, elapsed: Duration) {
+        self.push(ObserverCall::Processed(record.clone(), elapsed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::Scenario, transaction::Engine};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl EngineObserver for Recorder {
+        fn on_applied(&mut self, record: &Record, event: &AccountEvent) {
+            let event = match event {
+                AccountEvent::Opened { .. } => "opened".to_owned(),
+                AccountEvent::Updated { available, .. } => format!("available {available}"),
+                AccountEvent::Locked { .. } => "locked".to_owned(),
+            };
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} {event}", record.tx));
+        }
+
+        fn on_rejected(&mut self, record: &Record, reason: RejectionReason) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} {reason}", record.tx));
+        }
+    }
+
+    #[test]
+    fn observer_sees_every_state_change() {
+        let log = Arc::default();
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(Recorder(Arc::clone(&log))));
+
+        Scenario::from_engine(engine)
+            .deposit(1, 1, 10)
+            .withdraw(1, 2, 20)
+            .dispute(1, 1)
+            .chargeback(1, 1);
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "1 opened",
+                "1 available 10",
+                "2 insufficient_funds",
+                "1 available 0",
+                "1 available 0",
+                "1 locked",
+            ]
+        );
+    }
+}
@@ -0,0 +1,459 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    io::{Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::{
+    account_meta::AccountDirectory,
+    amounts::{format_amount, Money, Precision},
+    currency::Currency,
+    transaction::{Account, Balance, ClientId},
+};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+    Ndjson,
+    /// An Arrow IPC stream, with the `arrow` feature.
+    Arrow,
+    /// An Excel workbook, with the `xlsx` feature.
+    Xlsx,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "ndjson" => Ok(Self::Ndjson),
+            "arrow" => Ok(Self::Arrow),
+            "xlsx" => Ok(Self::Xlsx),
+            _ => Err(format!(
+                "unknown output format '{s}', expected one of csv, json, ndjson, arrow, xlsx"
+            )),
+        }
+    }
+}
+
+/// One CSV row of a multi-currency output: the balance of a client in one currency, or in
+/// the base currency when `currency` is blank.
+#[derive(Debug, Serialize, Deserialize)]
+struct CurrencyRow<A> {
+    client: ClientId,
+    #[serde(default)]
+    currency: Option<Currency>,
+    available: A,
+    held: A,
+    total: A,
+    locked: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<Option<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kyc_tier: Option<Option<String>>,
+}
+
+impl CurrencyRow<String> {
+    fn new(
+        account: &Account,
+        currency: Option<Currency>,
+        precision: Precision,
+        directory: Option<&AccountDirectory>,
+    ) -> Self {
+        let (name, kyc_tier) = meta_columns(account.client, directory);
+        let balance =
+            FormattedBalance::new(account.balance(currency), precision.decimals(currency));
+        Self {
+            client: account.client,
+            currency,
+            available: balance.available,
+            held: balance.held,
+            total: balance.total,
+            locked: account.locked,
+            name,
+            kyc_tier,
+        }
+    }
+}
+
+/// An account as it is written, with its amounts formatted at the precision of the output.
+#[derive(Debug, Serialize, PartialEq)]
+struct FormattedAccount {
+    client: ClientId,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    currencies: BTreeMap<Currency, FormattedBalance>,
+    /// Only written along with an accounts file, and blank for the clients it does not
+    /// name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kyc_tier: Option<Option<String>>,
+}
+
+impl FormattedAccount {
+    fn new(account: &Account, precision: Precision, directory: Option<&AccountDirectory>) -> Self {
+        let base = FormattedBalance::new(account.balance(None), precision.decimals(None));
+        let (name, kyc_tier) = meta_columns(account.client, directory);
+        Self {
+            client: account.client,
+            available: base.available,
+            held: base.held,
+            total: base.total,
+            locked: account.locked,
+            currencies: account
+                .currencies
+                .iter()
+                .map(|(&currency, &balance)| {
+                    let decimals = precision.decimals(Some(currency));
+                    (currency, FormattedBalance::new(balance, decimals))
+                })
+                .collect(),
+            name,
+            kyc_tier,
+        }
+    }
+}
+
+type MetaColumns = (Option<Option<String>>, Option<Option<String>>);
+
+/// The name and KYC tier columns of `client`, left out without a `directory`.
+fn meta_columns(client: ClientId, directory: Option<&AccountDirectory>) -> MetaColumns {
+    let Some(directory) = directory else {
+        return (None, None);
+    };
+    let meta = directory.get(client);
+    (
+        Some(meta.and_then(|meta| meta.name.clone())),
+        Some(meta.and_then(|meta| meta.kyc_tier.clone())),
+    )
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct FormattedBalance {
+    available: String,
+    held: String,
+    total: String,
+}
+
+impl FormattedBalance {
+    fn new(balance: Balance, decimals: u32) -> Self {
+        Self {
+            available: format_amount(balance.available, decimals),
+            held: format_amount(balance.held, decimals),
+            total: format_amount(balance.total, decimals),
+        }
+    }
+}
+
+/// The database file of an `--output sqlite://accounts.db` URL, which takes the results
+/// of a run as tables instead of a report.
+pub fn sqlite_output_path(path: &Path) -> Option<&Path> {
+    path.to_str()?
+        .strip_prefix("sqlite://")
+        .filter(|path| !path.is_empty())
+        .map(Path::new)
+}
+
+/// Writes the accounts sorted by client. CSV output gains a `currency` column as soon as
+/// any account holds funds in a currency other than the base one, with a row per client
+/// and currency; JSON output lists these balances under `currencies`.
+pub fn write_accounts<W: Write>(
+    writer: W,
+    accounts: &HashMap<ClientId, Account>,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    write_accounts_with(writer, accounts, format, Precision::default())
+}
+
+/// Whether [`write_accounts`] writes `a` and `b` the same. Balances that went through
+/// the same amounts in and out, e.g. a dispute and its resolve, need not be the same floats
+/// as before, but they round to the same decimal places.
+pub fn written_alike(a: &Account, b: &Account) -> bool {
+    let written = |account| FormattedAccount::new(account, Precision::default(), None);
+    written(a) == written(b)
+}
+
+/// Like [`write_accounts`], with the amounts written at `precision`. State that is read
+/// back is always written by [`write_accounts`], with the 4 decimal places amounts are
+/// kept at.
+pub fn write_accounts_with<W: Write>(
+    writer: W,
+    accounts: &HashMap<ClientId, Account>,
+    format: OutputFormat,
+    precision: Precision,
+) -> Result<(), Box<dyn Error>> {
+    write(writer, accounts, format, precision, None)
+}
+
+/// Like [`write_accounts_with`], with the name and KYC tier `directory` has for each
+/// client in `name` and `kyc_tier` columns.
+pub fn write_enriched_accounts<W: Write>(
+    writer: W,
+    accounts: &HashMap<ClientId, Account>,
+    format: OutputFormat,
+    precision: Precision,
+    directory: &AccountDirectory,
+) -> Result<(), Box<dyn Error>> {
+    write(writer, accounts, format, precision, Some(directory))
+}
+
+fn write<W: Write>(
+    mut writer: W,
+    accounts: &HashMap<ClientId, Account>,
+    format: OutputFormat,
+    precision: Precision,
+    directory: Option<&AccountDirectory>,
+) -> Result<(), Box<dyn Error>> {
+    // HashMap iteration order is not stable between runs, so accounts are always
+    // emitted sorted by client id to keep the output diffable.
+    let mut sorted: Vec<&Account> = accounts.values().collect();
+    sorted.sort_by_key(|account| account.client);
+
+    // Amounts are serialized as strings in every format, so the 4 decimal places are
+    // preserved in JSON output as well.
+    match format {
+        OutputFormat::Csv if sorted.iter().any(|a| !a.currencies.is_empty()) => {
+            let mut wtr = csv::WriterBuilder::new().from_writer(writer);
+            for account in sorted {
+                wtr.serialize(CurrencyRow::new(account, None, precision, directory))?;
+                for &currency in account.currencies.keys() {
+                    let row = CurrencyRow::new(account, Some(currency), precision, directory);
+                    wtr.serialize(row)?;
+                }
+            }
+
+            wtr.flush()?;
+        }
+        OutputFormat::Csv => {
+            let mut wtr = csv::WriterBuilder::new().from_writer(writer);
+            for account in sorted {
+                wtr.serialize(FormattedAccount::new(account, precision, directory))?;
+            }
+
+            wtr.flush()?;
+        }
+        OutputFormat::Json => {
+            let formatted: Vec<FormattedAccount> = sorted
+                .into_iter()
+                .map(|account| FormattedAccount::new(account, precision, directory))
+                .collect();
+            serde_json::to_writer(&mut writer, &formatted)?;
+            writeln!(writer)?;
+            writer.flush()?;
+        }
+        OutputFormat::Ndjson => {
+            for account in sorted {
+                let formatted = FormattedAccount::new(account, precision, directory);
+                serde_json::to_writer(&mut writer, &formatted)?;
+                writeln!(writer)?;
+            }
+
+            writer.flush()?;
+        }
+        #[cfg(feature = "arrow")]
+        OutputFormat::Arrow => {
+            crate::arrow::write_accounts(&mut writer, &sorted, precision, directory)?;
+            writer.flush()?;
+        }
+        #[cfg(not(feature = "arrow"))]
+        OutputFormat::Arrow => {
+            return Err("arrow output requires building with the `arrow` feature".into());
+        }
+        #[cfg(feature = "xlsx")]
+        OutputFormat::Xlsx => crate::xlsx::write_accounts(writer, &sorted, precision, directory)?,
+        #[cfg(not(feature = "xlsx"))]
+        OutputFormat::Xlsx => {
+            return Err("xlsx output requires building with the `xlsx` feature".into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads accounts back from CSV written by [`write_accounts`].
+pub fn read_accounts<R: Read>(reader: R) -> Result<Vec<Account>, Box<dyn Error>> {
+    let mut rdr = csv::Reader::from_reader(reader);
+    let mut accounts: Vec<Account> = Vec::new();
+    for row in rdr.deserialize::<CurrencyRow<Money>>() {
+        let row = row?;
+        let balance = Balance {
+            available: row.available,
+            held: row.held,
+            total: row.total,
+        };
+
+        // The rows of a client follow each other, base currency first.
+        match accounts.last_mut() {
+            Some(account) if account.client == row.client && row.currency.is_some() => {
+                account.set_balance(row.currency, balance);
+            }
+            _ => {
+                let mut account = Account {
+                    client: row.client,
+                    locked: row.locked,
+                    ..Default::default()
+                };
+                account.set_balance(row.currency, balance);
+                accounts.push(account);
+            }
+        }
+    }
+
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accounts() -> HashMap<ClientId, Account> {
+        let mut accounts = HashMap::new();
+        for client in [3, 1, 2].map(ClientId) {
+            accounts.insert(
+                client,
+                Account {
+                    client,
+                    available: Money::new(1.5),
+                    held: Money::ZERO,
+                    total: Money::new(1.5),
+                    locked: false,
+                    ..Default::default()
+                },
+            );
+        }
+
+        accounts
+    }
+
+    #[test]
+    fn write_accounts_sorted_by_client() {
+        let mut out = Vec::new();
+        write_accounts(&mut out, &accounts(), OutputFormat::Csv).unwrap();
+
+        let expected = "client,available,held,total,locked\n\
+                        1,1.5000,0.0000,1.5000,false\n\
+                        2,1.5000,0.0000,1.5000,false\n\
+                        3,1.5000,0.0000,1.5000,false\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn write_accounts_json() {
+        let mut accounts = accounts();
+        accounts.retain(|&client, _| client != ClientId(3));
+
+        let mut out = Vec::new();
+        write_accounts(&mut out, &accounts, OutputFormat::Json).unwrap();
+
+        let expected = "[{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false},\
+                        {\"client\":2,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false}]\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn write_accounts_ndjson() {
+        let mut accounts = accounts();
+        accounts.retain(|&client, _| client != ClientId(3));
+
+        let mut out = Vec::new();
+        write_accounts(&mut out, &accounts, OutputFormat::Ndjson).unwrap();
+
+        let expected = "{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false}\n\
+                        {\"client\":2,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false}\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn read_accounts_round_trip() {
+        let mut out = Vec::new();
+        write_accounts(&mut out, &accounts(), OutputFormat::Csv).unwrap();
+
+        let read = read_accounts(out.as_slice()).unwrap();
+        let mut expected: Vec<Account> = accounts().into_values().collect();
+        expected.sort_by_key(|account| account.client);
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn write_accounts_per_currency() {
+        let mut accounts = accounts();
+        accounts.retain(|&client, _| client != ClientId(3));
+        let eur = "EUR".parse().unwrap();
+        accounts.get_mut(&ClientId(2)).unwrap().currencies.insert(
+            eur,
+            Balance {
+                available: Money::new(2.0),
+                held: Money::new(1.0),
+                total: Money::new(3.0),
+            },
+        );
+
+        let mut out = Vec::new();
+        write_accounts(&mut out, &accounts, OutputFormat::Csv).unwrap();
+        let expected = "client,currency,available,held,total,locked\n\
+                        1,,1.5000,0.0000,1.5000,false\n\
+                        2,,1.5000,0.0000,1.5000,false\n\
+                        2,EUR,2.0000,1.0000,3.0000,false\n";
+        assert_eq!(String::from_utf8(out.clone()).unwrap(), expected);
+
+        let read = read_accounts(out.as_slice()).unwrap();
+        assert_eq!(read[1], accounts[&ClientId(2)]);
+
+        let mut out = Vec::new();
+        write_accounts(&mut out, &accounts, OutputFormat::Ndjson).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains(
+            "\"currencies\":{\"EUR\":{\"available\":\"2.0000\",\"held\":\"1.0000\",\"total\":\"3.0000\"}}"
+        ));
+    }
+
+    #[test]
+    fn write_accounts_with_precision() {
+        let mut accounts = accounts();
+        accounts.retain(|&client, _| client == ClientId(1));
+        let account = accounts.get_mut(&ClientId(1)).unwrap();
+        for (currency, amount) in [("JPY", 1234.5), ("BHD", 0.1257)] {
+            account.currencies.insert(
+                currency.parse().unwrap(),
+                Balance {
+                    available: Money::new(amount),
+                    held: Money::ZERO,
+                    total: Money::new(amount),
+                },
+            );
+        }
+
+        let mut out = Vec::new();
+        write_accounts_with(&mut out, &accounts, OutputFormat::Csv, Precision::Iso4217).unwrap();
+        let expected = "client,currency,available,held,total,locked\n\
+                        1,,1.5000,0.0000,1.5000,false\n\
+                        1,BHD,0.126,0.000,0.126,false\n\
+                        1,JPY,1235,0,1235,false\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        accounts.get_mut(&ClientId(1)).unwrap().currencies.clear();
+        let mut out = Vec::new();
+        write_accounts_with(&mut out, &accounts, OutputFormat::Csv, Precision::Fixed(2)).unwrap();
+        let expected = "client,available,held,total,locked\n\
+                        1,1.50,0.00,1.50,false\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn output_format_from_str() {
+        assert_eq!("json".parse(), Ok(OutputFormat::Json));
+        assert_eq!("NDJSON".parse(), Ok(OutputFormat::Ndjson));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
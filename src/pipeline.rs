@@ -0,0 +1,289 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt, mem,
+    sync::mpsc::{self, SyncSender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    records::{InputRow, Record},
+    rejects::{malformed, record_fields, Reject},
+    shared::SharedEngine,
+};
+
+/// Sizes of the bounded channels between the stages of [`process_pipelined`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PipelineConfig {
+    /// Rows handed from the reader to a processor at a time.
+    pub batch_size: usize,
+    /// Batches that may wait in front of each processor before the reader blocks.
+    pub queue_depth: usize,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 1024,
+            queue_depth: 16,
+        }
+    }
+}
+
+/// Rows handled by a stage and the time it spent on them, excluding time spent waiting
+/// for the neighbouring stages.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StageStats {
+    pub rows: u64,
+    pub busy: Duration,
+}
+
+impl StageStats {
+    /// Rows per second of busy time.
+    pub fn throughput(&self) -> f64 {
+        self.rows as f64 / self.busy.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PipelineStats {
+    pub elapsed: Duration,
+    pub reader: StageStats,
+    pub processors: Vec<StageStats>,
+    pub collector: StageStats,
+}
+
+impl fmt::Display for PipelineStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stage = |f: &mut fmt::Formatter, name: &str, stats: &StageStats| {
+            writeln!(
+                f,
+                "  {name:<12} {:>10} rows {:>9.3}s busy {:>12.0} rows/s",
+                stats.rows,
+                stats.busy.as_secs_f64(),
+                stats.throughput()
+            )
+        };
+
+        writeln!(f, "Pipeline finished in {:.3}s", self.elapsed.as_secs_f64())?;
+        stage(f, "reader", &self.reader)?;
+        for (index, processor) in self.processors.iter().enumerate() {
+            stage(f, &format!("processor {index}"), processor)?;
+        }
+        stage(f, "collector", &self.collector)
+    }
+}
+
+/// Rejects of a pipelined run, in input order, and how busy each stage was.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineRun {
+    pub rejects: Vec<Reject>,
+    pub stats: PipelineStats,
+}
+
+/// Like [`crate::rejects::process_rows_shared`], but applies rows while they are still
+/// being read. `read` runs on the calling thread and hands every row it reads to the
+/// callback, which sends it in batches over bounded channels to one processor thread per
+/// shard of clients. Rejects are gathered by a collector thread. An error of `read` is
+/// returned once the rows read up to then have been applied.
+pub fn process_pipelined(
+    engine: &SharedEngine,
+    processors: usize,
+    config: PipelineConfig,
+    read: impl FnOnce(&mut dyn FnMut(InputRow)) -> Result<(), Box<dyn Error>>,
+) -> Result<PipelineRun, Box<dyn Error>> {
+    let processors = processors.max(1);
+    let batch_size = config.batch_size.max(1);
+    let started = Instant::now();
+
+    thread::scope(|scope| {
+        let (reject_tx, reject_rx) = mpsc::sync_channel::<Vec<Reject>>(config.queue_depth);
+        let collector = scope.spawn(move || {
+            let mut stats = StageStats::default();
+            let mut rejects = Vec::new();
+            for batch in reject_rx {
+                let start = Instant::now();
+                stats.rows += batch.len() as u64;
+                rejects.extend(batch);
+                stats.busy += start.elapsed();
+            }
+
+            let start = Instant::now();
+            rejects.sort_by_key(|reject: &Reject| reject.line);
+            stats.busy += start.elapsed();
+            (rejects, stats)
+        });
+
+        let (queues, workers): (Vec<_>, Vec<_>) = (0..processors)
+            .map(|_| {
+                let (tx, rx) = mpsc::sync_channel::<Vec<(u64, Record)>>(config.queue_depth);
+                let reject_tx = reject_tx.clone();
+                let worker = scope.spawn(move || {
+                    let mut stats = StageStats::default();
+                    for batch in rx {
+                        let start = Instant::now();
+                        stats.rows += batch.len() as u64;
+                        let mut rejects = Vec::new();
+                        for (line, record) in batch {
+                            let rejected = record_fields(line, &record);
+                            if let Err(reason) = engine.apply(record) {
+                                rejects.push(rejected(reason));
+                            }
+                        }
+                        stats.busy += start.elapsed();
+
+                        if !rejects.is_empty() {
+                            let _ = reject_tx.send(rejects);
+                        }
+                    }
+
+                    stats
+                });
+                (tx, worker)
+            })
+            .collect();
+
+        let mut reader = Reader {
+            queues,
+            batches: vec![Vec::new(); processors],
+            batch_size,
+            malformed: Vec::new(),
+            stats: StageStats::default(),
+            blocked: Duration::ZERO,
+        };
+        let start = Instant::now();
+        let result = read(&mut |row| reader.dispatch(row));
+        reader.flush();
+        reader.stats.busy = start.elapsed().saturating_sub(reader.blocked);
+        let _ = reject_tx.send(mem::take(&mut reader.malformed));
+        let reader_stats = reader.stats;
+        drop((reader, reject_tx));
+
+        let processors = workers
+            .into_iter()
+            .map(|worker| worker.join().expect("processor thread panicked"))
+            .collect();
+        let (rejects, collector) = collector.join().expect("collector thread panicked");
+        result?;
+
+        Ok(PipelineRun {
+            rejects,
+            stats: PipelineStats {
+                elapsed: started.elapsed(),
+                reader: reader_stats,
+                processors,
+                collector,
+            },
+        })
+    })
+}
+
+/// Reader stage: splits rows by client into batches for the processors.
+struct Reader {
+    queues: Vec<SyncSender<Vec<(u64, Record)>>>,
+    batches: Vec<Vec<(u64, Record)>>,
+    batch_size: usize,
+    malformed: Vec<Reject>,
+    stats: StageStats,
+    blocked: Duration,
+}
+
+impl Reader {
+    fn dispatch(&mut self, row: InputRow) {
+        self.stats.rows += 1;
+        match row.record {
+            Ok(record) => {
+                let shard = record.client.0 as usize % self.queues.len();
+                self.batches[shard].push((row.line, record));
+                if self.batches[shard].len() >= self.batch_size {
+                    self.send(shard);
+                }
+            }
+            Err(detail) => self.malformed.push(malformed(row.line, detail)),
+        }
+    }
+
+    fn flush(&mut self) {
+        for shard in 0..self.queues.len() {
+            if !self.batches[shard].is_empty() {
+                self.send(shard);
+            }
+        }
+    }
+
+    fn send(&mut self, shard: usize) {
+        let start = Instant::now();
+        let _ = self.queues[shard].send(mem::take(&mut self.batches[shard]));
+        self.blocked += start.elapsed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generate::{generate, GenerateOptions},
+        records::{read_rows_from, CsvDialect, InputFormat},
+        rejects::process_rows_shared,
+    };
+
+    fn rows() -> Vec<InputRow> {
+        let mut csv = Vec::new();
+        let options = GenerateOptions {
+            clients: 20,
+            transactions: 2000,
+            dispute_rate: 0.1,
+            // Which of two clients on different shards reuses a tx id first depends on
+            // timing, so duplicates would make the rejects differ from run to run.
+            duplicate_rate: 0.0,
+            seed: 3,
+        };
+        generate(&mut csv, &options).unwrap();
+        let mut rows =
+            read_rows_from(csv.as_slice(), InputFormat::Csv, &CsvDialect::default()).unwrap();
+        rows.push(InputRow {
+            line: rows.len() as u64 + 2,
+            record: Err("bad row".to_owned()),
+        });
+
+        rows
+    }
+
+    #[test]
+    fn pipelined_matches_shared() {
+        let shared = SharedEngine::new(3);
+        let expected = process_rows_shared(&shared, rows(), 3);
+        assert!(expected.len() > 1);
+
+        let pipelined = SharedEngine::new(3);
+        let config = PipelineConfig {
+            batch_size: 7,
+            queue_depth: 1,
+        };
+        let run = process_pipelined(&pipelined, 3, config, |f| {
+            rows().into_iter().for_each(f);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(run.rejects, expected);
+        assert_eq!(pipelined.into_accounts(), shared.into_accounts());
+        let stats = run.stats;
+        assert_eq!(stats.reader.rows, 2001);
+        assert_eq!(stats.processors.iter().map(|p| p.rows).sum::<u64>(), 2000);
+        assert_eq!(stats.collector.rows, expected.len() as u64);
+    }
+
+    #[test]
+    fn read_error_after_applying_rows() {
+        let engine = SharedEngine::new(2);
+        let run = process_pipelined(&engine, 2, PipelineConfig::default(), |f| {
+            rows().into_iter().take(10).for_each(f);
+            Err("input truncated".into())
+        });
+
+        assert_eq!(run.unwrap_err().to_string(), "input truncated");
+        assert!(!engine.into_accounts().is_empty());
+    }
+}
@@ -0,0 +1,473 @@
+use postgres::{Client, NoTls};
+use std::{collections::BTreeMap, error::Error, fmt};
+
+use crate::{
+    amounts::Money,
+    currency::Currency,
+    events::AccountEvent,
+    observer::EngineObserver,
+    records::{Record, TxType},
+    transaction::{Account, Balance, ClientId, Engine, Hold, TxId},
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        client BIGINT PRIMARY KEY,
+        available DOUBLE PRECISION NOT NULL,
+        held DOUBLE PRECISION NOT NULL,
+        total DOUBLE PRECISION NOT NULL,
+        locked BOOLEAN NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        client BIGINT NOT NULL,
+        tx BIGINT NOT NULL,
+        type TEXT NOT NULL,
+        amount DOUBLE PRECISION,
+        currency TEXT,
+        PRIMARY KEY (client, tx)
+    );
+    CREATE TABLE IF NOT EXISTS currency_balances (
+        client BIGINT NOT NULL,
+        currency TEXT NOT NULL,
+        available DOUBLE PRECISION NOT NULL,
+        held DOUBLE PRECISION NOT NULL,
+        total DOUBLE PRECISION NOT NULL,
+        PRIMARY KEY (client, currency)
+    );
+    CREATE TABLE IF NOT EXISTS disputes (
+        client BIGINT NOT NULL,
+        tx BIGINT NOT NULL,
+        amount DOUBLE PRECISION,
+        PRIMARY KEY (client, tx)
+    );
+    -- Databases created before partial disputes.
+    ALTER TABLE disputes ADD COLUMN IF NOT EXISTS amount DOUBLE PRECISION;
+    CREATE TABLE IF NOT EXISTS holds (
+        client BIGINT NOT NULL,
+        tx BIGINT NOT NULL,
+        amount DOUBLE PRECISION NOT NULL,
+        currency TEXT,
+        placed_at TEXT,
+        PRIMARY KEY (client, tx)
+    );
+    -- Databases created before client ids were widened.
+    ALTER TABLE accounts ALTER COLUMN client TYPE BIGINT;
+    ALTER TABLE transactions ALTER COLUMN client TYPE BIGINT;
+    ALTER TABLE currency_balances ALTER COLUMN client TYPE BIGINT;
+    ALTER TABLE disputes ALTER COLUMN client TYPE BIGINT;
+    ALTER TABLE holds ALTER COLUMN client TYPE BIGINT;
+";
+
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Engine state kept in PostgreSQL, in the same tables as the
+/// [SQLite backend](crate::sqlite::SqliteBackend). Applied records are buffered and
+/// written in batches, each in one database transaction, so the database always holds the
+/// state after some applied record. Batches are written once `batch_size` records are
+/// buffered and on [`Engine::flush_observers`].
+pub struct PostgresBackend {
+    client: Client,
+    batch_size: usize,
+    /// Latest balances per client and currency touched by the buffered records.
+    balances: BTreeMap<(ClientId, Option<Currency>), Balance>,
+    /// Latest lock state of the clients touched by the buffered records.
+    locked: BTreeMap<ClientId, bool>,
+    records: Vec<Record>,
+}
+
+impl PostgresBackend {
+    /// Connects to the database at `url`, e.g. `postgres://user@localhost/accounts`, and
+    /// creates the tables if needed. Without a password in the URL, the one in the
+    /// `PGPASSWORD` environment variable is used.
+    pub fn connect(url: &str) -> Result<Self, Box<dyn Error>> {
+        let mut config: postgres::Config = url.parse()?;
+        if config.get_password().is_none() {
+            if let Ok(password) = std::env::var("PGPASSWORD") {
+                config.password(password);
+            }
+        }
+
+        let mut client = config.connect(NoTls)?;
+        client.batch_execute(SCHEMA)?;
+
+        Ok(Self {
+            client,
+            batch_size: DEFAULT_BATCH_SIZE,
+            balances: BTreeMap::new(),
+            locked: BTreeMap::new(),
+            records: Vec::new(),
+        })
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Puts the stored accounts, transactions, disputes and holds back into `engine`.
+    pub fn restore(&mut self, engine: &mut Engine) -> Result<(), Box<dyn Error>> {
+        for account in self.accounts()? {
+            engine.restore_account(account);
+        }
+
+        let rows = self.client.query(
+            "SELECT t.client, t.tx, t.type, t.amount, d.tx IS NOT NULL, t.currency, d.amount
+             FROM transactions t LEFT JOIN disputes d ON d.client = t.client AND d.tx = t.tx",
+            &[],
+        )?;
+        for row in rows {
+            let r#type = match row.get::<_, &str>(2) {
+                "deposit" => TxType::Deposit,
+                "withdrawal" => TxType::Withdrawal,
+                other => return Err(format!("unexpected transaction type '{other}'").into()),
+            };
+            let record = Record {
+                r#type,
+                client: client_from_sql(row.get(0))?,
+                tx: tx_from_sql(row.get(1))?,
+                amount: row
+                    .get::<_, Option<f64>>(3)
+                    .map(|amount| Money::new(amount as f32)),
+                timestamp: None,
+                currency: row.get::<_, Option<&str>>(5).map(str::parse).transpose()?,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
+            };
+            let (client, tx) = (record.client, record.tx);
+            engine.restore_transaction(record, row.get(4));
+            if let Some(amount) = row.get::<_, Option<f64>>(6) {
+                engine.restore_disputed_amount(client, tx, Money::new(amount as f32));
+            }
+        }
+
+        let rows = self.client.query(
+            "SELECT client, tx, amount, currency, placed_at FROM holds",
+            &[],
+        )?;
+        for row in rows {
+            engine.restore_hold(Hold {
+                client: client_from_sql(row.get(0))?,
+                tx: tx_from_sql(row.get(1))?,
+                amount: Money::new(row.get::<_, f64>(2) as f32),
+                currency: row.get::<_, Option<&str>>(3).map(str::parse).transpose()?,
+                placed_at: row.get::<_, Option<&str>>(4).map(str::parse).transpose()?,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// All stored accounts, sorted by client. Buffered records are not included.
+    pub fn accounts(&mut self) -> Result<Vec<Account>, Box<dyn Error>> {
+        let mut accounts = self
+            .client
+            .query(
+                "SELECT client, available, held, total, locked FROM accounts ORDER BY client",
+                &[],
+            )?
+            .into_iter()
+            .map(|row| {
+                Ok(Account {
+                    client: client_from_sql(row.get(0))?,
+                    available: Money::new(row.get::<_, f64>(1) as f32),
+                    held: Money::new(row.get::<_, f64>(2) as f32),
+                    total: Money::new(row.get::<_, f64>(3) as f32),
+                    locked: row.get(4),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        let rows = self.client.query(
+            "SELECT client, currency, available, held, total FROM currency_balances",
+            &[],
+        )?;
+        for row in rows {
+            let client = client_from_sql(row.get(0))?;
+            let currency: Currency = row.get::<_, &str>(1).parse()?;
+            let Ok(index) = accounts.binary_search_by_key(&client, |a| a.client) else {
+                continue;
+            };
+            accounts[index].currencies.insert(
+                currency,
+                Balance {
+                    available: Money::new(row.get::<_, f64>(2) as f32),
+                    held: Money::new(row.get::<_, f64>(3) as f32),
+                    total: Money::new(row.get::<_, f64>(4) as f32),
+                },
+            );
+        }
+
+        Ok(accounts)
+    }
+
+    /// Writes the buffered records in one database transaction.
+    pub fn write_batch(&mut self) -> Result<(), postgres::Error> {
+        if self.records.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = self.client.transaction()?;
+        let upsert_account = tx.prepare(
+            "INSERT INTO accounts (client, available, held, total, locked)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (client) DO UPDATE SET available = excluded.available,
+                held = excluded.held, total = excluded.total, locked = excluded.locked",
+        )?;
+        let upsert_locked = tx.prepare(
+            "INSERT INTO accounts (client, available, held, total, locked) VALUES ($1, 0, 0, 0, $2)
+             ON CONFLICT (client) DO UPDATE SET locked = excluded.locked",
+        )?;
+        let upsert_balance = tx.prepare(
+            "INSERT INTO currency_balances (client, currency, available, held, total)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (client, currency) DO UPDATE SET available = excluded.available,
+                held = excluded.held, total = excluded.total",
+        )?;
+        let insert_tx = tx.prepare(
+            "INSERT INTO transactions (client, tx, type, amount, currency)
+             VALUES ($1, $2, $3, $4, $5)",
+        )?;
+        let open_dispute = tx.prepare(
+            "INSERT INTO disputes (client, tx, amount) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        )?;
+        let close_dispute = tx.prepare("DELETE FROM disputes WHERE client = $1 AND tx = $2")?;
+        let insert_hold = tx.prepare(
+            "INSERT INTO holds (client, tx, amount, currency, placed_at)
+             VALUES ($1, $2, $3, $4, $5)",
+        )?;
+        let capture_hold = tx.prepare(
+            "INSERT INTO transactions (client, tx, type, amount, currency)
+             SELECT client, tx, 'withdrawal', COALESCE($3, amount), currency
+             FROM holds WHERE client = $1 AND tx = $2",
+        )?;
+        let remove_hold = tx.prepare("DELETE FROM holds WHERE client = $1 AND tx = $2")?;
+
+        for (&(client, currency), balance) in &self.balances {
+            let locked = self.locked[&client];
+            let Some(currency) = currency else {
+                tx.execute(
+                    &upsert_account,
+                    &[
+                        &i64::from(client.0),
+                        &f64::from(balance.available),
+                        &f64::from(balance.held),
+                        &f64::from(balance.total),
+                        &locked,
+                    ],
+                )?;
+                continue;
+            };
+
+            tx.execute(&upsert_locked, &[&i64::from(client.0), &locked])?;
+            tx.execute(
+                &upsert_balance,
+                &[
+                    &i64::from(client.0),
+                    &currency.as_str(),
+                    &f64::from(balance.available),
+                    &f64::from(balance.held),
+                    &f64::from(balance.total),
+                ],
+            )?;
+        }
+
+        // A balance written above may predate a later lock or unlock in the batch.
+        for (&client, locked) in &self.locked {
+            tx.execute(&upsert_locked, &[&i64::from(client.0), locked])?;
+        }
+
+        for record in &self.records {
+            let (client, tx_id) = (i64::from(record.client.0), tx_to_sql(record.tx));
+            match record.r#type {
+                TxType::Deposit | TxType::Withdrawal => {
+                    let r#type = if record.r#type == TxType::Deposit {
+                        "deposit"
+                    } else {
+                        "withdrawal"
+                    };
+                    let amount = record.amount.map(f64::from);
+                    let currency = record.currency.as_ref().map(Currency::as_str);
+                    tx.execute(&insert_tx, &[&client, &tx_id, &r#type, &amount, &currency])?;
+                }
+                TxType::Dispute => {
+                    let amount = record.amount.map(f64::from);
+                    tx.execute(&open_dispute, &[&client, &tx_id, &amount])?;
+                }
+                TxType::Resolve | TxType::Chargeback => {
+                    tx.execute(&close_dispute, &[&client, &tx_id])?;
+                }
+                TxType::Hold => {
+                    let amount = record.amount.map(f64::from);
+                    let currency = record.currency.as_ref().map(Currency::as_str);
+                    let placed_at = record.timestamp.map(|at| at.to_rfc3339());
+                    tx.execute(
+                        &insert_hold,
+                        &[&client, &tx_id, &amount, &currency, &placed_at],
+                    )?;
+                }
+                TxType::Capture => {
+                    let amount = record.amount.map(f64::from);
+                    tx.execute(&capture_hold, &[&client, &tx_id, &amount])?;
+                    tx.execute(&remove_hold, &[&client, &tx_id])?;
+                }
+                TxType::Release => {
+                    tx.execute(&remove_hold, &[&client, &tx_id])?;
+                }
+                TxType::Representment
+                | TxType::OpenAccount
+                | TxType::Lock
+                | TxType::Unlock
+                | TxType::Convert
+                | TxType::Fee
+                | TxType::Assert
+                | TxType::Custom(_) => {}
+            }
+        }
+
+        tx.commit()?;
+        self.balances.clear();
+        self.locked.clear();
+        self.records.clear();
+        Ok(())
+    }
+}
+
+/// Tx ids are stored in BIGINT columns by their bits, so the ones from 2^63 on, which only
+/// exist with the `wide-ids` feature, read as negative numbers in the database.
+fn tx_to_sql(tx: TxId) -> i64 {
+    tx.to_u64() as i64
+}
+
+fn tx_from_sql(value: i64) -> Result<TxId, String> {
+    TxId::from_u64(value as u64).ok_or_else(|| format!("tx {value} is out of range"))
+}
+
+fn client_from_sql(value: i64) -> Result<ClientId, String> {
+    u64::try_from(value)
+        .ok()
+        .and_then(ClientId::from_u64)
+        .ok_or_else(|| format!("client {value} is out of range"))
+}
+
+impl fmt::Debug for PostgresBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PostgresBackend")
+            .field("batch_size", &self.batch_size)
+            .field("buffered", &self.records.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Like the journal, a record that failed to be written cannot be reported as rejected
+/// anymore, so write errors abort the process.
+impl EngineObserver for PostgresBackend {
+    fn on_applied(&mut self, record: &Record, event: &AccountEvent) {
+        let AccountEvent::Updated {
+            client,
+            currency,
+            available,
+            held,
+            total,
+            locked,
+        } = *event
+        else {
+            return;
+        };
+
+        self.balances.insert(
+            (client, currency),
+            Balance {
+                available,
+                held,
+                total,
+            },
+        );
+        self.locked.insert(client, locked);
+        // A conversion updates two balances but is only one record.
+        if currency != record.currency {
+            return;
+        }
+
+        self.records.push(record.clone());
+        if self.records.len() >= self.batch_size {
+            self.write_batch()
+                .expect("writing to the PostgreSQL backend failed");
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(self.write_batch()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scenario;
+
+    /// Needs a database that may be wiped, e.g.
+    /// `TX_ACCOUNTS_TEST_POSTGRES=postgres://postgres@localhost/tx_accounts_test`; skipped
+    /// when the variable is not set.
+    #[test]
+    fn restore_continues_from_the_database() {
+        let Ok(url) = std::env::var("TX_ACCOUNTS_TEST_POSTGRES") else {
+            return;
+        };
+
+        let mut backend = PostgresBackend::connect(&url).unwrap();
+        backend
+            .client
+            .batch_execute("TRUNCATE accounts, currency_balances, transactions, disputes, holds")
+            .unwrap();
+
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(backend.with_batch_size(2)));
+        let mut engine = Scenario::from_engine(engine)
+            .in_currency(Some("EUR"))
+            .deposit(1, 5, 3)
+            .withdraw(1, 6, 1)
+            .in_currency(None)
+            .deposit(1, 1, 10)
+            .deposit(1, 2, 5)
+            .withdraw(1, 3, 2)
+            .dispute(1, 1)
+            .hold(1, 7, 2)
+            .hold(1, 8, 1)
+            .capture(1, 8, None)
+            .deposit(1, 9, 1)
+            .deposit(2, 4, 7)
+            .dispute(2, 4)
+            .chargeback(2, 4)
+            .into_engine();
+
+        // The last record is still buffered until the observers are flushed.
+        let mut reader = PostgresBackend::connect(&url).unwrap();
+        assert!(reader.accounts().unwrap()[1].held > 0.0);
+        engine.flush_observers().unwrap();
+        assert_eq!(
+            reader.accounts().unwrap(),
+            engine.accounts_iter().cloned().collect::<Vec<_>>()
+        );
+
+        let mut restored = Engine::new();
+        reader.restore(&mut restored).unwrap();
+        Scenario::from_engine(restored)
+            .expect_held(ClientId(1), 12)
+            .expect_locked(ClientId(2), true)
+            .release(1, 7)
+            .resolve(1, 1)
+            .expect_available(ClientId(1), 13)
+            .dispute(1, 8)
+            .expect_held(ClientId(1), 1)
+            .deposit(1, 2, 1)
+            .expect_rejected(crate::transaction::RejectionReason::DuplicateTx)
+            .in_currency(Some("EUR"))
+            .dispute(1, 5)
+            .expect_balance(ClientId(1), "EUR", -1, 3);
+    }
+}
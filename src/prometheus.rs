@@ -0,0 +1,180 @@
+use prometheus::{
+    exponential_buckets, Histogram, HistogramOpts, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::{error::Error, path::Path, time::Duration};
+
+use crate::{
+    events::AccountEvent,
+    observer::EngineObserver,
+    records::{Record, TxType},
+    transaction::{Engine, RejectionReason},
+};
+
+/// Prometheus metrics of the records applied by an engine: records per type and outcome,
+/// rejections per type and reason, how long the engine took per record, and the number
+/// of locked accounts and open disputes. Clones share the same metrics, so one instance
+/// can observe every shard of a [`crate::shared::SharedEngine`].
+#[derive(Debug, Clone)]
+pub struct PrometheusMetrics {
+    registry: Registry,
+    records: IntCounterVec,
+    rejections: IntCounterVec,
+    latency: Histogram,
+    locked_accounts: IntGauge,
+    open_disputes: IntGauge,
+}
+
+impl PrometheusMetrics {
+    pub fn new() -> Self {
+        let records = IntCounterVec::new(
+            Opts::new("tx_accounts_records_total", "Records processed"),
+            &["type", "outcome"],
+        )
+        .expect("valid metric");
+        let rejections = IntCounterVec::new(
+            Opts::new("tx_accounts_rejections_total", "Records rejected"),
+            &["type", "reason"],
+        )
+        .expect("valid metric");
+        let latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "tx_accounts_processing_seconds",
+                "Time the engine took to apply a record",
+            )
+            .buckets(exponential_buckets(1e-7, 4.0, 10).expect("valid buckets")),
+        )
+        .expect("valid metric");
+        let locked_accounts =
+            IntGauge::new("tx_accounts_locked_accounts", "Locked accounts").expect("valid metric");
+        let open_disputes = IntGauge::new(
+            "tx_accounts_open_disputes",
+            "Disputes not yet resolved or charged back",
+        )
+        .expect("valid metric");
+
+        let registry = Registry::new();
+        for collector in [
+            Box::new(records.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(rejections.clone()),
+            Box::new(latency.clone()),
+            Box::new(locked_accounts.clone()),
+            Box::new(open_disputes.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+
+        Self {
+            registry,
+            records,
+            rejections,
+            latency,
+            locked_accounts,
+            open_disputes,
+        }
+    }
+
+    /// Sets the gauges from the state `engine` was restored to, before any record is
+    /// applied to it.
+    pub fn set_state(&self, engine: &Engine) {
+        let locked = engine.accounts().values().filter(|a| a.locked).count();
+        let disputed = engine
+            .dispute_history()
+            .filter(|(_, disputed)| *disputed)
+            .count();
+        self.locked_accounts.set(locked as i64);
+        self.open_disputes.set(disputed as i64);
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .expect("text encoding cannot fail")
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, self.encode())?;
+        Ok(())
+    }
+}
+
+impl Default for PrometheusMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EngineObserver for PrometheusMetrics {
+    fn on_applied(&mut self, record: &Record, event: &AccountEvent) {
+        match event {
+            AccountEvent::Opened { .. } => return,
+            AccountEvent::Locked { .. } => {
+                self.locked_accounts.inc();
+                return;
+            }
+            AccountEvent::Updated { currency, .. } if *currency == record.currency => {}
+            AccountEvent::Updated { .. } => return,
+        }
+
+        // Every applied record causes exactly one update in its own currency.
+        self.records
+            .with_label_values(&[record.r#type.as_str(), "applied"])
+            .inc();
+        match record.r#type {
+            TxType::Dispute => self.open_disputes.inc(),
+            TxType::Resolve | TxType::Chargeback => self.open_disputes.dec(),
+            TxType::Unlock => self.locked_accounts.dec(),
+            _ => {}
+        }
+    }
+
+    fn on_rejected(&mut self, record: &Record, reason: RejectionReason) {
+        let r#type = record.r#type.as_str();
+        self.records.with_label_values(&[r#type, "rejected"]).inc();
+        self.rejections
+            .with_label_values(&[r#type, &reason.to_string()])
+            .inc();
+    }
+
+    fn on_processed(&mut self, _record: &Record, elapsed: Duration) {
+        self.latency.observe(elapsed.as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scenario;
+
+    #[test]
+    fn counts_applied_and_rejected_records() {
+        let metrics = PrometheusMetrics::new();
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(metrics.clone()));
+        Scenario::from_engine(engine)
+            .deposit(1, 1, 10)
+            .deposit(1, 2, 5)
+            .withdraw(1, 3, 50)
+            .dispute(1, 1)
+            .dispute(1, 2)
+            .chargeback(1, 1);
+
+        let text = metrics.encode();
+        for line in [
+            r#"tx_accounts_records_total{outcome="applied",type="deposit"} 2"#,
+            r#"tx_accounts_records_total{outcome="rejected",type="withdrawal"} 1"#,
+            r#"tx_accounts_rejections_total{reason="insufficient_funds",type="withdrawal"} 1"#,
+            "tx_accounts_processing_seconds_count 6",
+            "tx_accounts_locked_accounts 1",
+            "tx_accounts_open_disputes 1",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{line} missing from\n{text}"
+            );
+        }
+    }
+}
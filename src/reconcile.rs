@@ -0,0 +1,176 @@
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    io::Write,
+};
+
+use crate::{
+    amounts::{serialize_4dp, Money},
+    currency::Currency,
+    transaction::{Account, ClientId},
+};
+
+/// Why the balances of a client in one currency do not match the expected ones.
+#[derive(Debug, Serialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum DeltaKind {
+    /// The client is expected but has no account.
+    Missing,
+    /// The client has an account but is not expected.
+    Unexpected,
+    /// The balances or the lock differ.
+    Differs,
+}
+
+/// A difference between the accounts and the expected balances, as actual minus expected.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct BalanceDelta {
+    pub client: ClientId,
+    pub currency: Option<Currency>,
+    pub kind: DeltaKind,
+    #[serde(serialize_with = "serialize_4dp")]
+    pub available: Money,
+    #[serde(serialize_with = "serialize_4dp")]
+    pub held: Money,
+    #[serde(serialize_with = "serialize_4dp")]
+    pub total: Money,
+    pub expected_locked: Option<bool>,
+    pub locked: Option<bool>,
+}
+
+/// Compares `accounts` against `expected` currency by currency, with amounts compared at
+/// the 4 decimal places they are written with. A currency one side does not have counts as
+/// a zero balance, and the lock is compared in the base currency. The deltas are sorted by
+/// client, base currency first.
+pub fn reconcile(accounts: &HashMap<ClientId, Account>, expected: &[Account]) -> Vec<BalanceDelta> {
+    reconcile_within(accounts, expected, Money::ZERO)
+}
+
+/// Like [`reconcile`], but amounts that differ by no more than `tolerance` match.
+pub fn reconcile_within(
+    accounts: &HashMap<ClientId, Account>,
+    expected: &[Account],
+    tolerance: Money,
+) -> Vec<BalanceDelta> {
+    let tolerance = (f64::from(tolerance).abs() * 10_000.0).round();
+    let expected: HashMap<ClientId, &Account> = expected
+        .iter()
+        .map(|account| (account.client, account))
+        .collect();
+    let mut clients: Vec<ClientId> = accounts.keys().chain(expected.keys()).copied().collect();
+    clients.sort_unstable();
+    clients.dedup();
+
+    let mut deltas = Vec::new();
+    for client in clients {
+        let actual = accounts.get(&client);
+        let wanted = expected.get(&client).copied();
+        let kind = match (actual, wanted) {
+            (None, _) => DeltaKind::Missing,
+            (_, None) => DeltaKind::Unexpected,
+            _ => DeltaKind::Differs,
+        };
+
+        let currencies: BTreeSet<Currency> = actual
+            .into_iter()
+            .chain(wanted)
+            .flat_map(|account| account.currencies.keys().copied())
+            .collect();
+        let currencies = std::iter::once(None).chain(currencies.into_iter().map(Some));
+        for currency in currencies {
+            let balance = actual.map(|a| a.balance(currency)).unwrap_or_default();
+            let expected_balance = wanted.map(|a| a.balance(currency)).unwrap_or_default();
+            let (locked, expected_locked) = match currency {
+                None => (actual.map(|a| a.locked), wanted.map(|a| a.locked)),
+                Some(_) => (None, None),
+            };
+
+            let delta = BalanceDelta {
+                client,
+                currency,
+                kind,
+                available: balance.available - expected_balance.available,
+                held: balance.held - expected_balance.held,
+                total: balance.total - expected_balance.total,
+                expected_locked,
+                locked,
+            };
+            let differs = [delta.available, delta.held, delta.total]
+                .into_iter()
+                .any(|amount| (f64::from(amount).abs() * 10_000.0).round() > tolerance)
+                || locked != expected_locked;
+            // A missing or unexpected client is reported even with zero balances.
+            if differs || (kind != DeltaKind::Differs && currency.is_none()) {
+                deltas.push(delta);
+            }
+        }
+    }
+
+    deltas
+}
+
+pub fn write_deltas<W: Write>(writer: W, deltas: &[BalanceDelta]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for delta in deltas {
+        wtr.serialize(delta)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scenario;
+
+    #[test]
+    fn reports_per_client_deltas() {
+        let accounts = Scenario::new()
+            .deposit(1, 1, 10)
+            .deposit(2, 2, 5)
+            .dispute(2, 2)
+            .in_currency(Some("EUR"))
+            .deposit(2, 3, 4)
+            .in_currency(None)
+            .deposit(3, 4, 1)
+            .into_engine()
+            .into_accounts();
+
+        let expected = crate::output::read_accounts(
+            "client,currency,available,held,total,locked\n\
+             1,,10.00001,0,10.00001,false\n\
+             2,,0,5,5,true\n\
+             2,EUR,3.5,0,3.5,false\n\
+             4,,2,0,2,false\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let deltas = reconcile(&accounts, &expected);
+
+        let mut out = Vec::new();
+        write_deltas(&mut out, &deltas).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,currency,kind,available,held,total,expected_locked,locked\n\
+             2,,differs,0.0000,0.0000,0.0000,true,false\n\
+             2,EUR,differs,0.5000,0.0000,0.5000,,\n\
+             3,,unexpected,1.0000,0.0000,1.0000,,false\n\
+             4,,missing,-2.0000,0.0000,-2.0000,false,\n"
+        );
+        assert!(reconcile(&accounts, &accounts.values().cloned().collect::<Vec<_>>()).is_empty());
+
+        let kinds: Vec<DeltaKind> = reconcile_within(&accounts, &expected, Money::new(0.5))
+            .iter()
+            .map(|delta| delta.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                DeltaKind::Differs,
+                DeltaKind::Unexpected,
+                DeltaKind::Missing
+            ]
+        );
+    }
+}
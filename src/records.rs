@@ -1,30 +1,871 @@
-use serde::Deserialize;
-use std::{error::Error, fs::File, path::Path};
+use chrono::{DateTime, Utc};
+use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    num::ParseIntError,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
-#[serde(rename_all = "lowercase")]
+use crate::{
+    amounts::Money,
+    currency::Currency,
+    transaction::{ClientId, TxId},
+};
+
+/// Serialized as its name in input files, see [`TxType::as_str`].
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
 pub enum TxType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    /// Reverses the chargeback of the transaction `tx` after the chargeback was contested
+    /// and won, restoring the funds it took.
+    Representment,
+    /// Control record that opens the account of a client, required before any other
+    /// activity under the strict account policy.
+    OpenAccount,
+    /// Administrative records that lock or unlock the account of a client, e.g. to
+    /// restore service after a chargeback has been investigated.
+    Lock,
+    Unlock,
+    /// Moves `amount` of the client's funds in `currency` to its balance in `to_currency`
+    /// at the rate of the engine's rate table.
+    Convert,
+    /// Debits `amount` from the available funds of the client as a fee.
+    Fee,
+    /// Moves `amount` from available to held funds until a `capture` or `release` with
+    /// the same tx id settles it, as for a card authorization.
+    Hold,
+    /// Settles a hold as a withdrawal of `amount`, or of all of it without an amount.
+    Capture,
+    /// Returns the funds of a hold to the available funds.
+    Release,
+    /// Checkpoint that expects the client to have `amount` available and `total` in total
+    /// funds in `currency`, either of which may be left out. Changes nothing.
+    Assert,
+    /// Any other type, applied by the [`TxHandler`](crate::handler::TxHandler)
+    /// registered for it.
+    Custom(CustomType),
+}
+
+/// Name of a transaction type the engine does not know itself, e.g.
+/// `loyalty_adjustment`: up to 24 lowercase ASCII letters, digits and underscores,
+/// starting with a letter. Kept inline so that a [`TxType`] stays `Copy`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CustomType {
+    len: u8,
+    name: [u8; 24],
+}
+
+impl CustomType {
+    pub fn as_str(&self) -> &str {
+        // Only ASCII is ever stored.
+        std::str::from_utf8(&self.name[..usize::from(self.len)]).unwrap_or_default()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let valid = bytes.first().is_some_and(u8::is_ascii_alphabetic)
+            && bytes.len() <= 24
+            && bytes
+                .iter()
+                .all(|&byte| byte.is_ascii_alphanumeric() || byte == b'_');
+        if !valid {
+            return None;
+        }
+
+        let mut name = [0; 24];
+        for (stored, byte) in name.iter_mut().zip(bytes) {
+            *stored = byte.to_ascii_lowercase();
+        }
+        Some(Self {
+            len: bytes.len() as u8,
+            name,
+        })
+    }
+}
+
+impl fmt::Debug for CustomType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CustomType({})", self.as_str())
+    }
 }
 
 #[derive(Debug, Deserialize, PartialEq, Clone)]
 pub struct Record {
     #[serde(deserialize_with = "trim_and_parse_tx_type")]
     pub r#type: TxType,
-    #[serde(deserialize_with = "trim_and_parse_u16")]
-    pub client: u16,
-    #[serde(deserialize_with = "trim_and_parse_u32")]
-    pub tx: u32,
-    #[serde(deserialize_with = "trim_and_parse_f32_4dp")]
-    pub amount: Option<f32>,
+    #[serde(deserialize_with = "trim_and_parse_id")]
+    pub client: ClientId,
+    #[serde(deserialize_with = "trim_and_parse_id")]
+    pub tx: TxId,
+    #[serde(default, deserialize_with = "trim_and_parse_4dp")]
+    pub amount: Option<Money>,
+    /// When the transaction happened, as RFC 3339 or seconds since the Unix epoch.
+    #[serde(default, deserialize_with = "trim_and_parse_timestamp")]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Currency of the amount, or of the disputed transaction; the base currency of the
+    /// ledger when not set.
+    #[serde(default, deserialize_with = "trim_and_parse_currency")]
+    pub currency: Option<Currency>,
+    /// Currency a `convert` record moves the funds to.
+    #[serde(default, deserialize_with = "trim_and_parse_currency")]
+    pub to_currency: Option<Currency>,
+    /// Total funds an `assert` record expects, next to the available funds in `amount`.
+    #[serde(default, deserialize_with = "trim_and_parse_4dp")]
+    pub total: Option<Money>,
+    /// Why a `dispute` record was raised, e.g. a card scheme reason code such as `10.4`.
+    #[serde(default, deserialize_with = "trim_and_parse_text")]
+    pub reason_code: Option<String>,
+    /// Free text about the transaction, passed through to the outputs as is.
+    #[serde(default, deserialize_with = "trim_and_parse_text")]
+    pub memo: Option<String>,
+    /// Reference of the transaction at the partner, e.g. an order or invoice number.
+    #[serde(default, deserialize_with = "trim_and_parse_text")]
+    pub reference: Option<String>,
+    #[serde(default, deserialize_with = "trim_and_parse_text")]
+    pub merchant_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputFormat {
+    Csv,
+    JsonLines,
+}
+
+impl InputFormat {
+    /// Detects the format from the file extension, looking through a `.gz` or `.zst`
+    /// compression suffix.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let path = path.as_ref();
+        // The query of a URL, e.g. the signature of a presigned one, is not part of the name.
+        let path = match path.to_str().filter(|path| path.contains("://")) {
+            Some(url) => Path::new(url.split(['?', '#']).next().unwrap_or(url)),
+            None => path,
+        };
+        let mut extension = path.extension()?.to_str()?.to_lowercase();
+        if matches!(extension.as_str(), "gz" | "zst") {
+            extension = Path::new(path.file_stem()?)
+                .extension()?
+                .to_str()?
+                .to_lowercase();
+        }
+
+        match extension.as_str() {
+            "csv" => Some(Self::Csv),
+            "json" | "jsonl" | "ndjson" => Some(Self::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "json" | "jsonl" | "ndjson" => Ok(Self::JsonLines),
+            _ => Err(format!(
+                "unknown input format '{s}', expected one of csv, json"
+            )),
+        }
+    }
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Whether `path` is an `s3://bucket/key` URL rather than a local file.
+pub fn is_s3_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with("s3://"))
+}
+
+/// Whether `path` is an `http://` or `https://` URL rather than a local file.
+pub fn is_http_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// Opens an input file, `s3://` object or `http(s)://` URL, decompressing gzip and zstd
+/// files on the fly. Compression is detected from the leading magic bytes rather than the
+/// file name.
+pub fn open_input<P: AsRef<Path>>(path: P) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    let path = path.as_ref();
+    let input: Box<dyn Read> = if is_s3_url(path) {
+        open_s3(path)?
+    } else if is_http_url(path) {
+        open_http(path)?
+    } else {
+        Box::new(File::open(path)?)
+    };
+
+    let mut reader = BufReader::new(input);
+    let magic = reader.fill_buf()?;
+    let (gzip, zstd) = (
+        magic.starts_with(&GZIP_MAGIC),
+        magic.starts_with(&ZSTD_MAGIC),
+    );
+
+    if gzip || zstd {
+        return decompress(reader, gzip);
+    }
+
+    Ok(Box::new(reader))
+}
+
+#[cfg(feature = "s3")]
+fn open_s3(url: &Path) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    let url = url.to_str().unwrap_or_default();
+    Ok(Box::new(crate::s3::S3Object::from_url(url)?.reader()?))
+}
+
+#[cfg(not(feature = "s3"))]
+fn open_s3(_url: &Path) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    Err("s3:// inputs require building with the `s3` feature".into())
+}
+
+#[cfg(feature = "https")]
+fn open_http(url: &Path) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    let url = url.to_str().unwrap_or_default();
+    Ok(Box::new(crate::http_input::HttpInput::open(url)?))
+}
+
+#[cfg(not(feature = "https"))]
+fn open_http(_url: &Path) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    Err("http(s):// inputs require building with the `https` feature".into())
+}
+
+#[cfg(feature = "compression")]
+fn decompress(
+    reader: BufReader<Box<dyn Read>>,
+    gzip: bool,
+) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    if gzip {
+        // Large dumps are often concatenated from several gzip members.
+        return Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader)));
+    }
+
+    Ok(Box::new(zstd::Decoder::with_buffer(reader)?))
+}
+
+#[cfg(not(feature = "compression"))]
+fn decompress(
+    _reader: BufReader<Box<dyn Read>>,
+    _gzip: bool,
+) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    Err("compressed inputs require building with the `compression` feature".into())
+}
+
+/// Fields of a record in the order expected from files without a header row.
+const FIELDS: [&str; 12] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "timestamp",
+    "currency",
+    "to_currency",
+    "total",
+    "reason_code",
+    "memo",
+    "reference",
+    "merchant_id",
+];
+
+/// How CSV inputs are laid out, for partners whose files differ from the default
+/// comma-separated file with a `type,client,tx,amount` header.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CsvDialect {
+    pub delimiter: char,
+    /// Without a header row the fields are expected in
+    /// `type,client,tx,amount,timestamp,currency,to_currency,total,reason_code,memo,
+    /// reference,merchant_id` order.
+    pub has_headers: bool,
+    /// Header names used by the file instead of the field names, keyed by field.
+    pub columns: BTreeMap<String, String>,
+    /// Parse rows from raw bytes without allocating per field, instead of through serde.
+    /// Accepts and rejects the same rows, though with terser error messages.
+    #[serde(default)]
+    pub fast_parse: bool,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            has_headers: true,
+            columns: BTreeMap::new(),
+            fast_parse: false,
+        }
+    }
+}
+
+impl CsvDialect {
+    /// Opens a CSV reader for this dialect, together with the header row translated to
+    /// field names. Headerless rows are deserialized by position instead.
+    pub(crate) fn reader<R: Read>(
+        &self,
+        reader: R,
+    ) -> Result<(csv::Reader<R>, Option<csv::StringRecord>), Box<dyn Error>> {
+        let delimiter = u8::try_from(self.delimiter)
+            .ok()
+            .filter(u8::is_ascii)
+            .ok_or_else(|| format!("delimiter '{}' is not an ASCII character", self.delimiter))?;
+        let mut rdr = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .has_headers(self.has_headers)
+            .from_reader(reader);
+
+        if !self.has_headers {
+            return Ok((rdr, None));
+        }
+
+        let headers = rdr
+            .headers()?
+            .iter()
+            .map(|header| {
+                let header = header.trim();
+                self.columns
+                    .iter()
+                    .find(|(_, column)| column.as_str() == header)
+                    .map_or(header, |(field, _)| field.as_str())
+                    .to_owned()
+            })
+            .collect();
+
+        Ok((rdr, Some(headers)))
+    }
+}
+
+/// Maps a field of a record to the header name a file uses for it, e.g.
+/// `type=transaction_type`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnMapping {
+    pub field: String,
+    pub column: String,
+}
+
+impl FromStr for ColumnMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((field, column)) = s.split_once('=') else {
+            return Err(format!(
+                "invalid column mapping '{s}', expected e.g. type=transaction_type"
+            ));
+        };
+
+        let field = field.trim();
+        if !FIELDS.contains(&field) {
+            return Err(format!(
+                "unknown field '{field}', expected one of {}",
+                FIELDS.join(", ")
+            ));
+        }
+
+        Ok(Self {
+            field: field.to_owned(),
+            column: column.trim().to_owned(),
+        })
+    }
+}
+
+/// A single input row tagged with its line number in the source file. Rows that could
+/// not be parsed keep the parse error instead of the record.
+#[derive(Debug, PartialEq, Clone)]
+pub struct InputRow {
+    pub line: u64,
+    pub record: Result<Record, String>,
+}
+
+/// Reads every row of the input without failing on malformed rows, so that they can be
+/// reported individually. Only I/O errors abort the read.
+pub fn read_rows<P: AsRef<Path>>(
+    path: P,
+    format: InputFormat,
+) -> Result<Vec<InputRow>, Box<dyn Error>> {
+    read_rows_with(path, format, &CsvDialect::default())
+}
+
+/// Like [`read_rows`], for CSV inputs in the given dialect.
+pub fn read_rows_with<P: AsRef<Path>>(
+    path: P,
+    format: InputFormat,
+    dialect: &CsvDialect,
+) -> Result<Vec<InputRow>, Box<dyn Error>> {
+    read_rows_from(open_input(path)?, format, dialect)
+}
+
+/// Like [`read_rows_with`], for an input that is already open. Expects uncompressed data.
+pub fn read_rows_from<R: Read>(
+    reader: R,
+    format: InputFormat,
+    dialect: &CsvDialect,
+) -> Result<Vec<InputRow>, Box<dyn Error>> {
+    let mut rows = Vec::new();
+    for_each_row(reader, format, dialect, |row| rows.push(row))?;
+
+    Ok(rows)
+}
+
+/// Where the row after the one just read starts in its input, from which
+/// [`for_each_row_at`] continues.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct InputOffset {
+    pub byte: u64,
+    /// Line number of the row.
+    pub line: u64,
+}
+
+impl Default for InputOffset {
+    fn default() -> Self {
+        Self { byte: 0, line: 1 }
+    }
+}
+
+type RowResult = Result<(), Box<dyn Error>>;
+
+/// Like [`read_rows_from`], but hands every row to `f` as soon as it is read instead of
+/// collecting them, so that inputs can be processed while they are being read.
+pub fn for_each_row<R: Read>(
+    reader: R,
+    format: InputFormat,
+    dialect: &CsvDialect,
+    mut f: impl FnMut(InputRow),
+) -> Result<(), Box<dyn Error>> {
+    let f = |row, _| {
+        f(row);
+        Ok(())
+    };
+
+    match format {
+        InputFormat::Csv => {
+            let (rdr, headers) = dialect.reader(reader)?;
+            for_each_csv_row(rdr, headers, dialect.fast_parse, f)
+        }
+        InputFormat::JsonLines => {
+            for_each_json_row(BufReader::new(reader), InputOffset::default(), f)
+        }
+    }
+}
+
+/// Like [`for_each_row`] for a local, uncompressed file, starting at `start` instead of
+/// the first row. Hands `f` every row together with the offset of the row after it, from
+/// which a later call can continue. Stops at the first error `f` returns.
+pub fn for_each_row_at<P: AsRef<Path>>(
+    path: P,
+    format: InputFormat,
+    dialect: &CsvDialect,
+    start: InputOffset,
+    f: impl FnMut(InputRow, InputOffset) -> RowResult,
+) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    let mut file = File::open(path)?;
+    let mut magic = [0; 4];
+    let read = file.read(&mut magic)?;
+    if magic[..read].starts_with(&GZIP_MAGIC) || magic[..read].starts_with(&ZSTD_MAGIC) {
+        return Err("a compressed input cannot be resumed at an offset".into());
+    }
+    file.rewind()?;
+
+    match format {
+        InputFormat::Csv => {
+            let (mut rdr, headers) = dialect.reader(file)?;
+            if start.byte > 0 {
+                // The header row was read already and is kept.
+                let mut position = csv::Position::new();
+                position.set_byte(start.byte).set_line(start.line);
+                rdr.seek(position)?;
+            }
+            for_each_csv_row(rdr, headers, dialect.fast_parse, f)
+        }
+        InputFormat::JsonLines => {
+            file.seek(SeekFrom::Start(start.byte))?;
+            for_each_json_row(BufReader::new(file), start, f)
+        }
+    }
+}
+
+fn for_each_csv_row<R: Read>(
+    mut rdr: csv::Reader<R>,
+    headers: Option<csv::StringRecord>,
+    fast_parse: bool,
+    mut f: impl FnMut(InputRow, InputOffset) -> RowResult,
+) -> Result<(), Box<dyn Error>> {
+    if fast_parse {
+        return for_each_csv_row_fast(rdr, headers, f);
+    }
+
+    let mut string_record = csv::StringRecord::new();
+    loop {
+        let row = match rdr.read_record(&mut string_record) {
+            Ok(false) => break,
+            Ok(true) => InputRow {
+                line: string_record.position().map_or(0, |p| p.line()),
+                record: string_record
+                    .deserialize::<Record>(headers.as_ref())
+                    .map_err(|e| e.to_string()),
+            },
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => InputRow {
+                line: e.position().map_or(0, |p| p.line()),
+                record: Err(e.to_string()),
+            },
+        };
+        f(row, csv_offset(&rdr))?;
+    }
+
+    Ok(())
+}
+
+fn csv_offset<R: Read>(rdr: &csv::Reader<R>) -> InputOffset {
+    let position = rdr.position();
+    InputOffset {
+        byte: position.byte(),
+        line: position.line(),
+    }
+}
+
+fn for_each_json_row<R: BufRead>(
+    mut reader: R,
+    start: InputOffset,
+    mut f: impl FnMut(InputRow, InputOffset) -> RowResult,
+) -> Result<(), Box<dyn Error>> {
+    let mut next = start;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        let row_line = next.line;
+        next.byte += read as u64;
+        next.line += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let row = InputRow {
+            line: row_line,
+            record: serde_json::from_str::<Record>(&line).map_err(|e| e.to_string()),
+        };
+        f(row, next)?;
+    }
+
+    Ok(())
+}
+
+/// CSV path of [`for_each_row`] for [`CsvDialect::fast_parse`]: rows are read into one
+/// reused [`csv::ByteRecord`] and the fields are parsed straight from the trimmed bytes.
+fn for_each_csv_row_fast<R: Read>(
+    mut rdr: csv::Reader<R>,
+    headers: Option<csv::StringRecord>,
+    mut f: impl FnMut(InputRow, InputOffset) -> RowResult,
+) -> Result<(), Box<dyn Error>> {
+    let columns: [Option<usize>; 12] = std::array::from_fn(|i| match &headers {
+        Some(headers) => headers.iter().position(|header| header == FIELDS[i]),
+        None => Some(i),
+    });
+
+    let mut byte_record = csv::ByteRecord::new();
+    loop {
+        let row = match rdr.read_byte_record(&mut byte_record) {
+            Ok(false) => break,
+            Ok(true) => InputRow {
+                line: byte_record.position().map_or(0, |p| p.line()),
+                record: parse_byte_record(&byte_record, &columns),
+            },
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => InputRow {
+                line: e.position().map_or(0, |p| p.line()),
+                record: Err(e.to_string()),
+            },
+        };
+        f(row, csv_offset(&rdr))?;
+    }
+
+    Ok(())
+}
+
+fn parse_byte_record(
+    byte_record: &csv::ByteRecord,
+    columns: &[Option<usize>; 12],
+) -> Result<Record, String> {
+    let field = |i: usize| {
+        columns[i]
+            .and_then(|column| byte_record.get(column))
+            .map(<[u8]>::trim_ascii)
+    };
+    let required = |i: usize| field(i).ok_or_else(|| format!("missing field `{}`", FIELDS[i]));
+    let invalid = |i: usize, bytes: &[u8]| {
+        format!("invalid {} '{}'", FIELDS[i], String::from_utf8_lossy(bytes))
+    };
+    let text = |i: usize| match field(i) {
+        Some(bytes) => std::str::from_utf8(bytes)
+            .map(Some)
+            .map_err(|_| invalid(i, bytes)),
+        None => Ok(None),
+    };
+    let note =
+        |i: usize| text(i).map(|text| text.filter(|text| !text.is_empty()).map(str::to_owned));
+
+    let r#type = required(0)?;
+    let r#type = parse_tx_type_bytes(r#type).ok_or_else(|| invalid(0, r#type))?;
+    let client = required(1)?;
+    let client = parse_uint(client)
+        .map(ClientId)
+        .ok_or_else(|| invalid(1, client))?;
+    let tx = required(2)?;
+    let tx = parse_uint(tx).map(TxId).ok_or_else(|| invalid(2, tx))?;
+
+    Ok(Record {
+        r#type,
+        client,
+        tx,
+        amount: text(3)?.map_or(Ok(None), parse_amount)?,
+        timestamp: text(4)?.map_or(Ok(None), parse_timestamp)?,
+        currency: text(5)?.map_or(Ok(None), parse_currency)?,
+        to_currency: text(6)?.map_or(Ok(None), parse_currency)?,
+        total: text(7)?.map_or(Ok(None), parse_amount)?,
+        reason_code: note(8)?,
+        memo: note(9)?,
+        reference: note(10)?,
+        merchant_id: note(11)?,
+    })
+}
+
+impl TxType {
+    /// The name of the type as in input files.
+    pub fn as_str(&self) -> &str {
+        match self {
+            TxType::Deposit => "deposit",
+            TxType::Withdrawal => "withdrawal",
+            TxType::Dispute => "dispute",
+            TxType::Resolve => "resolve",
+            TxType::Chargeback => "chargeback",
+            TxType::Representment => "representment",
+            TxType::OpenAccount => "open_account",
+            TxType::Lock => "lock",
+            TxType::Unlock => "unlock",
+            TxType::Convert => "convert",
+            TxType::Fee => "fee",
+            TxType::Hold => "hold",
+            TxType::Capture => "capture",
+            TxType::Release => "release",
+            TxType::Assert => "assert",
+            TxType::Custom(name) => name.as_str(),
+        }
+    }
+}
+
+impl FromStr for TxType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_tx_type_bytes(s.trim().as_bytes())
+            .ok_or_else(|| format!("unknown transaction type '{s}'"))
+    }
+}
+
+impl fmt::Display for TxType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for TxType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for TxType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn parse_tx_type_bytes(bytes: &[u8]) -> Option<TxType> {
+    const TYPES: [(&[u8], TxType); 15] = [
+        (b"deposit", TxType::Deposit),
+        (b"withdrawal", TxType::Withdrawal),
+        (b"dispute", TxType::Dispute),
+        (b"resolve", TxType::Resolve),
+        (b"chargeback", TxType::Chargeback),
+        (b"representment", TxType::Representment),
+        (b"open_account", TxType::OpenAccount),
+        (b"lock", TxType::Lock),
+        (b"unlock", TxType::Unlock),
+        (b"convert", TxType::Convert),
+        (b"fee", TxType::Fee),
+        (b"hold", TxType::Hold),
+        (b"capture", TxType::Capture),
+        (b"release", TxType::Release),
+        (b"assert", TxType::Assert),
+    ];
+
+    TYPES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(bytes))
+        .map(|&(_, r#type)| r#type)
+        .or_else(|| CustomType::from_bytes(bytes).map(TxType::Custom))
+}
+
+/// Parses an unsigned integer the way `str::parse` does, without going through a string.
+fn parse_uint<T: TryFrom<u64>>(bytes: &[u8]) -> Option<T> {
+    let digits = bytes.strip_prefix(b"+").unwrap_or(bytes);
+    if digits.is_empty() {
+        return None;
+    }
+
+    let mut value: u64 = 0;
+    for &byte in digits {
+        if !byte.is_ascii_digit() {
+            return None;
+        }
+        value = value.checked_mul(10)?.checked_add(u64::from(byte - b'0'))?;
+    }
+
+    T::try_from(value).ok()
+}
+
+/// Reads several inputs of the same format as one stream, in the given order. Line numbers
+/// continue from one input to the next, as if the inputs had been concatenated.
+pub fn read_inputs<P: AsRef<Path>>(
+    paths: &[P],
+    format: InputFormat,
+    dialect: &CsvDialect,
+) -> Result<Vec<InputRow>, Box<dyn Error>> {
+    let mut rows = Vec::new();
+    for_each_input_row(paths, format, dialect, |row| rows.push(row))?;
+
+    Ok(rows)
+}
+
+/// Like [`read_inputs`], but hands every row to `f` as soon as it is read.
+pub fn for_each_input_row<P: AsRef<Path>>(
+    paths: &[P],
+    format: InputFormat,
+    dialect: &CsvDialect,
+    mut f: impl FnMut(InputRow),
+) -> Result<(), Box<dyn Error>> {
+    let mut last_line = 0;
+    for path in paths {
+        let path = path.as_ref();
+        let offset = last_line;
+        open_input(path)
+            .and_then(|input| {
+                for_each_row(input, format, dialect, |mut row| {
+                    row.line += offset;
+                    last_line = row.line;
+                    f(row);
+                })
+            })
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Expands the glob patterns among `args`, e.g. `incoming/*.csv`, into the matching paths
+/// in alphabetical order. Other arguments, including URLs, are kept as they are. A pattern
+/// without any match is an error rather than silently processing nothing.
+pub fn expand_inputs(args: &[PathBuf]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut paths = Vec::new();
+    for arg in args {
+        let Some(pattern) = arg
+            .to_str()
+            .filter(|s| !s.contains("://") && s.contains(['*', '?', '[']))
+        else {
+            paths.push(arg.clone());
+            continue;
+        };
+
+        let matches = glob::glob(pattern)?.collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            return Err(format!("no input matches '{pattern}'").into());
+        }
+        paths.extend(matches);
+    }
+
+    Ok(paths)
+}
+
+/// Orders rows by timestamp, with the tx id breaking ties, for inputs concatenated from
+/// several sources. Rows without a timestamp and malformed rows go first, and rows that
+/// compare equal keep their input order.
+pub fn sort_by_time(rows: &mut [InputRow]) {
+    rows.sort_by_key(|row| {
+        row.record
+            .as_ref()
+            .ok()
+            .map(|record| (record.timestamp, record.tx))
+    });
+}
+
+/// Writes `records` as a CSV input with all columns, e.g. to feed records deferred by one
+/// run to the next one.
+pub fn write_records<W: Write>(writer: W, records: &[Record]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for record in records {
+        wtr.serialize(RecordRow {
+            r#type: record.r#type,
+            client: record.client,
+            tx: record.tx,
+            amount: record.amount.map(|amount| format!("{amount:.4}")),
+            timestamp: record.timestamp.map(|timestamp| timestamp.to_rfc3339()),
+            currency: record.currency,
+            to_currency: record.to_currency,
+            total: record.total.map(|total| format!("{total:.4}")),
+            reason_code: record.reason_code.as_deref(),
+            memo: record.memo.as_deref(),
+            reference: record.reference.as_deref(),
+            merchant_id: record.merchant_id.as_deref(),
+        })?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct RecordRow<'a> {
+    r#type: TxType,
+    client: ClientId,
+    tx: TxId,
+    amount: Option<String>,
+    timestamp: Option<String>,
+    currency: Option<Currency>,
+    to_currency: Option<Currency>,
+    total: Option<String>,
+    reason_code: Option<&'a str>,
+    memo: Option<&'a str>,
+    reference: Option<&'a str>,
+    merchant_id: Option<&'a str>,
+}
+
+pub fn read_records<P: AsRef<Path>>(
+    path: P,
+    format: InputFormat,
+) -> Result<Vec<Record>, Box<dyn Error>> {
+    match format {
+        InputFormat::Csv => read_csv(path),
+        InputFormat::JsonLines => read_json_lines(path),
+    }
 }
 
 pub fn read_csv<P: AsRef<Path>>(path: P) -> Result<Vec<Record>, Box<dyn Error>> {
-    let file = File::open(path)?;
+    let file = open_input(path)?;
     // The CSV reader is buffered automatically, so it does not needed to
     // wrap rdr in a buffered reader like io::BufReader
     let mut rdr = csv::Reader::from_reader(file);
@@ -34,56 +875,171 @@ pub fn read_csv<P: AsRef<Path>>(path: P) -> Result<Vec<Record>, Box<dyn Error>>
     Ok(records?)
 }
 
-fn trim_and_parse_tx_type<'de, D>(deserializer: D) -> Result<TxType, D::Error>
+pub fn read_json_lines<P: AsRef<Path>>(path: P) -> Result<Vec<Record>, Box<dyn Error>> {
+    let file = open_input(path)?;
+    // One JSON object per line; serde_json reports the offending line on errors.
+    let records = serde_json::Deserializer::from_reader(BufReader::new(file))
+        .into_iter::<Record>()
+        .collect::<Result<Vec<_>, _>>();
+
+    Ok(records?)
+}
+
+/// Parses a single record from a message payload, e.g. from a message queue. CSV
+/// payloads are one headerless row in `type,client,tx,amount` order.
+pub fn parse_record(payload: &[u8], format: InputFormat) -> Result<Record, Box<dyn Error>> {
+    match format {
+        InputFormat::Csv => {
+            let mut rdr = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(payload);
+            match rdr.deserialize::<Record>().next() {
+                Some(record) => Ok(record?),
+                None => Err("empty payload".into()),
+            }
+        }
+        InputFormat::JsonLines => Ok(serde_json::from_slice(payload)?),
+    }
+}
+
+fn trim_to_string<'de, D>(deserializer: D) -> Result<String, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
-    let s: String = String::deserialize(deserializer)?;
-    let trimmed = s.trim();
-    match trimmed.to_lowercase().as_str() {
-        "deposit" => Ok(TxType::Deposit),
-        "withdrawal" => Ok(TxType::Withdrawal),
-        "dispute" => Ok(TxType::Dispute),
-        "resolve" => Ok(TxType::Resolve),
-        "chargeback" => Ok(TxType::Chargeback),
-        _ => Err(serde::de::Error::unknown_variant(
-            trimmed,
-            &["deposit", "withdrawal", "dispute", "resolve", "chargeback"],
-        )),
-    }
-}
-
-fn trim_and_parse_u32<'de, D>(deserializer: D) -> Result<u32, D::Error>
+    // CSV fields always arrive as strings, but JSON values may be numbers or null, so
+    // both are normalised into a trimmed string before the shared parsing below.
+    struct FieldVisitor;
+
+    impl Visitor<'_> for FieldVisitor {
+        type Value = String;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a string or a number")
+        }
+
+        fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<String, E> {
+            Ok(v.trim().to_owned())
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<String, E> {
+            Ok(v.to_string())
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<String, E> {
+            Ok(v.to_string())
+        }
+
+        fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<String, E> {
+            Ok(v.to_string())
+        }
+
+        fn visit_unit<E: serde::de::Error>(self) -> Result<String, E> {
+            Ok(String::new())
+        }
+
+        fn visit_none<E: serde::de::Error>(self) -> Result<String, E> {
+            Ok(String::new())
+        }
+    }
+
+    deserializer.deserialize_any(FieldVisitor)
+}
+
+fn trim_and_parse_tx_type<'de, D>(deserializer: D) -> Result<TxType, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
-    let s: String = String::deserialize(deserializer)?;
-    let trimmed = s.trim();
-    trimmed.parse::<u32>().map_err(serde::de::Error::custom)
+    let s = trim_to_string(deserializer)?;
+    let trimmed = s.as_str();
+    parse_tx_type_bytes(trimmed.as_bytes()).ok_or_else(|| {
+        serde::de::Error::invalid_value(
+            serde::de::Unexpected::Str(trimmed),
+            &"a transaction type such as deposit, or the name of a custom type",
+        )
+    })
 }
 
-fn trim_and_parse_u16<'de, D>(deserializer: D) -> Result<u16, D::Error>
+fn trim_and_parse_id<'de, D, T>(deserializer: D) -> Result<T, D::Error>
 where
     D: serde::Deserializer<'de>,
+    T: FromStr<Err = ParseIntError>,
 {
-    let s: String = String::deserialize(deserializer)?;
-    let trimmed = s.trim();
-    trimmed.parse::<u16>().map_err(serde::de::Error::custom)
+    let s = trim_to_string(deserializer)?;
+    let trimmed = s.as_str();
+    trimmed.parse::<T>().map_err(serde::de::Error::custom)
 }
 
-fn trim_and_parse_f32_4dp<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error>
+pub(crate) fn trim_and_parse_4dp<'de, D>(deserializer: D) -> Result<Option<Money>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
-    let s: String = String::deserialize(deserializer)?;
-    let trimmed = s.trim();
+    let s = trim_to_string(deserializer)?;
+    parse_amount(&s).map_err(serde::de::Error::custom)
+}
+
+/// Parses a trimmed amount, rounded to four decimal places. Empty means no amount.
+pub(crate) fn parse_amount(trimmed: &str) -> Result<Option<Money>, String> {
     if trimmed.is_empty() {
         Ok(None)
     } else {
-        let value: f32 = trimmed.parse().map_err(serde::de::Error::custom)?;
+        let value = trimmed.parse::<f32>().map_err(|e| e.to_string())?;
+        if !value.is_finite() {
+            return Err(format!("amount '{trimmed}' is not a finite number"));
+        }
         let rounded = (value * 10_000.0).round() / 10_000.0;
-        Ok(Some(rounded))
+        Ok(Some(Money::new(rounded)))
+    }
+}
+
+fn trim_and_parse_timestamp<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = trim_to_string(deserializer)?;
+    parse_timestamp(&s).map_err(serde::de::Error::custom)
+}
+
+/// Parses a trimmed timestamp, as RFC 3339 or seconds since the Unix epoch. Empty means no
+/// timestamp.
+pub(crate) fn parse_timestamp(trimmed: &str) -> Result<Option<DateTime<Utc>>, String> {
+    if trimmed.is_empty() {
+        return Ok(None);
+    }
+
+    if let Ok(seconds) = trimmed.parse::<i64>() {
+        return DateTime::from_timestamp(seconds, 0)
+            .map(Some)
+            .ok_or_else(|| format!("timestamp {seconds} is out of range"));
+    }
+
+    DateTime::parse_from_rfc3339(trimmed)
+        .map(|timestamp| Some(timestamp.with_timezone(&Utc)))
+        .map_err(|e| e.to_string())
+}
+
+fn trim_and_parse_currency<'de, D>(deserializer: D) -> Result<Option<Currency>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = trim_to_string(deserializer)?;
+    parse_currency(&s).map_err(serde::de::Error::custom)
+}
+
+fn trim_and_parse_text<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = trim_to_string(deserializer)?;
+    Ok(Some(s).filter(|s| !s.is_empty()))
+}
+
+/// Parses a trimmed currency code. Empty means the base currency.
+pub(crate) fn parse_currency(trimmed: &str) -> Result<Option<Currency>, String> {
+    if trimmed.is_empty() {
+        return Ok(None);
     }
+
+    trimmed.parse().map(Some)
 }
 
 #[cfg(test)]
@@ -96,36 +1052,468 @@ mod tests {
         let expected_records = vec![
             Record {
                 r#type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Some(1.0),
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Some(Money::new(1.0)),
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
             },
             Record {
                 r#type: TxType::Deposit,
-                client: 2,
-                tx: 2,
-                amount: Some(2.0),
+                client: ClientId(2),
+                tx: TxId(2),
+                amount: Some(Money::new(2.0)),
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
             },
             Record {
                 r#type: TxType::Deposit,
-                client: 1,
-                tx: 3,
-                amount: Some(2.0),
+                client: ClientId(1),
+                tx: TxId(3),
+                amount: Some(Money::new(2.0)),
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
             },
             Record {
                 r#type: TxType::Withdrawal,
-                client: 1,
-                tx: 4,
-                amount: Some(1.5),
+                client: ClientId(1),
+                tx: TxId(4),
+                amount: Some(Money::new(1.5)),
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
             },
             Record {
                 r#type: TxType::Withdrawal,
-                client: 2,
-                tx: 5,
-                amount: Some(3.0),
+                client: ClientId(2),
+                tx: TxId(5),
+                amount: Some(Money::new(3.0)),
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
             },
         ];
 
         assert_eq!(records, expected_records);
     }
+
+    #[test]
+    fn test_read_json_lines() {
+        let records = read_json_lines("test-inputs/test_input.jsonl").unwrap();
+        let expected_records = read_csv("test-inputs/test_input.csv").unwrap();
+
+        assert_eq!(records, expected_records);
+    }
+
+    #[test]
+    fn read_rows_keeps_malformed_rows() {
+        let path = std::env::temp_dir().join("tx_accounts_read_rows_malformed.csv");
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\ndeposit,1,1,1.0\nre-fund,1,2,1.0\ndeposit,x,3,1.0\n",
+        )
+        .unwrap();
+
+        let rows = read_rows(&path, InputFormat::Csv).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].line, 2);
+        assert!(rows[0].record.is_ok());
+        assert_eq!(rows[1].line, 3);
+        assert!(rows[1].record.is_err());
+        assert_eq!(rows[2].line, 4);
+        assert!(rows[2].record.is_err());
+
+        let rows = read_rows_from(
+            "type,client,tx,amount\ndeposit,1,1,NaN\ndeposit,1,2,inf\n".as_bytes(),
+            InputFormat::Csv,
+            &CsvDialect::default(),
+        )
+        .unwrap();
+        assert!(rows.iter().all(|row| row.record.is_err()), "{rows:?}");
+    }
+
+    #[test]
+    fn read_rows_json_lines() {
+        let rows = read_rows("test-inputs/test_input.jsonl", InputFormat::JsonLines).unwrap();
+        let records: Vec<Record> = rows.into_iter().map(|row| row.record.unwrap()).collect();
+
+        assert_eq!(records, read_csv("test-inputs/test_input.csv").unwrap());
+    }
+
+    #[test]
+    fn parse_record_payloads() {
+        let expected = Record {
+            r#type: TxType::Withdrawal,
+            client: ClientId(2),
+            tx: TxId(5),
+            amount: Some(Money::new(3.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        };
+
+        let csv = parse_record(b" withdrawal,2, 5,3.0", InputFormat::Csv).unwrap();
+        assert_eq!(csv, expected);
+
+        let json = parse_record(
+            br#"{"type":"withdrawal","client":2,"tx":5,"amount":"3.0"}"#,
+            InputFormat::JsonLines,
+        )
+        .unwrap();
+        assert_eq!(json, expected);
+
+        let open = parse_record(b"open_account,3,7,", InputFormat::Csv).unwrap();
+        assert_eq!(open.r#type, TxType::OpenAccount);
+        assert_eq!(open.amount, None);
+
+        let unlock = parse_record(b"unlock,3,0,", InputFormat::Csv).unwrap();
+        assert_eq!(unlock.r#type, TxType::Unlock);
+
+        assert!(parse_record(b"", InputFormat::Csv).is_err());
+        assert!(parse_record(b"{}", InputFormat::JsonLines).is_err());
+    }
+
+    #[test]
+    fn timestamps_and_sort_by_time() {
+        let path = std::env::temp_dir().join("tx_accounts_timestamps.csv");
+        std::fs::write(
+            &path,
+            "type,client,tx,amount,timestamp\n\
+             deposit,1,3,1.0,2024-01-02T10:00:00Z\n\
+             deposit,1,2,1.0,2024-01-02T11:00:00+01:00\n\
+             deposit,1,1,1.0,1704189600\n\
+             dispute,1,1,,\n\
+             deposit,1,4,1.0,yesterday\n",
+        )
+        .unwrap();
+        let mut rows = read_rows(&path, InputFormat::Csv).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let ten = DateTime::parse_from_rfc3339("2024-01-02T10:00:00Z").unwrap();
+        let first = rows[0].record.as_ref().unwrap();
+        assert_eq!(first.timestamp, Some(ten.with_timezone(&Utc)));
+        assert!(rows[3].record.as_ref().unwrap().timestamp.is_none());
+        assert!(rows[4].record.is_err());
+
+        sort_by_time(&mut rows);
+        let lines: Vec<u64> = rows.iter().map(|row| row.line).collect();
+        // All three deposits happened at 10:00 UTC, so the tx id decides.
+        assert_eq!(lines, vec![6, 5, 4, 3, 2]);
+    }
+
+    #[test]
+    fn write_records_round_trip() {
+        let records = vec![
+            Record {
+                r#type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(7),
+                amount: Some(Money::new(1.5)),
+                timestamp: "2024-01-03T08:00:00Z".parse().ok(),
+                currency: "EUR".parse().ok(),
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
+            },
+            Record {
+                r#type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(7),
+                amount: Some(Money::new(0.5)),
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: Some("4837".to_string()),
+                memo: None,
+                reference: None,
+                merchant_id: None,
+            },
+            Record {
+                r#type: TxType::OpenAccount,
+                client: ClientId(2),
+                tx: TxId(0),
+                amount: None,
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
+            },
+        ];
+
+        let path = std::env::temp_dir().join("tx_accounts_write_records.csv");
+        write_records(File::create(&path).unwrap(), &records).unwrap();
+        let read = read_csv(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn read_rows_with_dialect() {
+        let path = std::env::temp_dir().join("tx_accounts_dialect.csv");
+        std::fs::write(
+            &path,
+            "client_id;transaction_type;tx;amount\n1;deposit;1;1.5\n2; withdrawal ;2;3\n",
+        )
+        .unwrap();
+        let mut columns = BTreeMap::new();
+        for mapping in ["type=transaction_type", "client = client_id"] {
+            let mapping: ColumnMapping = mapping.parse().unwrap();
+            columns.insert(mapping.field, mapping.column);
+        }
+        let dialect = CsvDialect {
+            delimiter: ';',
+            columns,
+            ..Default::default()
+        };
+        let mapped = read_rows_with(&path, InputFormat::Csv, &dialect).unwrap();
+
+        std::fs::write(&path, "deposit;1;1;1.5\nwithdrawal;2;2;3\n").unwrap();
+        let dialect = CsvDialect {
+            delimiter: ';',
+            has_headers: false,
+            ..Default::default()
+        };
+        let headerless = read_rows_with(&path, InputFormat::Csv, &dialect).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let records = |rows: Vec<InputRow>| -> Vec<Record> {
+            rows.into_iter().map(|row| row.record.unwrap()).collect()
+        };
+        let headerless = records(headerless);
+        assert_eq!(headerless[0].r#type, TxType::Deposit);
+        assert_eq!(headerless[1].amount, Some(Money::new(3.0)));
+        assert_eq!(records(mapped), headerless);
+
+        assert!("kind=transaction_type".parse::<ColumnMapping>().is_err());
+        assert!("type".parse::<ColumnMapping>().is_err());
+    }
+
+    #[test]
+    fn input_format_from_path() {
+        assert_eq!(InputFormat::from_path("tx.csv"), Some(InputFormat::Csv));
+        assert_eq!(
+            InputFormat::from_path("tx.JSONL"),
+            Some(InputFormat::JsonLines)
+        );
+        assert_eq!(
+            InputFormat::from_path("tx.ndjson"),
+            Some(InputFormat::JsonLines)
+        );
+        assert_eq!(InputFormat::from_path("tx.csv.gz"), Some(InputFormat::Csv));
+        assert_eq!(
+            InputFormat::from_path("tx.ndjson.zst"),
+            Some(InputFormat::JsonLines)
+        );
+        assert_eq!(InputFormat::from_path("tx.gz"), None);
+        assert_eq!(InputFormat::from_path("tx.txt"), None);
+        assert_eq!(InputFormat::from_path("tx"), None);
+        assert_eq!(
+            InputFormat::from_path("https://example.com/tx.csv.gz?X-Amz-Signature=a.b"),
+            Some(InputFormat::Csv)
+        );
+    }
+
+    #[test]
+    fn read_inputs_as_one_stream() {
+        let dir = std::env::temp_dir().join("tx_accounts_inputs");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(
+            dir.join("b.csv"),
+            "type,client,tx,amount\nwithdrawal,1,2,1.0\nbad\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("a.csv"),
+            "type,client,tx,amount\ndeposit,1,1,2.0\n",
+        )
+        .unwrap();
+
+        let paths = expand_inputs(&[dir.join("*.csv")]).unwrap();
+        assert_eq!(paths, vec![dir.join("a.csv"), dir.join("b.csv")]);
+        assert!(expand_inputs(&[dir.join("*.jsonl")]).is_err());
+        assert_eq!(
+            expand_inputs(&[dir.join("b.csv"), dir.join("a.csv")]).unwrap(),
+            vec![dir.join("b.csv"), dir.join("a.csv")]
+        );
+        let urls = vec![PathBuf::from("https://example.com/tx.csv?sig=a")];
+        assert_eq!(expand_inputs(&urls).unwrap(), urls);
+
+        let rows = read_inputs(&paths, InputFormat::Csv, &CsvDialect::default()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let lines: Vec<u64> = rows.iter().map(|row| row.line).collect();
+        assert_eq!(lines, vec![2, 4, 5]);
+        assert_eq!(rows[1].record.as_ref().unwrap().tx, TxId(2));
+        assert!(rows[2].record.is_err());
+    }
+
+    #[test]
+    fn fast_parse_matches_serde() {
+        let with_headers = concat!(
+            "type, client,tx,amount,timestamp,note\n",
+            "deposit,1,1,1.23456,,x\n",
+            " WITHDRAWAL , +2 , 3 ,  0.5 ,1700000000,\n",
+            "dispute,1,1,,2024-01-02T03:04:05Z,\n",
+            "refund,1,4,1.0,,\n",
+            "deposit,70000,5,1.0,,\n",
+            "deposit,1,-6,1.0,,\n",
+            "deposit,1,7,NaN,,\n",
+            "deposit,1,8,abc,,\n",
+            "deposit,1,9,1.0,yesterday,\n",
+            "deposit,1,10\n",
+            "deposit,,11,1.0,,\n",
+            "open_account,3,12,,,\n",
+        );
+        let without_amount = "type,client,tx\ndeposit,1,1\nlock,2,2\n";
+        let with_currency = "type,client,tx,amount,currency,to_currency\n\
+                             deposit,1,1,1.0, eur,\n\
+                             deposit,1,2,1.0,,\n\
+                             dispute,1,1,,EUR,\n\
+                             deposit,1,3,1.0,EURO,\n\
+                             convert,1,4,1.0,EUR,usd\n";
+        let headerless = "deposit,1,1,2.5\nresolve,1,1\nunlock,2,2,,100\nx,1,1\n";
+        let assertion = "assert,1,3,2.5,,,,4.0\n";
+        let with_reason = "type,client,tx,amount,reason_code,merchant_id,memo\n\
+                           dispute,1,1,30.0, 10.4 ,m-7, chargeback requested \n\
+                           dispute,1,2,,,,\n";
+
+        for (input, has_headers) in [
+            (with_headers, true),
+            (without_amount, true),
+            (with_currency, true),
+            (headerless, false),
+            (assertion, false),
+            (with_reason, true),
+        ] {
+            let dialect = CsvDialect {
+                has_headers,
+                ..Default::default()
+            };
+            let fast = CsvDialect {
+                fast_parse: true,
+                ..dialect.clone()
+            };
+            let serde = read_rows_from(input.as_bytes(), InputFormat::Csv, &dialect).unwrap();
+            let fast = read_rows_from(input.as_bytes(), InputFormat::Csv, &fast).unwrap();
+
+            assert_eq!(serde.len(), fast.len());
+            for (serde, fast) in serde.iter().zip(&fast) {
+                assert_eq!(serde.line, fast.line);
+                match (&serde.record, &fast.record) {
+                    (Ok(serde), Ok(fast)) => assert_eq!(serde, fast),
+                    (Err(_), Err(_)) => {}
+                    _ => panic!("line {}: {serde:?} != {fast:?}", serde.line),
+                }
+            }
+        }
+
+        let rows = read_rows_from(
+            with_currency.as_bytes(),
+            InputFormat::Csv,
+            &CsvDialect::default(),
+        )
+        .unwrap();
+        let eur = "EUR".parse().ok();
+        assert_eq!(rows[0].record.as_ref().unwrap().currency, eur);
+        assert_eq!(rows[1].record.as_ref().unwrap().currency, None);
+        assert_eq!(rows[2].record.as_ref().unwrap().currency, eur);
+        assert!(rows[3].record.is_err());
+        let convert = rows[4].record.as_ref().unwrap();
+        assert_eq!(convert.r#type, TxType::Convert);
+        assert_eq!(convert.to_currency, "USD".parse().ok());
+
+        let dialect = CsvDialect {
+            has_headers: false,
+            ..Default::default()
+        };
+        let rows = read_rows_from(assertion.as_bytes(), InputFormat::Csv, &dialect).unwrap();
+        let assert = rows[0].record.as_ref().unwrap();
+        assert_eq!(assert.r#type, TxType::Assert);
+        assert_eq!(
+            (assert.amount, assert.total),
+            (Some(Money::new(2.5)), Some(Money::new(4.0)))
+        );
+
+        let rows = read_rows_from(
+            with_reason.as_bytes(),
+            InputFormat::Csv,
+            &CsvDialect::default(),
+        )
+        .unwrap();
+        let partial = rows[0].record.as_ref().unwrap();
+        assert_eq!(partial.amount, Some(Money::new(30.0)));
+        assert_eq!(partial.reason_code.as_deref(), Some("10.4"));
+        assert_eq!(rows[1].record.as_ref().unwrap().reason_code, None);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn read_rows_compressed() {
+        use std::io::Write;
+
+        let csv = std::fs::read("test-inputs/test_input.csv").unwrap();
+        let expected = read_rows("test-inputs/test_input.csv", InputFormat::Csv).unwrap();
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&csv).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(csv.as_slice(), 0).unwrap();
+
+        for (name, contents) in [
+            ("tx_accounts_input.csv.gz", gzip),
+            ("tx_accounts_input.csv.zst", zstd),
+        ] {
+            let path = std::env::temp_dir().join(name);
+            std::fs::write(&path, contents).unwrap();
+            let rows = read_rows(&path, InputFormat::Csv).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(rows, expected, "{name}");
+        }
+    }
 }
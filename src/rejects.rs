@@ -0,0 +1,273 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    io::{Read, Write},
+    thread,
+};
+
+use crate::{
+    amounts::Money,
+    records::{InputRow, Record, TxType},
+    shared::SharedEngine,
+    transaction::{ClientId, Engine, RejectionReason, TxId},
+};
+
+/// A skipped input row together with the reason it was not applied.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Reject {
+    pub line: u64,
+    pub reason: RejectionReason,
+    pub r#type: Option<TxType>,
+    pub client: Option<ClientId>,
+    pub tx: Option<TxId>,
+    pub amount: Option<Money>,
+    pub detail: String,
+}
+
+/// Applies every parsed row to the engine and collects the rows that were skipped,
+/// including the ones that could not be parsed at all.
+pub fn process_rows(engine: &mut Engine, rows: Vec<InputRow>) -> Vec<Reject> {
+    let mut rejects = Vec::new();
+    engine.reserve(rows.len());
+
+    for row in rows {
+        if let Err(reject) = process_row(engine, row) {
+            rejects.push(reject);
+        }
+    }
+
+    rejects
+}
+
+/// Applies a single row, or says why it was skipped.
+pub fn process_row(engine: &mut Engine, row: InputRow) -> Result<(), Reject> {
+    match row.record {
+        Ok(record) => {
+            let rejected = record_fields(row.line, &record);
+            engine.apply(record).map_err(rejected)
+        }
+        Err(detail) => Err(malformed(row.line, detail)),
+    }
+}
+
+/// Like [`process_rows`], but applies the rows on `threads` worker threads. Rows are split
+/// by client, so the transactions of each client are still applied in input order, while
+/// transactions of different clients may be applied in any order. Rejects are returned in
+/// input order.
+pub fn process_rows_shared(
+    engine: &SharedEngine,
+    rows: Vec<InputRow>,
+    threads: usize,
+) -> Vec<Reject> {
+    let threads = threads.max(1);
+    let mut rejects = Vec::new();
+    let mut queues: Vec<Vec<(u64, Record)>> = (0..threads).map(|_| Vec::new()).collect();
+
+    for row in rows {
+        match row.record {
+            Ok(record) => queues[record.client.0 as usize % threads].push((row.line, record)),
+            Err(detail) => rejects.push(malformed(row.line, detail)),
+        }
+    }
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = queues
+            .into_iter()
+            .map(|queue| {
+                scope.spawn(move || {
+                    let mut rejects = Vec::new();
+                    for (line, record) in queue {
+                        let rejected = record_fields(line, &record);
+                        if let Err(reason) = engine.apply(record) {
+                            rejects.push(rejected(reason));
+                        }
+                    }
+
+                    rejects
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            rejects.extend(worker.join().expect("worker thread panicked"));
+        }
+    });
+
+    rejects.sort_by_key(|reject| reject.line);
+    rejects
+}
+
+// Captures what a reject needs to know about a record before it is moved into the engine.
+pub(crate) fn record_fields(line: u64, record: &Record) -> impl FnOnce(RejectionReason) -> Reject {
+    let (r#type, client, tx, amount) = (record.r#type, record.client, record.tx, record.amount);
+    move |reason| Reject {
+        line,
+        reason,
+        r#type: Some(r#type),
+        client: Some(client),
+        tx: Some(tx),
+        amount,
+        detail: String::new(),
+    }
+}
+
+pub(crate) fn malformed(line: u64, detail: String) -> Reject {
+    Reject {
+        line,
+        reason: RejectionReason::Malformed,
+        r#type: None,
+        client: None,
+        tx: None,
+        amount: None,
+        detail,
+    }
+}
+
+pub fn write_rejects<W: Write>(writer: W, rejects: &[Reject]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::WriterBuilder::new().from_writer(writer);
+    for reject in rejects {
+        wtr.serialize(reject)?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// Reads a report written by [`write_rejects`].
+pub fn read_rejects<R: Read>(reader: R) -> Result<Vec<Reject>, Box<dyn Error>> {
+    let rejects = csv::Reader::from_reader(reader)
+        .deserialize::<Reject>()
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rejects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{RawClientId, RawTxId};
+
+    fn row(line: u64, r#type: TxType, client: ClientId, tx: TxId, amount: Option<f32>) -> InputRow {
+        InputRow {
+            line,
+            record: Ok(Record {
+                r#type,
+                client,
+                tx,
+                amount: amount.map(Money::new),
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn process_rows_collects_rejects() {
+        let rows = vec![
+            row(2, TxType::Deposit, ClientId(1), TxId(1), Some(10.0)),
+            row(3, TxType::Deposit, ClientId(1), TxId(1), Some(10.0)),
+            row(4, TxType::Withdrawal, ClientId(1), TxId(2), Some(50.0)),
+            row(5, TxType::Dispute, ClientId(1), TxId(99), None),
+            InputRow {
+                line: 6,
+                record: Err("invalid digit found in string".to_owned()),
+            },
+            row(7, TxType::Dispute, ClientId(1), TxId(1), None),
+            row(8, TxType::Chargeback, ClientId(1), TxId(1), None),
+            row(9, TxType::Deposit, ClientId(1), TxId(3), Some(10.0)),
+        ];
+
+        let mut engine = Engine::new();
+        let rejects = process_rows(&mut engine, rows);
+
+        let reasons: Vec<(u64, RejectionReason)> =
+            rejects.iter().map(|r| (r.line, r.reason)).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (3, RejectionReason::DuplicateTx),
+                (4, RejectionReason::InsufficientFunds),
+                (5, RejectionReason::TxNeverSeen),
+                (6, RejectionReason::Malformed),
+                (9, RejectionReason::AccountLocked),
+            ]
+        );
+        assert_eq!(rejects[3].detail, "invalid digit found in string");
+    }
+
+    #[test]
+    fn process_rows_shared_matches_serial_per_client() {
+        let rows: Vec<InputRow> = (0..200)
+            .map(|i| {
+                row(
+                    i + 2,
+                    TxType::Deposit,
+                    ClientId((i % 7) as RawClientId),
+                    TxId(i as RawTxId),
+                    Some(1.0),
+                )
+            })
+            .chain([
+                row(
+                    300,
+                    TxType::Withdrawal,
+                    ClientId(3),
+                    TxId(1000),
+                    Some(1000.0),
+                ),
+                row(301, TxType::Deposit, ClientId(4), TxId(4), Some(1.0)),
+                InputRow {
+                    line: 302,
+                    record: Err("bad row".to_owned()),
+                },
+            ])
+            .collect();
+
+        let engine = SharedEngine::new(4);
+        let rejects = process_rows_shared(&engine, rows.clone(), 3);
+
+        let mut serial = Engine::new();
+        let expected = process_rows(&mut serial, rows);
+        assert_eq!(rejects, expected);
+        assert_eq!(engine.into_accounts(), serial.into_accounts());
+    }
+
+    #[test]
+    fn write_rejects_csv() {
+        let rejects = vec![
+            Reject {
+                line: 3,
+                reason: RejectionReason::InsufficientFunds,
+                r#type: Some(TxType::Withdrawal),
+                client: Some(ClientId(1)),
+                tx: Some(TxId(2)),
+                amount: Some(Money::new(50.0)),
+                detail: String::new(),
+            },
+            Reject {
+                line: 4,
+                reason: RejectionReason::Malformed,
+                r#type: None,
+                client: None,
+                tx: None,
+                amount: None,
+                detail: "bad row".to_owned(),
+            },
+        ];
+
+        let mut out = Vec::new();
+        write_rejects(&mut out, &rejects).unwrap();
+
+        let expected = "line,reason,type,client,tx,amount,detail\n\
+                        3,insufficient_funds,withdrawal,1,2,50.0,\n\
+                        4,malformed,,,,,bad row\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+        assert_eq!(read_rejects(expected.as_bytes()).unwrap(), rejects);
+    }
+}
@@ -0,0 +1,230 @@
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::{self, File},
+    io::{BufReader, Write},
+    path::Path,
+};
+
+use crate::{
+    amounts::{serialize_4dp, Money},
+    config::Config,
+    transaction::{ClientActivity, ClientId},
+};
+
+/// Describes a finished run: when it ran, how it was configured and what it did to each
+/// client. Manifests of many runs are consolidated into periodic reports.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunManifest {
+    pub run_date: NaiveDate,
+    pub config: Config,
+    pub activity: Vec<ClientActivity>,
+}
+
+impl RunManifest {
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct QuarterlyTotals {
+    pub quarter: String,
+    pub client: ClientId,
+    #[serde(serialize_with = "serialize_4dp")]
+    pub net_deposited: Money,
+    #[serde(serialize_with = "serialize_4dp")]
+    pub net_withdrawn: Money,
+    pub chargebacks: u64,
+    #[serde(serialize_with = "serialize_4dp")]
+    pub charged_back: Money,
+}
+
+/// Sums the activity of every run manifest (`*.json`) in `runs_dir` whose run date lies
+/// within `from..=to`, per quarter and client.
+pub fn consolidate<P: AsRef<Path>>(
+    runs_dir: P,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<QuarterlyTotals>, Box<dyn Error>> {
+    let mut totals: BTreeMap<(String, ClientId), QuarterlyTotals> = BTreeMap::new();
+
+    for entry in fs::read_dir(runs_dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+
+        let manifest = RunManifest::read(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+        if manifest.run_date < from || manifest.run_date > to {
+            continue;
+        }
+
+        let quarter = quarter_of(manifest.run_date);
+        for activity in manifest.activity {
+            let total = totals
+                .entry((quarter.clone(), activity.client))
+                .or_insert_with(|| QuarterlyTotals {
+                    quarter: quarter.clone(),
+                    client: activity.client,
+                    net_deposited: Money::ZERO,
+                    net_withdrawn: Money::ZERO,
+                    chargebacks: 0,
+                    charged_back: Money::ZERO,
+                });
+
+            total.net_deposited += activity.net_deposited;
+            total.net_withdrawn += activity.net_withdrawn;
+            total.chargebacks += activity.chargebacks;
+            total.charged_back += activity.charged_back;
+        }
+    }
+
+    Ok(totals.into_values().collect())
+}
+
+pub fn write_quarterly_totals<W: Write>(
+    writer: W,
+    totals: &[QuarterlyTotals],
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::WriterBuilder::new().from_writer(writer);
+    for total in totals {
+        wtr.serialize(total)?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+fn quarter_of(date: NaiveDate) -> String {
+    format!("{}-Q{}", date.year(), date.month0() / 3 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::EngineConfig, integrity::IntegrityPolicy, output::OutputFormat,
+        records::InputFormat,
+    };
+
+    fn manifest(run_date: &str, client: ClientId, deposited: f32) -> RunManifest {
+        RunManifest {
+            run_date: run_date.parse().unwrap(),
+            config: Config {
+                inputs: vec!["transactions.csv".into()],
+                input_format: InputFormat::Csv,
+                csv_dialect: Default::default(),
+                sort_by_time: false,
+                output_format: OutputFormat::Csv,
+                precision: Default::default(),
+                output: None,
+                integrity_policy: IntegrityPolicy::Report,
+                corrections: None,
+                rejects: None,
+                reject_stats: None,
+                reject_stats_per_client: false,
+                manifest: None,
+                run_date: None,
+                engine: EngineConfig::default(),
+                journal: None,
+                audit_log: None,
+                tx_store: None,
+                max_memory: None,
+                sample: None,
+                state_dir: None,
+                dispute_lookback: None,
+                strict: false,
+                threads: 1,
+                pipeline: None,
+                checkpoint: None,
+                resume: false,
+                backend: None,
+                metrics_file: None,
+                rates: None,
+                deferred: None,
+                ledger: None,
+                ledger_format: Default::default(),
+                ledger_currency: None,
+                tx_status: None,
+                open_disputes: None,
+                changed_only: false,
+                rules: None,
+                limits: None,
+                blocklist: None,
+                accounts: None,
+                directory: Default::default(),
+                sanity: Default::default(),
+            },
+            activity: vec![ClientActivity {
+                client,
+                net_deposited: Money::new(deposited),
+                net_withdrawn: Money::new(1.0),
+                chargebacks: 1,
+                charged_back: Money::new(2.0),
+                fees: Money::ZERO,
+            }],
+        }
+    }
+
+    #[test]
+    fn consolidate_runs_per_quarter() {
+        let runs_dir = std::env::temp_dir().join("tx_accounts_consolidate_runs");
+        let _ = fs::remove_dir_all(&runs_dir);
+        fs::create_dir_all(&runs_dir).unwrap();
+
+        manifest("2024-01-15", ClientId(1), 10.0)
+            .write(runs_dir.join("a.json"))
+            .unwrap();
+        manifest("2024-03-31", ClientId(1), 5.0)
+            .write(runs_dir.join("b.json"))
+            .unwrap();
+        manifest("2024-04-01", ClientId(1), 7.0)
+            .write(runs_dir.join("c.json"))
+            .unwrap();
+        manifest("2024-02-01", ClientId(2), 3.0)
+            .write(runs_dir.join("d.json"))
+            .unwrap();
+        manifest("2023-12-31", ClientId(1), 100.0)
+            .write(runs_dir.join("e.json"))
+            .unwrap();
+        fs::write(runs_dir.join("notes.txt"), "not a manifest").unwrap();
+
+        let totals = consolidate(
+            &runs_dir,
+            "2024-01-01".parse().unwrap(),
+            "2024-06-30".parse().unwrap(),
+        )
+        .unwrap();
+        fs::remove_dir_all(&runs_dir).unwrap();
+
+        let mut out = Vec::new();
+        write_quarterly_totals(&mut out, &totals).unwrap();
+
+        let expected = "quarter,client,net_deposited,net_withdrawn,chargebacks,charged_back\n\
+                        2024-Q1,1,15.0000,2.0000,2,4.0000\n\
+                        2024-Q1,2,3.0000,1.0000,1,2.0000\n\
+                        2024-Q2,1,7.0000,1.0000,1,2.0000\n";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let path = std::env::temp_dir().join("tx_accounts_manifest_round_trip.json");
+        let manifest = manifest("2024-05-06", ClientId(3), 1.5);
+
+        manifest.write(&path).unwrap();
+        let read = RunManifest::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read, manifest);
+    }
+}
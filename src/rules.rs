@@ -0,0 +1,264 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    path::Path,
+};
+
+use crate::{
+    amounts::Money,
+    currency::Currency,
+    hash::FastMap,
+    records::{Record, TxType},
+    rejects::Reject,
+    transaction::{ClientId, RejectionReason, TxId},
+};
+
+/// Limits checked before deposits and withdrawals are applied, read from a TOML file
+/// with one `[[rules]]` table per rule:
+///
+/// ```toml
+/// [[rules]]
+/// id = "W1"
+/// kind = "max-withdrawal"
+/// amount = 1000.0
+/// ```
+///
+/// Limits hold per client and currency. Rules over a time window only count records with
+/// a timestamp, and records without one never break them.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct RuleSet {
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Rule {
+    /// Reported with the records the rule rejects.
+    pub id: String,
+    #[serde(flatten)]
+    pub limit: Limit,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Limit {
+    /// Largest amount a single withdrawal may have.
+    MaxWithdrawal { amount: Money },
+    /// Largest amount a client may withdraw per calendar day in UTC.
+    MaxDailyWithdrawal { amount: Money },
+    /// Most deposits a client may make within an hour.
+    MaxDepositsPerHour { count: usize },
+}
+
+/// A record a rule rejected.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct RuleViolation {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub rule: String,
+}
+
+/// What the rules over a time window need to know about the past records of a client in
+/// one currency.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Velocity {
+    day: Option<NaiveDate>,
+    withdrawn_today: Money,
+    /// Timestamps of the deposits of the last hour.
+    deposits: VecDeque<DateTime<Utc>>,
+}
+
+impl RuleSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let rules: Self = toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+
+        for rule in &rules.rules {
+            let valid = match rule.limit {
+                Limit::MaxWithdrawal { amount } | Limit::MaxDailyWithdrawal { amount } => {
+                    amount.is_finite() && amount >= 0.0
+                }
+                Limit::MaxDepositsPerHour { .. } => true,
+            };
+            if !valid {
+                return Err(
+                    format!("{}: invalid limit of rule {}", path.display(), rule.id).into(),
+                );
+            }
+        }
+
+        Ok(rules)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The first rule `record` would break, given the earlier records of the client in
+    /// `velocity`.
+    pub(crate) fn check(&self, record: &Record, velocity: Option<&Velocity>) -> Option<&Rule> {
+        let amount = record.amount.unwrap_or_default();
+        self.rules
+            .iter()
+            .find(|rule| match (rule.limit, record.r#type) {
+                (Limit::MaxWithdrawal { amount: max }, TxType::Withdrawal) => amount > max,
+                (Limit::MaxDailyWithdrawal { amount: max }, TxType::Withdrawal) => {
+                    let Some(timestamp) = record.timestamp else {
+                        return false;
+                    };
+                    let withdrawn = velocity
+                        .filter(|velocity| velocity.day == Some(timestamp.date_naive()))
+                        .map_or(Money::ZERO, |velocity| velocity.withdrawn_today);
+                    withdrawn + amount > max
+                }
+                (Limit::MaxDepositsPerHour { count }, TxType::Deposit) => {
+                    let Some(timestamp) = record.timestamp else {
+                        return false;
+                    };
+                    velocity.map_or(0, |velocity| velocity.deposits_since(timestamp)) >= count
+                }
+                _ => false,
+            })
+    }
+}
+
+impl Velocity {
+    /// Takes an applied deposit or withdrawal into account.
+    pub(crate) fn record(&mut self, record: &Record) {
+        let Some(timestamp) = record.timestamp else {
+            return;
+        };
+
+        match record.r#type {
+            TxType::Withdrawal => {
+                let day = timestamp.date_naive();
+                if self.day != Some(day) {
+                    self.day = Some(day);
+                    self.withdrawn_today = Money::ZERO;
+                }
+                self.withdrawn_today += record.amount.unwrap_or_default();
+            }
+            TxType::Deposit => {
+                let hour_ago = timestamp - Duration::hours(1);
+                self.deposits.retain(|&deposit| deposit > hour_ago);
+                self.deposits.push_back(timestamp);
+            }
+            _ => {}
+        }
+    }
+
+    /// Deposits within the hour before `timestamp`.
+    fn deposits_since(&self, timestamp: DateTime<Utc>) -> usize {
+        let hour_ago = timestamp - Duration::hours(1);
+        self.deposits
+            .iter()
+            .filter(|&&deposit| deposit > hour_ago && deposit <= timestamp)
+            .count()
+    }
+}
+
+/// Names the rule that rejected each reject with [`RejectionReason::LimitExceeded`] in its
+/// detail.
+pub fn annotate_rejects(rejects: &mut [Reject], violations: &[RuleViolation]) {
+    let rules: HashMap<(ClientId, TxId), &str> = violations
+        .iter()
+        .map(|violation| ((violation.client, violation.tx), violation.rule.as_str()))
+        .collect();
+    for reject in rejects {
+        if reject.reason != RejectionReason::LimitExceeded {
+            continue;
+        }
+        let (Some(client), Some(tx)) = (reject.client, reject.tx) else {
+            continue;
+        };
+        if let Some(rule) = rules.get(&(client, tx)) {
+            reject.detail = format!("rule {rule}");
+        }
+    }
+}
+
+/// Velocity of every client and currency the rules have seen.
+pub(crate) type VelocityMap = FastMap<(ClientId, Option<Currency>), Velocity>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::EngineConfig,
+        testing::Scenario,
+        transaction::{ClientId, Engine},
+    };
+
+    #[test]
+    fn rules_reject_with_rule_id() {
+        let path = std::env::temp_dir().join("tx_accounts_rules.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [[rules]]
+            id = "W1"
+            kind = "max-withdrawal"
+            amount = 50.0
+
+            [[rules]]
+            id = "W2"
+            kind = "max-daily-withdrawal"
+            amount = 60.0
+
+            [[rules]]
+            id = "D1"
+            kind = "max-deposits-per-hour"
+            count = 2
+            "#,
+        )
+        .unwrap();
+        let rules = RuleSet::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let engine = Engine::with_config(EngineConfig {
+            rules,
+            ..Default::default()
+        });
+        let engine = Scenario::from_engine(engine)
+            .at("2024-01-02T10:00:00Z")
+            .deposit(1, 1, 100)
+            .withdraw(1, 2, 51)
+            .expect_rejected(RejectionReason::LimitExceeded)
+            .withdraw(1, 3, 40)
+            .at("2024-01-02T10:30:00Z")
+            .deposit(1, 4, 100)
+            .withdraw(1, 5, 30)
+            .expect_rejected(RejectionReason::LimitExceeded)
+            .deposit(1, 6, 1)
+            .expect_rejected(RejectionReason::LimitExceeded)
+            .at("2024-01-02T11:00:01Z")
+            .deposit(1, 7, 1)
+            .at("2024-01-03T00:00:00Z")
+            .withdraw(1, 8, 30)
+            .expect_available(ClientId(1), 131)
+            .into_engine();
+
+        let violations: Vec<(TxId, &str)> = engine
+            .rule_violations()
+            .iter()
+            .map(|violation| (violation.tx, violation.rule.as_str()))
+            .collect();
+        assert_eq!(
+            violations,
+            vec![(TxId(2), "W1"), (TxId(5), "W2"), (TxId(6), "D1")]
+        );
+
+        let mut rejects = vec![crate::rejects::malformed(1, String::new())];
+        rejects[0].reason = RejectionReason::LimitExceeded;
+        (rejects[0].client, rejects[0].tx) = (Some(ClientId(1)), Some(TxId(5)));
+        annotate_rejects(&mut rejects, engine.rule_violations());
+        assert_eq!(rejects[0].detail, "rule W2");
+    }
+}
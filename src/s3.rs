@@ -0,0 +1,245 @@
+use futures_util::StreamExt;
+use object_store::{aws::AmazonS3Builder, path::Path, ObjectStore, ObjectStoreExt, WriteMultipart};
+use std::{
+    error::Error,
+    io::{self, Read, Write},
+    sync::{mpsc, Arc},
+    thread,
+};
+use tokio::runtime::Runtime;
+
+/// Downloaded chunks buffered ahead of the reader.
+const PREFETCH_CHUNKS: usize = 16;
+
+/// Size of the parts an object is uploaded in; S3 needs at least 5 MiB per part.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Parts uploaded at the same time.
+const UPLOAD_CONCURRENCY: usize = 4;
+
+/// An object named by an `s3://bucket/key` URL. Credentials, the region and the endpoint
+/// of S3 compatible stores come from the usual `AWS_*` environment variables, or from the
+/// instance, task or pod role when those are not set.
+#[derive(Debug)]
+pub struct S3Object {
+    store: Arc<dyn ObjectStore>,
+    path: Path,
+}
+
+impl S3Object {
+    pub fn from_url(url: &str) -> Result<Self, Box<dyn Error>> {
+        let Some((bucket, key)) = url
+            .strip_prefix("s3://")
+            .and_then(|rest| rest.split_once('/'))
+        else {
+            return Err(format!("'{url}' is not an s3://bucket/key URL").into());
+        };
+
+        let store = AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .build()?;
+        Ok(Self {
+            store: Arc::new(store),
+            path: Path::parse(key)?,
+        })
+    }
+
+    /// Streams the object without keeping more than a few chunks of it in memory.
+    pub fn reader(&self) -> Result<ObjectReader, Box<dyn Error>> {
+        let runtime = Runtime::new()?;
+        // Requesting the object up front reports a missing object or bucket right away.
+        let mut chunks = runtime.block_on(self.store.get(&self.path))?.into_stream();
+
+        let (sender, receiver) = mpsc::sync_channel(PREFETCH_CHUNKS);
+        thread::spawn(move || {
+            while let Some(chunk) = runtime.block_on(chunks.next()) {
+                let chunk = chunk.map(Vec::from).map_err(io::Error::other);
+                let failed = chunk.is_err();
+                // The reader was dropped, or the download failed and the reader is told why.
+                if sender.send(chunk).is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        Ok(ObjectReader {
+            receiver,
+            chunk: io::Cursor::new(Vec::new()),
+        })
+    }
+
+    /// Uploads the object in parts while it is written. The object only appears once
+    /// [`ObjectWriter::finish`] completed the upload; dropping the writer before that
+    /// aborts it.
+    pub fn writer(&self) -> Result<ObjectWriter, Box<dyn Error>> {
+        let runtime = Runtime::new()?;
+        let upload = runtime.block_on(self.store.put_multipart(&self.path))?;
+        let mut upload = WriteMultipart::new_with_chunk_size(upload, PART_SIZE);
+
+        let (sender, receiver) = mpsc::sync_channel::<Part>(UPLOAD_CONCURRENCY);
+        let worker = thread::spawn(move || -> UploadResult {
+            // Full parts start uploading as soon as they are written.
+            let _context = runtime.enter();
+            for part in receiver {
+                match part {
+                    Part::Data(data) => {
+                        runtime.block_on(upload.wait_for_capacity(UPLOAD_CONCURRENCY))?;
+                        upload.write(&data);
+                    }
+                    Part::Last => {
+                        runtime.block_on(upload.finish())?;
+                        return Ok(());
+                    }
+                }
+            }
+            runtime.block_on(upload.abort())?;
+            Err("the upload was aborted".into())
+        });
+
+        Ok(ObjectWriter {
+            sender: Some(sender),
+            buffer: Vec::with_capacity(PART_SIZE),
+            worker: Some(worker),
+        })
+    }
+}
+
+/// Reads an [`S3Object`] as it is downloaded on a background thread.
+#[derive(Debug)]
+pub struct ObjectReader {
+    receiver: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: io::Cursor<Vec<u8>>,
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.chunk.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            match self.receiver.recv() {
+                Ok(chunk) => self.chunk = io::Cursor::new(chunk?),
+                // The download thread is done once the object was read to the end.
+                Err(_) => return Ok(0),
+            }
+        }
+    }
+}
+
+enum Part {
+    Data(Vec<u8>),
+    Last,
+}
+
+type UploadResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+/// Writes an [`S3Object`], uploading it on a background thread.
+#[derive(Debug)]
+pub struct ObjectWriter {
+    sender: Option<mpsc::SyncSender<Part>>,
+    buffer: Vec<u8>,
+    worker: Option<thread::JoinHandle<UploadResult>>,
+}
+
+impl ObjectWriter {
+    /// Uploads the rest of the object and waits until the upload completed.
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        self.send_buffer()?;
+        self.send(Part::Last)?;
+        self.wait()
+    }
+
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let data = std::mem::replace(&mut self.buffer, Vec::with_capacity(PART_SIZE));
+        self.send(Part::Data(data))
+    }
+
+    fn send(&mut self, part: Part) -> io::Result<()> {
+        let sent = self.sender.as_ref().map(|sender| sender.send(part));
+        if let Some(Ok(())) = sent {
+            return Ok(());
+        }
+        // The worker only stops early when the upload failed.
+        Err(io::Error::other(match self.wait() {
+            Err(e) => e.to_string(),
+            Ok(()) => "the upload already completed".to_owned(),
+        }))
+    }
+
+    fn wait(&mut self) -> Result<(), Box<dyn Error>> {
+        self.sender = None;
+        let Some(worker) = self.worker.take() else {
+            return Err("the upload failed".into());
+        };
+        worker
+            .join()
+            .map_err(|_| "the upload thread panicked")?
+            .map_err(|e| e.to_string().into())
+    }
+}
+
+impl Write for ObjectWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= PART_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Parts are only uploaded once full, see [`ObjectWriter::finish`].
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ObjectWriter {
+    fn drop(&mut self) {
+        // Without the last part the worker aborts the upload.
+        self.sender = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn objects_round_trip_in_parts() {
+        let object = S3Object {
+            store: Arc::new(InMemory::new()),
+            path: Path::parse("runs/accounts.csv").unwrap(),
+        };
+        let contents: Vec<u8> = (0..PART_SIZE * 2 + 10).map(|i| (i % 251) as u8).collect();
+
+        let mut writer = object.writer().unwrap();
+        for chunk in contents.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        // Nothing appears before the upload is finished.
+        assert!(object.reader().is_err());
+        writer.finish().unwrap();
+
+        let mut read = Vec::new();
+        object.reader().unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, contents);
+
+        // A dropped writer leaves the object as it was.
+        let mut writer = object.writer().unwrap();
+        writer.write_all(b"client\n").unwrap();
+        drop(writer);
+        let mut read = Vec::new();
+        object.reader().unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read.len(), contents.len());
+
+        assert!(S3Object::from_url("s3://bucket-only").is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+use crate::transaction::{ClientActivity, ClientId};
+
+/// Share of clients kept when sampling an input, between 0 (exclusive) and 1.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub struct SampleRate(f64);
+
+impl SampleRate {
+    pub fn new(rate: f64) -> Option<Self> {
+        (rate > 0.0 && rate <= 1.0).then_some(Self(rate))
+    }
+
+    pub fn get(self) -> f64 {
+        self.0
+    }
+
+    /// Whether the records of `client` are part of the sample. The decision only depends
+    /// on the client id, so all records of a client are either kept or dropped, and the
+    /// same clients are picked on every run.
+    pub fn includes_client(self, client: ClientId) -> bool {
+        self.includes(u64::from(client.0))
+    }
+
+    /// Whether a row that could not be attributed to a client is part of the sample.
+    pub fn includes_line(self, line: u64) -> bool {
+        self.includes(line)
+    }
+
+    fn includes(self, key: u64) -> bool {
+        // The top 53 bits of the hash as a uniformly distributed value in [0, 1).
+        let position = (mix(key) >> 11) as f64 / (1u64 << 53) as f64;
+        position < self.0
+    }
+}
+
+impl FromStr for SampleRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rate = match s.trim().strip_suffix('%') {
+            Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+            None => s.trim().parse::<f64>(),
+        };
+
+        rate.ok()
+            .and_then(Self::new)
+            .ok_or_else(|| format!("invalid sample rate '{s}', expected e.g. 1% or 0.01"))
+    }
+}
+
+impl fmt::Display for SampleRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}%", self.0 * 100.0)
+    }
+}
+
+/// Figures of a sampled run, scaled up to estimate the full input.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SampleEstimate {
+    pub rate: SampleRate,
+    pub rows_read: u64,
+    pub rows_sampled: u64,
+    pub clients: f64,
+    pub rejected: f64,
+    pub net_deposited: f64,
+    pub net_withdrawn: f64,
+    pub chargebacks: f64,
+}
+
+impl SampleEstimate {
+    pub fn new(
+        rate: SampleRate,
+        rows_read: u64,
+        rows_sampled: u64,
+        activity: &[ClientActivity],
+        rejected: usize,
+    ) -> Self {
+        let scale = 1.0 / rate.get();
+        let sum = |f: fn(&ClientActivity) -> f64| {
+            activity.iter().map(f).fold(0.0, |sum, value| sum + value) * scale
+        };
+
+        Self {
+            rate,
+            rows_read,
+            rows_sampled,
+            clients: activity.len() as f64 * scale,
+            rejected: rejected as f64 * scale,
+            net_deposited: sum(|a| f64::from(a.net_deposited)),
+            net_withdrawn: sum(|a| f64::from(a.net_withdrawn)),
+            chargebacks: sum(|a| a.chargebacks as f64),
+        }
+    }
+}
+
+impl fmt::Display for SampleEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Sampled {} of clients: {} of {} rows processed",
+            self.rate, self.rows_sampled, self.rows_read
+        )?;
+        write!(
+            f,
+            "Estimated for the full input: {:.0} active clients, {:.0} rejected rows, \
+             {:.4} net deposited, {:.4} net withdrawn, {:.0} chargebacks",
+            self.clients, self.rejected, self.net_deposited, self.net_withdrawn, self.chargebacks
+        )
+    }
+}
+
+// SplitMix64 finalizer; spreads consecutive ids evenly over the whole range.
+pub(crate) fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amounts::Money, transaction::RawClientId};
+
+    #[test]
+    fn sample_rate_from_str() {
+        assert_eq!("1%".parse(), Ok(SampleRate(0.01)));
+        assert_eq!("0.25".parse(), Ok(SampleRate(0.25)));
+        assert_eq!("100%".parse(), Ok(SampleRate(1.0)));
+        assert!("0%".parse::<SampleRate>().is_err());
+        assert!("150%".parse::<SampleRate>().is_err());
+        assert!("some".parse::<SampleRate>().is_err());
+    }
+
+    #[test]
+    fn sampling_is_deterministic_and_proportional() {
+        let rate: SampleRate = "10%".parse().unwrap();
+        let clients = 0..=u16::MAX as RawClientId;
+        let sampled: Vec<ClientId> = clients
+            .clone()
+            .map(ClientId)
+            .filter(|&client| rate.includes_client(client))
+            .collect();
+
+        assert!((6_000..7_100).contains(&sampled.len()), "{}", sampled.len());
+        assert!(sampled.iter().all(|&client| rate.includes_client(client)));
+        assert!(clients
+            .map(ClientId)
+            .all(|client| SampleRate(1.0).includes_client(client)));
+    }
+
+    #[test]
+    fn estimate_scales_by_rate() {
+        let activity = vec![ClientActivity {
+            client: ClientId(1),
+            net_deposited: Money::new(10.0),
+            net_withdrawn: Money::new(2.5),
+            chargebacks: 1,
+            charged_back: Money::new(1.0),
+            fees: Money::ZERO,
+        }];
+
+        let estimate = SampleEstimate::new(SampleRate(0.5), 100, 48, &activity, 3);
+        assert_eq!(estimate.clients, 2.0);
+        assert_eq!(estimate.rejected, 6.0);
+        assert_eq!(estimate.net_deposited, 20.0);
+        assert_eq!(estimate.net_withdrawn, 5.0);
+        assert_eq!(estimate.chargebacks, 2.0);
+    }
+}
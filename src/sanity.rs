@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{amounts::Money, records::InputRow};
+
+/// Checks every row has to pass as it is read, before it reaches the engine, to catch
+/// fat-fingered inputs such as a `1e30` deposit. A row that fails one is rejected as
+/// malformed, like a row that cannot be parsed. All checks are off by default.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[serde(default)]
+pub struct SanityChecks {
+    /// Largest amount a single record may have.
+    pub max_amount: Option<Money>,
+    /// Most decimal places an amount may have. Amounts are rounded to 4 places when read,
+    /// so only limits below that reject anything.
+    pub max_decimal_places: Option<u32>,
+    /// Reject records of client 0, which some partners send for a missing client.
+    pub reject_zero_client: bool,
+}
+
+impl SanityChecks {
+    /// Turns a row that fails a check into a malformed row saying which one.
+    pub fn apply(&self, row: &mut InputRow) {
+        let Ok(record) = &row.record else {
+            return;
+        };
+        if self.reject_zero_client && record.client.0 == 0 {
+            row.record = Err("client 0 is not a valid client".to_owned());
+            return;
+        }
+        let Some(amount) = record.amount else {
+            return;
+        };
+
+        if let Some(max) = self.max_amount.filter(|&max| amount > max) {
+            row.record = Err(format!(
+                "amount {} is over the maximum of {}",
+                amount.to_f32(),
+                max.to_f32()
+            ));
+        } else if let Some(max) = self
+            .max_decimal_places
+            .filter(|&max| decimal_places(amount) > max)
+        {
+            row.record = Err(format!(
+                "amount {} has more than {max} decimal places",
+                amount.to_f32()
+            ));
+        }
+    }
+}
+
+/// Decimal places of the shortest representation that reads back as `amount`.
+fn decimal_places(amount: Money) -> u32 {
+    let text = amount.to_f32().to_string();
+    text.split_once('.')
+        .map_or(0, |(_, decimals)| decimals.len() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::{read_rows_from, CsvDialect, InputFormat};
+
+    #[test]
+    fn rows_failing_a_check_become_malformed() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1e30\n\
+                     deposit,1,2,123456.78\n\
+                     deposit,1,3,1.005\n\
+                     deposit,0,4,1\n\
+                     dispute,1,1,\n";
+        let checks = SanityChecks {
+            max_amount: Some(Money::new(1_000_000.0)),
+            max_decimal_places: Some(2),
+            reject_zero_client: true,
+        };
+        let rows = read_rows_from(input.as_bytes(), InputFormat::Csv, &CsvDialect::default());
+        let results: Vec<Result<(), String>> = rows
+            .unwrap()
+            .into_iter()
+            .map(|mut row| {
+                checks.apply(&mut row);
+                row.record.map(|_| ())
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                Err(
+                    "amount 1000000000000000000000000000000 is over the maximum of 1000000"
+                        .to_owned()
+                ),
+                Ok(()),
+                Err("amount 1.005 has more than 2 decimal places".to_owned()),
+                Err("client 0 is not a valid client".to_owned()),
+                Ok(()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,419 @@
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, Utf8Bytes, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, error::Error, net::SocketAddr, sync::Arc};
+use tokio::sync::broadcast;
+
+use crate::{
+    events::{AccountEvent, BalanceUpdate, Event},
+    observer::EngineObserver,
+    prometheus::PrometheusMetrics,
+    records::Record,
+    report::QuarterlyTotals,
+    shared::SharedEngine,
+    state::StateDir,
+    transaction::{Account, ClientId, RejectionReason},
+};
+
+type AppState = Arc<SharedEngine>;
+
+/// Updates buffered per WebSocket; a subscriber falling further behind is disconnected.
+const UPDATE_BUFFER: usize = 1024;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum SubmitResponse {
+    Applied,
+    Rejected { reason: RejectionReason },
+}
+
+/// Pushes a [`BalanceUpdate`] for every applied transaction to the WebSocket subscribers.
+#[derive(Debug, Clone)]
+struct BalanceUpdates(broadcast::Sender<(ClientId, Utf8Bytes)>);
+
+impl EngineObserver for BalanceUpdates {
+    fn on_applied(&mut self, record: &Record, event: &AccountEvent) {
+        let Some(update) = BalanceUpdate::new(record, event) else {
+            return;
+        };
+        let Ok(json) = Event::new(&update).to_json() else {
+            return;
+        };
+        // Sending only fails while nobody is subscribed.
+        let _ = self.0.send((update.client, json.into()));
+    }
+}
+
+/// Builds the HTTP API on top of an engine shared between all requests:
+/// `POST /transactions`, `GET /accounts`, `GET /accounts/{client}`, `GET /updates`,
+/// a WebSocket that pushes a `balance_updated` event for every applied transaction,
+/// optionally only those of `?clients=1,2`, and `GET /metrics`, which exposes
+/// [`PrometheusMetrics`] of the records applied since the router was built.
+pub fn router(engine: Arc<SharedEngine>) -> Router {
+    let metrics = PrometheusMetrics::new();
+    engine.add_observer(metrics.clone());
+    let (updates, _) = broadcast::channel(UPDATE_BUFFER);
+    let updates = BalanceUpdates(updates);
+    engine.add_observer(updates.clone());
+
+    Router::new()
+        .route("/transactions", post(submit_transaction))
+        .route("/accounts", get(list_accounts))
+        .route("/accounts/{client}", get(get_account))
+        .with_state(engine)
+        .merge(
+            Router::new()
+                .route("/metrics", get(export_metrics))
+                .with_state(metrics),
+        )
+        .merge(
+            Router::new()
+                .route("/updates", get(subscribe_updates))
+                .with_state(updates),
+        )
+}
+
+/// Builds a query-only API over persisted state: `GET /accounts`,
+/// `GET /accounts/{client}` and `GET /reports/quarterly?from=..&to=..`. There is no route
+/// that ingests transactions.
+pub fn read_only_router(state: StateDir) -> Router {
+    Router::new()
+        .route("/accounts", get(list_stored_accounts))
+        .route("/accounts/{client}", get(get_stored_account))
+        .route("/reports/quarterly", get(quarterly_report))
+        .with_state(Arc::new(state))
+}
+
+/// Serves the HTTP API on `addr` until the process is stopped.
+pub fn serve(addr: SocketAddr, engine: SharedEngine) -> Result<(), Box<dyn Error>> {
+    serve_router(addr, router(Arc::new(engine)))
+}
+
+/// Serves the read-only API over `state` on `addr` until the process is stopped.
+pub fn serve_read_only(addr: SocketAddr, state: StateDir) -> Result<(), Box<dyn Error>> {
+    serve_router(addr, read_only_router(state))
+}
+
+fn serve_router(addr: SocketAddr, router: Router) -> Result<(), Box<dyn Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        axum::serve(listener, router).await?;
+
+        Ok(())
+    })
+}
+
+async fn submit_transaction(
+    State(engine): State<AppState>,
+    Json(record): Json<Record>,
+) -> Response {
+    match engine.apply(record) {
+        Ok(()) => (StatusCode::OK, Json(SubmitResponse::Applied)).into_response(),
+        Err(reason) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(SubmitResponse::Rejected { reason }),
+        )
+            .into_response(),
+    }
+}
+
+async fn list_accounts(State(engine): State<AppState>) -> Json<Vec<Account>> {
+    Json(engine.accounts())
+}
+
+async fn get_account(
+    State(engine): State<AppState>,
+    Path(client): Path<ClientId>,
+) -> Result<Json<Account>, StatusCode> {
+    engine
+        .account(client)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn export_metrics(State(metrics): State<PrometheusMetrics>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        metrics.encode(),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateFilter {
+    /// Comma separated clients; all clients when missing.
+    clients: Option<String>,
+}
+
+async fn subscribe_updates(
+    State(updates): State<BalanceUpdates>,
+    Query(filter): Query<UpdateFilter>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, StatusCode> {
+    let clients = match filter.clients {
+        None => None,
+        Some(clients) => Some(
+            clients
+                .split(',')
+                .map(|client| client.trim().parse())
+                .collect::<Result<HashSet<ClientId>, _>>()
+                .map_err(|_| StatusCode::BAD_REQUEST)?,
+        ),
+    };
+    // Subscribing before the upgrade means no update is missed once the socket is open.
+    let receiver = updates.0.subscribe();
+
+    Ok(upgrade.on_upgrade(move |socket| push_updates(socket, receiver, clients)))
+}
+
+async fn push_updates(
+    mut socket: WebSocket,
+    mut updates: broadcast::Receiver<(ClientId, Utf8Bytes)>,
+    clients: Option<HashSet<ClientId>>,
+) {
+    loop {
+        tokio::select! {
+            update = updates.recv() => {
+                let (client, json) = match update {
+                    Ok(update) => update,
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        // Closing tells a dashboard to reload the accounts instead of
+                        // showing balances that missed updates.
+                        let _ = socket
+                            .send(Message::Close(Some(CloseFrame {
+                                code: close_code::AGAIN,
+                                reason: "fell behind the account updates".into(),
+                            })))
+                            .await;
+                        return;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                if clients.as_ref().is_some_and(|clients| !clients.contains(&client)) {
+                    continue;
+                }
+                if socket.send(Message::Text(json)).await.is_err() {
+                    return;
+                }
+            }
+            message = socket.recv() => {
+                // Messages from the subscriber are ignored until it closes the socket.
+                if matches!(message, None | Some(Err(_)) | Some(Ok(Message::Close(_)))) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ReportRange {
+    from: NaiveDate,
+    to: NaiveDate,
+}
+
+async fn list_stored_accounts(
+    State(state): State<Arc<StateDir>>,
+) -> Result<Json<Vec<Account>>, StatusCode> {
+    state.accounts().map(Json).map_err(state_error)
+}
+
+async fn get_stored_account(
+    State(state): State<Arc<StateDir>>,
+    Path(client): Path<ClientId>,
+) -> Result<Json<Account>, StatusCode> {
+    state
+        .account(client)
+        .map_err(state_error)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn quarterly_report(
+    State(state): State<Arc<StateDir>>,
+    Query(range): Query<ReportRange>,
+) -> Result<Json<Vec<QuarterlyTotals>>, StatusCode> {
+    state
+        .quarterly_totals(range.from, range.to)
+        .map(Json)
+        .map_err(state_error)
+}
+
+fn state_error(e: Box<dyn Error>) -> StatusCode {
+    eprintln!("Error: reading state failed: {e}");
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, String) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn post_transaction(body: &str) -> Request<Body> {
+        Request::post("/transactions")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_owned()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn submit_and_query_accounts() {
+        let app = router(Arc::new(SharedEngine::default()));
+
+        let (status, body) = send(
+            &app,
+            post_transaction(r#"{"type":"deposit","client":1,"tx":1,"amount":10.5}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"status":"applied"}"#);
+
+        let (status, body) = send(
+            &app,
+            post_transaction(r#"{"type":"withdrawal","client":1,"tx":2,"amount":20}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body,
+            r#"{"status":"rejected","reason":"insufficient_funds"}"#
+        );
+
+        let (status, body) = send(
+            &app,
+            Request::get("/accounts/1").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            r#"{"client":1,"available":"10.5000","held":"0.0000","total":"10.5000","locked":false}"#
+        );
+
+        let (status, body) =
+            send(&app, Request::get("/accounts").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.starts_with(r#"[{"client":1,"#));
+
+        let (status, _) = send(
+            &app,
+            Request::get("/accounts/2").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) =
+            send(&app, Request::get("/metrics").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            body.lines()
+                .any(|line| line
+                    == r#"tx_accounts_records_total{outcome="applied",type="deposit"} 1"#)
+        );
+        assert!(body.lines().any(|line| line
+            == r#"tx_accounts_rejections_total{reason="insufficient_funds",type="withdrawal"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn websocket_streams_balance_updates() {
+        use futures_util::StreamExt;
+        use tokio_tungstenite::{connect_async, tungstenite};
+
+        let app = router(Arc::new(SharedEngine::default()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = app.clone();
+        tokio::spawn(async move { axum::serve(listener, server).await });
+
+        let (mut socket, _) = connect_async(format!("ws://{addr}/updates?clients=2,3"))
+            .await
+            .unwrap();
+        for body in [
+            r#"{"type":"deposit","client":1,"tx":1,"amount":10}"#,
+            r#"{"type":"deposit","client":2,"tx":2,"amount":5}"#,
+            r#"{"type":"withdrawal","client":2,"tx":3,"amount":50}"#,
+            r#"{"type":"withdrawal","client":2,"tx":4,"amount":1.5}"#,
+        ] {
+            send(&app, post_transaction(body)).await;
+        }
+
+        // Neither client 1 nor the rejected withdrawal were pushed.
+        for expected in [
+            r#"{"version":1,"event":"balance_updated","type":"deposit","client":2,"tx":2,"available":"5.0000","held":"0.0000","total":"5.0000","locked":false}"#,
+            r#"{"version":1,"event":"balance_updated","type":"withdrawal","client":2,"tx":4,"available":"3.5000","held":"0.0000","total":"3.5000","locked":false}"#,
+        ] {
+            let message = socket.next().await.unwrap().unwrap();
+            assert_eq!(message, tungstenite::Message::text(expected));
+        }
+
+        assert!(connect_async(format!("ws://{addr}/updates?clients=x"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn read_only_queries_state_dir() {
+        let root = std::env::temp_dir().join("tx_accounts_read_only_server");
+        std::fs::create_dir_all(root.join("runs")).unwrap();
+        std::fs::write(
+            root.join("accounts.csv"),
+            "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n",
+        )
+        .unwrap();
+        let app = read_only_router(StateDir::new(&root));
+
+        let (status, body) = send(
+            &app,
+            Request::get("/accounts/1").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            r#"{"client":1,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false}"#
+        );
+
+        let (status, _) = send(
+            &app,
+            Request::get("/accounts/2").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = send(
+            &app,
+            Request::get("/reports/quarterly?from=2024-01-01&to=2024-12-31")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "[]");
+
+        let (status, _) = send(
+            &app,
+            post_transaction(r#"{"type":"deposit","client":1,"tx":1,"amount":10.5}"#),
+        )
+        .await;
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
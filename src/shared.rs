@@ -0,0 +1,465 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{
+    config::{DuplicateScope, EngineConfig},
+    ids::SharedAllocator,
+    metrics::RejectionMetrics,
+    observer::EngineObserver,
+    records::{Record, TxType},
+    rules::RuleViolation,
+    transaction::{
+        Account, AutoCreatedAccount, ClientActivity, ClientId, Engine, FailedAssertion,
+        OpenDispute, RejectionReason, TxId,
+    },
+};
+
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Engine handle that can be used from many threads at once. Clients are spread over
+/// independently locked shards, so transactions of different clients rarely contend,
+/// while all transactions of one client are applied one at a time in submission order.
+#[derive(Debug)]
+pub struct SharedEngine {
+    shards: Vec<Mutex<Engine>>,
+    // Under the global duplicate scope deposit and withdrawal ids are unique across all
+    // clients, so they are claimed here before the owning shard applies the transaction.
+    // Per-client uniqueness is left to the shard that owns the client, and ids are only
+    // recorded once applied. Either way a shard can look up here whether a tx id it does
+    // not know belongs to a client of another shard.
+    tx_ids: Mutex<HashSet<TxId>>,
+    duplicate_scope: DuplicateScope,
+    metrics: Arc<RejectionMetrics>,
+}
+
+impl Default for SharedEngine {
+    fn default() -> Self {
+        Self::new(DEFAULT_SHARDS)
+    }
+}
+
+impl SharedEngine {
+    pub fn new(shards: usize) -> Self {
+        Self::with_metrics(shards, Arc::default())
+    }
+
+    pub fn with_metrics(shards: usize, metrics: Arc<RejectionMetrics>) -> Self {
+        Self::with_config(shards, EngineConfig::default(), metrics)
+    }
+
+    pub fn with_config(
+        shards: usize,
+        config: EngineConfig,
+        metrics: Arc<RejectionMetrics>,
+    ) -> Self {
+        // The shards generate tx ids, e.g. for chargeback fees, from one allocator.
+        let id_allocator = SharedAllocator::default();
+        Self {
+            duplicate_scope: config.duplicate_scope,
+            shards: (0..shards.max(1))
+                .map(|_| {
+                    let mut engine = Engine::with_config(config.clone());
+                    engine.set_metrics(Arc::clone(&metrics));
+                    engine.set_id_allocator(Box::new(id_allocator.clone()));
+                    Mutex::new(engine)
+                })
+                .collect(),
+            tx_ids: Mutex::default(),
+            metrics,
+        }
+    }
+
+    pub fn metrics(&self) -> &Arc<RejectionMetrics> {
+        &self.metrics
+    }
+
+    /// Adds a clone of `observer` to every shard, see [`Engine::add_observer`]. Observers
+    /// of different shards may be called concurrently.
+    pub fn add_observer<O: EngineObserver + Clone + 'static>(&self, observer: O) {
+        for shard in &self.shards {
+            lock(shard).add_observer(Box::new(observer.clone()));
+        }
+    }
+
+    pub fn apply(&self, record: Record) -> Result<(), RejectionReason> {
+        let is_tx = matches!(
+            record.r#type,
+            TxType::Deposit | TxType::Withdrawal | TxType::Hold
+        );
+        let (client, tx) = (record.client, record.tx);
+        let mut shard = lock(self.shard(client));
+
+        // A deferred record is neither applied nor rejected, so its id is only claimed
+        // once it is applied, e.g. by the run it is fed to later.
+        if shard.defers(&record) {
+            return shard.apply(record);
+        }
+
+        let claims_tx = is_tx && self.duplicate_scope == DuplicateScope::Global;
+        if claims_tx && !lock(&self.tx_ids).insert(tx) {
+            shard.reject(&record, RejectionReason::DuplicateTx);
+            return Err(RejectionReason::DuplicateTx);
+        }
+
+        let result = shard.apply_with(record, |reason| match reason {
+            RejectionReason::TxNeverSeen if lock(&self.tx_ids).contains(&tx) => {
+                RejectionReason::TxNotFoundForClient
+            }
+            reason => reason,
+        });
+
+        match result {
+            // Rejected transactions do not use up their id.
+            Err(_) if claims_tx => {
+                lock(&self.tx_ids).remove(&tx);
+            }
+            Ok(()) if is_tx => {
+                lock(&self.tx_ids).insert(tx);
+            }
+            _ => {}
+        }
+
+        result
+    }
+
+    /// See [`Engine::block`].
+    pub fn block(&self, client: ClientId) {
+        lock(self.shard(client)).block(client);
+    }
+
+    pub fn unblock(&self, client: ClientId) {
+        lock(self.shard(client)).unblock(client);
+    }
+
+    /// See [`Engine::restore_account`].
+    pub fn restore_account(&self, account: Account) {
+        lock(self.shard(account.client)).restore_account(account);
+    }
+
+    pub fn account(&self, client: ClientId) -> Option<Account> {
+        lock(self.shard(client)).account(client).cloned()
+    }
+
+    /// Copies of all accounts, sorted by client id.
+    pub fn accounts(&self) -> Vec<Account> {
+        let mut accounts: Vec<Account> = self
+            .shards
+            .iter()
+            .flat_map(|shard| lock(shard).accounts().values().cloned().collect::<Vec<_>>())
+            .collect();
+        accounts.sort_by_key(|account| account.client);
+        accounts
+    }
+
+    pub fn activity(&self) -> Vec<ClientActivity> {
+        let mut activity: Vec<ClientActivity> = self
+            .shards
+            .iter()
+            .flat_map(|shard| lock(shard).activity())
+            .collect();
+        activity.sort_by_key(|a| a.client);
+        activity
+    }
+
+    /// Accounts opened by a deposit, see [`Engine::auto_created`], sorted by client id.
+    pub fn auto_created(&self) -> Vec<AutoCreatedAccount> {
+        let mut auto_created: Vec<AutoCreatedAccount> = self
+            .shards
+            .iter()
+            .flat_map(|shard| lock(shard).auto_created().to_vec())
+            .collect();
+        auto_created.sort_by_key(|created| created.client);
+        auto_created
+    }
+
+    /// `assert` records of all shards that failed, see [`Engine::failed_assertions`], in
+    /// tx id order.
+    pub fn failed_assertions(&self) -> Vec<FailedAssertion> {
+        let mut failed: Vec<FailedAssertion> = self
+            .shards
+            .iter()
+            .flat_map(|shard| lock(shard).failed_assertions().to_vec())
+            .collect();
+        failed.sort_by_key(|failure| failure.tx);
+        failed
+    }
+
+    /// Records of all shards rejected by a rule, see [`Engine::rule_violations`], in tx id
+    /// order.
+    pub fn rule_violations(&self) -> Vec<RuleViolation> {
+        let mut violations: Vec<RuleViolation> = self
+            .shards
+            .iter()
+            .flat_map(|shard| lock(shard).rule_violations().to_vec())
+            .collect();
+        violations.sort_by_key(|violation| violation.tx);
+        violations
+    }
+
+    /// Open disputes of all shards, sorted by client and tx id.
+    pub fn open_disputes(&self) -> Vec<OpenDispute> {
+        let mut open: Vec<OpenDispute> = self
+            .shards
+            .iter()
+            .flat_map(|shard| lock(shard).open_disputes())
+            .collect();
+        open.sort_by_key(|dispute| (dispute.client, dispute.tx));
+        open
+    }
+
+    /// Open disputes of `client`, sorted by tx id.
+    pub fn open_disputes_of(&self, client: ClientId) -> Vec<OpenDispute> {
+        let mut open = lock(self.shard(client)).open_disputes();
+        open.retain(|dispute| dispute.client == client);
+        open
+    }
+
+    /// Records of all shards deferred by the clock, in timestamp order.
+    pub fn deferred(&self) -> Vec<Record> {
+        let mut deferred: Vec<Record> = self
+            .shards
+            .iter()
+            .flat_map(|shard| lock(shard).deferred().to_vec())
+            .collect();
+        deferred.sort_by_key(|record| record.timestamp);
+        deferred
+    }
+
+    pub fn into_accounts(self) -> HashMap<ClientId, Account> {
+        self.shards
+            .into_iter()
+            .flat_map(|shard| {
+                shard
+                    .into_inner()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .into_accounts()
+            })
+            .collect()
+    }
+
+    fn shard(&self, client: ClientId) -> &Mutex<Engine> {
+        &self.shards[client.0 as usize % self.shards.len()]
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // The engine never panics halfway through applying a record, so a poisoned lock
+    // still guards a consistent state.
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        amounts::Money,
+        events::AccountEvent,
+        transaction::{RawClientId, RawTxId},
+    };
+    use std::thread;
+
+    fn record(r#type: TxType, client: RawClientId, tx: RawTxId, amount: f32) -> Record {
+        Record {
+            r#type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Some(Money::new(amount)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        }
+    }
+
+    #[test]
+    fn concurrent_withdrawals_never_overdraw() {
+        let engine = Arc::new(SharedEngine::new(4));
+        for client in 0..4 {
+            engine
+                .apply(record(TxType::Deposit, client, client.into(), 500.0))
+                .unwrap();
+        }
+
+        let handles: Vec<_> = (0..8)
+            .map(|thread_id| {
+                let engine = Arc::clone(&engine);
+                thread::spawn(move || {
+                    for i in 0..400 {
+                        let client = (i % 4) as RawClientId;
+                        let tx = 1_000 + thread_id * 1_000 + i;
+                        let _ = engine.apply(record(TxType::Withdrawal, client, tx, 1.0));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // Every client received 800 withdrawal attempts, of which exactly 500 fit.
+        for account in engine.accounts() {
+            assert_eq!(account.available, 0.0);
+            assert_eq!(account.total, 0.0);
+        }
+        assert_eq!(
+            engine
+                .metrics()
+                .count(TxType::Withdrawal, RejectionReason::InsufficientFunds),
+            4 * 300
+        );
+    }
+
+    #[test]
+    fn concurrent_duplicate_tx_ids_are_applied_once() {
+        let engine = Arc::new(SharedEngine::new(8));
+
+        let handles: Vec<_> = (0..8)
+            .map(|client| {
+                let engine = Arc::clone(&engine);
+                thread::spawn(move || {
+                    (0..100)
+                        .filter(|&tx| {
+                            engine
+                                .apply(record(TxType::Deposit, client, tx, 1.0))
+                                .is_ok()
+                        })
+                        .count()
+                })
+            })
+            .collect();
+        let applied: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+
+        assert_eq!(applied, 100);
+        let total: Money = engine.accounts().iter().map(|a| a.total).sum();
+        assert_eq!(total, 100.0);
+    }
+
+    #[test]
+    fn rejected_transactions_release_their_tx_id() {
+        let engine = SharedEngine::default();
+
+        assert_eq!(
+            engine.apply(record(TxType::Withdrawal, 1, 1, 5.0)),
+            Err(RejectionReason::UnknownAccount)
+        );
+        engine.apply(record(TxType::Deposit, 1, 1, 5.0)).unwrap();
+
+        assert_eq!(
+            engine.account(ClientId(1)).map(|a| a.available),
+            Some(Money::new(5.0))
+        );
+    }
+
+    #[test]
+    fn deferred_transactions_do_not_claim_their_tx_id() {
+        let config = EngineConfig {
+            as_of: "2024-01-01T00:00:00Z".parse().ok(),
+            ..Default::default()
+        };
+        let engine = SharedEngine::with_config(2, config, Arc::default());
+        let future = Record {
+            timestamp: "2024-01-02T00:00:00Z".parse().ok(),
+            ..record(TxType::Deposit, 1, 1, 5.0)
+        };
+
+        engine.apply(future).unwrap();
+        assert_eq!(engine.deferred().len(), 1);
+        engine.apply(record(TxType::Deposit, 2, 1, 5.0)).unwrap();
+        assert_eq!(
+            engine.account(ClientId(2)).map(|a| a.available),
+            Some(Money::new(5.0))
+        );
+    }
+
+    #[derive(Debug, Clone, Default)]
+    struct Fees(Arc<Mutex<Vec<TxId>>>);
+
+    impl EngineObserver for Fees {
+        fn on_applied(&mut self, record: &Record, _event: &AccountEvent) {
+            if record.r#type == TxType::Fee {
+                lock(&self.0).push(record.tx);
+            }
+        }
+    }
+
+    #[test]
+    fn chargeback_fees_of_different_shards_get_different_tx_ids() {
+        let config = EngineConfig {
+            chargeback_fee: Some(Money::new(1.0)),
+            ..Default::default()
+        };
+        let engine = SharedEngine::with_config(2, config, Arc::default());
+        let fees = Fees::default();
+        engine.add_observer(fees.clone());
+
+        // Clients 1 and 2 live on different shards.
+        for (client, tx) in [(1, 1), (2, 3)] {
+            engine
+                .apply(record(TxType::Deposit, client, tx, 10.0))
+                .unwrap();
+            engine
+                .apply(record(TxType::Deposit, client, tx + 1, 5.0))
+                .unwrap();
+            for r#type in [TxType::Dispute, TxType::Chargeback] {
+                let record = Record {
+                    amount: None,
+                    ..record(r#type, client, tx + 1, 0.0)
+                };
+                engine.apply(record).unwrap();
+            }
+        }
+
+        let fees = lock(&fees.0).clone();
+        assert_eq!(fees.len(), 2);
+        assert_ne!(fees[0], fees[1]);
+    }
+
+    #[test]
+    fn dispute_of_another_shards_tx_names_the_wrong_client() {
+        let dispute = |client, tx| Record {
+            r#type: TxType::Dispute,
+            client,
+            tx,
+            amount: None,
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        };
+
+        for duplicate_scope in [DuplicateScope::Global, DuplicateScope::PerClient] {
+            let config = EngineConfig {
+                duplicate_scope,
+                ..Default::default()
+            };
+            let engine = SharedEngine::with_config(2, config, Arc::default());
+            engine.apply(record(TxType::Deposit, 1, 1, 5.0)).unwrap();
+            engine.apply(record(TxType::Deposit, 2, 2, 5.0)).unwrap();
+
+            assert_eq!(
+                engine.apply(dispute(ClientId(2), TxId(1))),
+                Err(RejectionReason::TxNotFoundForClient)
+            );
+            assert_eq!(
+                engine.apply(dispute(ClientId(2), TxId(3))),
+                Err(RejectionReason::TxNeverSeen)
+            );
+            assert_eq!(
+                engine
+                    .metrics()
+                    .count(TxType::Dispute, RejectionReason::TxNotFoundForClient),
+                1
+            );
+        }
+    }
+}
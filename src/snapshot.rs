@@ -0,0 +1,140 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, Read, Write},
+    path::Path,
+};
+
+use crate::export::StateExport;
+
+/// First bytes of every state snapshot.
+pub const MAGIC: [u8; 8] = *b"TXSTATE\0";
+
+/// Version of the snapshot layout. Snapshots of a newer version are refused rather than
+/// misread.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Writes `export` as a snapshot: [`MAGIC`], the [`SNAPSHOT_VERSION`], the length and the
+/// CRC-32 of the payload, all little-endian, and then the payload, the state as JSON.
+pub fn write_snapshot<W: Write>(mut writer: W, export: &StateExport) -> Result<(), Box<dyn Error>> {
+    let payload = serde_json::to_vec(export)?;
+
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+    writer.write_all(&(payload.len() as u64).to_le_bytes())?;
+    writer.write_all(&crc32fast::hash(&payload).to_le_bytes())?;
+    writer.write_all(&payload)?;
+    Ok(())
+}
+
+/// Reads a snapshot written by [`write_snapshot`], refusing one that is truncated,
+/// corrupt or of a newer version, since loading part of a snapshot would silently lose
+/// balances.
+pub fn read_snapshot<R: Read>(mut reader: R) -> Result<StateExport, Box<dyn Error>> {
+    let mut magic = [0; 8];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|_| "not a state snapshot")?;
+    if magic != MAGIC {
+        return Err("not a state snapshot".into());
+    }
+
+    let mut header = [0; 16];
+    reader
+        .read_exact(&mut header)
+        .map_err(|_| "the snapshot is truncated: its header is incomplete")?;
+    let version = u32::from_le_bytes(header[..4].try_into()?);
+    let length = u64::from_le_bytes(header[4..12].try_into()?);
+    let checksum = u32::from_le_bytes(header[12..].try_into()?);
+    if version > SNAPSHOT_VERSION {
+        return Err(format!(
+            "the snapshot is of version {version}, newer than version {SNAPSHOT_VERSION} this \
+             build reads"
+        )
+        .into());
+    }
+
+    let mut payload = Vec::new();
+    reader.read_to_end(&mut payload)?;
+    if payload.len() as u64 != length {
+        return Err(format!(
+            "the snapshot is truncated or padded: {} bytes of state instead of {length}",
+            payload.len()
+        )
+        .into());
+    }
+    if crc32fast::hash(&payload) != checksum {
+        return Err("the snapshot is corrupt: its checksum does not match".into());
+    }
+
+    StateExport::read(payload.as_slice())
+}
+
+/// Reads the state in `path`, a snapshot or a JSON document written by `export-state`.
+pub fn read_state_file<P: AsRef<Path>>(path: P) -> Result<StateExport, Box<dyn Error>> {
+    let path = path.as_ref();
+    let mut contents = Vec::new();
+    File::open(path)
+        .and_then(|file| BufReader::new(file).read_to_end(&mut contents))
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+
+    let state = match contents.starts_with(&MAGIC) {
+        true => read_snapshot(contents.as_slice()),
+        false => StateExport::read(contents.as_slice()),
+    };
+    state.map_err(|e| format!("{}: {e}", path.display()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{state::HistoryDates, testing::Scenario, transaction::Engine};
+
+    #[test]
+    fn damaged_snapshots_are_refused() {
+        let scenario = Scenario::new().deposit(1, 1, 10).deposit(2, 2, 5);
+        let export = StateExport::new(scenario.engine(), &HistoryDates::new());
+        let mut snapshot = Vec::new();
+        write_snapshot(&mut snapshot, &export).unwrap();
+
+        let mut engine = Engine::new();
+        read_snapshot(snapshot.as_slice())
+            .unwrap()
+            .restore(&mut engine);
+        assert_eq!(engine.accounts(), scenario.engine().accounts());
+
+        let error = |bytes: &[u8]| read_snapshot(bytes).unwrap_err().to_string();
+        assert!(error(&snapshot[..snapshot.len() - 3]).contains("truncated"));
+        assert!(error(&snapshot[..12]).contains("truncated"));
+        assert!(error(b"{\"version\":1}").contains("not a state snapshot"));
+
+        let mut corrupt = snapshot.clone();
+        let last = corrupt.len() - 2;
+        corrupt[last] ^= 1;
+        assert!(error(&corrupt).contains("checksum"));
+
+        let mut newer = snapshot.clone();
+        newer[8..12].copy_from_slice(&2u32.to_le_bytes());
+        assert!(error(&newer).contains("version 2"));
+    }
+
+    #[cfg(feature = "wide-ids")]
+    #[test]
+    fn wide_ids_survive_snapshots() {
+        let scenario = Scenario::new()
+            .deposit(70_000, 5_000_000_000, 10)
+            .deposit(1, 1, 5);
+        let export = StateExport::new(scenario.engine(), &HistoryDates::new());
+        let mut snapshot = Vec::new();
+        write_snapshot(&mut snapshot, &export).unwrap();
+
+        let mut engine = Engine::new();
+        read_snapshot(snapshot.as_slice())
+            .unwrap()
+            .restore(&mut engine);
+        Scenario::from_engine(engine)
+            .dispute(70_000, 5_000_000_000)
+            .expect_held(70_000, 10)
+            .expect_available(1, 5);
+    }
+}
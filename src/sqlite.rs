@@ -0,0 +1,604 @@
+use chrono::NaiveDate;
+use rusqlite::{
+    params,
+    types::{FromSql, FromSqlResult, ToSqlOutput, ValueRef},
+    Connection, ToSql,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    amounts::Money,
+    currency::Currency,
+    events::AccountEvent,
+    observer::EngineObserver,
+    records::{Record, TxType},
+    rejects::Reject,
+    transaction::{
+        Account, Balance, ClientId, Engine, Hold, OpenDispute, RawClientId, RawTxId, TxId,
+    },
+};
+
+const ACCOUNTS_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        client INTEGER PRIMARY KEY,
+        available REAL NOT NULL,
+        held REAL NOT NULL,
+        total REAL NOT NULL,
+        locked INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS currency_balances (
+        client INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        available REAL NOT NULL,
+        held REAL NOT NULL,
+        total REAL NOT NULL,
+        PRIMARY KEY (client, currency)
+    );
+";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS transactions (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        amount REAL,
+        currency TEXT,
+        PRIMARY KEY (client, tx)
+    );
+    CREATE TABLE IF NOT EXISTS disputes (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        amount REAL,
+        PRIMARY KEY (client, tx)
+    );
+    CREATE TABLE IF NOT EXISTS holds (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        amount REAL NOT NULL,
+        currency TEXT,
+        placed_at TEXT,
+        PRIMARY KEY (client, tx)
+    );
+";
+
+const RESULTS_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS rejects (
+        run_date TEXT NOT NULL,
+        line INTEGER NOT NULL,
+        reason TEXT NOT NULL,
+        type TEXT,
+        client INTEGER,
+        tx INTEGER,
+        amount REAL,
+        detail TEXT NOT NULL,
+        PRIMARY KEY (run_date, line)
+    );
+    CREATE TABLE IF NOT EXISTS open_disputes (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        currency TEXT,
+        amount REAL NOT NULL,
+        opened TEXT,
+        PRIMARY KEY (client, tx)
+    );
+";
+
+/// Engine state kept in a SQLite database: the balances of every account, with those in
+/// other currencies than the base one in `currency_balances`, the deposits and
+/// withdrawals that can be disputed, the open disputes and the open holds. As an observer it writes
+/// every applied record through in a database transaction of its own, so the database
+/// always matches the engine up to the last applied record.
+///
+/// The database is in WAL mode, so other tools can query it while records are applied.
+/// Clones write to the same connection.
+#[derive(Debug, Clone)]
+pub struct SqliteBackend {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteBackend {
+    /// Opens the database at `path`, creating it and its tables if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(ACCOUNTS_SCHEMA)?;
+        conn.execute_batch(SCHEMA)?;
+        // Databases created before partial disputes lack the amount of a dispute.
+        let has_amount = conn
+            .prepare("SELECT 1 FROM pragma_table_info('disputes') WHERE name = 'amount'")?
+            .exists([])?;
+        if !has_amount {
+            conn.execute("ALTER TABLE disputes ADD COLUMN amount REAL", [])?;
+        }
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Puts the stored accounts, transactions, disputes and holds back into `engine`.
+    pub fn restore(&self, engine: &mut Engine) -> Result<(), Box<dyn Error>> {
+        for account in self.accounts()? {
+            engine.restore_account(account);
+        }
+
+        let conn = self.lock();
+        let mut statement = conn.prepare(
+            "SELECT t.client, t.tx, t.type, t.amount, d.tx IS NOT NULL, t.currency, d.amount
+             FROM transactions t LEFT JOIN disputes d ON d.client = t.client AND d.tx = t.tx",
+        )?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let r#type = match row.get::<_, String>(2)?.as_str() {
+                "deposit" => TxType::Deposit,
+                "withdrawal" => TxType::Withdrawal,
+                other => return Err(format!("unexpected transaction type '{other}'").into()),
+            };
+            let record = Record {
+                r#type,
+                client: row.get(0)?,
+                tx: row.get(1)?,
+                amount: row
+                    .get::<_, Option<f64>>(3)?
+                    .map(|amount| Money::new(amount as f32)),
+                timestamp: None,
+                currency: row
+                    .get::<_, Option<String>>(5)?
+                    .as_deref()
+                    .map(str::parse)
+                    .transpose()?,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
+            };
+            let (client, tx) = (record.client, record.tx);
+            engine.restore_transaction(record, row.get(4)?);
+            if let Some(amount) = row.get::<_, Option<f64>>(6)? {
+                engine.restore_disputed_amount(client, tx, Money::new(amount as f32));
+            }
+        }
+
+        let mut statement =
+            conn.prepare("SELECT client, tx, amount, currency, placed_at FROM holds")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            engine.restore_hold(Hold {
+                client: row.get(0)?,
+                tx: row.get(1)?,
+                amount: Money::new(row.get::<_, f64>(2)? as f32),
+                currency: row
+                    .get::<_, Option<String>>(3)?
+                    .as_deref()
+                    .map(str::parse)
+                    .transpose()?,
+                placed_at: row
+                    .get::<_, Option<String>>(4)?
+                    .as_deref()
+                    .map(str::parse)
+                    .transpose()?,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// All stored accounts, sorted by client.
+    pub fn accounts(&self) -> Result<Vec<Account>, Box<dyn Error>> {
+        let conn = self.lock();
+        let mut statement = conn.prepare(
+            "SELECT client, available, held, total, locked FROM accounts ORDER BY client",
+        )?;
+        let mut accounts: Vec<Account> = statement
+            .query_map([], |row| {
+                Ok(Account {
+                    client: row.get(0)?,
+                    available: Money::new(row.get::<_, f64>(1)? as f32),
+                    held: Money::new(row.get::<_, f64>(2)? as f32),
+                    total: Money::new(row.get::<_, f64>(3)? as f32),
+                    locked: row.get(4)?,
+                    ..Default::default()
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        let mut statement =
+            conn.prepare("SELECT client, currency, available, held, total FROM currency_balances")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let client: ClientId = row.get(0)?;
+            let currency: Currency = row.get::<_, String>(1)?.parse()?;
+            let Ok(index) = accounts.binary_search_by_key(&client, |a| a.client) else {
+                continue;
+            };
+            accounts[index].currencies.insert(
+                currency,
+                Balance {
+                    available: Money::new(row.get::<_, f64>(2)? as f32),
+                    held: Money::new(row.get::<_, f64>(3)? as f32),
+                    total: Money::new(row.get::<_, f64>(4)? as f32),
+                },
+            );
+        }
+
+        Ok(accounts)
+    }
+
+    fn write(
+        &self,
+        record: &Record,
+        account: &Account,
+        currency: Option<Currency>,
+    ) -> rusqlite::Result<()> {
+        let mut conn = self.lock();
+        let tx = conn.transaction()?;
+        if let Some(currency) = currency {
+            let balance = account.balance(Some(currency));
+            tx.execute(
+                "INSERT INTO accounts (client, available, held, total, locked)
+                 VALUES (?1, 0, 0, 0, ?2)
+                 ON CONFLICT (client) DO UPDATE SET locked = excluded.locked",
+                params![account.client, account.locked],
+            )?;
+            upsert_balance(&tx, account.client, currency, balance)?;
+        } else {
+            upsert_account(&tx, account)?;
+        }
+
+        let key = params![record.client, record.tx];
+        match record.r#type {
+            TxType::Deposit | TxType::Withdrawal => {
+                let r#type = if record.r#type == TxType::Deposit {
+                    "deposit"
+                } else {
+                    "withdrawal"
+                };
+                tx.execute(
+                    "INSERT INTO transactions (client, tx, type, amount, currency)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        record.client,
+                        record.tx,
+                        r#type,
+                        record.amount.map(f64::from),
+                        record.currency.as_ref().map(Currency::as_str)
+                    ],
+                )?;
+            }
+            TxType::Dispute => {
+                tx.execute(
+                    "INSERT OR IGNORE INTO disputes (client, tx, amount) VALUES (?1, ?2, ?3)",
+                    params![record.client, record.tx, record.amount.map(f64::from)],
+                )?;
+            }
+            TxType::Resolve | TxType::Chargeback => {
+                tx.execute("DELETE FROM disputes WHERE client = ?1 AND tx = ?2", key)?;
+            }
+            TxType::Hold => {
+                tx.execute(
+                    "INSERT INTO holds (client, tx, amount, currency, placed_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        record.client,
+                        record.tx,
+                        record.amount.map(f64::from),
+                        record.currency.as_ref().map(Currency::as_str),
+                        record.timestamp.map(|at| at.to_rfc3339())
+                    ],
+                )?;
+            }
+            TxType::Capture => {
+                tx.execute(
+                    "INSERT INTO transactions (client, tx, type, amount, currency)
+                     SELECT client, tx, 'withdrawal', COALESCE(?3, amount), currency
+                     FROM holds WHERE client = ?1 AND tx = ?2",
+                    params![record.client, record.tx, record.amount.map(f64::from)],
+                )?;
+                tx.execute("DELETE FROM holds WHERE client = ?1 AND tx = ?2", key)?;
+            }
+            TxType::Release => {
+                tx.execute("DELETE FROM holds WHERE client = ?1 AND tx = ?2", key)?;
+            }
+            TxType::Representment
+            | TxType::OpenAccount
+            | TxType::Lock
+            | TxType::Unlock
+            | TxType::Convert
+            | TxType::Fee
+            | TxType::Assert
+            | TxType::Custom(_) => {}
+        }
+
+        tx.commit()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn upsert_account(conn: &Connection, account: &Account) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO accounts (client, available, held, total, locked)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (client) DO UPDATE SET available = excluded.available,
+            held = excluded.held, total = excluded.total, locked = excluded.locked",
+        params![
+            account.client,
+            f64::from(account.available),
+            f64::from(account.held),
+            f64::from(account.total),
+            account.locked
+        ],
+    )?;
+    Ok(())
+}
+
+fn upsert_balance(
+    conn: &Connection,
+    client: ClientId,
+    currency: Currency,
+    balance: Balance,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO currency_balances (client, currency, available, held, total)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (client, currency) DO UPDATE SET available = excluded.available,
+            held = excluded.held, total = excluded.total",
+        params![
+            client,
+            currency.as_str(),
+            f64::from(balance.available),
+            f64::from(balance.held),
+            f64::from(balance.total)
+        ],
+    )?;
+    Ok(())
+}
+
+/// The results of a run written to a SQLite database with `--output sqlite://...`, so
+/// they can be queried without importing CSV files: the accounts with their balances
+/// in other currencies, the rejected rows and the open disputes.
+///
+/// Running again into the same database updates it in place. Accounts are upserted, so
+/// clients the run did not write keep their last balances; the rejects of the run date
+/// and all open disputes are replaced.
+pub fn write_results<P: AsRef<Path>>(
+    path: P,
+    accounts: &HashMap<ClientId, Account>,
+    rejects: &[Reject],
+    open_disputes: &[OpenDispute],
+    run_date: NaiveDate,
+) -> Result<(), Box<dyn Error>> {
+    let mut conn = Connection::open(path)?;
+    conn.execute_batch(ACCOUNTS_SCHEMA)?;
+    conn.execute_batch(RESULTS_SCHEMA)?;
+
+    // In one transaction, so readers never see a run half written.
+    let tx = conn.transaction()?;
+    for account in accounts.values() {
+        upsert_account(&tx, account)?;
+        for (&currency, &balance) in &account.currencies {
+            upsert_balance(&tx, account.client, currency, balance)?;
+        }
+    }
+
+    let run_date = run_date.to_string();
+    tx.execute("DELETE FROM rejects WHERE run_date = ?1", [&run_date])?;
+    for reject in rejects {
+        tx.execute(
+            "INSERT INTO rejects (run_date, line, reason, type, client, tx, amount, detail)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                run_date,
+                reject.line,
+                reject.reason.to_string(),
+                reject.r#type.as_ref().map(TxType::as_str),
+                reject.client,
+                reject.tx,
+                reject.amount.map(f64::from),
+                reject.detail
+            ],
+        )?;
+    }
+
+    tx.execute("DELETE FROM open_disputes", [])?;
+    for dispute in open_disputes {
+        tx.execute(
+            "INSERT INTO open_disputes (client, tx, currency, amount, opened)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                dispute.client,
+                dispute.tx,
+                dispute.currency.as_ref().map(Currency::as_str),
+                f64::from(dispute.amount),
+                dispute.opened.map(|at| at.to_rfc3339())
+            ],
+        )?;
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+impl ToSql for ClientId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+
+impl FromSql for ClientId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        RawClientId::column_result(value).map(Self)
+    }
+}
+
+impl ToSql for TxId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+
+impl FromSql for TxId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        RawTxId::column_result(value).map(Self)
+    }
+}
+
+/// Like the journal, a record that failed to be written cannot be reported as rejected
+/// anymore, so write errors abort the process.
+impl EngineObserver for SqliteBackend {
+    fn on_applied(&mut self, record: &Record, event: &AccountEvent) {
+        let AccountEvent::Updated {
+            client,
+            currency,
+            available,
+            held,
+            total,
+            locked,
+        } = *event
+        else {
+            return;
+        };
+
+        let mut account = Account {
+            client,
+            locked,
+            ..Default::default()
+        };
+        account.set_balance(
+            currency,
+            Balance {
+                available,
+                held,
+                total,
+            },
+        );
+        self.write(record, &account, currency)
+            .expect("writing to the SQLite backend failed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Scenario;
+
+    #[test]
+    fn restore_continues_from_the_database() {
+        let path = std::env::temp_dir().join("tx_accounts_backend.db");
+        let _ = std::fs::remove_file(&path);
+
+        let backend = SqliteBackend::open(&path).unwrap();
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(backend.clone()));
+        let engine = Scenario::from_engine(engine)
+            .deposit(1, 1, 10)
+            .deposit(1, 2, 5)
+            .withdraw(1, 3, 2)
+            .dispute(1, 1)
+            .deposit(2, 4, 7)
+            .dispute(2, 4)
+            .chargeback(2, 4)
+            .hold(1, 6, 2)
+            .hold(1, 7, 1)
+            .capture(1, 7, None)
+            .in_currency(Some("EUR"))
+            .deposit(1, 5, 3)
+            .into_engine();
+
+        // Another connection sees the state while the engine is still running.
+        let reader = SqliteBackend::open(&path).unwrap();
+        assert_eq!(
+            reader.accounts().unwrap(),
+            engine.accounts_iter().cloned().collect::<Vec<_>>()
+        );
+
+        let mut restored = Engine::new();
+        reader.restore(&mut restored).unwrap();
+        Scenario::from_engine(restored)
+            .expect_held(ClientId(1), 12)
+            .expect_locked(ClientId(2), true)
+            .release(1, 6)
+            .resolve(1, 1)
+            .expect_available(ClientId(1), 12)
+            .dispute(1, 7)
+            .expect_held(ClientId(1), 1)
+            .deposit(1, 2, 1)
+            .expect_rejected(crate::transaction::RejectionReason::DuplicateTx)
+            .in_currency(Some("EUR"))
+            .dispute(1, 5)
+            .expect_balance(ClientId(1), "EUR", 0, 3);
+
+        drop((backend, reader));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn results_are_upserted_on_repeated_runs() {
+        let path = std::env::temp_dir().join("tx_accounts_results.db");
+        let _ = std::fs::remove_file(&path);
+        let run_date = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        let reject = |line| Reject {
+            line,
+            reason: crate::transaction::RejectionReason::InsufficientFunds,
+            r#type: Some(TxType::Withdrawal),
+            client: Some(ClientId(1)),
+            tx: Some(TxId(9)),
+            amount: Some(Money::new(100.0)),
+            detail: String::new(),
+        };
+
+        let engine = Scenario::new()
+            .deposit(1, 1, 10)
+            .deposit(2, 2, 5)
+            .dispute(2, 2)
+            .in_currency(Some("EUR"))
+            .deposit(1, 3, 3)
+            .into_engine();
+        let open_disputes = engine.open_disputes();
+        let accounts = engine.into_accounts();
+        write_results(
+            &path,
+            &accounts,
+            &[reject(4), reject(5)],
+            &open_disputes,
+            run_date,
+        )
+        .unwrap();
+
+        // The next run of the same day only changes client 1 and leaves no dispute open.
+        let engine = Scenario::new().deposit(1, 1, 12).into_engine();
+        write_results(&path, &engine.into_accounts(), &[reject(2)], &[], run_date).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        let balances: Vec<(RawClientId, f64, f64)> = conn
+            .prepare("SELECT client, available, held FROM accounts ORDER BY client")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(balances, vec![(1, 12.0, 0.0), (2, 0.0, 5.0)]);
+        assert_eq!(count("currency_balances"), 1);
+        assert_eq!(count("rejects"), 1);
+        assert_eq!(count("open_disputes"), 0);
+
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
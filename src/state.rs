@@ -0,0 +1,591 @@
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{self, File},
+    io::{BufReader, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    amounts::Money,
+    currency::Currency,
+    output::{read_accounts, write_accounts, OutputFormat},
+    records::{Record, TxType},
+    report::{consolidate, QuarterlyTotals},
+    transaction::{Account, ClientId, Engine, Hold, TxId},
+};
+
+/// Persisted state of a deployment, laid out as:
+///
+/// - `accounts.csv`: the latest account snapshot, e.g. written by `serve --kafka --snapshot`
+///   or by `process --state-dir`
+/// - `history.csv`: deposits and withdrawals of earlier runs that can still be disputed
+/// - `tx_index.csv`: client, id and amount of every deposit and withdrawal ever applied,
+///   so that a file sent again is deduplicated even after its transactions left the history
+/// - `holds.csv`: holds that were neither captured nor released yet
+/// - `offsets.csv`: the offset each source of a stream, such as a Kafka partition, was
+///   applied up to
+/// - `ids.csv`: the last tx id the engine generated, e.g. for a chargeback fee, so that
+///   later runs do not generate it again
+/// - `runs/`: run manifests of batch runs
+///
+/// Files are read on every access and replaced by an atomic rename when written, so
+/// readers never see a partially written file and pick up new state without restarting.
+#[derive(Debug, Clone)]
+pub struct StateDir {
+    root: PathBuf,
+}
+
+impl StateDir {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// All accounts of the snapshot, sorted by client id.
+    pub fn accounts(&self) -> Result<Vec<Account>, Box<dyn Error>> {
+        let path = self.root.join("accounts.csv");
+        let file = File::open(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let mut accounts = read_accounts(BufReader::new(file))?;
+        accounts.sort_by_key(|account| account.client);
+
+        Ok(accounts)
+    }
+
+    pub fn account(&self, client: ClientId) -> Result<Option<Account>, Box<dyn Error>> {
+        Ok(self
+            .accounts()?
+            .into_iter()
+            .find(|account| account.client == client))
+    }
+
+    /// Restores the accounts and the dispute history of earlier runs into `engine`, and
+    /// returns the run date of every restored transaction. With a `lookback` of some days,
+    /// transactions from runs more than that many days before `run_date` are dropped and
+    /// can no longer be disputed, unless a dispute on them is still open or their
+    /// chargeback may still be reversed. Dropped ones are
+    /// still rejected as duplicates when sent again. A directory without any state yet
+    /// leaves the engine untouched.
+    pub fn restore(
+        &self,
+        engine: &mut Engine,
+        run_date: NaiveDate,
+        lookback: Option<u32>,
+    ) -> Result<HistoryDates, Box<dyn Error>> {
+        match self.accounts() {
+            Ok(accounts) => accounts
+                .into_iter()
+                .for_each(|account| engine.restore_account(account)),
+            Err(_) if !self.root.join("accounts.csv").exists() => {}
+            Err(e) => return Err(e),
+        }
+
+        let oldest = lookback.and_then(|days| run_date.checked_sub_days(Days::new(days.into())));
+        let mut dates = HashMap::new();
+        for stored in self.history()? {
+            let open = stored.disputed || stored.charged_back.is_some();
+            if oldest.is_some_and(|oldest| stored.run_date < oldest) && !open {
+                engine.retire_transaction(stored.client, stored.tx, stored.amount);
+                continue;
+            }
+
+            dates.insert((stored.client, stored.tx), stored.run_date);
+            engine.restore_transaction(
+                Record {
+                    r#type: stored.r#type,
+                    client: stored.client,
+                    tx: stored.tx,
+                    amount: stored.amount,
+                    timestamp: None,
+                    currency: stored.currency,
+                    to_currency: None,
+                    total: None,
+                    reason_code: None,
+                    memo: None,
+                    reference: None,
+                    merchant_id: None,
+                },
+                stored.disputed,
+            );
+            engine.restore_dispute_count(stored.client, stored.tx, stored.disputes);
+            if let Some(amount) = stored.disputed_amount {
+                engine.restore_disputed_amount(stored.client, stored.tx, amount);
+            }
+            if let Some(opened) = stored.dispute_opened {
+                engine.restore_dispute_opened(stored.client, stored.tx, opened);
+            }
+            if let Some(amount) = stored.charged_back {
+                engine.restore_chargeback(stored.client, stored.tx, amount);
+            }
+        }
+
+        for indexed in self.tx_index()? {
+            if !dates.contains_key(&(indexed.client, indexed.tx)) {
+                engine.retire_transaction(indexed.client, indexed.tx, indexed.amount);
+            }
+        }
+
+        for hold in self.read_csv::<Hold>("holds.csv")? {
+            engine.restore_hold(hold);
+        }
+
+        for stored in self.read_csv::<SourceOffset>("offsets.csv")? {
+            engine.set_source_offset(&stored.source, Some(stored.offset));
+        }
+
+        for generated in self.read_csv::<GeneratedIds>("ids.csv")? {
+            engine.restore_last_generated_tx(generated.last_tx);
+        }
+
+        Ok(dates)
+    }
+
+    /// Persists the dispute history, the tx index, the open holds, the source offsets and
+    /// the last generated tx id of `engine`. Transactions without a date in `dates` were applied by the current run and
+    /// are dated `run_date`.
+    pub fn save_history(
+        &self,
+        engine: &Engine,
+        dates: &HistoryDates,
+        run_date: NaiveDate,
+    ) -> Result<(), Box<dyn Error>> {
+        let snapshot = HistorySnapshot::new(engine, dates, run_date);
+        self.replace_all(snapshot.files())
+    }
+
+    /// Persists a history taken with [`HistorySnapshot::new`] along with the accounts it
+    /// goes with. Every file is written under a temporary name before any is renamed into
+    /// place, so a run that fails while saving leaves the previous state as it was.
+    pub fn save(
+        &self,
+        snapshot: &HistorySnapshot,
+        accounts: &HashMap<ClientId, Account>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut files = snapshot.files();
+        files.push((
+            "accounts.csv",
+            Box::new(|file| write_accounts(file, accounts, OutputFormat::Csv)),
+        ));
+        self.replace_all(files)
+    }
+
+    /// Persists only the source offsets of `engine`, e.g. once a source was forgotten.
+    pub fn save_offsets(&self, engine: &Engine) -> Result<(), Box<dyn Error>> {
+        let offsets = HistorySnapshot::offsets(engine);
+        self.replace_all(vec![(
+            "offsets.csv",
+            Box::new(|file| write_csv(file, &offsets)),
+        )])
+    }
+
+    pub fn save_accounts(
+        &self,
+        accounts: &HashMap<ClientId, Account>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.replace_all(vec![(
+            "accounts.csv",
+            Box::new(|file| write_accounts(file, accounts, OutputFormat::Csv)),
+        )])
+    }
+
+    fn history(&self) -> Result<Vec<StoredTx>, Box<dyn Error>> {
+        self.read_csv("history.csv")
+    }
+
+    fn tx_index(&self) -> Result<Vec<IndexedTx>, Box<dyn Error>> {
+        self.read_csv("tx_index.csv")
+    }
+
+    /// All rows of the CSV file `name`, none if it does not exist yet.
+    fn read_csv<T: DeserializeOwned>(&self, name: &str) -> Result<Vec<T>, Box<dyn Error>> {
+        let path = self.root.join(name);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("{}: {e}", path.display()).into()),
+        };
+
+        let rows = csv::Reader::from_reader(BufReader::new(file))
+            .deserialize::<T>()
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Writes every file under a temporary name first, then renames them all into place.
+    fn replace_all(&self, files: Vec<(&str, FileWriter<'_>)>) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(&self.root)?;
+        let mut written = Vec::with_capacity(files.len());
+        for (name, write) in files {
+            let path = self.root.join(name);
+            let tmp_path = path.with_extension("tmp");
+            write(File::create(&tmp_path)?)?;
+            written.push((tmp_path, path));
+        }
+        for (tmp_path, path) in written {
+            fs::rename(tmp_path, path)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn quarterly_totals(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<QuarterlyTotals>, Box<dyn Error>> {
+        consolidate(self.root.join("runs"), from, to)
+    }
+}
+
+/// Run date of every transaction restored from the history, keyed by client and tx id.
+pub type HistoryDates = HashMap<(ClientId, TxId), NaiveDate>;
+
+type FileWriter<'a> = Box<dyn FnOnce(File) -> Result<(), Box<dyn Error>> + 'a>;
+
+/// What [`StateDir::save_history`] persists of an engine, taken when a run has been
+/// applied so that it can be saved with [`StateDir::save`] once the run has succeeded.
+#[derive(Debug, Clone, Default)]
+pub struct HistorySnapshot {
+    history: Vec<StoredTx>,
+    index: Vec<IndexedTx>,
+    holds: Vec<Hold>,
+    offsets: Vec<SourceOffset>,
+    ids: Vec<GeneratedIds>,
+}
+
+impl HistorySnapshot {
+    /// Transactions without a date in `dates` were applied by the current run and are
+    /// dated `run_date`.
+    pub fn new(engine: &Engine, dates: &HistoryDates, run_date: NaiveDate) -> Self {
+        let mut history: Vec<StoredTx> = engine
+            .dispute_history()
+            .map(|(record, disputed)| StoredTx {
+                run_date: dates
+                    .get(&(record.client, record.tx))
+                    .copied()
+                    .unwrap_or(run_date),
+                r#type: record.r#type,
+                client: record.client,
+                tx: record.tx,
+                amount: record.amount,
+                disputed,
+                currency: record.currency,
+                disputes: engine.dispute_count(record.client, record.tx),
+                disputed_amount: engine.disputed_amount(record.client, record.tx),
+                charged_back: engine.charged_back(record.client, record.tx),
+                dispute_opened: engine.dispute_opened(record.client, record.tx),
+            })
+            .collect();
+        history.sort_by_key(|stored| (stored.run_date, stored.client, stored.tx));
+
+        let mut index: Vec<IndexedTx> = engine
+            .tx_index()
+            .map(|(client, tx, amount)| IndexedTx { client, tx, amount })
+            .collect();
+        index.sort_by_key(|indexed| (indexed.client, indexed.tx));
+
+        Self {
+            history,
+            index,
+            holds: engine.holds(),
+            offsets: Self::offsets(engine),
+            ids: engine
+                .last_generated_tx()
+                .map(|last_tx| GeneratedIds { last_tx })
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    fn offsets(engine: &Engine) -> Vec<SourceOffset> {
+        engine
+            .source_offsets()
+            .into_iter()
+            .map(|(source, offset)| SourceOffset { source, offset })
+            .collect()
+    }
+
+    fn files(&self) -> Vec<(&'static str, FileWriter<'_>)> {
+        vec![
+            (
+                "history.csv",
+                Box::new(|file| write_csv(file, &self.history)),
+            ),
+            (
+                "tx_index.csv",
+                Box::new(|file| write_csv(file, &self.index)),
+            ),
+            ("holds.csv", Box::new(|file| write_csv(file, &self.holds))),
+            (
+                "offsets.csv",
+                Box::new(|file| write_csv(file, &self.offsets)),
+            ),
+            ("ids.csv", Box::new(|file| write_csv(file, &self.ids))),
+        ]
+    }
+}
+
+fn write_csv<T: Serialize>(file: File, rows: &[T]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::WriterBuilder::new().from_writer(file);
+    for row in rows {
+        wtr.serialize(row)?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+/// A deposit or withdrawal in `history.csv`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct StoredTx {
+    pub run_date: NaiveDate,
+    pub r#type: TxType,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: Option<Money>,
+    pub disputed: bool,
+    /// Blank for the base currency, and in history files written before currencies.
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    /// How many times the transaction was disputed; zero in history files written before
+    /// the re-dispute policy.
+    #[serde(default)]
+    pub disputes: u32,
+    /// Amount of an open dispute over part of the transaction.
+    #[serde(default)]
+    pub disputed_amount: Option<Money>,
+    /// Amount of a chargeback a representment may still reverse.
+    #[serde(default)]
+    pub charged_back: Option<Money>,
+    /// When the open dispute was opened, if its record had a timestamp.
+    #[serde(default)]
+    pub dispute_opened: Option<DateTime<Utc>>,
+}
+
+/// A source in `offsets.csv`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct SourceOffset {
+    pub source: String,
+    pub offset: u64,
+}
+
+/// The row of `ids.csv`, missing until the engine generated a tx id.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+struct GeneratedIds {
+    last_tx: TxId,
+}
+
+/// A deposit or withdrawal in `tx_index.csv`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct IndexedTx {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: Option<Money>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::EngineConfig,
+        testing::Scenario,
+        transaction::{RawClientId, RawTxId, RejectionReason},
+    };
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    fn save(state: &StateDir, engine: Engine, dates: &HistoryDates, run_date: &str) {
+        state.save_history(&engine, dates, date(run_date)).unwrap();
+        state.save_accounts(&engine.into_accounts()).unwrap();
+    }
+
+    #[test]
+    fn late_disputes_resolve_against_earlier_runs() {
+        let root = std::env::temp_dir().join("tx_accounts_state_dir_late_disputes");
+        let _ = std::fs::remove_dir_all(&root);
+        let state = StateDir::new(&root);
+
+        // First run: nothing persisted yet.
+        let mut engine = Engine::new();
+        let dates = state
+            .restore(&mut engine, date("2024-01-05"), None)
+            .unwrap();
+        assert!(dates.is_empty());
+        let engine = Scenario::from_engine(engine)
+            .deposit(1, 1, 100)
+            .deposit(2, 2, 50)
+            .at("2024-01-05T12:00:00Z")
+            .dispute(2, 2)
+            .hold(1, 4, 30)
+            .into_engine();
+        save(&state, engine, &dates, "2024-01-05");
+
+        // A month later the dispute on a deposit of the first run still works, and the
+        // dispute and hold left open by the first run can be settled.
+        let mut engine = Engine::new();
+        let dates = state
+            .restore(&mut engine, date("2024-02-05"), None)
+            .unwrap();
+        assert_eq!(dates[&(ClientId(1), TxId(1))], date("2024-01-05"));
+        assert_eq!(
+            engine.holds()[0].placed_at,
+            "2024-01-05T12:00:00Z".parse().ok()
+        );
+        assert_eq!(
+            engine.dispute_opened(ClientId(2), TxId(2)),
+            "2024-01-05T12:00:00Z".parse().ok()
+        );
+        let engine = Scenario::from_engine(engine)
+            .expect_total(ClientId(1), 100)
+            .expect_held(ClientId(1), 30)
+            .capture(1, 4, Some(20.0))
+            .expect_total(ClientId(1), 80)
+            .deposit(1, 3, 10)
+            .dispute(1, 1)
+            .expect_held(ClientId(1), 100)
+            .expect_available(ClientId(1), -10)
+            .chargeback(2, 2)
+            .expect_total(ClientId(2), 0)
+            .expect_locked(ClientId(2), true)
+            .deposit(3, 1, 5)
+            .into_engine();
+        save(&state, engine, &dates, "2024-02-05");
+
+        let history = state.history().unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let rows: Vec<(NaiveDate, ClientId, TxId, bool)> = history
+            .iter()
+            .map(|s| (s.run_date, s.client, s.tx, s.disputed))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (date("2024-01-05"), ClientId(1), TxId(1), true),
+                (date("2024-01-05"), ClientId(2), TxId(2), false),
+                (date("2024-02-05"), ClientId(1), TxId(3), false),
+                (date("2024-02-05"), ClientId(1), TxId(4), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn dispute_lookback_drops_old_transactions() {
+        let root = std::env::temp_dir().join("tx_accounts_state_dir_lookback");
+        let _ = std::fs::remove_dir_all(&root);
+        let state = StateDir::new(&root);
+
+        let engine = Scenario::new()
+            .deposit(1, 1, 100)
+            .deposit(1, 2, 100)
+            .dispute(1, 2)
+            .into_engine();
+        save(&state, engine, &HashMap::new(), "2024-01-01");
+
+        let mut engine = Engine::new();
+        let dates = state
+            .restore(&mut engine, date("2024-03-01"), Some(30))
+            .unwrap();
+
+        // The open dispute is kept however old it is.
+        assert_eq!(
+            dates.keys().collect::<Vec<_>>(),
+            vec![&(ClientId(1), TxId(2))]
+        );
+        let engine = Scenario::from_engine(engine)
+            .dispute(1, 1)
+            .expect_rejected(RejectionReason::TxNeverSeen)
+            .resolve(1, 2)
+            .expect_available(ClientId(1), 200)
+            .into_engine();
+        save(&state, engine, &dates, "2024-03-01");
+
+        // The dropped deposit is gone from the history, but sending the first file again
+        // is still deduplicated through the tx index.
+        let mut engine = Engine::new();
+        state
+            .restore(&mut engine, date("2024-03-02"), Some(30))
+            .unwrap();
+        let index = state.tx_index().unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(index.len(), 2);
+        Scenario::from_engine(engine)
+            .deposit(1, 1, 100)
+            .expect_rejected(RejectionReason::DuplicateTx)
+            .deposit(1, 2, 100)
+            .expect_rejected(RejectionReason::DuplicateTx)
+            .expect_total(ClientId(1), 200);
+    }
+
+    #[test]
+    fn generated_tx_ids_are_not_reused_by_later_runs() {
+        let root = std::env::temp_dir().join("tx_accounts_state_dir_generated_ids");
+        let _ = std::fs::remove_dir_all(&root);
+        let state = StateDir::new(&root);
+        let run = |client: RawClientId, run_date| {
+            let mut engine = Engine::with_config(EngineConfig {
+                chargeback_fee: Some(Money::new(1.0)),
+                ..Default::default()
+            });
+            let dates = state.restore(&mut engine, date(run_date), None).unwrap();
+            let tx = RawTxId::from(client) * 2;
+            let engine = Scenario::from_engine(engine)
+                .deposit(client, tx, 10)
+                .deposit(client, tx + 1, 5)
+                .dispute(client, tx + 1)
+                .chargeback(client, tx + 1)
+                .into_engine();
+            let fee_tx = engine.last_generated_tx();
+            assert!(fee_tx.is_some());
+            save(&state, engine, &dates, run_date);
+            fee_tx
+        };
+
+        let first = run(1, "2024-01-01");
+        let second = run(2, "2024-01-02");
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert!(second > first);
+    }
+
+    #[test]
+    fn reads_accounts_snapshot() {
+        let root = std::env::temp_dir().join("tx_accounts_state_dir");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(
+            root.join("accounts.csv"),
+            "client,available,held,total,locked\n\
+             2,1.0000,0.0000,1.0000,true\n\
+             1,1.5000,0.5000,2.0000,false\n",
+        )
+        .unwrap();
+
+        let state = StateDir::new(&root);
+        let accounts = state.accounts().unwrap();
+        let client_2 = state.account(ClientId(2)).unwrap();
+        let client_3 = state.account(ClientId(3)).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            accounts.iter().map(|a| a.client).collect::<Vec<_>>(),
+            [1, 2].map(ClientId)
+        );
+        assert_eq!(accounts[0].held, 0.5);
+        assert!(client_2.unwrap().locked);
+        assert_eq!(client_3, None);
+    }
+
+    #[test]
+    fn missing_snapshot_is_an_error() {
+        let state = StateDir::new(std::env::temp_dir().join("tx_accounts_state_dir_missing"));
+        assert!(state.accounts().is_err());
+    }
+}
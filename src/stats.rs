@@ -0,0 +1,292 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+};
+
+use crate::{
+    amounts::Money,
+    records::{InputRow, TxType},
+    transaction::{ClientId, Engine, TxId},
+};
+
+/// Overview of an input file, taken from a dry run on a throwaway engine.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct BatchSummary {
+    pub rows: u64,
+    /// Parsed rows per transaction type.
+    pub counts: BTreeMap<TxType, u64>,
+    /// Sum of the amounts of all deposits in the input, whether applied or not.
+    pub deposit_volume: f64,
+    /// Sum of the amounts of all withdrawals in the input, whether applied or not.
+    pub withdrawal_volume: f64,
+    pub clients: usize,
+    pub disputes_opened: u64,
+    pub disputes_resolved: u64,
+    pub charged_back: u64,
+    /// Rows that would be skipped, including malformed ones.
+    pub rejected: u64,
+    pub malformed: u64,
+}
+
+impl BatchSummary {
+    pub fn new(rows: Vec<InputRow>) -> Self {
+        let mut engine = Engine::new();
+        let mut clients = HashSet::new();
+        let mut summary = Self::default();
+
+        for row in rows {
+            summary.rows += 1;
+            let record = match row.record {
+                Ok(record) => record,
+                Err(_) => {
+                    summary.malformed += 1;
+                    summary.rejected += 1;
+                    continue;
+                }
+            };
+
+            let r#type = record.r#type;
+            let amount = f64::from(record.amount.unwrap_or_default());
+            *summary.counts.entry(r#type).or_default() += 1;
+            clients.insert(record.client);
+            match r#type {
+                TxType::Deposit => summary.deposit_volume += amount,
+                TxType::Withdrawal => summary.withdrawal_volume += amount,
+                _ => {}
+            }
+
+            if engine.apply(record).is_err() {
+                summary.rejected += 1;
+                continue;
+            }
+
+            match r#type {
+                TxType::Dispute => summary.disputes_opened += 1,
+                TxType::Resolve => summary.disputes_resolved += 1,
+                TxType::Chargeback => summary.charged_back += 1,
+                _ => {}
+            }
+        }
+
+        summary.clients = clients.len();
+        summary
+    }
+}
+
+impl fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "rows: {}", self.rows)?;
+        for (r#type, count) in &self.counts {
+            let name = serde_json::to_string(r#type).map_err(|_| fmt::Error)?;
+            writeln!(f, "{}: {count}", name.trim_matches('"'))?;
+        }
+        writeln!(f, "deposit volume: {:.4}", self.deposit_volume)?;
+        writeln!(f, "withdrawal volume: {:.4}", self.withdrawal_volume)?;
+        writeln!(f, "clients: {}", self.clients)?;
+        writeln!(f, "disputes opened: {}", self.disputes_opened)?;
+        writeln!(f, "disputes resolved: {}", self.disputes_resolved)?;
+        writeln!(f, "disputes charged back: {}", self.charged_back)?;
+        writeln!(f, "rejected rows: {}", self.rejected)?;
+        writeln!(f, "malformed rows: {}", self.malformed)
+    }
+}
+
+/// The clients and transactions that stand out in an input file, and the held funds and
+/// locked accounts it leaves behind. Only amounts in the base currency are ranked.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct TopReport {
+    /// Clients with the largest total funds, largest first.
+    pub largest_balances: Vec<(ClientId, Money)>,
+    /// Clients with the most disputes opened, most first.
+    pub most_disputed: Vec<(ClientId, u64)>,
+    /// Largest deposits and withdrawals applied, largest first.
+    pub largest_transactions: Vec<TopTransaction>,
+    pub held: f64,
+    pub locked: usize,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TopTransaction {
+    pub r#type: TxType,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: Money,
+}
+
+impl TopReport {
+    /// Applies `rows` to a throwaway engine and keeps the `top` entries of every ranking.
+    /// Ties are broken by client and tx id.
+    pub fn new(rows: Vec<InputRow>, top: usize) -> Self {
+        let mut engine = Engine::new();
+        let mut disputes: HashMap<ClientId, u64> = HashMap::new();
+        let mut transactions = Vec::new();
+
+        for record in rows.into_iter().filter_map(|row| row.record.ok()) {
+            let transaction = TopTransaction {
+                r#type: record.r#type,
+                client: record.client,
+                tx: record.tx,
+                amount: record.amount.unwrap_or_default(),
+            };
+            let base_currency = record.currency.is_none();
+            if engine.apply(record).is_err() {
+                continue;
+            }
+
+            match transaction.r#type {
+                TxType::Dispute => *disputes.entry(transaction.client).or_default() += 1,
+                TxType::Deposit | TxType::Withdrawal if base_currency => {
+                    transactions.push(transaction)
+                }
+                _ => {}
+            }
+        }
+
+        let mut largest_balances: Vec<(ClientId, Money)> = engine
+            .accounts_iter()
+            .map(|account| (account.client, account.total))
+            .collect();
+        largest_balances.sort_by(|a, b| b.1.to_f32().total_cmp(&a.1.to_f32()));
+        largest_balances.truncate(top);
+
+        let mut most_disputed: Vec<(ClientId, u64)> = disputes.into_iter().collect();
+        most_disputed.sort_by_key(|&(client, count)| (std::cmp::Reverse(count), client));
+        most_disputed.truncate(top);
+
+        transactions.sort_by_key(|transaction| (transaction.client, transaction.tx));
+        transactions.sort_by(|a, b| b.amount.to_f32().total_cmp(&a.amount.to_f32()));
+        transactions.truncate(top);
+
+        Self {
+            largest_balances,
+            most_disputed,
+            largest_transactions: transactions,
+            held: engine
+                .accounts()
+                .values()
+                .map(|account| f64::from(account.held))
+                .sum(),
+            locked: engine
+                .accounts()
+                .values()
+                .filter(|account| account.locked)
+                .count(),
+        }
+    }
+}
+
+impl fmt::Display for TopReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "largest balances:")?;
+        for (client, total) in &self.largest_balances {
+            writeln!(f, "  client {client}: {:.4}", total.to_f32())?;
+        }
+        writeln!(f, "most disputed clients:")?;
+        for (client, count) in &self.most_disputed {
+            writeln!(f, "  client {client}: {count}")?;
+        }
+        writeln!(f, "largest transactions:")?;
+        for transaction in &self.largest_transactions {
+            let name = serde_json::to_string(&transaction.r#type).map_err(|_| fmt::Error)?;
+            writeln!(
+                f,
+                "  {} {} by client {}: {:.4}",
+                name.trim_matches('"'),
+                transaction.tx,
+                transaction.client,
+                transaction.amount.to_f32()
+            )?;
+        }
+        writeln!(f, "held funds: {:.4}", self.held)?;
+        writeln!(f, "locked accounts: {}", self.locked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::{read_rows, InputFormat};
+
+    #[test]
+    fn batch_summary() {
+        let path = std::env::temp_dir().join("tx_accounts_stats.csv");
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             deposit,2,2,5.5\n\
+             withdrawal,1,3,20.0\n\
+             withdrawal,1,4,2.5\n\
+             dispute,1,1,\n\
+             dispute,2,2,\n\
+             resolve,2,2,\n\
+             chargeback,1,1,\n\
+             dispute,1,9,\n\
+             re-fund,1,5,1.0\n",
+        )
+        .unwrap();
+        let rows = read_rows(&path, InputFormat::Csv).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let summary = BatchSummary::new(rows);
+        assert_eq!(summary.rows, 10);
+        assert_eq!(
+            summary.counts,
+            BTreeMap::from([
+                (TxType::Deposit, 2),
+                (TxType::Withdrawal, 2),
+                (TxType::Dispute, 3),
+                (TxType::Resolve, 1),
+                (TxType::Chargeback, 1),
+            ])
+        );
+        assert_eq!(summary.deposit_volume, 15.5);
+        assert_eq!(summary.withdrawal_volume, 22.5);
+        assert_eq!(summary.clients, 2);
+        assert_eq!(summary.disputes_opened, 2);
+        assert_eq!(summary.disputes_resolved, 1);
+        assert_eq!(summary.charged_back, 1);
+        // The 20.0 withdrawal, the dispute on an unknown tx and the malformed row.
+        assert_eq!(summary.rejected, 3);
+        assert_eq!(summary.malformed, 1);
+    }
+
+    #[test]
+    fn top_report() {
+        let path = std::env::temp_dir().join("tx_accounts_top.csv");
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             deposit,2,2,50.0\n\
+             deposit,3,3,20.0\n\
+             withdrawal,2,4,30.0\n\
+             dispute,1,1,\n\
+             resolve,1,1,\n\
+             dispute,1,1,\n\
+             dispute,3,3,\n\
+             chargeback,3,3,\n\
+             dispute,3,9,\n",
+        )
+        .unwrap();
+        let rows = read_rows(&path, InputFormat::Csv).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let report = TopReport::new(rows, 2);
+        assert_eq!(
+            report.largest_balances,
+            vec![
+                (ClientId(2), Money::new(20.0)),
+                (ClientId(1), Money::new(10.0))
+            ]
+        );
+        assert_eq!(
+            report.most_disputed,
+            vec![(ClientId(1), 2), (ClientId(3), 1)]
+        );
+        let largest: Vec<TxId> = report.largest_transactions.iter().map(|t| t.tx).collect();
+        assert_eq!(largest, vec![TxId(2), TxId(4)]);
+        assert_eq!(report.held, 10.0);
+        assert_eq!(report.locked, 1);
+    }
+}
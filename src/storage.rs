@@ -0,0 +1,484 @@
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::{
+    amounts::Money,
+    records::Record,
+    transaction::{Account, ClientId, Hold, TxId},
+    tx_store::{TxLookup, TxStore},
+};
+
+/// Everything the transaction rules read and write: balances, the deposits and
+/// withdrawals that can be disputed and the open disputes. The rules in
+/// [`crate::transaction`] only go through this trait, so the state can live elsewhere than
+/// in memory without touching them.
+pub trait Storage: TxLookup {
+    fn account(&self, client: ClientId) -> Option<Account>;
+
+    /// Stores `account`, replacing the one of the same client.
+    fn put_account(&mut self, account: Account);
+
+    /// Keeps an applied deposit or withdrawal so that it can be disputed later on.
+    fn record_tx(&mut self, record: Record);
+
+    fn is_disputed(&self, client: ClientId, tx: TxId) -> bool;
+
+    /// Opens a dispute, counting it towards [`Storage::dispute_count`]. `amount` is set for a
+    /// dispute over part of the transaction, `opened` when the dispute has a timestamp.
+    fn open_dispute(
+        &mut self,
+        client: ClientId,
+        tx: TxId,
+        amount: Option<Money>,
+        opened: Option<DateTime<Utc>>,
+    );
+
+    /// The amount held by the open dispute on a transaction, if it disputes only part of
+    /// the transaction.
+    fn disputed_amount(&self, client: ClientId, tx: TxId) -> Option<Money>;
+
+    /// How many times the transaction was disputed, including a dispute still open.
+    fn dispute_count(&self, client: ClientId, tx: TxId) -> u32;
+
+    fn close_dispute(&mut self, client: ClientId, tx: TxId);
+
+    /// The amount the chargeback of a transaction took, until a representment reverses it.
+    fn charged_back(&self, client: ClientId, tx: TxId) -> Option<Money>;
+
+    fn set_charged_back(&mut self, client: ClientId, tx: TxId, amount: Option<Money>);
+
+    fn hold(&self, client: ClientId, tx: TxId) -> Option<Hold>;
+
+    /// Keeps a hold until it is captured or released.
+    fn put_hold(&mut self, hold: Hold);
+
+    fn remove_hold(&mut self, client: ClientId, tx: TxId);
+
+    /// Offset of the last record applied from `source`, e.g. a Kafka partition or a watched
+    /// file.
+    fn source_offset(&self, source: &str) -> Option<u64>;
+
+    /// Records that `source` was applied up to `offset`, or forgets about the source.
+    fn set_source_offset(&mut self, source: &str, offset: Option<u64>);
+}
+
+/// The default storage, which keeps everything in memory apart from whatever the
+/// [`TxStore`] spills to disk.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    pub(crate) accounts: HashMap<ClientId, Account>,
+    pub(crate) transactions: TxStore,
+    pub(crate) disputes: HashMap<ClientId, HashSet<TxId>>,
+    pub(crate) dispute_counts: HashMap<(ClientId, TxId), u32>,
+    pub(crate) partial_disputes: HashMap<(ClientId, TxId), Money>,
+    /// When the open disputes with a timestamp were opened.
+    pub(crate) dispute_opened: HashMap<(ClientId, TxId), DateTime<Utc>>,
+    pub(crate) chargebacks: HashMap<(ClientId, TxId), Money>,
+    pub(crate) holds: HashMap<(ClientId, TxId), Hold>,
+    /// How many open holds use each tx id, so that duplicates across clients are found
+    /// without scanning the holds.
+    hold_tx_ids: HashMap<TxId, u32>,
+    /// The open holds with a timestamp in the order they were placed, so that expired ones
+    /// are found without scanning the holds.
+    holds_placed: BTreeSet<(DateTime<Utc>, ClientId, TxId)>,
+    pub(crate) source_offsets: HashMap<String, u64>,
+    staged: Option<Box<Staged>>,
+}
+
+/// What a batch changed in a [`MemoryStorage`] until it is committed or rolled back: the
+/// transactions it recorded, which only reach the [`TxStore`] once the batch is committed,
+/// and the value before the batch of everything else it touched.
+#[derive(Debug, Default)]
+struct Staged {
+    transactions: HashMap<(ClientId, TxId), Record>,
+    tx_ids: HashSet<TxId>,
+    accounts: HashMap<ClientId, Option<Account>>,
+    disputed: HashMap<(ClientId, TxId), bool>,
+    dispute_counts: HashMap<(ClientId, TxId), Option<u32>>,
+    partial_disputes: HashMap<(ClientId, TxId), Option<Money>>,
+    dispute_opened: HashMap<(ClientId, TxId), Option<DateTime<Utc>>>,
+    chargebacks: HashMap<(ClientId, TxId), Option<Money>>,
+    holds: HashMap<(ClientId, TxId), Option<Hold>>,
+    source_offsets: HashMap<String, Option<u64>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts keeping what changes from now on, so that it can be undone by
+    /// [`MemoryStorage::roll_back_batch`].
+    pub(crate) fn begin_batch(&mut self) {
+        self.staged = Some(Box::default());
+    }
+
+    /// Keeps the changes since [`MemoryStorage::begin_batch`].
+    pub(crate) fn commit_batch(&mut self) {
+        let Some(staged) = self.staged.take() else {
+            return;
+        };
+
+        for record in staged.transactions.into_values() {
+            self.transactions.insert(record);
+        }
+    }
+
+    /// Undoes the changes since [`MemoryStorage::begin_batch`].
+    pub(crate) fn roll_back_batch(&mut self) {
+        let Some(staged) = self.staged.take() else {
+            return;
+        };
+
+        for (client, account) in staged.accounts {
+            match account {
+                Some(account) => self.accounts.insert(client, account),
+                None => self.accounts.remove(&client),
+            };
+        }
+        for ((client, tx), disputed) in staged.disputed {
+            let disputes = self.disputes.entry(client).or_default();
+            match disputed {
+                true => disputes.insert(tx),
+                false => disputes.remove(&tx),
+            };
+        }
+        restore(&mut self.dispute_counts, staged.dispute_counts);
+        restore(&mut self.partial_disputes, staged.partial_disputes);
+        restore(&mut self.dispute_opened, staged.dispute_opened);
+        restore(&mut self.chargebacks, staged.chargebacks);
+        for ((client, tx), hold) in staged.holds {
+            match hold {
+                Some(hold) => self.insert_hold(hold),
+                None => self.take_hold(client, tx),
+            }
+        }
+        restore(&mut self.source_offsets, staged.source_offsets);
+    }
+
+    fn stage_dispute(&mut self, client: ClientId, tx: TxId) {
+        let Some(staged) = &mut self.staged else {
+            return;
+        };
+
+        let key = (client, tx);
+        let disputed = self
+            .disputes
+            .get(&client)
+            .is_some_and(|disputes| disputes.contains(&tx));
+        staged.disputed.entry(key).or_insert(disputed);
+        stage(&mut staged.dispute_counts, &self.dispute_counts, &key);
+        stage(&mut staged.partial_disputes, &self.partial_disputes, &key);
+        stage(&mut staged.dispute_opened, &self.dispute_opened, &key);
+    }
+
+    pub fn accounts(&self) -> &HashMap<ClientId, Account> {
+        &self.accounts
+    }
+
+    /// Whether an open hold of any client uses the tx id `tx`.
+    pub(crate) fn contains_hold_tx_id(&self, tx: TxId) -> bool {
+        self.hold_tx_ids.contains_key(&tx)
+    }
+
+    /// Open holds placed at or before `until`, in the order they were placed.
+    pub(crate) fn holds_placed_until(&self, until: DateTime<Utc>) -> Vec<Hold> {
+        self.holds_placed
+            .iter()
+            .take_while(|&&(placed_at, _, _)| placed_at <= until)
+            .filter_map(|&(_, client, tx)| self.holds.get(&(client, tx)).copied())
+            .collect()
+    }
+
+    fn insert_hold(&mut self, hold: Hold) {
+        match self.holds.insert((hold.client, hold.tx), hold) {
+            Some(replaced) => self.unplace(&replaced),
+            None => *self.hold_tx_ids.entry(hold.tx).or_default() += 1,
+        }
+        if let Some(placed_at) = hold.placed_at {
+            self.holds_placed.insert((placed_at, hold.client, hold.tx));
+        }
+    }
+
+    fn take_hold(&mut self, client: ClientId, tx: TxId) {
+        let Some(hold) = self.holds.remove(&(client, tx)) else {
+            return;
+        };
+        self.unplace(&hold);
+        if let Some(count) = self.hold_tx_ids.get_mut(&tx) {
+            *count -= 1;
+            if *count == 0 {
+                self.hold_tx_ids.remove(&tx);
+            }
+        }
+    }
+
+    fn unplace(&mut self, hold: &Hold) {
+        if let Some(placed_at) = hold.placed_at {
+            self.holds_placed.remove(&(placed_at, hold.client, hold.tx));
+        }
+    }
+
+    pub fn transactions(&self) -> &TxStore {
+        &self.transactions
+    }
+}
+
+/// Puts back the values a batch replaced, removing the keys it added.
+fn restore<K, V>(map: &mut HashMap<K, V>, before: HashMap<K, Option<V>>)
+where
+    K: Eq + std::hash::Hash,
+{
+    for (key, value) in before {
+        match value {
+            Some(value) => map.insert(key, value),
+            None => map.remove(&key),
+        };
+    }
+}
+
+/// Keeps the value `key` had before the batch, unless an earlier change of the batch did.
+fn stage<K, V>(staged: &mut HashMap<K, Option<V>>, map: &HashMap<K, V>, key: &K)
+where
+    K: Eq + std::hash::Hash + Clone,
+    V: Clone,
+{
+    staged
+        .entry(key.clone())
+        .or_insert_with(|| map.get(key).cloned());
+}
+
+impl TxLookup for MemoryStorage {
+    fn find(&self, client: ClientId, tx: TxId) -> Option<Record> {
+        let staged = self.staged.as_ref();
+        staged
+            .and_then(|staged| staged.transactions.get(&(client, tx)).cloned())
+            .or_else(|| self.transactions.find(client, tx))
+    }
+
+    fn contains_tx_id(&self, tx: TxId) -> bool {
+        self.staged
+            .as_ref()
+            .is_some_and(|staged| staged.tx_ids.contains(&tx))
+            || self.transactions.contains_tx_id(tx)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.staged
+            .as_ref()
+            .is_none_or(|staged| staged.transactions.is_empty())
+            && self.transactions.is_empty()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn account(&self, client: ClientId) -> Option<Account> {
+        self.accounts.get(&client).cloned()
+    }
+
+    fn put_account(&mut self, account: Account) {
+        if let Some(staged) = &mut self.staged {
+            stage(&mut staged.accounts, &self.accounts, &account.client);
+        }
+        self.accounts.insert(account.client, account);
+    }
+
+    fn record_tx(&mut self, record: Record) {
+        match &mut self.staged {
+            Some(staged) => {
+                staged.tx_ids.insert(record.tx);
+                staged
+                    .transactions
+                    .insert((record.client, record.tx), record);
+            }
+            None => self.transactions.insert(record),
+        }
+    }
+
+    fn is_disputed(&self, client: ClientId, tx: TxId) -> bool {
+        self.disputes
+            .get(&client)
+            .is_some_and(|disputes| disputes.contains(&tx))
+    }
+
+    fn open_dispute(
+        &mut self,
+        client: ClientId,
+        tx: TxId,
+        amount: Option<Money>,
+        opened: Option<DateTime<Utc>>,
+    ) {
+        self.stage_dispute(client, tx);
+        self.disputes.entry(client).or_default().insert(tx);
+        *self.dispute_counts.entry((client, tx)).or_default() += 1;
+        if let Some(amount) = amount {
+            self.partial_disputes.insert((client, tx), amount);
+        }
+        if let Some(opened) = opened {
+            self.dispute_opened.insert((client, tx), opened);
+        }
+    }
+
+    fn disputed_amount(&self, client: ClientId, tx: TxId) -> Option<Money> {
+        self.partial_disputes.get(&(client, tx)).copied()
+    }
+
+    fn dispute_count(&self, client: ClientId, tx: TxId) -> u32 {
+        self.dispute_counts
+            .get(&(client, tx))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn close_dispute(&mut self, client: ClientId, tx: TxId) {
+        self.stage_dispute(client, tx);
+        if let Some(disputes) = self.disputes.get_mut(&client) {
+            disputes.remove(&tx);
+        }
+        self.partial_disputes.remove(&(client, tx));
+        self.dispute_opened.remove(&(client, tx));
+    }
+
+    fn charged_back(&self, client: ClientId, tx: TxId) -> Option<Money> {
+        self.chargebacks.get(&(client, tx)).copied()
+    }
+
+    fn set_charged_back(&mut self, client: ClientId, tx: TxId, amount: Option<Money>) {
+        if let Some(staged) = &mut self.staged {
+            stage(&mut staged.chargebacks, &self.chargebacks, &(client, tx));
+        }
+        match amount {
+            Some(amount) => self.chargebacks.insert((client, tx), amount),
+            None => self.chargebacks.remove(&(client, tx)),
+        };
+    }
+
+    fn hold(&self, client: ClientId, tx: TxId) -> Option<Hold> {
+        self.holds.get(&(client, tx)).copied()
+    }
+
+    fn put_hold(&mut self, hold: Hold) {
+        if let Some(staged) = &mut self.staged {
+            stage(&mut staged.holds, &self.holds, &(hold.client, hold.tx));
+        }
+        self.insert_hold(hold);
+    }
+
+    fn remove_hold(&mut self, client: ClientId, tx: TxId) {
+        if let Some(staged) = &mut self.staged {
+            stage(&mut staged.holds, &self.holds, &(client, tx));
+        }
+        self.take_hold(client, tx);
+    }
+
+    fn source_offset(&self, source: &str) -> Option<u64> {
+        self.source_offsets.get(source).copied()
+    }
+
+    fn set_source_offset(&mut self, source: &str, offset: Option<u64>) {
+        if let Some(staged) = &mut self.staged {
+            stage(
+                &mut staged.source_offsets,
+                &self.source_offsets,
+                &source.to_owned(),
+            );
+        }
+        match offset {
+            Some(offset) => self.source_offsets.insert(source.to_owned(), offset),
+            None => self.source_offsets.remove(source),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{DisputeHoldPolicy, LockedAccountPolicy, RedisputePolicy},
+        records::TxType,
+        transaction::{deposit, dispute, resolve},
+    };
+
+    #[test]
+    fn rules_go_through_storage() {
+        let mut storage = MemoryStorage::new();
+        let record = |r#type, amount| Record {
+            r#type,
+            client: ClientId(1),
+            tx: TxId(7),
+            amount,
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        };
+
+        let deposited = record(TxType::Deposit, Some(Money::new(10.0)));
+        deposit(&mut storage, &deposited).unwrap();
+        storage.record_tx(deposited);
+        dispute(
+            &mut storage,
+            &record(TxType::Dispute, None),
+            LockedAccountPolicy::default(),
+            DisputeHoldPolicy::default(),
+            RedisputePolicy::default(),
+        )
+        .unwrap();
+        assert!(storage.is_disputed(ClientId(1), TxId(7)));
+        assert_eq!(storage.account(ClientId(1)).unwrap().held, 10.0);
+
+        resolve(
+            &mut storage,
+            &record(TxType::Resolve, None),
+            LockedAccountPolicy::default(),
+        )
+        .unwrap();
+        assert!(!storage.is_disputed(ClientId(1), TxId(7)));
+        assert_eq!(storage.account(ClientId(1)).unwrap().available, 10.0);
+        assert!(storage.contains_tx_id(TxId(7)));
+        assert_eq!(storage.dispute_count(ClientId(7), TxId(1)), 0);
+        assert_eq!(storage.dispute_count(ClientId(1), TxId(7)), 1);
+    }
+
+    #[test]
+    fn hold_indexes_follow_the_holds() {
+        let mut storage = MemoryStorage::new();
+        let placed_at = |client| format!("2024-01-0{client}T00:00:00Z").parse().ok();
+        let hold = |client| Hold {
+            client: ClientId(client),
+            tx: TxId(9),
+            amount: Money::new(1.0),
+            currency: None,
+            placed_at: placed_at(client),
+        };
+        let placed_until = |storage: &MemoryStorage, client| {
+            let until = placed_at(client).unwrap();
+            let holds = storage.holds_placed_until(until).into_iter();
+            holds.map(|hold| hold.client.0).collect::<Vec<_>>()
+        };
+
+        storage.put_hold(hold(1));
+        storage.begin_batch();
+        storage.put_hold(hold(2));
+        storage.remove_hold(ClientId(1), TxId(9));
+        assert!(storage.contains_hold_tx_id(TxId(9)));
+        storage.remove_hold(ClientId(2), TxId(9));
+        assert!(!storage.contains_hold_tx_id(TxId(9)));
+
+        assert!(placed_until(&storage, 2).is_empty());
+
+        storage.roll_back_batch();
+        assert!(storage.contains_hold_tx_id(TxId(9)));
+        storage.put_hold(hold(3));
+        assert_eq!(placed_until(&storage, 2), vec![1]);
+        assert_eq!(placed_until(&storage, 3), vec![1, 3]);
+        storage.remove_hold(ClientId(1), TxId(9));
+        storage.remove_hold(ClientId(3), TxId(9));
+        assert!(!storage.contains_hold_tx_id(TxId(9)));
+        assert!(placed_until(&storage, 3).is_empty());
+    }
+}
@@ -0,0 +1,332 @@
+use chrono::{DateTime, Utc};
+
+use crate::{
+    amounts::Money,
+    currency::Currency,
+    records::{Record, TxType},
+    transaction::{Account, ClientId, Engine, RejectionReason, TxId},
+};
+
+/// Small builder for readable scenario tests against the engine. Every
+/// transaction is applied as soon as it is added, so expectations can be
+/// interleaved with the records they depend on.
+///
+/// ```ignore
+/// Scenario::new()
+///     .deposit(1, 1, 100)
+///     .dispute(1, 1)
+///     .expect_held(1, 100);
+/// ```
+#[derive(Debug, Default)]
+pub struct Scenario {
+    engine: Engine,
+    last_result: Option<Result<(), RejectionReason>>,
+    currency: Option<Currency>,
+    timestamp: Option<DateTime<Utc>>,
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Continues a scenario on top of an existing engine.
+    pub fn from_engine(engine: Engine) -> Self {
+        Self {
+            engine,
+            last_result: None,
+            currency: None,
+            timestamp: None,
+        }
+    }
+
+    /// Makes the deposits, withdrawals and disputes added from now on use `currency`, or
+    /// the base currency for `None`.
+    pub fn in_currency(mut self, currency: Option<&str>) -> Self {
+        self.currency = currency.map(|currency| currency.parse().expect("valid currency"));
+        self
+    }
+
+    /// Dates the records added from now on, e.g. `at("2024-01-02T10:00:00Z")`.
+    pub fn at(mut self, timestamp: &str) -> Self {
+        self.timestamp = Some(timestamp.parse().expect("valid timestamp"));
+        self
+    }
+
+    pub fn apply(mut self, record: Record) -> Self {
+        self.last_result = Some(self.engine.apply(record));
+        self
+    }
+
+    pub fn deposit(
+        self,
+        client: impl Into<ClientId>,
+        tx: impl Into<TxId>,
+        amount: impl Into<f64>,
+    ) -> Self {
+        let record = self.record(TxType::Deposit, client, tx, Some(amount.into()));
+        self.apply(record)
+    }
+
+    pub fn withdraw(
+        self,
+        client: impl Into<ClientId>,
+        tx: impl Into<TxId>,
+        amount: impl Into<f64>,
+    ) -> Self {
+        let record = self.record(TxType::Withdrawal, client, tx, Some(amount.into()));
+        self.apply(record)
+    }
+
+    pub fn dispute(self, client: impl Into<ClientId>, tx: impl Into<TxId>) -> Self {
+        let record = self.record(TxType::Dispute, client, tx, None);
+        self.apply(record)
+    }
+
+    /// Disputes `amount` of the transaction `tx` only.
+    pub fn dispute_part(
+        self,
+        client: impl Into<ClientId>,
+        tx: impl Into<TxId>,
+        amount: impl Into<f64>,
+    ) -> Self {
+        let record = self.record(TxType::Dispute, client, tx, Some(amount.into()));
+        self.apply(record)
+    }
+
+    pub fn resolve(self, client: impl Into<ClientId>, tx: impl Into<TxId>) -> Self {
+        let record = self.record(TxType::Resolve, client, tx, None);
+        self.apply(record)
+    }
+
+    pub fn chargeback(self, client: impl Into<ClientId>, tx: impl Into<TxId>) -> Self {
+        let record = self.record(TxType::Chargeback, client, tx, None);
+        self.apply(record)
+    }
+
+    pub fn representment(self, client: impl Into<ClientId>, tx: impl Into<TxId>) -> Self {
+        let record = self.record(TxType::Representment, client, tx, None);
+        self.apply(record)
+    }
+
+    pub fn fee(
+        self,
+        client: impl Into<ClientId>,
+        tx: impl Into<TxId>,
+        amount: impl Into<f64>,
+    ) -> Self {
+        let record = self.record(TxType::Fee, client, tx, Some(amount.into()));
+        self.apply(record)
+    }
+
+    pub fn hold(
+        self,
+        client: impl Into<ClientId>,
+        tx: impl Into<TxId>,
+        amount: impl Into<f64>,
+    ) -> Self {
+        let record = self.record(TxType::Hold, client, tx, Some(amount.into()));
+        self.apply(record)
+    }
+
+    /// Captures the hold `tx`, all of it for an `amount` of `None`.
+    pub fn capture(
+        self,
+        client: impl Into<ClientId>,
+        tx: impl Into<TxId>,
+        amount: Option<f64>,
+    ) -> Self {
+        let record = self.record(TxType::Capture, client, tx, amount);
+        self.apply(record)
+    }
+
+    pub fn release(self, client: impl Into<ClientId>, tx: impl Into<TxId>) -> Self {
+        let record = self.record(TxType::Release, client, tx, None);
+        self.apply(record)
+    }
+
+    /// Expects the client to have `available` and `total` in the current currency of the
+    /// scenario, as an `assert` record.
+    pub fn assert_balance(
+        self,
+        client: impl Into<ClientId>,
+        tx: impl Into<TxId>,
+        available: Option<f64>,
+        total: Option<f64>,
+    ) -> Self {
+        let record = Record {
+            total: total.map(|total| Money::new(total as f32)),
+            ..self.record(TxType::Assert, client, tx, available)
+        };
+        self.apply(record)
+    }
+
+    /// Converts `amount` from the current currency of the scenario to `to`, or to the base
+    /// currency for `None`.
+    pub fn convert(
+        self,
+        client: impl Into<ClientId>,
+        tx: impl Into<TxId>,
+        amount: impl Into<f64>,
+        to: Option<&str>,
+    ) -> Self {
+        let record = Record {
+            to_currency: to.map(|to| to.parse().expect("valid currency")),
+            ..self.record(TxType::Convert, client, tx, Some(amount.into()))
+        };
+        self.apply(record)
+    }
+
+    pub fn open_account(self, client: impl Into<ClientId>) -> Self {
+        self.apply(record(TxType::OpenAccount, client, TxId(0), None))
+    }
+
+    pub fn lock(self, client: impl Into<ClientId>) -> Self {
+        self.apply(record(TxType::Lock, client, TxId(0), None))
+    }
+
+    pub fn unlock(self, client: impl Into<ClientId>) -> Self {
+        self.apply(record(TxType::Unlock, client, TxId(0), None))
+    }
+
+    #[track_caller]
+    pub fn expect_available(self, client: impl Into<ClientId>, amount: impl Into<f64>) -> Self {
+        let client = client.into();
+        let account = self.account(client);
+        assert_eq!(
+            account.available,
+            amount.into() as f32,
+            "available of client {client}"
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_held(self, client: impl Into<ClientId>, amount: impl Into<f64>) -> Self {
+        let client = client.into();
+        let account = self.account(client);
+        assert_eq!(
+            account.held,
+            amount.into() as f32,
+            "held of client {client}"
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_total(self, client: impl Into<ClientId>, amount: impl Into<f64>) -> Self {
+        let client = client.into();
+        let account = self.account(client);
+        assert_eq!(
+            account.total,
+            amount.into() as f32,
+            "total of client {client}"
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_locked(self, client: impl Into<ClientId>, locked: bool) -> Self {
+        let client = client.into();
+        let account = self.account(client);
+        assert_eq!(account.locked, locked, "locked of client {client}");
+        self
+    }
+
+    /// Checks the available and held funds of `client` in `currency`.
+    #[track_caller]
+    pub fn expect_balance(
+        self,
+        client: impl Into<ClientId>,
+        currency: &str,
+        available: impl Into<f64>,
+        held: impl Into<f64>,
+    ) -> Self {
+        let client = client.into();
+        let balance = self
+            .account(client)
+            .balance(Some(currency.parse().expect("valid currency")));
+        assert_eq!(
+            (balance.available, balance.held),
+            (
+                Money::new(available.into() as f32),
+                Money::new(held.into() as f32)
+            ),
+            "{currency} available and held of client {client}"
+        );
+        self
+    }
+
+    /// Checks that the most recently added transaction was rejected for `reason`.
+    #[track_caller]
+    pub fn expect_rejected(self, reason: RejectionReason) -> Self {
+        assert_eq!(
+            self.last_result,
+            Some(Err(reason)),
+            "outcome of the last transaction"
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_no_account(self, client: impl Into<ClientId>) -> Self {
+        let client = client.into();
+        assert!(
+            self.engine.account(client).is_none(),
+            "client {client} should not have an account"
+        );
+        self
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn into_engine(self) -> Engine {
+        self.engine
+    }
+
+    fn record(
+        &self,
+        r#type: TxType,
+        client: impl Into<ClientId>,
+        tx: impl Into<TxId>,
+        amount: Option<f64>,
+    ) -> Record {
+        Record {
+            currency: self.currency,
+            timestamp: self.timestamp,
+            ..record(r#type, client, tx, amount)
+        }
+    }
+
+    #[track_caller]
+    fn account(&self, client: ClientId) -> &Account {
+        match self.engine.account(client) {
+            Some(account) => account,
+            None => panic!("client {client} has no account"),
+        }
+    }
+}
+
+fn record(
+    r#type: TxType,
+    client: impl Into<ClientId>,
+    tx: impl Into<TxId>,
+    amount: Option<f64>,
+) -> Record {
+    Record {
+        r#type,
+        client: client.into(),
+        tx: tx.into(),
+        amount: amount.map(|amount| Money::new(amount as f32)),
+        timestamp: None,
+        currency: None,
+        to_currency: None,
+        total: None,
+        reason_code: None,
+        memo: None,
+        reference: None,
+        merchant_id: None,
+    }
+}
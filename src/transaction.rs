@@ -1,295 +1,2086 @@
-use serde::{Serialize, Serializer};
-use std::collections::{HashMap, HashSet};
-
-use crate::records::{Record, TxType};
-
-pub type ClientId = u16;
-pub type TxId = u32;
-
-#[derive(Debug, Serialize, PartialEq, Default)]
-pub struct AccountRecord {
-    pub client: u16,
-    #[serde(serialize_with = "serialize_f32_4dp")]
-    pub available: f32,
-    #[serde(serialize_with = "serialize_f32_4dp")]
-    pub held: f32,
-    #[serde(serialize_with = "serialize_f32_4dp")]
-    pub total: f32,
-    pub locked: bool,
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt,
+    num::ParseIntError,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::{
+    amounts::{deserialize_4dp, serialize_4dp, Money},
+    config::{
+        AccountPolicy, BatchPolicy, ChargebackPolicy, DisputeHoldPolicy, DuplicateScope,
+        EngineConfig, LockedAccountPolicy, RedisputePolicy, RepresentmentPolicy,
+    },
+    currency::Currency,
+    events::AccountEvent,
+    fx::{FxRounding, RateTable},
+    handler::TxHandler,
+    hash::{FastMap, FastSet},
+    history::AppliedTx,
+    ids::IdAllocator,
+    integrity::{check_integrity, Finding, IntegrityIssue, IntegrityPolicy},
+    metrics::RejectionMetrics,
+    observer::{EngineObserver, HeldBack},
+    records::{CustomType, Record, TxType},
+    rules::{RuleViolation, Velocity, VelocityMap},
+    storage::{MemoryStorage, Storage},
+    tx_store::{TxLookup, TxStore},
+};
+
+/// Integer behind a [`ClientId`]: `u16`, or `u32` with the `wide-ids` feature.
+#[cfg(not(feature = "wide-ids"))]
+pub type RawClientId = u16;
+#[cfg(feature = "wide-ids")]
+pub type RawClientId = u32;
+
+/// Integer behind a [`TxId`]: `u32`, or `u64` with the `wide-ids` feature.
+#[cfg(not(feature = "wide-ids"))]
+pub type RawTxId = u32;
+#[cfg(feature = "wide-ids")]
+pub type RawTxId = u64;
+
+/// Id of a client, and of its account.
+#[derive(
+    Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Clone, Copy,
+)]
+#[serde(transparent)]
+pub struct ClientId(pub RawClientId);
+
+/// Id of a transaction, unique across clients.
+#[derive(
+    Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Clone, Copy,
+)]
+#[serde(transparent)]
+pub struct TxId(pub RawTxId);
+
+impl ClientId {
+    /// The client id `id`, unless it does not fit in a [`RawClientId`].
+    pub fn from_u64(id: u64) -> Option<Self> {
+        RawClientId::try_from(id).ok().map(Self)
+    }
 }
 
-pub fn process_records(records: Vec<Record>) -> HashMap<ClientId, AccountRecord> {
-    let mut result: HashMap<ClientId, AccountRecord> = HashMap::new();
-    let mut processed_records: HashMap<(ClientId, TxId), Record> = HashMap::new();
-    let mut disputes: HashMap<ClientId, HashSet<TxId>> = HashMap::new();
+impl TxId {
+    pub const MAX: Self = Self(RawTxId::MAX);
 
-    for record in records {
-        if matches!(record.r#type, TxType::Deposit | TxType::Withdrawal)
-            && processed_records
-                .keys()
-                .any(|&(_, tx_id)| tx_id == record.tx)
-        {
-            continue;
+    /// The tx id `id`, unless it does not fit in a [`RawTxId`].
+    // Infallible with `wide-ids`.
+    #[allow(clippy::unnecessary_fallible_conversions)]
+    pub fn from_u64(id: u64) -> Option<Self> {
+        RawTxId::try_from(id).ok().map(Self)
+    }
+
+    // A no-op with `wide-ids`.
+    #[allow(clippy::useless_conversion)]
+    pub fn to_u64(self) -> u64 {
+        self.0.into()
+    }
+}
+
+impl From<RawClientId> for ClientId {
+    fn from(id: RawClientId) -> Self {
+        Self(id)
+    }
+}
+
+impl From<RawTxId> for TxId {
+    fn from(id: RawTxId) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl fmt::Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for ClientId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+impl FromStr for TxId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+/// An account with its balances in the base currency, the one of records without a
+/// currency, and in every other currency it ever held.
+///
+/// Its methods keep `total = available + held` in every currency. Only `deposit` and
+/// `withdraw` reject a locked account; whether the funds of a locked account can still be
+/// held, released or charged back is up to the caller's [`LockedAccountPolicy`].
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default, Clone)]
+pub struct Account {
+    pub client: ClientId,
+    #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+    pub available: Money,
+    #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+    pub held: Money,
+    #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+    pub total: Money,
+    pub locked: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub currencies: BTreeMap<Currency, Balance>,
+}
+
+impl Account {
+    /// The balance in `currency`, or in the base currency for `None`. Zero for a currency
+    /// the account never held.
+    pub fn balance(&self, currency: Option<Currency>) -> Balance {
+        match currency {
+            None => Balance {
+                available: self.available,
+                held: self.held,
+                total: self.total,
+            },
+            Some(currency) => self.currencies.get(&currency).copied().unwrap_or_default(),
         }
+    }
 
-        match record.r#type {
-            TxType::Deposit => {
-                deposit(&mut result, &record);
-                processed_records.insert((record.client, record.tx), record);
+    /// How far the available funds in the base currency are below zero, e.g. after
+    /// withdrawals on credit.
+    pub fn credit_used(&self) -> Money {
+        (-self.available).max(Money::ZERO)
+    }
+
+    pub fn set_balance(&mut self, currency: Option<Currency>, balance: Balance) {
+        match currency {
+            None => {
+                self.available = balance.available;
+                self.held = balance.held;
+                self.total = balance.total;
             }
-            TxType::Withdrawal => {
-                withdraw(&mut result, &record);
-                processed_records.insert((record.client, record.tx), record);
+            Some(currency) => {
+                self.currencies.insert(currency, balance);
             }
-            TxType::Dispute => dispute(&mut result, &mut disputes, &processed_records, &record),
-            TxType::Resolve => resolve(&mut result, &mut disputes, &processed_records, &record),
-            TxType::Chargeback => {
-                chargeback(&mut result, &mut disputes, &processed_records, &record)
+        }
+    }
+
+    pub fn deposit(
+        &mut self,
+        currency: Option<Currency>,
+        amount: Money,
+    ) -> Result<(), RejectionReason> {
+        if amount <= Money::ZERO {
+            return Err(RejectionReason::InvalidAmount);
+        }
+
+        if self.locked {
+            return Err(RejectionReason::AccountLocked);
+        }
+
+        self.adjust(currency, amount, Money::ZERO);
+        Ok(())
+    }
+
+    /// Withdraws `amount`, taking the available funds down to `-credit_limit` at most.
+    pub fn withdraw(
+        &mut self,
+        currency: Option<Currency>,
+        amount: Money,
+        credit_limit: Money,
+    ) -> Result<(), RejectionReason> {
+        if amount <= Money::ZERO {
+            return Err(RejectionReason::InvalidAmount);
+        }
+
+        if self.locked {
+            return Err(RejectionReason::AccountLocked);
+        }
+
+        if self.balance(currency).available < amount - credit_limit {
+            return Err(if credit_limit > 0.0 {
+                RejectionReason::CreditLimitExceeded
+            } else {
+                RejectionReason::InsufficientFunds
+            });
+        }
+
+        self.adjust(currency, -amount, Money::ZERO);
+        Ok(())
+    }
+
+    /// Moves `amount` from the available to the held funds. Under
+    /// [`DisputeHoldPolicy::RequireFunds`] the available funds have to cover it.
+    pub fn hold(
+        &mut self,
+        currency: Option<Currency>,
+        amount: Money,
+        policy: DisputeHoldPolicy,
+    ) -> Result<(), RejectionReason> {
+        if amount <= Money::ZERO {
+            return Err(RejectionReason::InvalidAmount);
+        }
+
+        if self.balance(currency).available < amount && policy == DisputeHoldPolicy::RequireFunds {
+            return Err(RejectionReason::InsufficientFunds);
+        }
+
+        self.adjust(currency, -amount, amount);
+        Ok(())
+    }
+
+    /// Moves `amount` from the held funds back to the available ones.
+    pub fn release(
+        &mut self,
+        currency: Option<Currency>,
+        amount: Money,
+    ) -> Result<(), RejectionReason> {
+        if amount < Money::ZERO {
+            return Err(RejectionReason::InvalidAmount);
+        }
+
+        self.adjust(currency, amount, -amount);
+        Ok(())
+    }
+
+    /// Takes `amount` out of the held funds and locks the account.
+    pub fn charge_back(
+        &mut self,
+        currency: Option<Currency>,
+        amount: Money,
+    ) -> Result<(), RejectionReason> {
+        if amount < Money::ZERO {
+            return Err(RejectionReason::InvalidAmount);
+        }
+
+        self.adjust(currency, Money::ZERO, -amount);
+        self.locked = true;
+        Ok(())
+    }
+
+    pub fn lock(&mut self) -> Result<(), RejectionReason> {
+        if self.locked {
+            return Err(RejectionReason::AccountLocked);
+        }
+
+        self.locked = true;
+        Ok(())
+    }
+
+    pub fn unlock(&mut self) -> Result<(), RejectionReason> {
+        if !self.locked {
+            return Err(RejectionReason::NotLocked);
+        }
+
+        self.locked = false;
+        Ok(())
+    }
+
+    /// Adds `available` and `held` to the funds in `currency`, which may be negative, and
+    /// recomputes the total.
+    pub(crate) fn adjust(&mut self, currency: Option<Currency>, available: Money, held: Money) {
+        let mut balance = self.balance(currency);
+        balance.available += available;
+        balance.held += held;
+        balance.total = balance.available + balance.held;
+        self.set_balance(currency, balance);
+    }
+}
+
+/// The funds of an account in one currency.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default, Clone, Copy)]
+pub struct Balance {
+    #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+    pub available: Money,
+    #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+    pub held: Money,
+    #[serde(serialize_with = "serialize_4dp", deserialize_with = "deserialize_4dp")]
+    pub total: Money,
+}
+
+/// Funds moved from available to held by a `hold` record until a `capture` or `release`
+/// settles them.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub struct Hold {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: Money,
+    pub currency: Option<Currency>,
+    /// Timestamp of the `hold` record, from which [`EngineConfig::hold_expiry`] counts.
+    pub placed_at: Option<DateTime<Utc>>,
+}
+
+/// Money movements in the base currency applied to a client during a run, net of
+/// chargebacks.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default, Clone)]
+pub struct ClientActivity {
+    pub client: ClientId,
+    pub net_deposited: Money,
+    pub net_withdrawn: Money,
+    pub chargebacks: u64,
+    pub charged_back: Money,
+    #[serde(default)]
+    pub fees: Money,
+}
+
+/// An account opened implicitly by a deposit, reported under [`AccountPolicy::Report`].
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy)]
+pub struct AutoCreatedAccount {
+    pub client: ClientId,
+    pub tx: TxId,
+}
+
+/// A dispute that was neither resolved nor charged back yet.
+#[derive(Debug, Serialize, PartialEq, Clone, Copy)]
+pub struct OpenDispute {
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    /// The held amount: all of the disputed transaction, or the part a partial dispute
+    /// holds.
+    #[serde(serialize_with = "serialize_4dp")]
+    pub amount: Money,
+    /// Timestamp of the dispute record, if it had one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opened: Option<DateTime<Utc>>,
+}
+
+/// An `assert` record whose expected balances did not match the account.
+#[derive(Debug, Serialize, PartialEq, Clone, Copy)]
+pub struct FailedAssertion {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub currency: Option<Currency>,
+    pub expected_available: Option<Money>,
+    pub expected_total: Option<Money>,
+    pub available: Money,
+    pub total: Money,
+}
+
+impl fmt::Display for FailedAssertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "assert tx {}: client {} has {:.4} available and {:.4} in total",
+            self.tx, self.client, self.available, self.total
+        )?;
+        if let Some(currency) = &self.currency {
+            write!(f, " in {}", currency.as_str())?;
+        }
+        match (self.expected_available, self.expected_total) {
+            (Some(available), Some(total)) => {
+                write!(f, ", expected {available:.4} and {total:.4}")
             }
+            (Some(available), None) => write!(f, ", expected {available:.4} available"),
+            (None, Some(total)) => write!(f, ", expected {total:.4} in total"),
+            (None, None) => Ok(()),
         }
     }
+}
 
-    result
+/// Why a transaction was not applied. Serialized as a machine-readable reason code.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionReason {
+    /// The row could not be parsed into a transaction; produced by the reader, never by
+    /// the engine.
+    Malformed,
+    DuplicateTx,
+    InvalidAmount,
+    UnknownAccount,
+    AccountLocked,
+    InsufficientFunds,
+    /// The tx id belongs to a transaction of another client.
+    TxNotFoundForClient,
+    /// No deposit or withdrawal with this tx id was ever applied.
+    TxNeverSeen,
+    AlreadyDisputed,
+    NotDisputed,
+    /// The tx id belongs to the range reserved for engine-generated transactions.
+    ReservedTxId,
+    /// The account holds less than the amount to charge back.
+    InsufficientHeldFunds,
+    /// An `open_account` record for a client whose account already exists.
+    AccountExists,
+    /// An `unlock` record for an account that is not locked.
+    NotLocked,
+    /// A dispute, resolve or chargeback in another currency than the disputed transaction.
+    CurrencyMismatch,
+    /// A `convert` record between currencies the rate table has no rate for.
+    MissingRate,
+    /// A `capture` or `release` without an open hold of this tx id.
+    UnknownHold,
+    /// A deposit or withdrawal that breaks one of the engine's rules, see
+    /// [`Engine::rule_violations`].
+    LimitExceeded,
+    /// A withdrawal that would take the account further below zero than its credit limit.
+    CreditLimitExceeded,
+    /// Any record of a client on the blocklist, see [`Engine::block`].
+    ClientBlocked,
+    /// A dispute on a transaction that was disputed as often as the re-dispute policy
+    /// allows.
+    DisputeLimitReached,
+    /// A representment for a transaction without a chargeback to reverse.
+    NotChargedBack,
+    /// A record of a custom type no handler is registered for, see
+    /// [`Engine::register_handler`].
+    UnsupportedType,
 }
 
-pub fn deposit(result: &mut HashMap<ClientId, AccountRecord>, record: &Record) {
-    if let Some(amount) = record.amount {
-        if amount <= 0 as f32 {
-            return;
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = match self {
+            RejectionReason::Malformed => "malformed",
+            RejectionReason::DuplicateTx => "duplicate_tx",
+            RejectionReason::InvalidAmount => "invalid_amount",
+            RejectionReason::UnknownAccount => "unknown_account",
+            RejectionReason::AccountLocked => "account_locked",
+            RejectionReason::InsufficientFunds => "insufficient_funds",
+            RejectionReason::TxNotFoundForClient => "tx_not_found_for_client",
+            RejectionReason::TxNeverSeen => "tx_never_seen",
+            RejectionReason::AlreadyDisputed => "already_disputed",
+            RejectionReason::NotDisputed => "not_disputed",
+            RejectionReason::ReservedTxId => "reserved_tx_id",
+            RejectionReason::InsufficientHeldFunds => "insufficient_held_funds",
+            RejectionReason::AccountExists => "account_exists",
+            RejectionReason::NotLocked => "not_locked",
+            RejectionReason::CurrencyMismatch => "currency_mismatch",
+            RejectionReason::MissingRate => "missing_rate",
+            RejectionReason::UnknownHold => "unknown_hold",
+            RejectionReason::LimitExceeded => "limit_exceeded",
+            RejectionReason::CreditLimitExceeded => "credit_limit_exceeded",
+            RejectionReason::ClientBlocked => "client_blocked",
+            RejectionReason::DisputeLimitReached => "dispute_limit_reached",
+            RejectionReason::NotChargedBack => "not_charged_back",
+            RejectionReason::UnsupportedType => "unsupported_type",
+        };
+
+        f.write_str(code)
+    }
+}
+
+/// Outcome of [`Engine::apply_batch`].
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct BatchResult {
+    /// Result of each record, up to the one that rolled the batch back if it was.
+    pub results: Vec<Result<(), RejectionReason>>,
+    /// Whether the batch was rolled back, leaving the engine as it was before it.
+    pub rolled_back: bool,
+}
+
+/// What [`Engine::apply_batch`] needs to roll back a batch besides what the storage keeps:
+/// the value before the batch of everything the batch touched.
+#[derive(Debug, Default)]
+struct BatchUndo {
+    activity: FastMap<ClientId, Option<ClientActivity>>,
+    velocity: FastMap<(ClientId, Option<Currency>), Option<Velocity>>,
+    history: FastMap<ClientId, usize>,
+    auto_created: usize,
+    failed_assertions: usize,
+    rule_violations: usize,
+    deferred: Vec<Record>,
+}
+
+#[derive(Debug, Default)]
+pub struct Engine {
+    storage: MemoryStorage,
+    /// Deposits and withdrawals of earlier runs that are past the dispute lookback. They
+    /// can no longer be disputed, but sending them again is still a duplicate.
+    retired: FastMap<(ClientId, TxId), Option<Money>>,
+    retired_tx_ids: FastSet<TxId>,
+    metrics: Arc<RejectionMetrics>,
+    id_allocator: Box<dyn IdAllocator>,
+    activity: FastMap<ClientId, ClientActivity>,
+    auto_created: Vec<AutoCreatedAccount>,
+    failed_assertions: Vec<FailedAssertion>,
+    velocity: VelocityMap,
+    rule_violations: Vec<RuleViolation>,
+    config: EngineConfig,
+    observers: Vec<Box<dyn EngineObserver>>,
+    /// Records dated after this are deferred rather than applied.
+    clock: Option<DateTime<Utc>>,
+    /// Deferred records in timestamp order.
+    deferred: Vec<Record>,
+    /// Applied records per client, kept only once [`Engine::keep_history`] was called.
+    history: Option<FastMap<ClientId, Vec<AppliedTx>>>,
+    /// Set while [`Engine::apply_batch`] applies a batch.
+    undo: Option<BatchUndo>,
+    handlers: FastMap<CustomType, Box<dyn TxHandler>>,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an engine that counts its rejections into `metrics`, which may be shared
+    /// with other threads.
+    pub fn with_metrics(metrics: Arc<RejectionMetrics>) -> Self {
+        Self {
+            metrics,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
+        let mut engine = Self {
+            clock: config.as_of,
+            config,
+            ..Self::default()
+        };
+        engine.set_tx_store(TxStore::new());
+        engine
+    }
+
+    /// Replaces the history of applied transactions, e.g. with one that spills to disk.
+    /// Meant to be called before any transaction is applied.
+    pub fn set_tx_store(&mut self, tx_store: TxStore) {
+        self.storage.transactions = tx_store;
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    pub fn set_metrics(&mut self, metrics: Arc<RejectionMetrics>) {
+        self.metrics = metrics;
+    }
+
+    pub fn metrics(&self) -> &Arc<RejectionMetrics> {
+        &self.metrics
+    }
+
+    pub fn set_id_allocator(&mut self, id_allocator: Box<dyn IdAllocator>) {
+        self.id_allocator = id_allocator;
+    }
+
+    /// Adds an observer that is told about every record applied or rejected from now on.
+    pub fn add_observer(&mut self, observer: Box<dyn EngineObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Hands the records of the custom type `name` to `handler` from now on. Fails if
+    /// `name` is a built-in type or not a valid type name, see [`CustomType`].
+    pub fn register_handler(
+        &mut self,
+        name: &str,
+        handler: Box<dyn TxHandler>,
+    ) -> Result<(), String> {
+        match name.parse()? {
+            TxType::Custom(custom) => {
+                self.handlers.insert(custom, handler);
+                Ok(())
+            }
+            _ => Err(format!("'{name}' is a built-in transaction type")),
+        }
+    }
+
+    /// Makes room for `additional` more deposits and withdrawals, e.g. ahead of a batch of
+    /// that many records.
+    pub fn reserve(&mut self, additional: usize) {
+        self.storage.transactions.reserve(additional);
+    }
+
+    /// Lets every observer write out what it buffers, e.g. at the end of a run.
+    pub fn flush_observers(&mut self) -> Result<(), Box<dyn Error>> {
+        for observer in &mut self.observers {
+            observer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Allocates a tx id for a transaction generated by the engine itself.
+    pub fn next_tx_id(&mut self) -> Option<TxId> {
+        self.id_allocator.next_id()
+    }
+
+    /// The last tx id allocated with [`Engine::next_tx_id`], see [`IdAllocator::last_id`].
+    pub fn last_generated_tx(&self) -> Option<TxId> {
+        self.id_allocator.last_id()
+    }
+
+    /// Carries on allocating tx ids after `last`, the last one allocated by an earlier run.
+    pub fn restore_last_generated_tx(&mut self, last: TxId) {
+        self.id_allocator.skip_past(last);
+    }
+
+    /// Applies `record`, or defers it if it is dated after the clock, see
+    /// [`Engine::set_clock`]. A deferred record is neither applied nor rejected.
+    pub fn apply(&mut self, record: Record) -> Result<(), RejectionReason> {
+        self.apply_with(record, |reason| reason)
+    }
+
+    /// Applies `records` in order as one batch. Under [`BatchPolicy::AllOrNothing`] the
+    /// first rejected record rolls back the whole batch, and the records after it are not
+    /// applied. Observers only hear about a batch once it was committed, while rejections
+    /// are counted into the metrics either way.
+    pub fn apply_batch(&mut self, records: &[Record]) -> BatchResult {
+        let observers = std::mem::take(&mut self.observers);
+        let held_back = Arc::new(Mutex::new(Vec::new()));
+        if !observers.is_empty() {
+            self.observers
+                .push(Box::new(HeldBack(Arc::clone(&held_back))));
+        }
+        self.storage.begin_batch();
+        self.undo = Some(BatchUndo {
+            auto_created: self.auto_created.len(),
+            failed_assertions: self.failed_assertions.len(),
+            rule_violations: self.rule_violations.len(),
+            deferred: self.deferred.clone(),
+            ..Default::default()
+        });
+
+        let mut result = BatchResult::default();
+        for record in records {
+            let applied = self.apply(record.clone());
+            result.results.push(applied);
+            if applied.is_err() && self.config.batch_policy == BatchPolicy::AllOrNothing {
+                result.rolled_back = true;
+                break;
+            }
+        }
+
+        self.observers = observers;
+        let undo = self.undo.take().unwrap_or_default();
+        if result.rolled_back {
+            self.storage.roll_back_batch();
+            self.roll_back(undo);
+            return result;
+        }
+
+        self.storage.commit_batch();
+        let held_back = std::mem::take(&mut *held_back.lock().unwrap_or_else(|e| e.into_inner()));
+        for call in &held_back {
+            for observer in &mut self.observers {
+                call.replay(observer.as_mut());
+            }
         }
 
         result
+    }
+
+    fn roll_back(&mut self, undo: BatchUndo) {
+        for (client, activity) in undo.activity {
+            match activity {
+                Some(activity) => self.activity.insert(client, activity),
+                None => self.activity.remove(&client),
+            };
+        }
+        for (key, velocity) in undo.velocity {
+            match velocity {
+                Some(velocity) => self.velocity.insert(key, velocity),
+                None => self.velocity.remove(&key),
+            };
+        }
+        if let Some(history) = &mut self.history {
+            for (client, len) in undo.history {
+                if let Some(applied) = history.get_mut(&client) {
+                    applied.truncate(len);
+                }
+            }
+        }
+        self.auto_created.truncate(undo.auto_created);
+        self.failed_assertions.truncate(undo.failed_assertions);
+        self.rule_violations.truncate(undo.rule_violations);
+        self.deferred = undo.deferred;
+    }
+
+    /// Makes the engine keep every record it applies from now on, see [`Engine::history`].
+    pub fn keep_history(&mut self) {
+        self.history.get_or_insert_with(FastMap::default);
+    }
+
+    /// The records applied to the account of `client` in the order they were applied,
+    /// with the balance each one left. Empty unless [`Engine::keep_history`] was called.
+    pub fn history(&self, client: ClientId) -> impl Iterator<Item = &AppliedTx> {
+        self.history
+            .as_ref()
+            .and_then(|history| history.get(&client))
+            .into_iter()
+            .flatten()
+    }
+
+    fn record_history(&mut self, record: &Record) {
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        let Some(account) = self.storage.accounts.get(&record.client) else {
+            return;
+        };
+
+        if let Some(undo) = &mut self.undo {
+            undo.history
+                .entry(record.client)
+                .or_insert_with(|| history.get(&record.client).map_or(0, Vec::len));
+        }
+        history
             .entry(record.client)
-            .and_modify(|r| {
-                if !r.locked {
-                    r.available += amount;
-                    r.total = r.available + r.held;
+            .or_default()
+            .push(AppliedTx::new(record, account));
+    }
+
+    /// Sets the time up to which records are applied. Records dated after it are
+    /// deferred until the clock is advanced past them, while records without a timestamp
+    /// are always applied. The clock starts at [`EngineConfig::as_of`], and without one
+    /// nothing is deferred.
+    pub fn set_clock(&mut self, now: DateTime<Utc>) {
+        self.clock = Some(now);
+    }
+
+    /// Moves the clock to `now`, expires the holds due by then and applies the deferred
+    /// records dated up to `now` in timestamp order. Returns them with their results.
+    pub fn advance_clock(
+        &mut self,
+        now: DateTime<Utc>,
+    ) -> Vec<(Record, Result<(), RejectionReason>)> {
+        self.clock = Some(now);
+        self.expire_holds(now);
+
+        let due = self
+            .deferred
+            .partition_point(|record| record.timestamp <= Some(now));
+        let due: Vec<Record> = self.deferred.drain(..due).collect();
+        due.into_iter()
+            .map(|record| {
+                let result = self.apply(record.clone());
+                (record, result)
+            })
+            .collect()
+    }
+
+    /// Records dated after the clock, in timestamp order.
+    pub fn deferred(&self) -> &[Record] {
+        &self.deferred
+    }
+
+    /// Whether [`Engine::apply`] would defer `record` rather than apply it.
+    pub(crate) fn defers(&self, record: &Record) -> bool {
+        matches!(
+            (self.clock, record.timestamp),
+            (Some(clock), Some(timestamp)) if timestamp > clock
+        )
+    }
+
+    /// Like [`Engine::apply`], but lets the caller refine the rejection reason before it
+    /// is counted, e.g. with knowledge of transactions applied by other engines.
+    pub(crate) fn apply_with(
+        &mut self,
+        record: Record,
+        refine: impl FnOnce(RejectionReason) -> RejectionReason,
+    ) -> Result<(), RejectionReason> {
+        if self.defers(&record) {
+            let index = self
+                .deferred
+                .partition_point(|deferred| deferred.timestamp <= record.timestamp);
+            self.deferred.insert(index, record);
+            return Ok(());
+        }
+
+        let (r#type, client, tx, amount) = (record.r#type, record.client, record.tx, record.amount);
+        let currency = record.currency;
+        if let Some(now) = record.timestamp {
+            self.expire_holds(now);
+        }
+
+        let observed = (!self.observers.is_empty() || self.history.is_some())
+            .then(|| (record.clone(), Instant::now()));
+        let was_locked = self
+            .storage
+            .accounts
+            .get(&client)
+            .map(|account| account.locked);
+
+        let result = self.apply_record(record).map_err(refine);
+        match result {
+            Ok(()) if currency.is_none() => self.record_activity(r#type, client, tx, amount),
+            Ok(()) => {}
+            Err(reason) => self.metrics.record(r#type, client, reason),
+        }
+
+        if let Some((record, started)) = observed {
+            let elapsed = started.elapsed();
+            if result.is_ok() {
+                self.record_history(&record);
+            }
+            if !self.observers.is_empty() {
+                self.notify(&record, was_locked, result);
+                for observer in &mut self.observers {
+                    observer.on_processed(&record, elapsed);
                 }
+            }
+        }
+
+        if result.is_ok() && r#type == TxType::Chargeback {
+            self.charge_chargeback_fee(client, currency);
+        }
+
+        result
+    }
+
+    /// Rejects a deposit or withdrawal that breaks a rule of the config.
+    fn check_rules(&mut self, record: &Record) -> Result<(), RejectionReason> {
+        let velocity = self.velocity.get(&(record.client, record.currency));
+        let Some(rule) = self.config.rules.check(record, velocity) else {
+            return Ok(());
+        };
+
+        self.rule_violations.push(RuleViolation {
+            client: record.client,
+            tx: record.tx,
+            rule: rule.id.clone(),
+        });
+        Err(RejectionReason::LimitExceeded)
+    }
+
+    /// Counts an applied deposit or withdrawal towards the rules over a time window.
+    fn count_towards_rules(&mut self, record: &Record) {
+        if !self.config.rules.is_empty() {
+            let key = (record.client, record.currency);
+            if let Some(undo) = &mut self.undo {
+                undo.velocity
+                    .entry(key)
+                    .or_insert_with(|| self.velocity.get(&key).cloned());
+            }
+            self.velocity.entry(key).or_default().record(record);
+        }
+    }
+
+    /// Deposits and withdrawals rejected by a rule, in the order they were applied.
+    pub fn rule_violations(&self) -> &[RuleViolation] {
+        &self.rule_violations
+    }
+
+    /// Compares the balances of an `assert` record with the account at the 4 decimal places
+    /// amounts are kept at, keeping the record as failed if they differ. Clients without an
+    /// account have zero balances.
+    fn check_assertion(&mut self, record: &Record) -> Result<(), RejectionReason> {
+        if record.amount.is_none() && record.total.is_none() {
+            return Err(RejectionReason::InvalidAmount);
+        }
+
+        let balance = self
+            .storage
+            .accounts
+            .get(&record.client)
+            .map(|account| account.balance(record.currency))
+            .unwrap_or_default();
+        let matches = |expected: Option<Money>, actual: Money| {
+            expected.is_none_or(|expected| {
+                (f64::from(expected) * 10_000.0).round() == (f64::from(actual) * 10_000.0).round()
             })
-            .or_insert_with(|| AccountRecord {
+        };
+        if !matches(record.amount, balance.available) || !matches(record.total, balance.total) {
+            self.failed_assertions.push(FailedAssertion {
                 client: record.client,
-                available: amount,
-                total: amount,
-                held: 0.0,
-                locked: false,
+                tx: record.tx,
+                currency: record.currency,
+                expected_available: record.amount,
+                expected_total: record.total,
+                available: balance.available,
+                total: balance.total,
             });
+        }
+
+        Ok(())
     }
-}
 
-pub fn withdraw(result: &mut HashMap<ClientId, AccountRecord>, record: &Record) {
-    // In the case that the client does not exist or the client does not have enough available
-    // to withdraw, this operation will not do anything.
-    if let Some(amount) = record.amount {
-        if let Some(account_record) = result.get_mut(&record.client) {
-            if account_record.locked {
-                return;
+    /// Charges the configured chargeback fee as a `fee` record of its own, with a tx id of
+    /// the engine, so that it shows up in the journal and the events like any other record.
+    fn charge_chargeback_fee(&mut self, client: ClientId, currency: Option<Currency>) {
+        let Some(charge) = self.config.chargeback_fee else {
+            return;
+        };
+        let Some(account) = self.storage.accounts.get(&client) else {
+            return;
+        };
+
+        // The fee never takes the account below zero.
+        let amount = charge.min(account.balance(currency).available);
+        if amount <= 0.0 {
+            return;
+        }
+        let Some(tx) = self.next_tx_id() else {
+            return;
+        };
+
+        let record = Record {
+            r#type: TxType::Fee,
+            client,
+            tx,
+            amount: Some(amount),
+            timestamp: None,
+            currency,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        };
+        let result = fee(&mut self.storage, &record);
+        if result.is_ok() && currency.is_none() {
+            self.record_activity(TxType::Fee, client, tx, Some(amount));
+        }
+        if result.is_ok() {
+            self.record_history(&record);
+        }
+        if !self.observers.is_empty() {
+            self.notify(&record, Some(true), result);
+        }
+    }
+
+    /// Counts and reports a record that was rejected before it reached the engine, e.g. by
+    /// a check across shards.
+    pub(crate) fn reject(&mut self, record: &Record, reason: RejectionReason) {
+        self.metrics.record(record.r#type, record.client, reason);
+        for observer in &mut self.observers {
+            observer.on_rejected(record, reason);
+        }
+    }
+
+    fn notify(
+        &mut self,
+        record: &Record,
+        was_locked: Option<bool>,
+        result: Result<(), RejectionReason>,
+    ) {
+        if let Err(reason) = result {
+            for observer in &mut self.observers {
+                observer.on_rejected(record, reason);
             }
+            return;
+        }
+
+        let Some(account) = self.storage.accounts.get(&record.client) else {
+            return;
+        };
+
+        let (client, tx) = (record.client, record.tx);
+        let mut events = Vec::new();
+        if was_locked.is_none() {
+            events.push(AccountEvent::Opened { client, tx });
+        }
+        events.push(AccountEvent::updated(account, record.currency));
+        if record.r#type == TxType::Convert {
+            events.push(AccountEvent::updated(account, record.to_currency));
+        }
+        if account.locked && was_locked != Some(true) {
+            events.push(AccountEvent::Locked { client, tx });
+        }
+
+        for observer in &mut self.observers {
+            for event in &events {
+                observer.on_applied(record, event);
+            }
+        }
+    }
+
+    /// Releases every hold placed at least [`EngineConfig::hold_expiry`] hours before
+    /// `now`, and returns how many. Each one is released by a `release` record that
+    /// observers see like any other. Records with a timestamp call this before they are
+    /// applied, so holds expire as the input moves past them.
+    pub fn expire_holds(&mut self, now: DateTime<Utc>) -> usize {
+        let Some(hours) = self.config.hold_expiry else {
+            return 0;
+        };
+
+        let Some(until) = now.checked_sub_signed(TimeDelta::hours(hours.into())) else {
+            return 0;
+        };
+        let mut expired = self.storage.holds_placed_until(until);
+        expired.sort_by_key(|hold| (hold.client, hold.tx));
+
+        for hold in &expired {
+            let record = Record {
+                r#type: TxType::Release,
+                client: hold.client,
+                tx: hold.tx,
+                amount: None,
+                timestamp: Some(now),
+                currency: hold.currency,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
+            };
+            let result = release(&mut self.storage, &record);
+            if result.is_ok() {
+                self.record_history(&record);
+            }
+            if !self.observers.is_empty() {
+                let was_locked = self.storage.accounts.get(&hold.client).map(|a| a.locked);
+                self.notify(&record, was_locked, result);
+            }
+        }
+
+        expired.len()
+    }
+
+    fn record_activity(
+        &mut self,
+        r#type: TxType,
+        client: ClientId,
+        tx: TxId,
+        amount: Option<Money>,
+    ) {
+        if let Some(undo) = &mut self.undo {
+            undo.activity
+                .entry(client)
+                .or_insert_with(|| self.activity.get(&client).cloned());
+        }
+        let activity = self
+            .activity
+            .entry(client)
+            .or_insert_with(|| ClientActivity {
+                client,
+                ..Default::default()
+            });
+
+        match r#type {
+            TxType::Deposit => activity.net_deposited += amount.unwrap_or_default(),
+            TxType::Withdrawal => activity.net_withdrawn += amount.unwrap_or_default(),
+            TxType::Chargeback => {
+                let Some(charged_back) = self.storage.find(client, tx) else {
+                    return;
+                };
+                let amount = charged_back.amount.unwrap_or_default();
+                match charged_back.r#type {
+                    TxType::Deposit => activity.net_deposited -= amount,
+                    TxType::Withdrawal => activity.net_withdrawn -= amount,
+                    _ => {}
+                }
+
+                activity.chargebacks += 1;
+                activity.charged_back += amount;
+            }
+            TxType::Representment => {
+                let Some(represented) = self.storage.find(client, tx) else {
+                    return;
+                };
+                let amount = represented.amount.unwrap_or_default();
+                match represented.r#type {
+                    TxType::Deposit => activity.net_deposited += amount,
+                    TxType::Withdrawal => activity.net_withdrawn += amount,
+                    _ => {}
+                }
+
+                activity.charged_back -= amount;
+            }
+            TxType::Dispute
+            | TxType::Resolve
+            | TxType::OpenAccount
+            | TxType::Lock
+            | TxType::Unlock
+            | TxType::Convert => {}
+            TxType::Fee => activity.fees += amount.unwrap_or_default(),
+            TxType::Capture => {
+                let Some(captured) = self.storage.find(client, tx) else {
+                    return;
+                };
+                activity.net_withdrawn += captured.amount.unwrap_or_default();
+            }
+            TxType::Hold | TxType::Release | TxType::Assert | TxType::Custom(_) => {}
+        }
+    }
+
+    /// Money movements per client since the engine was created, sorted by client id.
+    pub fn activity(&self) -> Vec<ClientActivity> {
+        let mut activity: Vec<ClientActivity> = self.activity.values().cloned().collect();
+        activity.sort_by_key(|a| a.client);
+        activity
+    }
+
+    /// Accounts opened by a deposit rather than an `open_account` record, in the order
+    /// they were opened. Only tracked under [`AccountPolicy::Report`].
+    pub fn auto_created(&self) -> &[AutoCreatedAccount] {
+        &self.auto_created
+    }
+
+    /// `assert` records whose balances did not match, in the order they were applied.
+    pub fn failed_assertions(&self) -> &[FailedAssertion] {
+        &self.failed_assertions
+    }
+
+    fn apply_record(&mut self, record: Record) -> Result<(), RejectionReason> {
+        if self.config.blocklist.contains(&record.client) {
+            return Err(RejectionReason::ClientBlocked);
+        }
+
+        let is_tx = matches!(
+            record.r#type,
+            TxType::Deposit | TxType::Withdrawal | TxType::Hold
+        );
+        if is_tx && self.id_allocator.is_reserved(record.tx) {
+            return Err(RejectionReason::ReservedTxId);
+        }
+
+        if is_tx && self.is_duplicate(&record) {
+            return Err(RejectionReason::DuplicateTx);
+        }
+
+        if matches!(record.r#type, TxType::Deposit | TxType::Withdrawal) {
+            self.check_rules(&record)?;
+        }
+
+        let opens_account =
+            record.r#type == TxType::Deposit && !self.storage.accounts.contains_key(&record.client);
+        if opens_account && self.config.account_policy == AccountPolicy::Strict {
+            return Err(RejectionReason::UnknownAccount);
+        }
+
+        // Only transactions that were actually applied are kept, so that a rejected
+        // deposit or withdrawal can never be disputed later on.
+        match record.r#type {
+            TxType::Deposit => {
+                deposit(&mut self.storage, &record)?;
+                if opens_account && self.config.account_policy == AccountPolicy::Report {
+                    self.auto_created.push(AutoCreatedAccount {
+                        client: record.client,
+                        tx: record.tx,
+                    });
+                }
+                self.count_towards_rules(&record);
+                self.store(record);
+                Ok(())
+            }
+            TxType::Withdrawal => {
+                let credit_limit = match record.currency {
+                    None => self.config.credit_limits.limit(record.client),
+                    Some(_) => Money::ZERO,
+                };
+                withdraw(&mut self.storage, &record, credit_limit)?;
+                self.count_towards_rules(&record);
+                self.store(record);
+                Ok(())
+            }
+            TxType::Dispute => dispute(
+                &mut self.storage,
+                &record,
+                self.config.locked_account_policy,
+                self.config.dispute_hold_policy,
+                self.config.redispute_policy,
+            ),
+            TxType::Resolve => resolve(
+                &mut self.storage,
+                &record,
+                self.config.locked_account_policy,
+            ),
+            TxType::Chargeback => chargeback(
+                &mut self.storage,
+                &record,
+                self.config.chargeback_policy,
+                self.config.locked_account_policy,
+            ),
+            TxType::Representment => {
+                representment(&mut self.storage, &record, self.config.representment_policy)
+            }
+            TxType::OpenAccount => open_account(&mut self.storage, &record),
+            TxType::Lock => lock(&mut self.storage, record.client),
+            TxType::Unlock => unlock(&mut self.storage, record.client),
+            TxType::Fee => fee(&mut self.storage, &record),
+            TxType::Hold => hold(&mut self.storage, &record),
+            TxType::Capture => capture(&mut self.storage, &record),
+            TxType::Release => release(&mut self.storage, &record),
+            TxType::Assert => self.check_assertion(&record),
+            TxType::Convert => convert(
+                &mut self.storage,
+                &record,
+                &self.config.rates,
+                self.config.fx_rounding,
+            ),
+            TxType::Custom(name) => self.apply_custom(name, &record),
+        }
+    }
+
+    fn apply_custom(&mut self, name: CustomType, record: &Record) -> Result<(), RejectionReason> {
+        let Some(handler) = self.handlers.get_mut(&name) else {
+            return Err(RejectionReason::UnsupportedType);
+        };
+        let Some(mut account) = self.storage.account(record.client) else {
+            return Err(RejectionReason::UnknownAccount);
+        };
+
+        handler.apply(record, &mut account, &self.storage)?;
+        self.storage.put_account(account);
+        Ok(())
+    }
+
+    /// Rejects every record of `client` from now on, without touching its account, e.g.
+    /// for compliance to freeze a customer without a `lock` record.
+    pub fn block(&mut self, client: ClientId) {
+        self.config.blocklist.insert(client);
+    }
+
+    pub fn unblock(&mut self, client: ClientId) {
+        self.config.blocklist.remove(&client);
+    }
+
+    pub fn is_blocked(&self, client: ClientId) -> bool {
+        self.config.blocklist.contains(&client)
+    }
+
+    /// Locks the account of `client`, rejecting any further transactions on it.
+    pub fn lock(&mut self, client: ClientId) -> Result<(), RejectionReason> {
+        lock(&mut self.storage, client)
+    }
+
+    /// Reopens the account of `client` after it was locked, e.g. by a chargeback.
+    pub fn unlock(&mut self, client: ClientId) -> Result<(), RejectionReason> {
+        unlock(&mut self.storage, client)
+    }
+
+    fn is_duplicate(&self, record: &Record) -> bool {
+        match self.config.duplicate_scope {
+            DuplicateScope::Global => {
+                self.retired_tx_ids.contains(&record.tx)
+                    || self.storage.contains_tx_id(record.tx)
+                    || self.storage.contains_hold_tx_id(record.tx)
+            }
+            DuplicateScope::PerClient => {
+                self.retired.contains_key(&(record.client, record.tx))
+                    || self.storage.find(record.client, record.tx).is_some()
+                    || self.storage.hold(record.client, record.tx).is_some()
+            }
+        }
+    }
+
+    fn store(&mut self, record: Record) {
+        self.storage.record_tx(record);
+    }
+
+    /// Accounting invariants that hold after every applied record, reported as integrity
+    /// findings: the total is the sum of the available and held funds, and held funds are
+    /// never negative unless the chargeback policy allows it.
+    pub fn invariant_violations(&self) -> Vec<Finding> {
+        let allow_negative_held = self.config.chargeback_policy == ChargebackPolicy::AllowNegative;
+        let mut accounts = self.storage.accounts.clone();
+        check_integrity(&mut accounts, IntegrityPolicy::Report)
+            .into_iter()
+            .filter(|finding| {
+                !(allow_negative_held && finding.issue == IntegrityIssue::NegativeHeld)
+            })
+            .collect()
+    }
+
+    pub fn accounts(&self) -> &HashMap<ClientId, Account> {
+        &self.storage.accounts
+    }
+
+    pub fn account(&self, client: ClientId) -> Option<&Account> {
+        self.storage.accounts.get(&client)
+    }
+
+    /// Iterates over all accounts sorted by client id.
+    pub fn accounts_iter(&self) -> impl Iterator<Item = &Account> {
+        let mut accounts: Vec<&Account> = self.storage.accounts.values().collect();
+        accounts.sort_by_key(|account| account.client);
+        accounts.into_iter()
+    }
+
+    pub fn into_accounts(self) -> HashMap<ClientId, Account> {
+        self.storage.accounts
+    }
+
+    /// Puts back an account persisted by an earlier run, replacing any account of the
+    /// same client.
+    pub fn restore_account(&mut self, account: Account) {
+        self.storage.accounts.insert(account.client, account);
+    }
+
+    /// Puts back a deposit or withdrawal applied by an earlier run, so that it can still
+    /// be disputed, together with whether it is currently under dispute. The balances
+    /// are not touched, they are restored with the account.
+    pub fn restore_transaction(&mut self, record: Record, disputed: bool) {
+        if disputed {
+            self.storage
+                .open_dispute(record.client, record.tx, None, None);
+        }
+
+        self.store(record);
+    }
+
+    /// Puts back how many times a transaction restored with
+    /// [`Engine::restore_transaction`] was disputed by earlier runs. A dispute restored as
+    /// open counts as one already.
+    pub fn restore_dispute_count(&mut self, client: ClientId, tx: TxId, disputes: u32) {
+        let count = self.storage.dispute_counts.entry((client, tx)).or_default();
+        *count = (*count).max(disputes);
+    }
+
+    /// Puts back the amount of an open dispute of an earlier run that disputes only part
+    /// of the transaction.
+    pub fn restore_disputed_amount(&mut self, client: ClientId, tx: TxId, amount: Money) {
+        self.storage.partial_disputes.insert((client, tx), amount);
+    }
+
+    /// The amount held by the open dispute on a transaction, if it disputes only part of
+    /// the transaction.
+    pub fn disputed_amount(&self, client: ClientId, tx: TxId) -> Option<Money> {
+        self.storage.disputed_amount(client, tx)
+    }
+
+    /// Puts back when an open dispute of an earlier run was opened.
+    pub fn restore_dispute_opened(&mut self, client: ClientId, tx: TxId, opened: DateTime<Utc>) {
+        self.storage.dispute_opened.insert((client, tx), opened);
+    }
+
+    /// When the open dispute on a transaction was opened, if its record had a timestamp.
+    pub fn dispute_opened(&self, client: ClientId, tx: TxId) -> Option<DateTime<Utc>> {
+        self.storage.dispute_opened.get(&(client, tx)).copied()
+    }
+
+    /// Puts back a chargeback of an earlier run that a representment may still reverse.
+    pub fn restore_chargeback(&mut self, client: ClientId, tx: TxId, amount: Money) {
+        self.storage.chargebacks.insert((client, tx), amount);
+    }
+
+    /// The amount the chargeback of a transaction took, until a representment reverses it.
+    pub fn charged_back(&self, client: ClientId, tx: TxId) -> Option<Money> {
+        self.storage.charged_back(client, tx)
+    }
+
+    /// How many times a deposit or withdrawal was disputed, see [`RedisputePolicy`].
+    pub fn dispute_count(&self, client: ClientId, tx: TxId) -> u32 {
+        self.storage.dispute_count(client, tx)
+    }
+
+    /// Puts back a hold placed by an earlier run. The balances are not touched, they are
+    /// restored with the account.
+    pub fn restore_hold(&mut self, hold: Hold) {
+        self.storage.put_hold(hold);
+    }
+
+    /// Holds that were neither captured nor released yet, sorted by client and tx id.
+    pub fn holds(&self) -> Vec<Hold> {
+        let mut holds: Vec<Hold> = self.storage.holds.values().copied().collect();
+        holds.sort_by_key(|hold| (hold.client, hold.tx));
+        holds
+    }
+
+    /// Offset of the last record applied from `source`. Consumers of a stream that restart
+    /// from persisted state skip the records up to it, so that none is applied twice.
+    pub fn source_offset(&self, source: &str) -> Option<u64> {
+        self.storage.source_offset(source)
+    }
+
+    /// Records that `source` was applied up to `offset`, or forgets about the source.
+    pub fn set_source_offset(&mut self, source: &str, offset: Option<u64>) {
+        self.storage.set_source_offset(source, offset);
+    }
+
+    /// The offset of every source, sorted by source.
+    pub fn source_offsets(&self) -> Vec<(String, u64)> {
+        let mut offsets: Vec<(String, u64)> = self
+            .storage
+            .source_offsets
+            .iter()
+            .map(|(source, &offset)| (source.clone(), offset))
+            .collect();
+        offsets.sort();
+        offsets
+    }
+
+    /// Puts back the id of a deposit or withdrawal applied by an earlier run that can no
+    /// longer be disputed, so that sending it again is rejected as a duplicate.
+    pub fn retire_transaction(&mut self, client: ClientId, tx: TxId, amount: Option<Money>) {
+        self.retired.insert((client, tx), amount);
+        self.retired_tx_ids.insert(tx);
+    }
+
+    /// Client, id and amount of every deposit and withdrawal applied so far, including
+    /// retired ones.
+    pub fn tx_index(&self) -> impl Iterator<Item = (ClientId, TxId, Option<Money>)> + '_ {
+        let retired = self
+            .retired
+            .iter()
+            .map(|(&(client, tx), &amount)| (client, tx, amount));
+        self.storage
+            .transactions
+            .iter()
+            .map(|record| (record.client, record.tx, record.amount))
+            .chain(retired)
+    }
+
+    /// All deposits and withdrawals that can still be disputed, with whether they are
+    /// currently under dispute.
+    pub fn dispute_history(&self) -> impl Iterator<Item = (Record, bool)> + '_ {
+        self.storage.transactions.iter().map(|record| {
+            let disputed = self.storage.is_disputed(record.client, record.tx);
+            (record, disputed)
+        })
+    }
+
+    /// Disputes not resolved or charged back yet, sorted by client and tx id.
+    pub fn open_disputes(&self) -> Vec<OpenDispute> {
+        let mut open: Vec<OpenDispute> = self
+            .storage
+            .disputes
+            .iter()
+            .flat_map(|(&client, txs)| txs.iter().map(move |&tx| (client, tx)))
+            .filter_map(|(client, tx)| {
+                let record = self.storage.find(client, tx)?;
+                Some(OpenDispute {
+                    client,
+                    tx,
+                    currency: record.currency,
+                    amount: self
+                        .storage
+                        .disputed_amount(client, tx)
+                        .or(record.amount)
+                        .unwrap_or_default(),
+                    opened: self.dispute_opened(client, tx),
+                })
+            })
+            .collect();
+        open.sort_by_key(|dispute| (dispute.client, dispute.tx));
+        open
+    }
+}
+
+pub fn process_records(records: Vec<Record>) -> HashMap<ClientId, Account> {
+    let mut engine = Engine::new();
+    for record in records {
+        // Rejected transactions are simply skipped.
+        let _ = engine.apply(record);
+    }
+
+    engine.into_accounts()
+}
+
+pub fn open_account(storage: &mut impl Storage, record: &Record) -> Result<(), RejectionReason> {
+    if storage.account(record.client).is_some() {
+        return Err(RejectionReason::AccountExists);
+    }
+
+    storage.put_account(Account {
+        client: record.client,
+        ..Default::default()
+    });
+
+    Ok(())
+}
+
+pub fn lock(storage: &mut impl Storage, client: ClientId) -> Result<(), RejectionReason> {
+    let Some(mut account) = storage.account(client) else {
+        return Err(RejectionReason::UnknownAccount);
+    };
+
+    account.lock()?;
+    storage.put_account(account);
+
+    Ok(())
+}
+
+pub fn unlock(storage: &mut impl Storage, client: ClientId) -> Result<(), RejectionReason> {
+    let Some(mut account) = storage.account(client) else {
+        return Err(RejectionReason::UnknownAccount);
+    };
+
+    account.unlock()?;
+    storage.put_account(account);
+
+    Ok(())
+}
+
+pub fn deposit(storage: &mut impl Storage, record: &Record) -> Result<(), RejectionReason> {
+    let Some(amount) = record.amount else {
+        return Err(RejectionReason::InvalidAmount);
+    };
+
+    let mut account = storage.account(record.client).unwrap_or_else(|| Account {
+        client: record.client,
+        ..Default::default()
+    });
+
+    account.deposit(record.currency, amount)?;
+    storage.put_account(account);
+
+    Ok(())
+}
+
+/// Withdraws `amount`, taking the available funds down to `-credit_limit` at most.
+pub fn withdraw(
+    storage: &mut impl Storage,
+    record: &Record,
+    credit_limit: Money,
+) -> Result<(), RejectionReason> {
+    let Some(amount) = record.amount else {
+        return Err(RejectionReason::InvalidAmount);
+    };
+
+    if amount <= Money::ZERO {
+        return Err(RejectionReason::InvalidAmount);
+    }
+
+    let Some(mut account) = storage.account(record.client) else {
+        return Err(RejectionReason::UnknownAccount);
+    };
+
+    account.withdraw(record.currency, amount, credit_limit)?;
+    storage.put_account(account);
+
+    Ok(())
+}
+
+pub fn dispute(
+    storage: &mut impl Storage,
+    record: &Record,
+    locked_policy: LockedAccountPolicy,
+    hold_policy: DisputeHoldPolicy,
+    redispute_policy: RedisputePolicy,
+) -> Result<(), RejectionReason> {
+    if storage.is_empty() {
+        return Err(RejectionReason::TxNeverSeen);
+    }
+
+    let Some(mut out_record) = storage.account(record.client) else {
+        return Err(RejectionReason::UnknownAccount);
+    };
+
+    if out_record.locked && locked_policy == LockedAccountPolicy::Freeze {
+        return Err(RejectionReason::AccountLocked);
+    }
+
+    if storage.is_disputed(record.client, record.tx) {
+        return Err(RejectionReason::AlreadyDisputed);
+    }
+
+    let Some(processed_record) = storage.find(record.client, record.tx) else {
+        return Err(tx_not_found(&*storage, record.tx));
+    };
+
+    if !redispute_policy.allows(storage.dispute_count(record.client, record.tx)) {
+        return Err(RejectionReason::DisputeLimitReached);
+    }
+
+    if processed_record.currency != record.currency {
+        return Err(RejectionReason::CurrencyMismatch);
+    }
+
+    let Some(full_amount) = processed_record.amount else {
+        return Err(RejectionReason::InvalidAmount);
+    };
+
+    // A dispute with an amount holds only that part of the transaction.
+    let partial = match record.amount {
+        Some(amount) if amount <= 0.0 || amount > full_amount => {
+            return Err(RejectionReason::InvalidAmount)
+        }
+        Some(amount) if amount < full_amount => Some(amount),
+        _ => None,
+    };
+    let amount = partial.unwrap_or(full_amount);
+
+    match processed_record.r#type {
+        TxType::Deposit => out_record.hold(record.currency, amount, hold_policy)?,
+        TxType::Withdrawal => {
+            // The withdrawn funds are provisionally returned to the client, but held
+            // until the dispute is settled.
+            out_record.adjust(record.currency, Money::ZERO, amount);
+        }
+        _ => return Err(RejectionReason::TxNeverSeen),
+    }
+
+    storage.put_account(out_record);
+    storage.open_dispute(record.client, record.tx, partial, record.timestamp);
+
+    Ok(())
+}
+
+pub fn resolve(
+    storage: &mut impl Storage,
+    record: &Record,
+    locked_policy: LockedAccountPolicy,
+) -> Result<(), RejectionReason> {
+    if !storage.is_disputed(record.client, record.tx) {
+        // Assume there is an error on the partner's side.
+        return Err(RejectionReason::NotDisputed);
+    }
+
+    let Some(mut out_record) = storage.account(record.client) else {
+        return Err(RejectionReason::UnknownAccount);
+    };
+
+    if out_record.locked && locked_policy == LockedAccountPolicy::Freeze {
+        return Err(RejectionReason::AccountLocked);
+    }
+
+    let Some(processed_record) = storage.find(record.client, record.tx) else {
+        return Err(tx_not_found(&*storage, record.tx));
+    };
+
+    if processed_record.currency != record.currency {
+        return Err(RejectionReason::CurrencyMismatch);
+    }
+
+    let Some(amount) = storage
+        .disputed_amount(record.client, record.tx)
+        .or(processed_record.amount)
+    else {
+        return Err(RejectionReason::InvalidAmount);
+    };
+
+    match processed_record.r#type {
+        TxType::Deposit => out_record.release(record.currency, amount)?,
+        TxType::Withdrawal => {
+            // The withdrawal stands, so the held funds leave the account again.
+            out_record.adjust(record.currency, Money::ZERO, -amount);
+        }
+        _ => return Err(RejectionReason::TxNeverSeen),
+    }
+
+    storage.put_account(out_record);
+    storage.close_dispute(record.client, record.tx);
+
+    Ok(())
+}
+
+pub fn chargeback(
+    storage: &mut impl Storage,
+    record: &Record,
+    policy: ChargebackPolicy,
+    locked_policy: LockedAccountPolicy,
+) -> Result<(), RejectionReason> {
+    if !storage.is_disputed(record.client, record.tx) {
+        // Assume there is an error on the partner's side.
+        return Err(RejectionReason::NotDisputed);
+    }
+
+    let Some(mut out_record) = storage.account(record.client) else {
+        return Err(RejectionReason::UnknownAccount);
+    };
+
+    if out_record.locked && locked_policy == LockedAccountPolicy::Freeze {
+        return Err(RejectionReason::AccountLocked);
+    }
+
+    let Some(processed_record) = storage.find(record.client, record.tx) else {
+        return Err(tx_not_found(&*storage, record.tx));
+    };
+
+    if processed_record.currency != record.currency {
+        return Err(RejectionReason::CurrencyMismatch);
+    }
+
+    let Some(amount) = storage
+        .disputed_amount(record.client, record.tx)
+        .or(processed_record.amount)
+    else {
+        return Err(RejectionReason::InvalidAmount);
+    };
+
+    let held = out_record.balance(record.currency).held;
+    let charged_back = if held >= amount {
+        amount
+    } else {
+        match policy {
+            ChargebackPolicy::Reject => return Err(RejectionReason::InsufficientHeldFunds),
+            ChargebackPolicy::AllowNegative => amount,
+            ChargebackPolicy::Partial => held.max(Money::ZERO),
+        }
+    };
+
+    match processed_record.r#type {
+        TxType::Deposit => {}
+        TxType::Withdrawal => {
+            // The withdrawal is reversed and the funds are returned to the client.
+            out_record.adjust(record.currency, charged_back, Money::ZERO);
+        }
+        _ => return Err(RejectionReason::TxNeverSeen),
+    }
+
+    out_record.charge_back(record.currency, charged_back)?;
+    storage.put_account(out_record);
+    storage.close_dispute(record.client, record.tx);
+    storage.set_charged_back(record.client, record.tx, Some(charged_back));
+
+    Ok(())
+}
+
+/// Reverses the chargeback of a deposit or withdrawal after it was contested and won.
+/// Applies to the account the chargeback locked, and unlocks it under
+/// [`RepresentmentPolicy::Unlock`].
+pub fn representment(
+    storage: &mut impl Storage,
+    record: &Record,
+    policy: RepresentmentPolicy,
+) -> Result<(), RejectionReason> {
+    let Some(processed_record) = storage.find(record.client, record.tx) else {
+        return Err(tx_not_found(&*storage, record.tx));
+    };
+
+    let Some(charged_back) = storage.charged_back(record.client, record.tx) else {
+        return Err(RejectionReason::NotChargedBack);
+    };
+
+    if processed_record.currency != record.currency {
+        return Err(RejectionReason::CurrencyMismatch);
+    }
+
+    let Some(mut out_record) = storage.account(record.client) else {
+        return Err(RejectionReason::UnknownAccount);
+    };
+
+    match processed_record.r#type {
+        TxType::Deposit => out_record.adjust(record.currency, charged_back, Money::ZERO),
+        TxType::Withdrawal => {
+            // The withdrawal stands after all, so the returned funds leave again.
+            out_record.adjust(record.currency, -charged_back, Money::ZERO);
+        }
+        _ => return Err(RejectionReason::TxNeverSeen),
+    }
+
+    if policy == RepresentmentPolicy::Unlock {
+        out_record.locked = false;
+    }
+    storage.put_account(out_record);
+    storage.set_charged_back(record.client, record.tx, None);
+
+    Ok(())
+}
+
+/// Debits the amount of a `fee` record from the available funds. Unlike a withdrawal it
+/// also applies to locked accounts, but never takes the available funds below zero.
+pub fn fee(storage: &mut impl Storage, record: &Record) -> Result<(), RejectionReason> {
+    let Some(amount) = record.amount.filter(|&amount| amount > 0.0) else {
+        return Err(RejectionReason::InvalidAmount);
+    };
+
+    let Some(mut account_record) = storage.account(record.client) else {
+        return Err(RejectionReason::UnknownAccount);
+    };
 
-            if account_record.available >= amount {
-                account_record.available -= amount;
-                account_record.total = account_record.available + account_record.held;
-            }
-        }
+    if account_record.balance(record.currency).available < amount {
+        return Err(RejectionReason::InsufficientFunds);
     }
+
+    account_record.adjust(record.currency, -amount, Money::ZERO);
+    storage.put_account(account_record);
+
+    Ok(())
 }
 
-pub fn dispute(
-    result: &mut HashMap<ClientId, AccountRecord>,
-    disputes: &mut HashMap<ClientId, HashSet<TxId>>,
-    processed_records: &HashMap<(ClientId, TxId), Record>,
-    record: &Record,
-) {
-    if processed_records.is_empty() {
-        return;
-    }
+/// Moves the amount of a `hold` record from available to held funds and keeps the hold
+/// until a `capture` or `release` settles it.
+pub fn hold(storage: &mut impl Storage, record: &Record) -> Result<(), RejectionReason> {
+    let Some(amount) = record.amount.filter(|&amount| amount > 0.0) else {
+        return Err(RejectionReason::InvalidAmount);
+    };
 
-    let Some(out_record) = result.get_mut(&record.client) else {
-        return;
+    let Some(mut account_record) = storage.account(record.client) else {
+        return Err(RejectionReason::UnknownAccount);
     };
 
-    if out_record.locked {
-        return;
+    if account_record.locked {
+        return Err(RejectionReason::AccountLocked);
     }
 
-    let client_disputes = disputes.entry(record.client).or_default();
+    account_record.hold(record.currency, amount, DisputeHoldPolicy::RequireFunds)?;
+    storage.put_account(account_record);
+    storage.put_hold(Hold {
+        client: record.client,
+        tx: record.tx,
+        amount,
+        currency: record.currency,
+        placed_at: record.timestamp,
+    });
+
+    Ok(())
+}
 
-    if client_disputes.contains(&record.tx) {
-        // Transaction already disputed
-        return;
-    }
+/// Settles a hold as a withdrawal with its tx id, which can be disputed like any other.
+/// A `capture` record with an amount captures only that much of the hold and returns the
+/// rest to the available funds.
+pub fn capture(storage: &mut impl Storage, record: &Record) -> Result<(), RejectionReason> {
+    let Some(hold) = storage.hold(record.client, record.tx) else {
+        return Err(RejectionReason::UnknownHold);
+    };
 
-    if let Some(processed_record) = processed_records.get(&(record.client, record.tx)) {
-        if let Some(amount) = processed_record.amount {
-            match processed_record.r#type {
-                TxType::Deposit | TxType::Withdrawal => {
-                    out_record.available -= amount;
-                    out_record.held += amount;
-                    out_record.total = out_record.available + out_record.held;
-                    client_disputes.insert(record.tx);
-                }
-                _ => {}
-            }
-        }
+    if hold.currency != record.currency {
+        return Err(RejectionReason::CurrencyMismatch);
     }
-}
 
-pub fn resolve(
-    result: &mut HashMap<ClientId, AccountRecord>,
-    disputes: &mut HashMap<ClientId, HashSet<TxId>>,
-    processed_records: &HashMap<(ClientId, TxId), Record>,
-    record: &Record,
-) {
-    let Some(client_disputes) = disputes.get_mut(&record.client) else {
-        return;
+    let captured = match record.amount {
+        None => hold.amount,
+        Some(amount) if amount > 0.0 && amount <= hold.amount => amount,
+        Some(_) => return Err(RejectionReason::InvalidAmount),
     };
 
-    if !client_disputes.contains(&record.tx) {
-        // Assume there is an error on the partner's side.
-        return;
+    let Some(mut account_record) = storage.account(record.client) else {
+        return Err(RejectionReason::UnknownAccount);
+    };
+
+    if account_record.locked {
+        return Err(RejectionReason::AccountLocked);
     }
 
-    let Some(out_record) = result.get_mut(&record.client) else {
-        return;
+    account_record.adjust(record.currency, hold.amount - captured, -hold.amount);
+    storage.put_account(account_record);
+    storage.remove_hold(record.client, record.tx);
+    storage.record_tx(Record {
+        r#type: TxType::Withdrawal,
+        amount: Some(captured),
+        to_currency: None,
+        ..record.clone()
+    });
+
+    Ok(())
+}
+
+/// Returns the funds of a hold to the available funds. Unlike a capture it also applies
+/// to locked accounts, since no money leaves the account.
+pub fn release(storage: &mut impl Storage, record: &Record) -> Result<(), RejectionReason> {
+    let Some(hold) = storage.hold(record.client, record.tx) else {
+        return Err(RejectionReason::UnknownHold);
     };
 
-    if out_record.locked {
-        return;
+    if hold.currency != record.currency {
+        return Err(RejectionReason::CurrencyMismatch);
     }
 
-    if let Some(processed_record) = processed_records.get(&(record.client, record.tx)) {
-        if let Some(amount) = processed_record.amount {
-            out_record.available += amount;
-            out_record.held -= amount;
-            out_record.total = out_record.available + out_record.held;
+    let Some(mut account_record) = storage.account(record.client) else {
+        return Err(RejectionReason::UnknownAccount);
+    };
 
-            client_disputes.remove(&record.tx);
-        }
-    }
+    account_record.release(record.currency, hold.amount)?;
+    storage.put_account(account_record);
+    storage.remove_hold(record.client, record.tx);
+
+    Ok(())
 }
 
-pub fn chargeback(
-    result: &mut HashMap<ClientId, AccountRecord>,
-    disputes: &mut HashMap<ClientId, HashSet<TxId>>,
-    processed_records: &HashMap<(ClientId, TxId), Record>,
+/// Moves the amount of a `convert` record from the client's balance in its currency to
+/// the one in its target currency, at the rate of `rates`.
+pub fn convert(
+    storage: &mut impl Storage,
     record: &Record,
-) {
-    let Some(client_disputes) = disputes.get_mut(&record.client) else {
-        return;
+    rates: &RateTable,
+    rounding: FxRounding,
+) -> Result<(), RejectionReason> {
+    let Some(amount) = record.amount.filter(|&amount| amount > 0.0) else {
+        return Err(RejectionReason::InvalidAmount);
     };
 
-    if !client_disputes.contains(&record.tx) {
-        // Assume there is an error on the partner's side.
-        return;
+    let Some(mut account_record) = storage.account(record.client) else {
+        return Err(RejectionReason::UnknownAccount);
+    };
+
+    if account_record.locked {
+        return Err(RejectionReason::AccountLocked);
     }
 
-    let Some(out_record) = result.get_mut(&record.client) else {
-        return;
+    let Some(converted) = rates.convert(amount, record.currency, record.to_currency, rounding)
+    else {
+        return Err(RejectionReason::MissingRate);
     };
 
-    if out_record.locked {
-        return;
+    if account_record.balance(record.currency).available < amount {
+        return Err(RejectionReason::InsufficientFunds);
     }
 
-    if let Some(processed_record) = processed_records.get(&(record.client, record.tx)) {
-        if let Some(amount) = processed_record.amount {
-            if out_record.held >= amount {
-                out_record.held -= amount;
-                out_record.total = out_record.available + out_record.held;
-            }
+    account_record.adjust(record.currency, -amount, Money::ZERO);
+    account_record.adjust(record.to_currency, converted, Money::ZERO);
+    storage.put_account(account_record);
 
-            client_disputes.remove(&record.tx);
-            out_record.locked = true;
-        }
-    }
+    Ok(())
 }
 
-fn serialize_f32_4dp<S>(value: &f32, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    let rounded = (value * 10_000.0).round() / 10_000.0;
-    serializer.serialize_str(&format!("{:.4}", rounded))
+// Tells a dispute naming the wrong client apart from one naming an unknown tx id.
+fn tx_not_found(processed_records: &impl TxLookup, tx: TxId) -> RejectionReason {
+    if processed_records.contains_tx_id(tx) {
+        RejectionReason::TxNotFoundForClient
+    } else {
+        RejectionReason::TxNeverSeen
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::ids::RangeAllocator;
     use crate::records::{read_csv, TxType};
+    use crate::testing::Scenario;
 
     use super::*;
-    use std::{collections::HashMap, collections::HashSet};
+    use std::collections::HashMap;
+
+    #[test]
+    fn accounts_keep_their_total() {
+        let mut account = Account {
+            client: ClientId(1),
+            ..Default::default()
+        };
+        let usd = "USD".parse().ok();
+
+        assert_eq!(account.deposit(None, Money::new(10.0)), Ok(()));
+        assert_eq!(account.deposit(usd, Money::new(4.0)), Ok(()));
+        assert_eq!(
+            account.withdraw(None, Money::new(12.0), Money::ZERO),
+            Err(RejectionReason::InsufficientFunds)
+        );
+        assert_eq!(
+            account.hold(None, Money::new(12.0), DisputeHoldPolicy::RequireFunds),
+            Err(RejectionReason::InsufficientFunds)
+        );
+        assert_eq!(
+            account.hold(None, Money::new(6.0), DisputeHoldPolicy::RequireFunds),
+            Ok(())
+        );
+        assert_eq!(account.release(None, Money::new(2.0)), Ok(()));
+        assert_eq!(account.charge_back(None, Money::new(4.0)), Ok(()));
+
+        assert_eq!(
+            (account.available, account.held, account.total),
+            (Money::new(6.0), Money::ZERO, Money::new(6.0))
+        );
+        assert_eq!(account.balance(usd).total, 4.0);
+        assert_eq!(
+            account.deposit(None, Money::new(1.0)),
+            Err(RejectionReason::AccountLocked)
+        );
+        assert_eq!(account.lock(), Err(RejectionReason::AccountLocked));
+        assert_eq!(account.unlock(), Ok(()));
+        assert_eq!(account.unlock(), Err(RejectionReason::NotLocked));
+        assert_eq!(
+            account.deposit(None, Money::new(-1.0)),
+            Err(RejectionReason::InvalidAmount)
+        );
+    }
 
     #[test]
     fn deposit_existing_client() {
-        let mut result: HashMap<u16, AccountRecord> = HashMap::new();
-        result.insert(1, AccountRecord::default());
+        let mut storage = MemoryStorage::new();
+        storage.put_account(Account {
+            client: ClientId(1),
+            ..Default::default()
+        });
         let record = Record {
             r#type: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Some(100.0),
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Money::new(100.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
         };
 
-        deposit(&mut result, &record);
+        assert_eq!(deposit(&mut storage, &record), Ok(()));
 
-        assert_eq!(result[&1].available, 100.0);
-        assert_eq!(result[&1].total, 100.0);
+        assert_eq!(storage.accounts[&ClientId(1)].available, 100.0);
+        assert_eq!(storage.accounts[&ClientId(1)].total, 100.0);
     }
 
     #[test]
     fn deposit_new_client() {
-        let mut result: HashMap<u16, AccountRecord> = HashMap::new();
+        let mut storage = MemoryStorage::new();
         let record = Record {
             r#type: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Some(100.0),
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Money::new(100.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
         };
 
-        deposit(&mut result, &record);
+        assert_eq!(deposit(&mut storage, &record), Ok(()));
 
-        assert_eq!(result[&1].available, 100.0);
-        assert_eq!(result[&1].total, 100.0);
+        assert_eq!(storage.accounts[&ClientId(1)].available, 100.0);
+        assert_eq!(storage.accounts[&ClientId(1)].total, 100.0);
     }
 
     #[test]
     fn deposit_zero_amount() {
-        let mut result: HashMap<u16, AccountRecord> = HashMap::new();
+        let mut storage = MemoryStorage::new();
         let record = Record {
             r#type: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Some(0.0),
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Money::ZERO),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
         };
 
-        deposit(&mut result, &record);
+        assert_eq!(
+            deposit(&mut storage, &record),
+            Err(RejectionReason::InvalidAmount)
+        );
 
-        assert_eq!(result.get(&1), None);
+        assert_eq!(storage.accounts.get(&ClientId(1)), None);
     }
 
     #[test]
     fn deposit_negative_amount() {
-        let mut result: HashMap<u16, AccountRecord> = HashMap::new();
+        let mut storage = MemoryStorage::new();
         let record_positive_amount = Record {
             r#type: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Some(100.0),
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Money::new(100.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
         };
 
-        deposit(&mut result, &record_positive_amount);
-        assert_eq!(result[&1].available, 100.0);
-        assert_eq!(result[&1].total, 100.0);
+        assert_eq!(deposit(&mut storage, &record_positive_amount), Ok(()));
+        assert_eq!(storage.accounts[&ClientId(1)].available, 100.0);
+        assert_eq!(storage.accounts[&ClientId(1)].total, 100.0);
 
         let record_negative_amount = Record {
             r#type: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Some(-100.0),
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Money::new(-100.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
         };
 
-        deposit(&mut result, &record_negative_amount);
-        assert_eq!(result[&1].available, 100.0);
-        assert_eq!(result[&1].total, 100.0);
+        assert_eq!(
+            deposit(&mut storage, &record_negative_amount),
+            Err(RejectionReason::InvalidAmount)
+        );
+        assert_eq!(storage.accounts[&ClientId(1)].available, 100.0);
+        assert_eq!(storage.accounts[&ClientId(1)].total, 100.0);
     }
 
     #[test]
@@ -297,15 +2088,31 @@ mod tests {
         let records = vec![
             Record {
                 r#type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Some(100.0),
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Some(Money::new(100.0)),
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
             },
             Record {
                 r#type: TxType::Withdrawal,
-                client: 1,
-                tx: 1,
-                amount: Some(50.0),
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Some(Money::new(50.0)),
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
             },
         ];
 
@@ -313,308 +2120,1255 @@ mod tests {
 
         // The available amount and the total should be 100.0 since the second (Withdrawal) record
         // will not be processed because other record with same tx id already processed.
-        assert_eq!(processed_records[&1].available, 100.0);
-        assert_eq!(processed_records[&1].total, 100.0);
+        assert_eq!(processed_records[&ClientId(1)].available, 100.0);
+        assert_eq!(processed_records[&ClientId(1)].total, 100.0);
     }
 
     #[test]
     fn withdraw_sufficient_funds() {
-        let mut result: HashMap<u16, AccountRecord> = HashMap::new();
-        result.insert(
-            1,
-            AccountRecord {
-                client: 1,
-                available: 100.0,
-                held: 0.0,
-                total: 100.0,
-                locked: false,
-            },
-        );
+        let mut storage = MemoryStorage::new();
+        storage.put_account(Account {
+            client: ClientId(1),
+            available: Money::new(100.0),
+            held: Money::ZERO,
+            total: Money::new(100.0),
+            locked: false,
+            ..Default::default()
+        });
         let record = Record {
             r#type: TxType::Withdrawal,
-            client: 1,
-            tx: 1,
-            amount: Some(50.0),
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Money::new(50.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
         };
 
-        withdraw(&mut result, &record);
+        assert_eq!(withdraw(&mut storage, &record, Money::ZERO), Ok(()));
 
-        assert_eq!(result[&1].available, 50.0);
-        assert_eq!(result[&1].total, 50.0);
+        assert_eq!(storage.accounts[&ClientId(1)].available, 50.0);
+        assert_eq!(storage.accounts[&ClientId(1)].total, 50.0);
     }
 
     #[test]
     fn withdraw_insufficient_funds() {
-        let mut result: HashMap<u16, AccountRecord> = HashMap::new();
-        result.insert(
-            1,
-            AccountRecord {
-                client: 1,
-                available: 100.0,
-                held: 0.0,
-                total: 100.0,
-                locked: false,
-            },
-        );
+        let mut storage = MemoryStorage::new();
+        storage.put_account(Account {
+            client: ClientId(1),
+            available: Money::new(100.0),
+            held: Money::ZERO,
+            total: Money::new(100.0),
+            locked: false,
+            ..Default::default()
+        });
 
         let record = Record {
             r#type: TxType::Withdrawal,
-            client: 1,
-            tx: 1,
-            amount: Some(150.0),
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Money::new(150.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
         };
 
-        withdraw(&mut result, &record);
+        assert_eq!(
+            withdraw(&mut storage, &record, Money::ZERO),
+            Err(RejectionReason::InsufficientFunds)
+        );
 
-        assert_eq!(result[&1].available, 100.0);
-        assert_eq!(result[&1].total, 100.0);
+        assert_eq!(storage.accounts[&ClientId(1)].available, 100.0);
+        assert_eq!(storage.accounts[&ClientId(1)].total, 100.0);
     }
 
     #[test]
     fn dispute_existing_transaction() {
-        let mut result: HashMap<u16, AccountRecord> = HashMap::new();
-        result.insert(
-            1,
-            AccountRecord {
-                client: 1,
-                available: 100.0,
-                held: 0.0,
-                total: 100.0,
-                locked: false,
-            },
-        );
-
-        let mut disputes: HashMap<u16, HashSet<u32>> = HashMap::new();
-        let mut processed_records = HashMap::new();
-        processed_records.insert(
-            (1, 1),
-            Record {
-                r#type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Some(50.0),
-            },
-        );
-        processed_records.insert(
-            (1, 123),
-            Record {
-                r#type: TxType::Deposit,
-                client: 1,
-                tx: 123,
-                amount: Some(50.0),
-            },
-        );
+        let mut storage = MemoryStorage::new();
+        storage.put_account(Account {
+            client: ClientId(1),
+            available: Money::new(100.0),
+            held: Money::ZERO,
+            total: Money::new(100.0),
+            locked: false,
+            ..Default::default()
+        });
+
+        storage.record_tx(Record {
+            r#type: TxType::Deposit,
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Money::new(50.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        });
+        storage.record_tx(Record {
+            r#type: TxType::Deposit,
+            client: ClientId(1),
+            tx: TxId(123),
+            amount: Some(Money::new(50.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        });
 
         let record = Record {
             r#type: TxType::Dispute,
-            client: 1,
-            tx: 123,
+            client: ClientId(1),
+            tx: TxId(123),
             amount: None,
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
         };
 
-        dispute(&mut result, &mut disputes, &processed_records, &record);
+        assert_eq!(
+            dispute(
+                &mut storage,
+                &record,
+                LockedAccountPolicy::default(),
+                DisputeHoldPolicy::default(),
+                RedisputePolicy::default()
+            ),
+            Ok(())
+        );
 
-        assert_eq!(result[&1].available, 50.0);
-        assert_eq!(result[&1].held, 50.0);
-        assert_eq!(result[&1].total, 100.0);
-        assert!(disputes[&1].contains(&123));
+        assert_eq!(storage.accounts[&ClientId(1)].available, 50.0);
+        assert_eq!(storage.accounts[&ClientId(1)].held, 50.0);
+        assert_eq!(storage.accounts[&ClientId(1)].total, 100.0);
+        assert!(storage.is_disputed(ClientId(1), TxId(123)));
     }
 
     #[test]
     fn dispute_non_existing_transaction() {
-        let mut result: HashMap<u16, AccountRecord> = HashMap::new();
-        result.insert(
-            1,
-            AccountRecord {
-                client: 1,
-                available: 100.0,
-                held: 0.0,
-                total: 100.0,
-                locked: false,
-            },
-        );
-
-        let mut disputes: HashMap<u16, HashSet<u32>> = HashMap::new();
-        let processed_records = HashMap::new();
+        let mut storage = MemoryStorage::new();
+        storage.put_account(Account {
+            client: ClientId(1),
+            available: Money::new(100.0),
+            held: Money::ZERO,
+            total: Money::new(100.0),
+            locked: false,
+            ..Default::default()
+        });
 
         let record = Record {
             r#type: TxType::Dispute,
-            client: 1,
-            tx: 123,
+            client: ClientId(1),
+            tx: TxId(123),
             amount: None,
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
         };
 
-        dispute(&mut result, &mut disputes, &processed_records, &record);
+        assert_eq!(
+            dispute(
+                &mut storage,
+                &record,
+                LockedAccountPolicy::default(),
+                DisputeHoldPolicy::default(),
+                RedisputePolicy::default()
+            ),
+            Err(RejectionReason::TxNeverSeen)
+        );
 
-        assert_eq!(result[&1].available, 100.0);
-        assert_eq!(result[&1].held, 0.0);
-        assert_eq!(result[&1].total, 100.0);
-        assert!(!disputes.contains_key(&1));
+        assert_eq!(storage.accounts[&ClientId(1)].available, 100.0);
+        assert_eq!(storage.accounts[&ClientId(1)].held, 0.0);
+        assert_eq!(storage.accounts[&ClientId(1)].total, 100.0);
+        assert!(!storage.is_disputed(ClientId(1), TxId(123)));
     }
 
     #[test]
     fn resolve_existing_dispute() {
-        let mut result: HashMap<u16, AccountRecord> = HashMap::new();
-        result.insert(
-            1,
-            AccountRecord {
-                client: 1,
-                available: 50.0,
-                held: 50.0,
-                total: 100.0,
-                locked: false,
-            },
-        );
-
-        let mut disputes: HashMap<u16, HashSet<u32>> = HashMap::new();
-        let mut tx_disputed = HashSet::new();
-        tx_disputed.insert(123);
-        disputes.insert(1, tx_disputed);
-
-        let processed_records = vec![
+        let mut storage = MemoryStorage::new();
+        storage.put_account(Account {
+            client: ClientId(1),
+            available: Money::new(50.0),
+            held: Money::new(50.0),
+            total: Money::new(100.0),
+            locked: false,
+            ..Default::default()
+        });
+
+        storage.open_dispute(ClientId(1), TxId(123), None, None);
+
+        for record in [
             Record {
                 r#type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Some(50.0),
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Some(Money::new(50.0)),
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
             },
             Record {
                 r#type: TxType::Deposit,
-                client: 1,
-                tx: 123,
-                amount: Some(50.0),
+                client: ClientId(1),
+                tx: TxId(123),
+                amount: Some(Money::new(50.0)),
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
             },
-        ]
-        .into_iter()
-        .map(|r| ((r.client, r.tx), r))
-        .collect();
+        ] {
+            storage.record_tx(record);
+        }
 
         let record = Record {
             r#type: TxType::Resolve,
-            client: 1,
-            tx: 123,
+            client: ClientId(1),
+            tx: TxId(123),
             amount: None,
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
         };
 
-        resolve(&mut result, &mut disputes, &processed_records, &record);
+        assert_eq!(
+            resolve(&mut storage, &record, LockedAccountPolicy::default()),
+            Ok(())
+        );
 
-        assert_eq!(result[&1].available, 100.0);
-        assert_eq!(result[&1].held, 0.0);
-        assert_eq!(result[&1].total, 100.0);
-        assert!(!disputes[&1].contains(&123));
+        assert_eq!(storage.accounts[&ClientId(1)].available, 100.0);
+        assert_eq!(storage.accounts[&ClientId(1)].held, 0.0);
+        assert_eq!(storage.accounts[&ClientId(1)].total, 100.0);
+        assert!(!storage.is_disputed(ClientId(1), TxId(123)));
     }
 
     #[test]
     fn resolve_without_dispute() {
-        let mut result: HashMap<u16, AccountRecord> = HashMap::new();
-        let mut disputes: HashMap<u16, HashSet<u32>> = HashMap::new();
-        let mut processed_records = HashMap::new();
+        let mut storage = MemoryStorage::new();
 
         let deposit_record = Record {
             r#type: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Some(100.0),
-        };
-
-        deposit(&mut result, &deposit_record);
-        processed_records.insert((deposit_record.client, deposit_record.tx), deposit_record);
-
-        resolve(
-            &mut result,
-            &mut disputes,
-            &processed_records,
-            &Record {
-                r#type: TxType::Resolve,
-                client: 1,
-                tx: 1,
-                amount: None,
-            },
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Money::new(100.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        };
+
+        deposit(&mut storage, &deposit_record).unwrap();
+        storage.record_tx(deposit_record);
+
+        assert_eq!(
+            resolve(
+                &mut storage,
+                &Record {
+                    r#type: TxType::Resolve,
+                    client: ClientId(1),
+                    tx: TxId(1),
+                    amount: None,
+                    timestamp: None,
+                    currency: None,
+                    to_currency: None,
+                    total: None,
+                    reason_code: None,
+                    memo: None,
+                    reference: None,
+                    merchant_id: None,
+                },
+                LockedAccountPolicy::default(),
+            ),
+            Err(RejectionReason::NotDisputed)
         );
 
-        assert_eq!(result[&1].available, 100.0);
-        assert_eq!(result[&1].held, 0.0);
-        assert_eq!(result[&1].total, 100.0);
+        assert_eq!(storage.accounts[&ClientId(1)].available, 100.0);
+        assert_eq!(storage.accounts[&ClientId(1)].held, 0.0);
+        assert_eq!(storage.accounts[&ClientId(1)].total, 100.0);
     }
 
     #[test]
     fn chargeback_existing_dispute() {
-        let mut result: HashMap<u16, AccountRecord> = HashMap::new();
-        result.insert(
-            1,
-            AccountRecord {
-                client: 1,
-                available: 50.0,
-                held: 50.0,
-                total: 100.0,
-                locked: false,
-            },
-        );
-
-        let mut disputes: HashMap<u16, HashSet<u32>> = HashMap::new();
-        let mut tx_disputed = HashSet::new();
-        tx_disputed.insert(123);
-        disputes.insert(1, tx_disputed);
-
-        let processed_records = vec![
+        let mut storage = MemoryStorage::new();
+        storage.put_account(Account {
+            client: ClientId(1),
+            available: Money::new(50.0),
+            held: Money::new(50.0),
+            total: Money::new(100.0),
+            locked: false,
+            ..Default::default()
+        });
+
+        storage.open_dispute(ClientId(1), TxId(123), None, None);
+
+        for record in [
             Record {
                 r#type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Some(50.0),
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Some(Money::new(50.0)),
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
             },
             Record {
                 r#type: TxType::Deposit,
-                client: 1,
-                tx: 123,
-                amount: Some(50.0),
+                client: ClientId(1),
+                tx: TxId(123),
+                amount: Some(Money::new(50.0)),
+                timestamp: None,
+                currency: None,
+                to_currency: None,
+                total: None,
+                reason_code: None,
+                memo: None,
+                reference: None,
+                merchant_id: None,
             },
-        ]
-        .into_iter()
-        .map(|r| ((r.client, r.tx), r))
-        .collect();
+        ] {
+            storage.record_tx(record);
+        }
+
+        let record = Record {
+            r#type: TxType::Chargeback,
+            client: ClientId(1),
+            tx: TxId(123),
+            amount: None,
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        };
+
+        assert_eq!(
+            chargeback(
+                &mut storage,
+                &record,
+                ChargebackPolicy::default(),
+                LockedAccountPolicy::default()
+            ),
+            Ok(())
+        );
+
+        assert_eq!(storage.accounts[&ClientId(1)].available, 50.0);
+        assert_eq!(storage.accounts[&ClientId(1)].held, 0.0);
+        assert_eq!(storage.accounts[&ClientId(1)].total, 50.0);
+        assert!(storage.accounts[&ClientId(1)].locked);
+        assert!(!storage.is_disputed(ClientId(1), TxId(123)));
+    }
+
+    fn short_held_chargeback(
+        policy: ChargebackPolicy,
+    ) -> (Result<(), RejectionReason>, Account, bool) {
+        // Only 30 of the disputed 50 are still held.
+        let mut storage = MemoryStorage::new();
+        storage.put_account(Account {
+            client: ClientId(1),
+            available: Money::new(10.0),
+            held: Money::new(30.0),
+            total: Money::new(40.0),
+            locked: false,
+            ..Default::default()
+        });
+
+        storage.open_dispute(ClientId(1), TxId(1), None, None);
+
+        storage.record_tx(Record {
+            r#type: TxType::Deposit,
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Money::new(50.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        });
 
         let record = Record {
             r#type: TxType::Chargeback,
-            client: 1,
-            tx: 123,
+            client: ClientId(1),
+            tx: TxId(1),
             amount: None,
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
         };
 
-        chargeback(&mut result, &mut disputes, &processed_records, &record);
+        let outcome = chargeback(
+            &mut storage,
+            &record,
+            policy,
+            LockedAccountPolicy::default(),
+        );
+        (
+            outcome,
+            storage.accounts[&ClientId(1)].clone(),
+            storage.is_disputed(ClientId(1), TxId(1)),
+        )
+    }
+
+    #[test]
+    fn chargeback_insufficient_held_reject() {
+        let (outcome, account, disputed) = short_held_chargeback(ChargebackPolicy::Reject);
+
+        assert_eq!(outcome, Err(RejectionReason::InsufficientHeldFunds));
+        assert_eq!(account.available, 10.0);
+        assert_eq!(account.held, 30.0);
+        assert_eq!(account.total, 40.0);
+        assert!(!account.locked);
+        assert!(disputed);
+    }
 
-        assert_eq!(result[&1].available, 50.0);
-        assert_eq!(result[&1].held, 0.0);
-        assert_eq!(result[&1].total, 50.0);
-        assert!(result[&1].locked);
-        assert!(!disputes[&1].contains(&123));
+    #[test]
+    fn chargeback_insufficient_held_allow_negative() {
+        let (outcome, account, disputed) = short_held_chargeback(ChargebackPolicy::AllowNegative);
+
+        assert_eq!(outcome, Ok(()));
+        assert_eq!(account.available, 10.0);
+        assert_eq!(account.held, -20.0);
+        assert_eq!(account.total, -10.0);
+        assert!(account.locked);
+        assert!(!disputed);
     }
 
     #[test]
-    fn transactions_on_locked_account() {
-        let mut result: HashMap<u16, AccountRecord> = HashMap::new();
-        result.insert(
-            1,
-            AccountRecord {
-                client: 1,
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
-                locked: true,
-            },
+    fn chargeback_insufficient_held_partial() {
+        let (outcome, account, disputed) = short_held_chargeback(ChargebackPolicy::Partial);
+
+        assert_eq!(outcome, Ok(()));
+        assert_eq!(account.available, 10.0);
+        assert_eq!(account.held, 0.0);
+        assert_eq!(account.total, 10.0);
+        assert!(account.locked);
+        assert!(!disputed);
+    }
+
+    #[test]
+    fn engine_keeps_config() {
+        let config = EngineConfig {
+            chargeback_policy: ChargebackPolicy::AllowNegative,
+            ..Default::default()
+        };
+        let engine = Engine::with_config(config.clone());
+        assert_eq!(engine.config(), &config);
+
+        Scenario::from_engine(engine)
+            .deposit(1, 1, 100)
+            .dispute(1, 1)
+            .chargeback(1, 1)
+            .expect_held(ClientId(1), 0)
+            .expect_total(ClientId(1), 0)
+            .expect_locked(ClientId(1), true);
+    }
+
+    #[test]
+    fn strict_account_policy_requires_open_account() {
+        let engine = Engine::with_config(EngineConfig {
+            account_policy: AccountPolicy::Strict,
+            ..Default::default()
+        });
+
+        Scenario::from_engine(engine)
+            .deposit(1, 1, 100)
+            .expect_rejected(RejectionReason::UnknownAccount)
+            .expect_no_account(ClientId(1))
+            .open_account(ClientId(1))
+            .expect_total(ClientId(1), 0)
+            .deposit(1, 2, 100)
+            .expect_total(ClientId(1), 100)
+            .open_account(ClientId(1))
+            .expect_rejected(RejectionReason::AccountExists)
+            .expect_total(ClientId(1), 100);
+    }
+
+    #[test]
+    fn lock_and_unlock() {
+        let mut engine = Scenario::new()
+            .unlock(ClientId(1))
+            .expect_rejected(RejectionReason::UnknownAccount)
+            .deposit(1, 1, 100)
+            .dispute(1, 1)
+            .chargeback(1, 1)
+            .expect_locked(ClientId(1), true)
+            .unlock(ClientId(1))
+            .expect_locked(ClientId(1), false)
+            .deposit(1, 2, 50)
+            .expect_total(ClientId(1), 50)
+            .unlock(ClientId(1))
+            .expect_rejected(RejectionReason::NotLocked)
+            .lock(ClientId(1))
+            .expect_locked(ClientId(1), true)
+            .withdraw(1, 3, 10)
+            .expect_rejected(RejectionReason::AccountLocked)
+            .into_engine();
+
+        assert_eq!(engine.unlock(ClientId(1)), Ok(()));
+        assert_eq!(engine.unlock(ClientId(1)), Err(RejectionReason::NotLocked));
+        assert!(!engine.account(ClientId(1)).unwrap().locked);
+    }
+
+    #[test]
+    fn locked_account_policy() {
+        let after_first_chargeback = |policy| {
+            let engine = Engine::with_config(EngineConfig {
+                locked_account_policy: policy,
+                ..Default::default()
+            });
+            Scenario::from_engine(engine)
+                .deposit(1, 1, 100)
+                .deposit(1, 2, 50)
+                .dispute(1, 1)
+                .dispute(1, 2)
+                .chargeback(1, 1)
+                .expect_locked(ClientId(1), true)
+        };
+
+        after_first_chargeback(LockedAccountPolicy::Freeze)
+            .resolve(1, 2)
+            .expect_rejected(RejectionReason::AccountLocked)
+            .expect_held(ClientId(1), 50);
+
+        after_first_chargeback(LockedAccountPolicy::AllowDisputes)
+            .resolve(1, 2)
+            .expect_available(ClientId(1), 50)
+            .expect_held(ClientId(1), 0)
+            .dispute(1, 2)
+            .chargeback(1, 2)
+            .expect_total(ClientId(1), 0)
+            .expect_locked(ClientId(1), true)
+            .deposit(1, 3, 10)
+            .expect_rejected(RejectionReason::AccountLocked)
+            .withdraw(1, 4, 10)
+            .expect_rejected(RejectionReason::AccountLocked);
+    }
+
+    #[test]
+    fn dispute_hold_policy() {
+        let spent_deposit = |policy| {
+            let engine = Engine::with_config(EngineConfig {
+                dispute_hold_policy: policy,
+                ..Default::default()
+            });
+            Scenario::from_engine(engine)
+                .deposit(1, 1, 100)
+                .withdraw(1, 2, 80)
+                .dispute(1, 1)
+        };
+
+        spent_deposit(DisputeHoldPolicy::AllowNegative)
+            .expect_available(ClientId(1), -80)
+            .expect_held(ClientId(1), 100);
+
+        spent_deposit(DisputeHoldPolicy::RequireFunds)
+            .expect_rejected(RejectionReason::InsufficientFunds)
+            .expect_available(ClientId(1), 20)
+            .expect_held(ClientId(1), 0)
+            // Disputing the withdrawal itself never needs available funds.
+            .dispute(1, 2)
+            .expect_available(ClientId(1), 20)
+            .expect_held(ClientId(1), 80);
+    }
+
+    #[test]
+    fn partial_disputes_hold_only_the_disputed_amount() {
+        Scenario::new()
+            .deposit(1, 1, 100)
+            .dispute_part(1, 1, 150)
+            .expect_rejected(RejectionReason::InvalidAmount)
+            .dispute_part(1, 1, 30)
+            .expect_available(ClientId(1), 70)
+            .expect_held(ClientId(1), 30)
+            .resolve(1, 1)
+            .expect_available(ClientId(1), 100)
+            .expect_held(ClientId(1), 0)
+            .dispute_part(1, 1, 40)
+            .chargeback(1, 1)
+            .expect_available(ClientId(1), 60)
+            .expect_total(ClientId(1), 60)
+            .expect_locked(ClientId(1), true);
+
+        let engine = Scenario::new()
+            .deposit(1, 1, 100)
+            .withdraw(1, 2, 50)
+            .dispute_part(1, 2, 20)
+            .expect_available(ClientId(1), 50)
+            .expect_held(ClientId(1), 20)
+            .into_engine();
+        assert_eq!(
+            engine.disputed_amount(ClientId(1), TxId(2)),
+            Some(Money::new(20.0))
+        );
+
+        let engine = Scenario::from_engine(engine)
+            .chargeback(1, 2)
+            .expect_available(ClientId(1), 70)
+            .expect_held(ClientId(1), 0)
+            .into_engine();
+        assert_eq!(engine.disputed_amount(ClientId(1), TxId(2)), None);
+
+        // Disputing the full amount is the same as a dispute without one.
+        let engine = Scenario::new()
+            .deposit(1, 1, 100)
+            .dispute_part(1, 1, 100)
+            .expect_held(ClientId(1), 100)
+            .into_engine();
+        assert_eq!(engine.disputed_amount(ClientId(1), TxId(1)), None);
+    }
+
+    #[test]
+    fn representment_reverses_chargebacks() {
+        let charged_back = |policy| {
+            let engine = Engine::with_config(EngineConfig {
+                representment_policy: policy,
+                ..Default::default()
+            });
+            Scenario::from_engine(engine)
+                .deposit(1, 1, 100)
+                .withdraw(1, 2, 30)
+                .representment(1, 1)
+                .expect_rejected(RejectionReason::NotChargedBack)
+                .dispute(1, 1)
+                .chargeback(1, 1)
+                .expect_total(ClientId(1), -30)
+                .expect_locked(ClientId(1), true)
+        };
+
+        charged_back(RepresentmentPolicy::KeepLocked)
+            .representment(1, 1)
+            .expect_available(ClientId(1), 70)
+            .expect_held(ClientId(1), 0)
+            .expect_locked(ClientId(1), true)
+            .representment(1, 1)
+            .expect_rejected(RejectionReason::NotChargedBack)
+            .expect_available(ClientId(1), 70);
+
+        let engine = charged_back(RepresentmentPolicy::Unlock)
+            .representment(1, 1)
+            .expect_available(ClientId(1), 70)
+            .expect_locked(ClientId(1), false)
+            // The charged back withdrawal stands again.
+            .dispute(1, 2)
+            .chargeback(1, 2)
+            .expect_available(ClientId(1), 100)
+            .representment(1, 2)
+            .expect_available(ClientId(1), 70)
+            .into_engine();
+        assert_eq!(engine.charged_back(ClientId(1), TxId(2)), None);
+    }
+
+    #[test]
+    fn redispute_policy() {
+        let cycles = |policy: &str, cycles| {
+            let engine = Engine::with_config(EngineConfig {
+                redispute_policy: policy.parse().unwrap(),
+                ..Default::default()
+            });
+            (0..cycles).fold(
+                Scenario::from_engine(engine).deposit(1, 1, 10),
+                |scenario, _| {
+                    scenario
+                        .dispute(1, 1)
+                        .expect_held(ClientId(1), 10)
+                        .resolve(1, 1)
+                },
+            )
+        };
+
+        let engine = cycles("allow-once", 1)
+            .dispute(1, 1)
+            .expect_rejected(RejectionReason::DisputeLimitReached)
+            .expect_available(ClientId(1), 10)
+            .expect_held(ClientId(1), 0)
+            .into_engine();
+        assert_eq!(engine.dispute_count(ClientId(1), TxId(1)), 1);
+
+        cycles("allow-2", 2)
+            .dispute(1, 1)
+            .expect_rejected(RejectionReason::DisputeLimitReached);
+
+        let engine = cycles("allow-always", 5).into_engine();
+        assert_eq!(engine.dispute_count(ClientId(1), TxId(1)), 5);
+
+        assert!("allow-0".parse::<RedisputePolicy>().is_err());
+        assert_eq!(RedisputePolicy::AllowN(1).to_string(), "allow-once");
+    }
+
+    #[test]
+    fn report_account_policy_tracks_auto_created_accounts() {
+        let engine = Engine::with_config(EngineConfig {
+            account_policy: AccountPolicy::Report,
+            ..Default::default()
+        });
+
+        let engine = Scenario::from_engine(engine)
+            .open_account(ClientId(1))
+            .deposit(1, 1, 10)
+            .deposit(2, 2, 10)
+            .deposit(2, 3, 10)
+            .expect_total(ClientId(2), 20)
+            .into_engine();
+        assert_eq!(
+            engine.auto_created(),
+            &[AutoCreatedAccount {
+                client: ClientId(2),
+                tx: TxId(2)
+            }]
+        );
+
+        let engine = Scenario::new().deposit(2, 2, 10).into_engine();
+        assert!(engine.auto_created().is_empty());
+    }
+
+    #[test]
+    fn duplicate_scope_global() {
+        Scenario::new()
+            .deposit(1, 1, 10)
+            .deposit(2, 1, 10)
+            .expect_rejected(RejectionReason::DuplicateTx)
+            .expect_no_account(ClientId(2))
+            .withdraw(1, 1, 5)
+            .expect_rejected(RejectionReason::DuplicateTx)
+            .expect_total(ClientId(1), 10);
+    }
+
+    #[test]
+    fn duplicate_scope_per_client() {
+        let engine = Engine::with_config(EngineConfig {
+            duplicate_scope: DuplicateScope::PerClient,
+            ..Default::default()
+        });
+
+        Scenario::from_engine(engine)
+            .deposit(1, 1, 10)
+            .deposit(2, 1, 20)
+            .expect_total(ClientId(2), 20)
+            .deposit(2, 1, 20)
+            .expect_rejected(RejectionReason::DuplicateTx)
+            .dispute(2, 1)
+            .expect_held(ClientId(2), 20)
+            .expect_held(ClientId(1), 0);
+    }
+
+    #[test]
+    fn dispute_withdrawal() {
+        let mut storage = MemoryStorage::new();
+        storage.put_account(Account {
+            client: ClientId(1),
+            available: Money::new(50.0),
+            held: Money::ZERO,
+            total: Money::new(50.0),
+            locked: false,
+            ..Default::default()
+        });
+
+        storage.record_tx(Record {
+            r#type: TxType::Withdrawal,
+            client: ClientId(1),
+            tx: TxId(2),
+            amount: Some(Money::new(50.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        });
+
+        let record = Record {
+            r#type: TxType::Dispute,
+            client: ClientId(1),
+            tx: TxId(2),
+            amount: None,
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        };
+
+        assert_eq!(
+            dispute(
+                &mut storage,
+                &record,
+                LockedAccountPolicy::default(),
+                DisputeHoldPolicy::default(),
+                RedisputePolicy::default()
+            ),
+            Ok(())
         );
 
+        assert_eq!(storage.accounts[&ClientId(1)].available, 50.0);
+        assert_eq!(storage.accounts[&ClientId(1)].held, 50.0);
+        assert_eq!(storage.accounts[&ClientId(1)].total, 100.0);
+        assert!(storage.is_disputed(ClientId(1), TxId(2)));
+    }
+
+    #[test]
+    fn resolve_disputed_withdrawal() {
+        Scenario::new()
+            .deposit(1, 1, 100)
+            .withdraw(1, 2, 50)
+            .dispute(1, 2)
+            .expect_held(ClientId(1), 50)
+            .expect_total(ClientId(1), 100)
+            .resolve(1, 2)
+            .expect_available(ClientId(1), 50)
+            .expect_held(ClientId(1), 0)
+            .expect_total(ClientId(1), 50)
+            .expect_locked(ClientId(1), false);
+    }
+
+    #[test]
+    fn chargeback_disputed_withdrawal() {
+        // The withdrawal is reversed, so the client gets the withdrawn funds back.
+        Scenario::new()
+            .deposit(1, 1, 100)
+            .withdraw(1, 2, 50)
+            .dispute(1, 2)
+            .chargeback(1, 2)
+            .expect_available(ClientId(1), 100)
+            .expect_held(ClientId(1), 0)
+            .expect_total(ClientId(1), 100)
+            .expect_locked(ClientId(1), true);
+    }
+
+    #[test]
+    fn transactions_on_locked_account() {
+        let mut storage = MemoryStorage::new();
+        storage.put_account(Account {
+            client: ClientId(1),
+            available: Money::ZERO,
+            held: Money::ZERO,
+            total: Money::ZERO,
+            locked: true,
+            ..Default::default()
+        });
+
         let record = Record {
             r#type: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Some(100.0),
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Money::new(100.0)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
         };
 
-        deposit(&mut result, &record);
+        assert_eq!(
+            deposit(&mut storage, &record),
+            Err(RejectionReason::AccountLocked)
+        );
+
+        assert_eq!(storage.accounts[&ClientId(1)].available, 0.0);
+        assert_eq!(storage.accounts[&ClientId(1)].total, 0.0);
+    }
+
+    #[test]
+    fn dispute_tells_wrong_client_from_unknown_tx() {
+        Scenario::new()
+            .deposit(1, 1, 10)
+            .deposit(2, 2, 10)
+            .dispute(2, 1)
+            .expect_rejected(RejectionReason::TxNotFoundForClient)
+            .dispute(2, 3)
+            .expect_rejected(RejectionReason::TxNeverSeen)
+            .chargeback(2, 1)
+            .expect_rejected(RejectionReason::NotDisputed)
+            .expect_held(ClientId(1), 0)
+            .expect_held(ClientId(2), 0);
+    }
+
+    #[test]
+    fn balances_per_currency() {
+        let engine = Scenario::new()
+            .deposit(1, 1, 10)
+            .in_currency(Some("EUR"))
+            .deposit(1, 2, 5)
+            .withdraw(1, 3, 8)
+            .expect_rejected(RejectionReason::InsufficientFunds)
+            .withdraw(1, 4, 1)
+            .dispute(1, 1)
+            .expect_rejected(RejectionReason::CurrencyMismatch)
+            .dispute(1, 2)
+            .expect_balance(ClientId(1), "EUR", -1, 5)
+            .in_currency(Some("USD"))
+            .chargeback(1, 2)
+            .expect_rejected(RejectionReason::CurrencyMismatch)
+            .in_currency(None)
+            .resolve(1, 2)
+            .expect_rejected(RejectionReason::CurrencyMismatch)
+            .dispute(1, 1)
+            .expect_held(ClientId(1), 10)
+            .expect_balance(ClientId(1), "EUR", -1, 5)
+            .into_engine();
+
+        let account = engine.account(ClientId(1)).unwrap();
+        assert_eq!(account.currencies.len(), 1);
+        assert_eq!(account.balance("EUR".parse().ok()).total, 4.0);
+        // Only the base currency counts towards the activity.
+        assert_eq!(engine.activity()[0].net_deposited, 10.0);
+    }
+
+    #[test]
+    fn convert_between_currencies() {
+        let mut rates = RateTable::new();
+        rates.insert(None, "EUR".parse().ok(), 0.33333);
+        let engine = Engine::with_config(EngineConfig {
+            rates,
+            fx_rounding: FxRounding::Down,
+            ..Default::default()
+        });
+
+        Scenario::from_engine(engine)
+            .convert(1, 1, 1, Some("EUR"))
+            .expect_rejected(RejectionReason::UnknownAccount)
+            .deposit(1, 2, 10)
+            .convert(1, 3, 1, Some("USD"))
+            .expect_rejected(RejectionReason::MissingRate)
+            .convert(1, 4, 11, Some("EUR"))
+            .expect_rejected(RejectionReason::InsufficientFunds)
+            .convert(1, 5, 4, Some("EUR"))
+            .expect_available(ClientId(1), 6)
+            .expect_balance(ClientId(1), "EUR", 1.3333, 0)
+            .in_currency(Some("EUR"))
+            .convert(1, 6, 1, None)
+            .expect_balance(ClientId(1), "EUR", 0.3333, 0)
+            .expect_total(ClientId(1), 9)
+            .in_currency(None)
+            .lock(ClientId(1))
+            .convert(1, 7, 1, Some("EUR"))
+            .expect_rejected(RejectionReason::AccountLocked);
+    }
+
+    #[test]
+    fn fees_and_chargeback_fees() {
+        Scenario::new()
+            .fee(1, 1, 1)
+            .expect_rejected(RejectionReason::UnknownAccount)
+            .deposit(1, 2, 10)
+            .fee(1, 3, 0)
+            .expect_rejected(RejectionReason::InvalidAmount)
+            .fee(1, 4, 11)
+            .expect_rejected(RejectionReason::InsufficientFunds)
+            .fee(1, 5, 10)
+            .expect_available(ClientId(1), 0)
+            .expect_total(ClientId(1), 0);
+
+        let engine = Engine::with_config(EngineConfig {
+            chargeback_fee: Some(Money::new(15.0)),
+            ..Default::default()
+        });
+        let engine = Scenario::from_engine(engine)
+            .deposit(1, 1, 100)
+            .deposit(1, 2, 20)
+            .dispute(1, 2)
+            .chargeback(1, 2)
+            .expect_available(ClientId(1), 85)
+            .expect_total(ClientId(1), 85)
+            .expect_locked(ClientId(1), true)
+            .deposit(2, 3, 10)
+            .dispute(2, 3)
+            .chargeback(2, 3)
+            .expect_available(ClientId(2), 0)
+            .deposit(3, 4, 5)
+            .withdraw(3, 5, 5)
+            .dispute(3, 4)
+            .chargeback(3, 4)
+            .expect_available(ClientId(3), -5)
+            .into_engine();
+
+        // Only the account with funds left was charged, and only as far as they went.
+        let fees: Vec<Money> = engine.activity().iter().map(|a| a.fees).collect();
+        assert_eq!(fees, vec![15.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn balance_assertions() {
+        let engine = Scenario::new()
+            .assert_balance(1, 1, Some(0.0), None)
+            .deposit(1, 2, 10)
+            .deposit(1, 3, 5)
+            .dispute(1, 3)
+            .assert_balance(1, 4, Some(10.0), Some(15.0))
+            .assert_balance(1, 5, None, Some(10.0))
+            .assert_balance(1, 6, None, None)
+            .expect_rejected(RejectionReason::InvalidAmount)
+            .in_currency(Some("EUR"))
+            .assert_balance(1, 7, Some(1.0), None)
+            .expect_available(ClientId(1), 10)
+            .into_engine();
+
+        let failed = engine.failed_assertions();
+        assert_eq!(failed.len(), 2);
+        assert_eq!(
+            failed[0].to_string(),
+            "assert tx 5: client 1 has 10.0000 available and 15.0000 in total, \
+             expected 10.0000 in total"
+        );
+        assert_eq!(
+            failed[1].to_string(),
+            "assert tx 7: client 1 has 0.0000 available and 0.0000 in total in EUR, \
+             expected 1.0000 available"
+        );
+    }
+
+    #[test]
+    fn holds_capture_and_release() {
+        let engine = Scenario::new()
+            .deposit(1, 1, 100)
+            .hold(1, 2, 150)
+            .expect_rejected(RejectionReason::InsufficientFunds)
+            .hold(1, 2, 60)
+            .expect_available(ClientId(1), 40)
+            .expect_held(ClientId(1), 60)
+            .expect_total(ClientId(1), 100)
+            .deposit(1, 2, 5)
+            .expect_rejected(RejectionReason::DuplicateTx)
+            .hold(1, 3, 30)
+            .capture(1, 2, Some(61.0))
+            .expect_rejected(RejectionReason::InvalidAmount)
+            .capture(1, 2, Some(50.0))
+            .expect_available(ClientId(1), 20)
+            .expect_held(ClientId(1), 30)
+            .expect_total(ClientId(1), 50)
+            .capture(1, 2, None)
+            .expect_rejected(RejectionReason::UnknownHold)
+            .release(1, 3)
+            .expect_available(ClientId(1), 50)
+            .expect_held(ClientId(1), 0)
+            .release(1, 3)
+            .expect_rejected(RejectionReason::UnknownHold)
+            // The captured amount is a withdrawal that can be disputed.
+            .dispute(1, 2)
+            .expect_held(ClientId(1), 50)
+            .into_engine();
+
+        assert!(engine.holds().is_empty());
+        assert_eq!(engine.activity()[0].net_withdrawn, 50.0);
+    }
+
+    #[test]
+    fn holds_expire() {
+        let engine = Engine::with_config(EngineConfig {
+            hold_expiry: Some(24),
+            ..Default::default()
+        });
+        let engine = Scenario::from_engine(engine)
+            .at("2024-01-01T10:00:00Z")
+            .deposit(1, 1, 100)
+            .hold(1, 2, 60)
+            .at("2024-01-02T09:59:59Z")
+            .hold(1, 3, 10)
+            .expect_available(ClientId(1), 30)
+            .at("2024-01-02T10:00:00Z")
+            .deposit(1, 4, 1)
+            .expect_available(ClientId(1), 91)
+            .expect_held(ClientId(1), 10)
+            .capture(1, 2, None)
+            .expect_rejected(RejectionReason::UnknownHold)
+            .into_engine();
+
+        assert_eq!(engine.holds().len(), 1);
+    }
+
+    #[test]
+    fn future_dated_records_are_deferred() {
+        let engine = Engine::with_config(EngineConfig {
+            as_of: "2024-01-02T23:59:59Z".parse().ok(),
+            ..Default::default()
+        });
+        let mut engine = Scenario::from_engine(engine)
+            .at("2024-01-02T09:00:00Z")
+            .deposit(1, 1, 10)
+            .at("2024-01-03T12:00:00Z")
+            .withdraw(1, 3, 12)
+            .at("2024-01-03T08:00:00Z")
+            .deposit(1, 2, 5)
+            .expect_available(ClientId(1), 10)
+            .into_engine();
+
+        let txs: Vec<TxId> = engine.deferred().iter().map(|record| record.tx).collect();
+        assert_eq!(txs, [2, 3].map(TxId));
+
+        let applied = engine.advance_clock("2024-01-03T10:00:00Z".parse().unwrap());
+        assert_eq!(applied.len(), 1);
+        assert_eq!(applied[0].1, Ok(()));
+        assert_eq!(engine.account(ClientId(1)).unwrap().available, 15.0);
+
+        let applied = engine.advance_clock("2024-01-03T23:59:59Z".parse().unwrap());
+        assert_eq!(applied[0].0.tx, TxId(3));
+        assert_eq!(applied[0].1, Ok(()));
+        assert!(engine.deferred().is_empty());
+        assert_eq!(engine.account(ClientId(1)).unwrap().available, 3.0);
+    }
+
+    #[test]
+    fn dispute_rejected_withdrawal() {
+        Scenario::new()
+            .deposit(1, 1, 10)
+            .withdraw(1, 2, 50)
+            .expect_rejected(RejectionReason::InsufficientFunds)
+            .dispute(1, 2)
+            .expect_rejected(RejectionReason::TxNeverSeen)
+            .expect_held(ClientId(1), 0)
+            .expect_total(ClientId(1), 10);
+    }
+
+    #[test]
+    fn engine_counts_rejections() {
+        let engine = Scenario::new()
+            .withdraw(1, 1, 10)
+            .deposit(1, 2, 10)
+            .deposit(1, 2, 10)
+            .withdraw(1, 3, 50)
+            .withdraw(1, 4, 50)
+            .into_engine();
+
+        let metrics = engine.metrics();
+        assert_eq!(
+            metrics.count(TxType::Withdrawal, RejectionReason::UnknownAccount),
+            1
+        );
+        assert_eq!(
+            metrics.count(TxType::Deposit, RejectionReason::DuplicateTx),
+            1
+        );
+        assert_eq!(
+            metrics.count(TxType::Withdrawal, RejectionReason::InsufficientFunds),
+            2
+        );
+    }
+
+    #[test]
+    fn reserved_tx_ids_are_rejected() {
+        let high_bit: RawTxId = 1 << (RawTxId::BITS - 1);
+        let mut engine = Scenario::new()
+            .deposit(1, high_bit, 10)
+            .expect_rejected(RejectionReason::ReservedTxId)
+            .expect_no_account(ClientId(1))
+            .into_engine();
+        assert_eq!(engine.next_tx_id(), Some(TxId(high_bit)));
+
+        engine.set_id_allocator(Box::new(RangeAllocator::new(TxId(100), TxId(199))));
+        Scenario::from_engine(engine)
+            .deposit(1, high_bit, 10)
+            .expect_available(ClientId(1), 10)
+            .withdraw(1, 150, 5)
+            .expect_rejected(RejectionReason::ReservedTxId);
+    }
+
+    #[test]
+    fn engine_tracks_client_activity() {
+        let engine = Scenario::new()
+            .deposit(1, 1, 100)
+            .deposit(1, 2, 30)
+            .withdraw(1, 3, 20)
+            .withdraw(1, 4, 500)
+            .dispute(1, 2)
+            .chargeback(1, 2)
+            .into_engine();
+
+        assert_eq!(
+            engine.activity(),
+            vec![ClientActivity {
+                client: ClientId(1),
+                net_deposited: Money::new(100.0),
+                net_withdrawn: Money::new(20.0),
+                chargebacks: 1,
+                charged_back: Money::new(30.0),
+                fees: Money::ZERO,
+            }]
+        );
+    }
 
-        assert_eq!(result[&1].available, 0.0);
-        assert_eq!(result[&1].total, 0.0);
+    #[test]
+    fn engine_account_views() {
+        let engine = Scenario::new()
+            .deposit(3, 1, 30)
+            .deposit(1, 2, 10)
+            .deposit(2, 3, 20)
+            .into_engine();
+
+        let clients: Vec<ClientId> = engine.accounts_iter().map(|a| a.client).collect();
+        assert_eq!(clients, [1, 2, 3].map(ClientId));
+
+        assert_eq!(
+            engine.account(ClientId(2)).map(|a| a.available),
+            Some(Money::new(20.0))
+        );
+        assert_eq!(engine.account(ClientId(4)), None);
     }
 
     #[test]
@@ -625,27 +3379,245 @@ mod tests {
         let mut expected_processed_records = HashMap::new();
 
         expected_processed_records.insert(
-            1,
-            AccountRecord {
-                client: 1,
-                available: 200.0,
-                held: 0.0,
-                total: 200.0,
+            ClientId(1),
+            Account {
+                client: ClientId(1),
+                available: Money::new(200.0),
+                held: Money::ZERO,
+                total: Money::new(200.0),
                 locked: false,
+                ..Default::default()
             },
         );
         expected_processed_records.insert(
-            2,
-            AccountRecord {
-                client: 2,
-                available: 250.0,
-                held: 0.0,
-                total: 250.0,
+            ClientId(2),
+            Account {
+                client: ClientId(2),
+                available: Money::new(450.0),
+                held: Money::ZERO,
+                total: Money::new(450.0),
                 locked: true,
+                ..Default::default()
             },
         );
 
-        assert_eq!(processed_records[&1], expected_processed_records[&1]);
-        assert_eq!(processed_records[&2], expected_processed_records[&2]);
+        assert_eq!(
+            processed_records[&ClientId(1)],
+            expected_processed_records[&ClientId(1)]
+        );
+        assert_eq!(
+            processed_records[&ClientId(2)],
+            expected_processed_records[&ClientId(2)]
+        );
+    }
+
+    #[test]
+    fn rejected_batches_roll_back() {
+        let record = |r#type, tx: RawTxId, amount: Option<f32>| Record {
+            r#type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: amount.map(Money::new),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        };
+        let mut engine = Engine::new();
+        engine.keep_history();
+        assert_eq!(engine.apply(record(TxType::Deposit, 1, Some(10.0))), Ok(()));
+
+        let batch = [
+            record(TxType::Deposit, 2, Some(5.0)),
+            record(TxType::Dispute, 2, None),
+            record(TxType::Withdrawal, 3, Some(100.0)),
+            record(TxType::Deposit, 4, Some(1.0)),
+        ];
+        let result = engine.apply_batch(&batch);
+        assert!(result.rolled_back);
+        assert_eq!(
+            result.results,
+            vec![Ok(()), Ok(()), Err(RejectionReason::InsufficientFunds)]
+        );
+        assert_eq!(engine.account(ClientId(1)).unwrap().total, 10.0);
+        assert_eq!(engine.activity()[0].net_deposited, 10.0);
+        assert_eq!(engine.history(ClientId(1)).count(), 1);
+        assert!(engine.open_disputes().is_empty());
+
+        // Nothing of the batch was kept, so it can be sent again once it is fixed.
+        let result = engine.apply_batch(&batch[..2]);
+        assert_eq!(result.results, vec![Ok(()), Ok(())]);
+        assert!(!result.rolled_back);
+        assert_eq!(engine.account(ClientId(1)).unwrap().held, 5.0);
+        assert_eq!(engine.open_disputes().len(), 1);
+
+        let mut engine = Engine::with_config(
+            EngineConfig::builder()
+                .batch_policy(BatchPolicy::BestEffort)
+                .build(),
+        );
+        let result = engine.apply_batch(&batch);
+        assert!(!result.rolled_back);
+        assert_eq!(result.results.len(), 4);
+        assert_eq!(engine.account(ClientId(1)).unwrap().total, 6.0);
+    }
+
+    mod invariants {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn record_strategy() -> impl Strategy<Value = Record> {
+            // Quarters add up exactly in f32, so the invariants can be checked without
+            // rounding noise.
+            let amount = (0u32..4000).prop_map(|quarters| Some(Money::new(quarters as f32 / 4.0)));
+            let r#type = prop_oneof![
+                4 => Just(TxType::Deposit),
+                3 => Just(TxType::Withdrawal),
+                2 => Just(TxType::Dispute),
+                1 => Just(TxType::Resolve),
+                1 => Just(TxType::Chargeback),
+                1 => Just(TxType::OpenAccount),
+                1 => Just(TxType::Lock),
+                1 => Just(TxType::Unlock),
+                1 => Just(TxType::Hold),
+                1 => Just(TxType::Capture),
+                1 => Just(TxType::Release),
+            ];
+            (r#type, 1..4 as RawClientId, 1..30 as RawTxId, amount).prop_map(
+                |(r#type, client, tx, amount)| {
+                    let amount = amount.filter(|_| {
+                        matches!(r#type, TxType::Deposit | TxType::Withdrawal | TxType::Hold)
+                    });
+                    Record {
+                        r#type,
+                        client: ClientId(client),
+                        tx: TxId(tx),
+                        amount,
+                        timestamp: None,
+                        currency: None,
+                        to_currency: None,
+                        total: None,
+                        reason_code: None,
+                        memo: None,
+                        reference: None,
+                        merchant_id: None,
+                    }
+                },
+            )
+        }
+
+        fn config_strategy() -> impl Strategy<Value = EngineConfig> {
+            let chargeback_policy = prop_oneof![
+                Just(ChargebackPolicy::Reject),
+                Just(ChargebackPolicy::AllowNegative),
+                Just(ChargebackPolicy::Partial),
+            ];
+            let account_policy = prop_oneof![
+                Just(AccountPolicy::AutoCreate),
+                Just(AccountPolicy::Report),
+                Just(AccountPolicy::Strict),
+            ];
+            let duplicate_scope = prop_oneof![
+                Just(DuplicateScope::Global),
+                Just(DuplicateScope::PerClient)
+            ];
+            let locked_account_policy = prop_oneof![
+                Just(LockedAccountPolicy::Freeze),
+                Just(LockedAccountPolicy::AllowDisputes)
+            ];
+            let dispute_hold_policy = prop_oneof![
+                Just(DisputeHoldPolicy::AllowNegative),
+                Just(DisputeHoldPolicy::RequireFunds)
+            ];
+            (
+                chargeback_policy,
+                account_policy,
+                duplicate_scope,
+                locked_account_policy,
+                dispute_hold_policy,
+            )
+                .prop_map(
+                    |(
+                        chargeback_policy,
+                        account_policy,
+                        duplicate_scope,
+                        locked_account_policy,
+                        dispute_hold_policy,
+                    )| EngineConfig {
+                        chargeback_policy,
+                        account_policy,
+                        duplicate_scope,
+                        locked_account_policy,
+                        dispute_hold_policy,
+                        ..Default::default()
+                    },
+                )
+        }
+
+        proptest! {
+            #[test]
+            fn invariants_hold_after_every_record(
+                config in config_strategy(),
+                records in prop::collection::vec(record_strategy(), 0..200),
+            ) {
+                let mut engine = Engine::with_config(config);
+                for record in records {
+                    let before = engine.accounts().get(&record.client).cloned();
+                    let r#type = record.r#type;
+                    let _ = engine.apply(record.clone());
+
+                    let violations = engine.invariant_violations();
+                    prop_assert!(violations.is_empty(), "{record:?}: {violations:?}");
+                    if engine.config().dispute_hold_policy == DisputeHoldPolicy::RequireFunds {
+                        let account = engine.accounts().get(&record.client);
+                        prop_assert!(account.is_none_or(|account| account.available >= 0.0));
+                    }
+
+                    // Only unlocking, releasing a hold, and disputes where the policy allows
+                    // them, change a locked account.
+                    let dispute_lifecycle =
+                        matches!(r#type, TxType::Dispute | TxType::Resolve | TxType::Chargeback);
+                    let may_change = matches!(r#type, TxType::Unlock | TxType::Release)
+                        || (dispute_lifecycle
+                            && engine.config().locked_account_policy
+                                == LockedAccountPolicy::AllowDisputes);
+                    if let Some(before) = before.filter(|account| account.locked && !may_change) {
+                        prop_assert_eq!(&before, &engine.accounts()[&record.client]);
+                    }
+                }
+            }
+
+            #[test]
+            fn replaying_applied_records_rebuilds_state(
+                config in config_strategy(),
+                records in prop::collection::vec(record_strategy(), 0..200),
+            ) {
+                let mut engine = Engine::with_config(config.clone());
+                let applied: Vec<Record> = records
+                    .iter()
+                    .filter(|record| engine.apply((*record).clone()).is_ok())
+                    .cloned()
+                    .collect();
+
+                // This is what a journal replay does: rejected records leave no trace, so
+                // the applied ones alone rebuild the same state.
+                let mut replayed = Engine::with_config(config.clone());
+                for record in &applied {
+                    prop_assert_eq!(replayed.apply(record.clone()), Ok(()), "{:?}", record);
+                }
+                prop_assert_eq!(replayed.accounts(), engine.accounts());
+
+                // And the same input always gives the same result.
+                let mut again = Engine::with_config(config);
+                for record in records {
+                    let _ = again.apply(record);
+                }
+                prop_assert_eq!(again.accounts(), engine.accounts());
+            }
+        }
     }
 }
@@ -0,0 +1,205 @@
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    amounts::Money,
+    currency::Currency,
+    events::{serialize_amount, AccountEvent},
+    observer::EngineObserver,
+    records::{Record, TxType},
+    transaction::{Balance, ClientId, Engine, RejectionReason, TxId},
+};
+
+/// Whether the engine applied a record.
+#[derive(Debug, Serialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Applied,
+    Rejected,
+}
+
+/// What happened to a record, and the funds of the client in the currency of the record
+/// afterwards. The funds are left out for a client without an account.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct TxStatus {
+    pub r#type: TxType,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub currency: Option<Currency>,
+    pub outcome: Outcome,
+    pub reason: Option<RejectionReason>,
+    #[serde(serialize_with = "serialize_amount")]
+    pub available: Option<Money>,
+    #[serde(serialize_with = "serialize_amount")]
+    pub held: Option<Money>,
+    pub memo: Option<String>,
+    pub reference: Option<String>,
+    pub merchant_id: Option<String>,
+}
+
+/// Writes a [`TxStatus`] row for every record the engine applies or rejects, in the order
+/// they are processed. Rows that could not be parsed never reach the engine and are only
+/// in the rejects report.
+///
+/// Clones write to the same file, so one log can observe all shards of a
+/// [`SharedEngine`](crate::shared::SharedEngine).
+#[derive(Debug, Clone)]
+pub struct TxStatusLog {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    writer: csv::Writer<BufWriter<File>>,
+    /// Last known balances, reported with the records that are rejected.
+    balances: HashMap<(ClientId, Option<Currency>), Balance>,
+}
+
+impl TxStatusLog {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Inner {
+                writer: csv::Writer::from_writer(BufWriter::new(File::create(path)?)),
+                balances: HashMap::new(),
+            })),
+        })
+    }
+
+    /// Takes the balances of `engine` as the starting point, e.g. after restoring the state
+    /// of an earlier run, so that records rejected before a client's first applied record
+    /// still show its funds.
+    pub fn set_state(&self, engine: &Engine) {
+        let mut inner = self.lock();
+        for account in engine.accounts().values() {
+            inner
+                .balances
+                .insert((account.client, None), account.balance(None));
+            for (&currency, &balance) in &account.currencies {
+                inner
+                    .balances
+                    .insert((account.client, Some(currency)), balance);
+            }
+        }
+    }
+
+    pub fn finish(&self) -> Result<(), Box<dyn Error>> {
+        Ok(self.lock().writer.flush()?)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Inner {
+    fn write(&mut self, record: &Record, reason: Option<RejectionReason>) {
+        let balance = self.balances.get(&(record.client, record.currency));
+        let status = TxStatus {
+            r#type: record.r#type,
+            client: record.client,
+            tx: record.tx,
+            currency: record.currency,
+            outcome: match reason {
+                Some(_) => Outcome::Rejected,
+                None => Outcome::Applied,
+            },
+            reason,
+            available: balance.map(|balance| balance.available),
+            held: balance.map(|balance| balance.held),
+            memo: record.memo.clone(),
+            reference: record.reference.clone(),
+            merchant_id: record.merchant_id.clone(),
+        };
+        self.writer
+            .serialize(status)
+            .expect("writing the tx status failed");
+    }
+}
+
+/// A status cannot be left out without the output silently missing a record, so write
+/// errors abort the process instead, as for the journal.
+impl EngineObserver for TxStatusLog {
+    fn on_applied(&mut self, record: &Record, event: &AccountEvent) {
+        let AccountEvent::Updated {
+            client,
+            currency,
+            available,
+            held,
+            total,
+            ..
+        } = *event
+        else {
+            return;
+        };
+
+        let mut inner = self.lock();
+        let balance = Balance {
+            available,
+            held,
+            total,
+        };
+        inner.balances.insert((client, currency), balance);
+        // A conversion reports a second update in the target currency.
+        if currency == record.currency {
+            inner.write(record, None);
+        }
+    }
+
+    fn on_rejected(&mut self, record: &Record, reason: RejectionReason) {
+        self.lock().write(record, Some(reason));
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        records::{parse_record, InputFormat},
+        testing::Scenario,
+    };
+
+    #[test]
+    fn every_record_gets_a_status() {
+        let path = std::env::temp_dir().join("tx_accounts_tx_status.csv");
+        let status = TxStatusLog::create(&path).unwrap();
+
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(status.clone()));
+        Scenario::from_engine(engine)
+            .deposit(1, 1, 10)
+            .withdraw(1, 2, 50)
+            .dispute(1, 1)
+            .withdraw(2, 3, 1)
+            .apply(
+                parse_record(
+                    b"deposit,1,4,2.5,,,,,,\"refund, order 12\",ORD-12,m-7",
+                    InputFormat::Csv,
+                )
+                .unwrap(),
+            );
+        status.finish().unwrap();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            csv,
+            "type,client,tx,currency,outcome,reason,available,held,memo,reference,merchant_id\n\
+             deposit,1,1,,applied,,10.0000,0.0000,,,\n\
+             withdrawal,1,2,,rejected,insufficient_funds,10.0000,0.0000,,,\n\
+             dispute,1,1,,applied,,0.0000,10.0000,,,\n\
+             withdrawal,2,3,,rejected,unknown_account,,,,,\n\
+             deposit,1,4,,applied,,2.5000,10.0000,\"refund, order 12\",ORD-12,m-7\n"
+        );
+    }
+}
@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+
+use crate::{
+    amounts::Money,
+    currency::Currency,
+    hash::{FastMap, FastSet},
+    records::{Record, TxType},
+    transaction::{ClientId, TxId},
+};
+
+/// Read access to previously applied deposits and withdrawals, as needed to settle
+/// disputes.
+pub trait TxLookup {
+    fn find(&self, client: ClientId, tx: TxId) -> Option<Record>;
+
+    /// Whether any client has a transaction with this id.
+    fn contains_tx_id(&self, tx: TxId) -> bool;
+
+    fn is_empty(&self) -> bool;
+}
+
+impl TxLookup for HashMap<(ClientId, TxId), Record> {
+    fn find(&self, client: ClientId, tx: TxId) -> Option<Record> {
+        self.get(&(client, tx)).cloned()
+    }
+
+    fn contains_tx_id(&self, tx: TxId) -> bool {
+        self.keys().any(|&(_, stored)| stored == tx)
+    }
+
+    fn is_empty(&self) -> bool {
+        HashMap::is_empty(self)
+    }
+}
+
+/// A deposit or withdrawal as kept in memory. The client and tx id are part of the key
+/// and settling a dispute only needs the amount, the currency and the kind of transaction,
+/// so this takes 8 bytes instead of the 36 of a full [`Record`].
+#[derive(Debug, Clone, Copy)]
+pub struct PackedTx {
+    /// NaN when the record had no amount; parsed amounts are always finite.
+    amount: f32,
+    withdrawal: bool,
+    /// All zeros for the base currency.
+    currency: [u8; 3],
+}
+
+impl PackedTx {
+    pub fn new(record: &Record) -> Self {
+        Self {
+            amount: record.amount.map_or(f32::NAN, Money::to_f32),
+            withdrawal: record.r#type == TxType::Withdrawal,
+            currency: record.currency.map_or([0; 3], Currency::to_bytes),
+        }
+    }
+
+    pub fn amount(self) -> Option<Money> {
+        (!self.amount.is_nan()).then_some(Money::new(self.amount))
+    }
+
+    pub fn to_record(self, client: ClientId, tx: TxId) -> Record {
+        Record {
+            r#type: if self.withdrawal {
+                TxType::Withdrawal
+            } else {
+                TxType::Deposit
+            },
+            client,
+            tx,
+            amount: self.amount(),
+            timestamp: None,
+            currency: Currency::from_bytes(self.currency),
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        }
+    }
+}
+
+/// Memo, reference and merchant id of a stored transaction. Only the transactions that
+/// carry any have one, so that the others stay packed.
+#[derive(Debug, Clone)]
+struct Metadata {
+    memo: Option<String>,
+    reference: Option<String>,
+    merchant_id: Option<String>,
+}
+
+impl Metadata {
+    fn take(record: &mut Record) -> Option<Self> {
+        let metadata = Self {
+            memo: record.memo.take(),
+            reference: record.reference.take(),
+            merchant_id: record.merchant_id.take(),
+        };
+        (metadata.memo.is_some() || metadata.reference.is_some() || metadata.merchant_id.is_some())
+            .then_some(metadata)
+    }
+}
+
+/// History of applied deposits and withdrawals. Everything is kept in memory unless a
+/// disk store is attached, in which case resident transactions are spilled to disk
+/// whenever the memory budget is used up and looked up there afterwards. Metadata is
+/// never spilled.
+#[derive(Debug, Default)]
+pub struct TxStore {
+    resident: FastMap<(ClientId, TxId), PackedTx>,
+    tx_ids: FastSet<TxId>,
+    metadata: FastMap<(ClientId, TxId), Metadata>,
+    #[cfg(feature = "tx-store")]
+    disk: Option<disk::DiskStore>,
+}
+
+impl TxStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a store that spills to `path` once the resident transactions would take up
+    /// more than `max_memory` bytes. Any transactions left in `path` by an earlier run
+    /// are discarded.
+    #[cfg(feature = "tx-store")]
+    pub fn open<P: AsRef<std::path::Path>>(
+        path: P,
+        max_memory: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            disk: Some(disk::DiskStore::open(path, max_memory)?),
+            ..Self::default()
+        })
+    }
+
+    /// Makes room for `additional` more transactions, as far as they would stay resident.
+    pub fn reserve(&mut self, additional: usize) {
+        #[cfg(feature = "tx-store")]
+        let additional = match &self.disk {
+            Some(disk) => additional.min(disk.max_resident().saturating_sub(self.resident.len())),
+            None => additional,
+        };
+
+        self.resident.reserve(additional);
+        self.tx_ids.reserve(additional);
+    }
+
+    pub fn insert(&mut self, mut record: Record) {
+        match Metadata::take(&mut record) {
+            Some(metadata) => {
+                self.metadata.insert((record.client, record.tx), metadata);
+            }
+            None if !self.metadata.is_empty() => {
+                self.metadata.remove(&(record.client, record.tx));
+            }
+            None => {}
+        }
+        self.tx_ids.insert(record.tx);
+        self.resident
+            .insert((record.client, record.tx), PackedTx::new(&record));
+
+        #[cfg(feature = "tx-store")]
+        if let Some(disk) = &self.disk {
+            if self.resident.len() >= disk.max_resident() {
+                disk.spill(
+                    self.resident
+                        .drain()
+                        .map(|((client, tx), packed)| packed.to_record(client, tx)),
+                );
+                self.tx_ids.clear();
+            }
+        }
+    }
+
+    pub fn contains(&self, client: ClientId, tx: TxId) -> bool {
+        self.find(client, tx).is_some()
+    }
+
+    /// All stored transactions, resident ones first.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Record> + '_> {
+        let resident = self
+            .resident
+            .iter()
+            .map(|(&(client, tx), packed)| packed.to_record(client, tx));
+
+        #[cfg(feature = "tx-store")]
+        if let Some(disk) = &self.disk {
+            return Box::new(
+                resident
+                    .chain(disk.iter())
+                    .map(|record| self.with_metadata(record)),
+            );
+        }
+
+        Box::new(resident.map(|record| self.with_metadata(record)))
+    }
+
+    fn with_metadata(&self, mut record: Record) -> Record {
+        if let Some(metadata) = self.metadata.get(&(record.client, record.tx)) {
+            record.memo.clone_from(&metadata.memo);
+            record.reference.clone_from(&metadata.reference);
+            record.merchant_id.clone_from(&metadata.merchant_id);
+        }
+        record
+    }
+
+    /// Number of transactions currently held in memory.
+    pub fn resident_len(&self) -> usize {
+        self.resident.len()
+    }
+}
+
+impl TxLookup for TxStore {
+    fn find(&self, client: ClientId, tx: TxId) -> Option<Record> {
+        if let Some(packed) = self.resident.get(&(client, tx)) {
+            return Some(self.with_metadata(packed.to_record(client, tx)));
+        }
+
+        #[cfg(feature = "tx-store")]
+        if let Some(disk) = &self.disk {
+            return disk
+                .find(client, tx)
+                .map(|record| self.with_metadata(record));
+        }
+
+        None
+    }
+
+    fn contains_tx_id(&self, tx: TxId) -> bool {
+        if self.tx_ids.contains(&tx) {
+            return true;
+        }
+
+        #[cfg(feature = "tx-store")]
+        if let Some(disk) = &self.disk {
+            return disk.contains_tx_id(tx);
+        }
+
+        false
+    }
+
+    fn is_empty(&self) -> bool {
+        #[cfg(feature = "tx-store")]
+        if let Some(disk) = &self.disk {
+            return self.resident.is_empty() && disk.is_empty();
+        }
+
+        self.resident.is_empty()
+    }
+}
+
+#[cfg(feature = "tx-store")]
+mod disk {
+    use std::{error::Error, mem, path::Path};
+
+    use super::PackedTx;
+    use crate::{
+        amounts::Money,
+        currency::Currency,
+        records::{Record, TxType},
+        transaction::{ClientId, RawClientId, RawTxId, TxId},
+    };
+
+    // Rough size of one resident transaction including the index entry and hash map
+    // overhead, used to turn a memory budget into a number of transactions.
+    const RESIDENT_TX_BYTES: usize =
+        2 * (mem::size_of::<(ClientId, TxId)>() + mem::size_of::<PackedTx>());
+
+    /// Transactions spilled to disk. Disk errors cannot be reported as a rejection of the
+    /// transaction at hand, so they abort the process instead.
+    #[derive(Debug)]
+    pub(super) struct DiskStore {
+        records: sled::Tree,
+        tx_ids: sled::Tree,
+        max_resident: usize,
+    }
+
+    impl DiskStore {
+        pub(super) fn open<P: AsRef<Path>>(
+            path: P,
+            max_memory: usize,
+        ) -> Result<Self, Box<dyn Error>> {
+            let db = sled::open(path)?;
+            let records = db.open_tree("records")?;
+            let tx_ids = db.open_tree("tx_ids")?;
+            records.clear()?;
+            tx_ids.clear()?;
+
+            Ok(Self {
+                records,
+                tx_ids,
+                max_resident: (max_memory / RESIDENT_TX_BYTES).max(1),
+            })
+        }
+
+        pub(super) fn max_resident(&self) -> usize {
+            self.max_resident
+        }
+
+        pub(super) fn spill(&self, records: impl Iterator<Item = Record>) {
+            let mut batch = sled::Batch::default();
+            let mut ids = sled::Batch::default();
+            for record in records {
+                batch.insert(&key(record.client, record.tx), &encode(&record));
+                ids.insert(&record.tx.0.to_be_bytes(), &[]);
+            }
+
+            self.records
+                .apply_batch(batch)
+                .expect("writing to the transaction store failed");
+            self.tx_ids
+                .apply_batch(ids)
+                .expect("writing to the transaction store failed");
+        }
+
+        pub(super) fn find(&self, client: ClientId, tx: TxId) -> Option<Record> {
+            let value = self
+                .records
+                .get(key(client, tx))
+                .expect("reading the transaction store failed")?;
+            decode(client, tx, &value)
+        }
+
+        pub(super) fn contains_tx_id(&self, tx: TxId) -> bool {
+            self.tx_ids
+                .contains_key(tx.0.to_be_bytes())
+                .expect("reading the transaction store failed")
+        }
+
+        pub(super) fn is_empty(&self) -> bool {
+            self.records.is_empty()
+        }
+
+        pub(super) fn iter(&self) -> impl Iterator<Item = Record> + '_ {
+            self.records.iter().filter_map(|entry| {
+                let (key, value) = entry.expect("reading the transaction store failed");
+                let (client, tx) = key.split_at_checked(CLIENT_BYTES)?;
+                let client = ClientId(RawClientId::from_be_bytes(client.try_into().ok()?));
+                let tx = TxId(RawTxId::from_be_bytes(tx.try_into().ok()?));
+                decode(client, tx, &value)
+            })
+        }
+    }
+
+    const CLIENT_BYTES: usize = mem::size_of::<RawClientId>();
+
+    fn key(client: ClientId, tx: TxId) -> [u8; CLIENT_BYTES + mem::size_of::<RawTxId>()] {
+        let mut key = [0; CLIENT_BYTES + mem::size_of::<RawTxId>()];
+        key[..CLIENT_BYTES].copy_from_slice(&client.0.to_be_bytes());
+        key[CLIENT_BYTES..].copy_from_slice(&tx.0.to_be_bytes());
+        key
+    }
+
+    fn encode(record: &Record) -> [u8; 9] {
+        let mut value = [0; 9];
+        value[0] = match record.r#type {
+            TxType::Deposit => 0,
+            _ => 1,
+        };
+        if let Some(amount) = record.amount {
+            value[1] = 1;
+            value[2..6].copy_from_slice(&amount.to_f32().to_le_bytes());
+        }
+        if let Some(currency) = record.currency {
+            value[6..].copy_from_slice(&currency.to_bytes());
+        }
+        value
+    }
+
+    fn decode(client: ClientId, tx: TxId, value: &[u8]) -> Option<Record> {
+        let r#type = match value.first()? {
+            0 => TxType::Deposit,
+            _ => TxType::Withdrawal,
+        };
+        let amount = match value.get(1)? {
+            1 => Some(Money::new(f32::from_le_bytes(
+                value.get(2..6)?.try_into().ok()?,
+            ))),
+            _ => None,
+        };
+        let currency = value
+            .get(6..9)
+            .and_then(|bytes| Currency::from_bytes(bytes.try_into().ok()?));
+
+        Some(Record {
+            r#type,
+            client,
+            tx,
+            amount,
+            timestamp: None,
+            currency,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::TxType;
+
+    fn deposit(client: ClientId, tx: TxId) -> Record {
+        Record {
+            r#type: TxType::Deposit,
+            client,
+            tx,
+            amount: Some(Money::new(1.5)),
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        }
+    }
+
+    #[test]
+    fn memory_store() {
+        let mut store = TxStore::new();
+        assert!(TxLookup::is_empty(&store));
+
+        store.insert(deposit(ClientId(1), TxId(7)));
+        assert_eq!(
+            store.find(ClientId(1), TxId(7)),
+            Some(deposit(ClientId(1), TxId(7)))
+        );
+        assert!(!store.contains(ClientId(2), TxId(7)));
+        assert!(store.contains_tx_id(TxId(7)));
+        assert!(!store.contains_tx_id(TxId(8)));
+
+        let with_memo = Record {
+            memo: Some("order 1234".to_owned()),
+            merchant_id: Some("m-77".to_owned()),
+            ..deposit(ClientId(1), TxId(9))
+        };
+        store.insert(with_memo.clone());
+        assert_eq!(store.find(ClientId(1), TxId(9)), Some(with_memo));
+    }
+
+    #[test]
+    fn packed_tx() {
+        assert_eq!(std::mem::size_of::<PackedTx>(), 8);
+
+        let withdrawal = Record {
+            r#type: TxType::Withdrawal,
+            amount: None,
+            ..deposit(ClientId(2), TxId(9))
+        };
+        let in_euro = Record {
+            currency: "EUR".parse().ok(),
+            ..deposit(ClientId(3), TxId(11))
+        };
+        for record in [deposit(ClientId(1), TxId(7)), withdrawal, in_euro] {
+            assert_eq!(
+                PackedTx::new(&record).to_record(record.client, record.tx),
+                record
+            );
+        }
+    }
+
+    #[cfg(feature = "tx-store")]
+    #[test]
+    fn spills_to_disk() {
+        let path = std::env::temp_dir().join("tx_accounts_tx_store");
+        let mut store = TxStore::open(&path, 1).unwrap();
+
+        store.insert(deposit(ClientId(1), TxId(7)));
+        store.insert(Record {
+            r#type: TxType::Withdrawal,
+            client: ClientId(2),
+            tx: TxId(8),
+            amount: None,
+            timestamp: None,
+            currency: "EUR".parse().ok(),
+            to_currency: None,
+            total: None,
+            reason_code: None,
+            memo: None,
+            reference: None,
+            merchant_id: None,
+        });
+        assert_eq!(store.resident_len(), 0);
+
+        assert_eq!(
+            store.find(ClientId(1), TxId(7)),
+            Some(deposit(ClientId(1), TxId(7)))
+        );
+        assert_eq!(
+            store.find(ClientId(2), TxId(8)).map(|record| (
+                record.r#type,
+                record.amount,
+                record.currency
+            )),
+            Some((TxType::Withdrawal, None, "EUR".parse().ok()))
+        );
+        assert!(!store.contains(ClientId(2), TxId(7)));
+        assert!(store.contains_tx_id(TxId(8)));
+        assert!(!TxLookup::is_empty(&store));
+        assert_eq!(store.iter().count(), 2);
+
+        drop(store);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[cfg(feature = "tx-store")]
+    #[test]
+    fn engine_disputes_spilled_transactions() {
+        use crate::{testing::Scenario, transaction::Engine};
+
+        let path = std::env::temp_dir().join("tx_accounts_tx_store_engine");
+        let mut engine = Engine::new();
+        engine.set_tx_store(TxStore::open(&path, 1).unwrap());
+
+        Scenario::from_engine(engine)
+            .deposit(1, 1, 10)
+            .deposit(2, 2, 10)
+            .deposit(3, 1, 10)
+            .expect_no_account(ClientId(3))
+            .dispute(1, 1)
+            .expect_held(ClientId(1), 10)
+            .chargeback(1, 1)
+            .expect_total(ClientId(1), 0)
+            .expect_locked(ClientId(1), true);
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
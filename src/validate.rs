@@ -0,0 +1,259 @@
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    error::Error,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+use crate::{
+    records::{open_input, read_rows_with, CsvDialect, InputFormat, TxType},
+    transaction::{ClientId, TxId},
+};
+
+/// Amounts are kept with this many decimal places; anything finer is rounded away.
+const MAX_DECIMAL_PLACES: usize = 4;
+
+#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    Malformed,
+    DuplicateTx,
+    /// A dispute, resolve or chargeback refers to a transaction of the client that does
+    /// not appear earlier in the file.
+    UnknownTx,
+    NegativeAmount,
+    /// The amount has more decimal places than are kept, so it would be rounded.
+    ExcessPrecision,
+}
+
+/// A problem found in an input file, by line number.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct Issue {
+    pub line: u64,
+    pub issue: IssueKind,
+    pub detail: String,
+}
+
+/// Lints an input file without applying it: reports malformed rows, duplicate tx ids,
+/// disputes on transactions that do not precede them, negative amounts and amounts with
+/// more than 4 decimal places. Issues are sorted by line.
+pub fn validate_file<P: AsRef<Path>>(
+    path: P,
+    format: InputFormat,
+    dialect: &CsvDialect,
+) -> Result<Vec<Issue>, Box<dyn Error>> {
+    let mut issues = Vec::new();
+    let mut tx_ids: HashSet<TxId> = HashSet::new();
+    let mut transactions: HashSet<(ClientId, TxId)> = HashSet::new();
+
+    for row in read_rows_with(&path, format, dialect)? {
+        let record = match row.record {
+            Ok(record) => record,
+            Err(detail) => {
+                issues.push(Issue {
+                    line: row.line,
+                    issue: IssueKind::Malformed,
+                    detail,
+                });
+                continue;
+            }
+        };
+
+        match record.r#type {
+            TxType::Deposit | TxType::Withdrawal | TxType::Hold => {
+                if !tx_ids.insert(record.tx) {
+                    issues.push(Issue {
+                        line: row.line,
+                        issue: IssueKind::DuplicateTx,
+                        detail: format!("tx {} was already used", record.tx),
+                    });
+                }
+                transactions.insert((record.client, record.tx));
+
+                if record.amount.is_some_and(|amount| amount < 0.0) {
+                    issues.push(Issue {
+                        line: row.line,
+                        issue: IssueKind::NegativeAmount,
+                        detail: String::new(),
+                    });
+                }
+            }
+            TxType::Dispute
+            | TxType::Resolve
+            | TxType::Chargeback
+            | TxType::Representment
+            | TxType::Capture
+            | TxType::Release => {
+                if !transactions.contains(&(record.client, record.tx)) {
+                    issues.push(Issue {
+                        line: row.line,
+                        issue: IssueKind::UnknownTx,
+                        detail: format!(
+                            "client {} has no earlier transaction {}",
+                            record.client, record.tx
+                        ),
+                    });
+                }
+            }
+            TxType::OpenAccount
+            | TxType::Lock
+            | TxType::Unlock
+            | TxType::Convert
+            | TxType::Fee
+            | TxType::Assert
+            | TxType::Custom(_) => {}
+        }
+    }
+
+    // Parsing already rounds amounts to 4 decimal places, so the raw text is checked
+    // separately.
+    for (line, amount) in raw_amounts(path, format, dialect)? {
+        if decimal_places(&amount) > MAX_DECIMAL_PLACES {
+            issues.push(Issue {
+                line,
+                issue: IssueKind::ExcessPrecision,
+                detail: amount,
+            });
+        }
+    }
+
+    issues.sort_by_key(|issue| (issue.line, issue.issue));
+    Ok(issues)
+}
+
+pub fn write_issues<W: Write>(writer: W, issues: &[Issue]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::WriterBuilder::new().from_writer(writer);
+    for issue in issues {
+        wtr.serialize(issue)?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+// The unparsed amount of every row that has one, by line number.
+fn raw_amounts<P: AsRef<Path>>(
+    path: P,
+    format: InputFormat,
+    dialect: &CsvDialect,
+) -> Result<Vec<(u64, String)>, Box<dyn Error>> {
+    let file = open_input(path)?;
+    let mut amounts = Vec::new();
+
+    match format {
+        InputFormat::Csv => {
+            let (mut rdr, headers) = dialect.reader(file)?;
+            let column = match headers {
+                Some(headers) => headers.iter().position(|h| h == "amount"),
+                None => Some(3),
+            };
+            let Some(column) = column else {
+                return Ok(amounts);
+            };
+            // Malformed rows are already reported while parsing.
+            for string_record in rdr.records().flatten() {
+                if let Some(amount) = string_record.get(column) {
+                    let line = string_record.position().map_or(0, |p| p.line());
+                    amounts.push((line, amount.trim().to_owned()));
+                }
+            }
+        }
+        InputFormat::JsonLines => {
+            for (index, line) in BufReader::new(file).lines().enumerate() {
+                let Ok(value) = serde_json::from_str::<serde_json::Value>(&line?) else {
+                    continue;
+                };
+                let amount = match value.get("amount") {
+                    Some(serde_json::Value::String(s)) => s.trim().to_owned(),
+                    Some(serde_json::Value::Number(n)) => n.to_string(),
+                    _ => continue,
+                };
+                amounts.push((index as u64 + 1, amount));
+            }
+        }
+    }
+
+    Ok(amounts)
+}
+
+fn decimal_places(amount: &str) -> usize {
+    amount
+        .split_once('.')
+        .map_or(0, |(_, fraction)| fraction.trim_end_matches('0').len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate_str(name: &str, format: InputFormat, contents: &str) -> Vec<(u64, IssueKind)> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, contents).unwrap();
+        let issues = validate_file(&path, format, &CsvDialect::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        issues
+            .into_iter()
+            .map(|issue| (issue.line, issue.issue))
+            .collect()
+    }
+
+    #[test]
+    fn validate_csv() {
+        let issues = validate_str(
+            "tx_accounts_validate.csv",
+            InputFormat::Csv,
+            "type,client,tx,amount\n\
+             deposit,1,1,1.0\n\
+             deposit,2,1,1.0\n\
+             withdrawal,1,2,-3\n\
+             dispute,1,3,\n\
+             dispute,2,2,\n\
+             deposit,1,4,1.12345\n\
+             deposit,1,5,1.12340\n\
+             re-fund,1,6,1.0\n\
+             resolve,1,2,\n",
+        );
+
+        assert_eq!(
+            issues,
+            vec![
+                (3, IssueKind::DuplicateTx),
+                (4, IssueKind::NegativeAmount),
+                (5, IssueKind::UnknownTx),
+                (6, IssueKind::UnknownTx),
+                (7, IssueKind::ExcessPrecision),
+                (9, IssueKind::Malformed),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_json_lines() {
+        let issues = validate_str(
+            "tx_accounts_validate.jsonl",
+            InputFormat::JsonLines,
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":0.00001}\n\
+             {\"type\":\"chargeback\",\"client\":1,\"tx\":1}\n\
+             {\"type\":\"deposit\",\"client\":1}\n",
+        );
+
+        assert_eq!(
+            issues,
+            vec![(1, IssueKind::ExcessPrecision), (3, IssueKind::Malformed)]
+        );
+    }
+
+    #[test]
+    fn valid_file_has_no_issues() {
+        let issues = validate_file(
+            "test-inputs/test_input.csv",
+            InputFormat::Csv,
+            &CsvDialect::default(),
+        )
+        .unwrap();
+        assert!(issues.is_empty());
+    }
+}
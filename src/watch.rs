@@ -0,0 +1,246 @@
+use chrono::NaiveDate;
+use notify::{event::ModifyKind, EventKind, RecursiveMode, Watcher};
+use std::{
+    error::Error,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use crate::{
+    records::{read_rows_with, CsvDialect, InputFormat},
+    rejects::{process_rows, write_rejects},
+    state::{HistoryDates, StateDir},
+    transaction::Engine,
+};
+
+/// Cumulative state of a watched directory: the engine, persisted to a state directory
+/// after every ingested file.
+#[derive(Debug)]
+pub struct Inbox {
+    dir: PathBuf,
+    engine: Engine,
+    state: StateDir,
+    dates: HistoryDates,
+    dialect: CsvDialect,
+}
+
+impl Inbox {
+    /// Restores the accounts and dispute history of `state` into `engine`.
+    pub fn new(
+        dir: PathBuf,
+        mut engine: Engine,
+        state: StateDir,
+        lookback: Option<u32>,
+        dialect: CsvDialect,
+    ) -> Result<Self, Box<dyn Error>> {
+        let dates = state.restore(&mut engine, today(), lookback)?;
+        Ok(Self {
+            dir,
+            engine,
+            state,
+            dates,
+            dialect,
+        })
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Inputs waiting in the directory, in alphabetical order. Hidden files and files
+    /// without a known extension are left alone, so producers can write a file under a
+    /// temporary name and rename it once it is complete.
+    pub fn pending(&self) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let mut pending = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let hidden = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_none_or(|name| name.starts_with('.'));
+            if path.is_file() && !hidden && InputFormat::from_path(&path).is_some() {
+                pending.push(path);
+            }
+        }
+        pending.sort();
+
+        Ok(pending)
+    }
+
+    /// Applies every pending input and moves it to `processed/`, with its rejects next to
+    /// it. An input that cannot be read is moved to `failed/` instead. The state is saved
+    /// with the last line applied from the input before it is moved, so after a crash in
+    /// between the rows of the input are skipped rather than applied again.
+    pub fn ingest_pending(&mut self) -> Result<(), Box<dyn Error>> {
+        for path in self.pending()? {
+            let Some(format) = InputFormat::from_path(&path) else {
+                continue;
+            };
+
+            let mut rows = match read_rows_with(&path, format, &self.dialect) {
+                Ok(rows) => rows,
+                Err(e) => {
+                    eprintln!("Error: {}: {e}", path.display());
+                    self.move_to("failed", &path)?;
+                    continue;
+                }
+            };
+
+            let source = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let count = rows.len();
+            if let Some(applied) = self.engine.source_offset(&source) {
+                rows.retain(|row| row.line > applied);
+            }
+            if rows.len() < count {
+                eprintln!(
+                    "Notice: skipping {} row(s) of {} applied before a restart",
+                    count - rows.len(),
+                    path.display()
+                );
+            }
+            if let Some(last) = rows.last() {
+                self.engine.set_source_offset(&source, Some(last.line));
+            }
+
+            let rejects = process_rows(&mut self.engine, rows);
+            let run_date = today();
+            self.state
+                .save_accounts(self.engine.accounts())
+                .and_then(|_| self.state.save_history(&self.engine, &self.dates, run_date))?;
+            for (record, _) in self.engine.dispute_history() {
+                self.dates
+                    .entry((record.client, record.tx))
+                    .or_insert(run_date);
+            }
+
+            let processed = self.move_to("processed", &path)?;
+            // A later input of the same name is a new one.
+            self.engine.set_source_offset(&source, None);
+            self.state.save_offsets(&self.engine)?;
+            if !rejects.is_empty() {
+                let mut name = processed.clone().into_os_string();
+                name.push(".rejects.csv");
+                write_rejects(File::create(name)?, &rejects)?;
+            }
+            eprintln!(
+                "Processed {}: {count} row(s), {} rejected",
+                path.display(),
+                rejects.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    fn move_to(&self, subdir: &str, path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let dir = self.dir.join(subdir);
+        fs::create_dir_all(&dir)?;
+        let target = dir.join(path.file_name().ok_or("input without a file name")?);
+        fs::rename(path, &target)?;
+
+        Ok(target)
+    }
+}
+
+/// Ingests the inputs already waiting in the directory, then every input that appears in
+/// it, until the process is stopped.
+pub fn watch(inbox: &mut Inbox) -> Result<(), Box<dyn Error>> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(&inbox.dir, RecursiveMode::NonRecursive)?;
+
+    inbox.ingest_pending()?;
+    for event in rx {
+        if matches!(
+            event?.kind,
+            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_))
+        ) {
+            inbox.ingest_pending()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn today() -> NaiveDate {
+    chrono::Local::now().date_naive()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{amounts::Money, transaction::ClientId};
+
+    #[test]
+    fn ingest_pending_keeps_cumulative_state() {
+        let root = std::env::temp_dir().join("tx_accounts_watch");
+        let _ = fs::remove_dir_all(&root);
+        let dir = root.join("incoming");
+        fs::create_dir_all(&dir).unwrap();
+        let state = StateDir::new(root.join("state"));
+
+        fs::write(
+            dir.join("1.csv"),
+            "type,client,tx,amount\ndeposit,1,1,10.0\n",
+        )
+        .unwrap();
+        fs::write(dir.join(".2.csv"), "type,client,tx,amount\n").unwrap();
+        let new_inbox = || {
+            Inbox::new(
+                dir.clone(),
+                Engine::new(),
+                state.clone(),
+                None,
+                CsvDialect::default(),
+            )
+            .unwrap()
+        };
+
+        let mut inbox = new_inbox();
+        inbox.ingest_pending().unwrap();
+        assert!(dir.join("processed/1.csv").exists());
+        assert!(dir.join(".2.csv").exists());
+
+        // A restarted daemon continues from the saved state.
+        fs::write(
+            dir.join("2.csv"),
+            "type,client,tx,amount\nwithdrawal,1,2,4.0\ndeposit,1,1,10.0\ndispute,1,1,\n",
+        )
+        .unwrap();
+        let mut inbox = new_inbox();
+        inbox.ingest_pending().unwrap();
+
+        let account = inbox.engine().accounts()[&ClientId(1)].clone();
+        assert_eq!(
+            (account.available, account.held),
+            (Money::new(-4.0), Money::new(10.0))
+        );
+        assert_eq!(state.account(ClientId(1)).unwrap(), Some(account));
+        let rejects = fs::read_to_string(dir.join("processed/2.csv.rejects.csv")).unwrap();
+        assert_eq!(rejects.lines().count(), 2, "{rejects}");
+        assert!(inbox.pending().unwrap().is_empty());
+
+        // After a crash between saving the state and moving an input, its rows are skipped.
+        fs::write(
+            dir.join("3.csv"),
+            "type,client,tx,amount\ndeposit,1,3,1.0\n",
+        )
+        .unwrap();
+        fs::write(root.join("state/offsets.csv"), "source,offset\n3.csv,2\n").unwrap();
+        let mut inbox = new_inbox();
+        inbox.ingest_pending().unwrap();
+        assert_eq!(inbox.engine().accounts()[&ClientId(1)].available, -4.0);
+        assert!(dir.join("processed/3.csv").exists());
+        assert_eq!(inbox.engine().source_offset("3.csv"), None);
+        assert_eq!(
+            fs::read_to_string(root.join("state/offsets.csv")).unwrap(),
+            ""
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
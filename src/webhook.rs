@@ -0,0 +1,348 @@
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    error::Error,
+    fmt::Write,
+    path::Path,
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    amounts::Money,
+    events::{AccountEvent, Event, TxOutcome},
+    observer::EngineObserver,
+    records::{Record, TxType},
+    transaction::RejectionReason,
+};
+
+/// Webhooks read from a TOML file with one `[[webhooks]]` table per endpoint:
+///
+/// ```toml
+/// [[webhooks]]
+/// url = "https://fraud.example.com/hooks"
+/// events = ["chargeback", "account_locked", "rejection"]
+/// min_amount = 1000.0
+/// secret = "shared-secret"
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct WebhookConfig {
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Webhook {
+    pub url: String,
+    /// Events posted to the endpoint.
+    pub events: Vec<WebhookEvent>,
+    /// Smallest amount of a rejected record that fires a `rejection` event.
+    #[serde(default)]
+    pub min_amount: Money,
+    /// Key of the HMAC-SHA256 signature sent in the `X-Signature` header.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Attempts per event before it is dropped.
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+    /// Delay before the first retry, doubled for every further one.
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A chargeback was applied, posted as a `tx_applied` event.
+    Chargeback,
+    /// An account was locked, posted as an `account_locked` event.
+    AccountLocked,
+    /// A record of at least `min_amount` was rejected, posted as a `tx_rejected` event.
+    Rejection,
+}
+
+fn default_attempts() -> u32 {
+    5
+}
+
+fn default_backoff_ms() -> u64 {
+    500
+}
+
+impl WebhookConfig {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let config: Self = toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+
+        for webhook in &config.webhooks {
+            if webhook.attempts == 0 {
+                return Err(format!(
+                    "{}: {} needs at least one attempt",
+                    path.display(),
+                    webhook.url
+                )
+                .into());
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// A payload for one webhook, or a request to report once everything sent before it was
+/// delivered.
+enum Message {
+    Post { webhook: usize, body: String },
+    Flush(mpsc::Sender<()>),
+}
+
+/// Posts the events the webhooks of a [`WebhookConfig`] ask for. Deliveries happen in
+/// the order of the events on a background thread, so a slow endpoint never holds up
+/// the engine. Failed deliveries are retried with exponential backoff, and dropped with
+/// a warning once the attempts of the webhook are used up.
+///
+/// Clones post through the same thread, so one notifier can observe all shards of a
+/// [`SharedEngine`](crate::shared::SharedEngine).
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    webhooks: Arc<[Webhook]>,
+    sender: mpsc::Sender<Message>,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: WebhookConfig) -> Self {
+        let webhooks: Arc<[Webhook]> = config.webhooks.into();
+        let (sender, receiver) = mpsc::channel();
+        let worker = Arc::clone(&webhooks);
+        thread::spawn(move || deliver(&worker, receiver));
+
+        Self { webhooks, sender }
+    }
+
+    fn post<T: Serialize>(&self, event: WebhookEvent, payload: T, amount: Option<Money>) {
+        let mut body = None;
+        for (index, webhook) in self.webhooks.iter().enumerate() {
+            if !webhook.events.contains(&event) {
+                continue;
+            }
+            if event == WebhookEvent::Rejection && amount.unwrap_or_default() < webhook.min_amount {
+                continue;
+            }
+
+            let body = match &body {
+                Some(body) => body,
+                None => body.insert(Event::new(&payload).to_json()),
+            };
+            let Ok(body) = body else {
+                return;
+            };
+            self.send(Message::Post {
+                webhook: index,
+                body: body.clone(),
+            });
+        }
+    }
+
+    fn send(&self, message: Message) {
+        // The worker only stops once every sender is gone.
+        let _ = self.sender.send(message);
+    }
+}
+
+impl EngineObserver for WebhookNotifier {
+    fn on_applied(&mut self, record: &Record, event: &AccountEvent) {
+        match event {
+            AccountEvent::Updated { .. } if record.r#type == TxType::Chargeback => {
+                self.post(
+                    WebhookEvent::Chargeback,
+                    TxOutcome::new(record, Ok(())),
+                    None,
+                );
+            }
+            AccountEvent::Locked { .. } => {
+                self.post(WebhookEvent::AccountLocked, event, None);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_rejected(&mut self, record: &Record, reason: RejectionReason) {
+        self.post(
+            WebhookEvent::Rejection,
+            TxOutcome::new(record, Err(reason)),
+            record.amount,
+        );
+    }
+
+    /// Waits until every event posted so far was delivered or dropped.
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        let (done, wait) = mpsc::channel();
+        self.send(Message::Flush(done));
+        wait.recv()
+            .map_err(|_| "the webhook delivery thread stopped".into())
+    }
+}
+
+fn deliver(webhooks: &[Webhook], receiver: mpsc::Receiver<Message>) {
+    let agent = ureq::Agent::new_with_config(
+        ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(10)))
+            .build(),
+    );
+
+    for message in receiver {
+        let (webhook, body) = match message {
+            Message::Post { webhook, body } => (&webhooks[webhook], body),
+            Message::Flush(done) => {
+                let _ = done.send(());
+                continue;
+            }
+        };
+
+        let mut backoff = Duration::from_millis(webhook.backoff_ms);
+        for attempt in 1..=webhook.attempts {
+            let mut request = agent
+                .post(&webhook.url)
+                .header("Content-Type", "application/json");
+            if let Some(secret) = &webhook.secret {
+                request = request.header("X-Signature", signature(secret, &body));
+            }
+
+            let error = match request.send(&body) {
+                Ok(_) => break,
+                Err(error) => error,
+            };
+            // Other client errors would fail again the same way.
+            let retry = match error {
+                ureq::Error::StatusCode(status) => status == 429 || status >= 500,
+                _ => true,
+            };
+            if !retry || attempt == webhook.attempts {
+                eprintln!(
+                    "Warning: dropped webhook event for {} after {attempt} attempt(s): {error}",
+                    webhook.url
+                );
+                break;
+            }
+
+            thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+}
+
+/// `sha256=` followed by the hex encoded HMAC-SHA256 of `body` keyed with `secret`.
+pub fn signature(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key size works");
+    mac.update(body.as_bytes());
+
+    let mut signature = String::from("sha256=");
+    for byte in mac.finalize().into_bytes() {
+        let _ = write!(signature, "{byte:02x}");
+    }
+    signature
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::Scenario, transaction::Engine};
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    /// Answers the first request with a server error and every further one with success,
+    /// and returns the signature header and body of each request.
+    fn flaky_endpoint(listener: TcpListener) -> thread::JoinHandle<Vec<(String, String)>> {
+        thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (index, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let (mut length, mut signature) = (0, String::new());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(':').unwrap_or((line, ""));
+                    match name.to_lowercase().as_str() {
+                        "content-length" => length = value.trim().parse().unwrap(),
+                        "x-signature" => signature = value.trim().to_owned(),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let status = if index == 0 {
+                    "500 Internal Server Error"
+                } else {
+                    "200 OK"
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                )
+                .unwrap();
+                requests.push((signature, String::from_utf8(body).unwrap()));
+                if requests.len() == 3 {
+                    return requests;
+                }
+            }
+            requests
+        })
+    }
+
+    #[test]
+    fn webhooks_post_signed_events_and_retry() {
+        let config: WebhookConfig = toml::from_str(
+            r#"
+            [[webhooks]]
+            url = "http://placeholder"
+            events = ["chargeback", "rejection"]
+            min_amount = 100.0
+            secret = "s3cret"
+            backoff_ms = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.webhooks[0].attempts, 5);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks", listener.local_addr().unwrap());
+        let endpoint = flaky_endpoint(listener);
+
+        let mut webhooks = config.webhooks;
+        webhooks[0].url = url;
+        let notifier = WebhookNotifier::new(WebhookConfig { webhooks });
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(notifier));
+
+        let mut engine = Scenario::from_engine(engine)
+            .deposit(1, 1, 10)
+            .withdraw(1, 2, 50)
+            .withdraw(1, 3, 500)
+            .dispute(1, 1)
+            .chargeback(1, 1)
+            .into_engine();
+        engine.flush_observers().unwrap();
+
+        let requests = endpoint.join().unwrap();
+        let rejection = r#"{"version":1,"event":"tx_rejected","type":"withdrawal","client":1,"tx":3,"amount":"500.0000","reason":"insufficient_funds"}"#;
+        let chargeback = r#"{"version":1,"event":"tx_applied","type":"chargeback","client":1,"tx":1,"amount":null}"#;
+        // The rejection was retried after the server error, and the small rejection and
+        // the lock were never posted.
+        let bodies: Vec<&str> = requests.iter().map(|(_, body)| body.as_str()).collect();
+        assert_eq!(bodies, vec![rejection, rejection, chargeback]);
+        for (header, body) in &requests {
+            assert_eq!(*header, signature("s3cret", body));
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use chrono::NaiveDate;
+use rust_xlsxwriter::{ColNum, Format, RowNum, Workbook, Worksheet};
+use std::{error::Error, io::Write};
+
+use crate::{
+    account_meta::AccountDirectory,
+    amounts::{amount_to_f64, Money, Precision, DECIMALS},
+    disputes::OpenDisputeRow,
+    rejects::Reject,
+    transaction::{Account, OpenDispute},
+};
+
+/// Writes `accounts`, sorted by client, as an XLSX workbook with an `Accounts` sheet. It
+/// has a row per client and currency, like multi-currency CSV output, with the amounts
+/// as numbers shown at `precision`, and `name` and `kyc_tier` columns with a `directory`.
+pub fn write_accounts<W: Write>(
+    writer: W,
+    accounts: &[&Account],
+    precision: Precision,
+    directory: Option<&AccountDirectory>,
+) -> Result<(), Box<dyn Error>> {
+    let mut workbook = Workbook::new();
+    accounts_sheet(workbook.add_worksheet(), accounts, precision, directory)?;
+    save(workbook, writer)
+}
+
+/// Like [`write_accounts`], with the rows the run rejected on a `Rejects` sheet and the
+/// disputes still open on an `Open disputes` sheet, aged up to `run_date`.
+pub fn write_report<W: Write>(
+    writer: W,
+    accounts: &[&Account],
+    precision: Precision,
+    directory: Option<&AccountDirectory>,
+    rejects: &[Reject],
+    open_disputes: &[OpenDispute],
+    run_date: NaiveDate,
+) -> Result<(), Box<dyn Error>> {
+    let mut workbook = Workbook::new();
+    accounts_sheet(workbook.add_worksheet(), accounts, precision, directory)?;
+    rejects_sheet(workbook.add_worksheet(), rejects)?;
+    open_disputes_sheet(workbook.add_worksheet(), open_disputes, run_date)?;
+    save(workbook, writer)
+}
+
+fn accounts_sheet(
+    sheet: &mut Worksheet,
+    accounts: &[&Account],
+    precision: Precision,
+    directory: Option<&AccountDirectory>,
+) -> Result<(), Box<dyn Error>> {
+    sheet.set_name("Accounts")?;
+    let mut columns = vec!["client", "currency", "available", "held", "total", "locked"];
+    if directory.is_some() {
+        columns.extend(["name", "kyc_tier"]);
+    }
+    header(sheet, &columns)?;
+
+    let formats: Vec<Format> = (0..=DECIMALS).map(amount_format).collect();
+    let rows = accounts.iter().flat_map(|&account| {
+        let currencies = account.currencies.keys().copied().map(Some);
+        std::iter::once(None)
+            .chain(currencies)
+            .map(move |currency| (account, currency))
+    });
+    for (row, (account, currency)) in (1..).zip(rows) {
+        let balance = account.balance(currency);
+        let decimals = precision.decimals(currency).min(DECIMALS);
+        let format = &formats[decimals as usize];
+
+        sheet.write_number(row, 0, account.client.0)?;
+        if let Some(currency) = currency {
+            sheet.write_string(row, 1, currency.as_str())?;
+        }
+        for (col, amount) in [
+            (2, balance.available),
+            (3, balance.held),
+            (4, balance.total),
+        ] {
+            sheet.write_number_with_format(row, col, amount_to_f64(amount, decimals), format)?;
+        }
+        sheet.write_boolean(row, 5, account.locked)?;
+        let meta = directory.and_then(|directory| directory.get(account.client));
+        if let Some(name) = meta.and_then(|meta| meta.name.as_deref()) {
+            sheet.write_string(row, 6, name)?;
+        }
+        if let Some(tier) = meta.and_then(|meta| meta.kyc_tier.as_deref()) {
+            sheet.write_string(row, 7, tier)?;
+        }
+    }
+
+    sheet.autofit();
+    Ok(())
+}
+
+fn rejects_sheet(sheet: &mut Worksheet, rejects: &[Reject]) -> Result<(), Box<dyn Error>> {
+    sheet.set_name("Rejects")?;
+    let columns = ["line", "reason", "type", "client", "tx", "amount", "detail"];
+    header(sheet, &columns)?;
+
+    let format = amount_format(DECIMALS);
+    for (row, reject) in (1..).zip(rejects) {
+        sheet.write_number(row, 0, reject.line as f64)?;
+        sheet.write_string(row, 1, reject.reason.to_string())?;
+        if let Some(r#type) = &reject.r#type {
+            sheet.write_string(row, 2, r#type.as_str())?;
+        }
+        if let Some(client) = reject.client {
+            sheet.write_number(row, 3, client.0)?;
+        }
+        if let Some(tx) = reject.tx {
+            sheet.write_number(row, 4, tx.to_u64() as f64)?;
+        }
+        if let Some(amount) = reject.amount {
+            write_amount(sheet, row, 5, amount, &format)?;
+        }
+        sheet.write_string(row, 6, &reject.detail)?;
+    }
+
+    sheet.autofit();
+    Ok(())
+}
+
+fn open_disputes_sheet(
+    sheet: &mut Worksheet,
+    open_disputes: &[OpenDispute],
+    run_date: NaiveDate,
+) -> Result<(), Box<dyn Error>> {
+    sheet.set_name("Open disputes")?;
+    let columns = ["client", "tx", "currency", "amount", "opened", "age_days"];
+    header(sheet, &columns)?;
+
+    let format = amount_format(DECIMALS);
+    let opened_format = Format::new().set_num_format("yyyy-mm-dd hh:mm:ss");
+    for (row, dispute) in (1..).zip(open_disputes) {
+        let dispute = OpenDisputeRow::new(dispute, run_date);
+        sheet.write_number(row, 0, dispute.client.0)?;
+        sheet.write_number(row, 1, dispute.tx.to_u64() as f64)?;
+        if let Some(currency) = dispute.currency {
+            sheet.write_string(row, 2, currency.as_str())?;
+        }
+        write_amount(sheet, row, 3, dispute.amount, &format)?;
+        // In UTC, as Excel has no time zones.
+        if let Some(opened) = dispute.opened {
+            sheet.write_datetime_with_format(row, 4, opened.naive_utc(), &opened_format)?;
+        }
+        if let Some(age) = dispute.age_days {
+            sheet.write_number(row, 5, age as f64)?;
+        }
+    }
+
+    sheet.autofit();
+    Ok(())
+}
+
+/// Writes the column names in bold on the first row, which stays in view when scrolling.
+fn header(sheet: &mut Worksheet, columns: &[&str]) -> Result<(), Box<dyn Error>> {
+    let bold = Format::new().set_bold();
+    for (col, name) in (0..).zip(columns) {
+        sheet.write_string_with_format(0, col, *name, &bold)?;
+    }
+    sheet.set_freeze_panes(1, 0)?;
+    Ok(())
+}
+
+fn write_amount(
+    sheet: &mut Worksheet,
+    row: RowNum,
+    col: ColNum,
+    amount: Money,
+    format: &Format,
+) -> Result<(), Box<dyn Error>> {
+    sheet.write_number_with_format(row, col, amount_to_f64(amount, DECIMALS), format)?;
+    Ok(())
+}
+
+/// Shows numbers with `decimals` places, e.g. `0.00` for 2.
+fn amount_format(decimals: u32) -> Format {
+    let places = "0".repeat(decimals as usize);
+    let pattern = match decimals {
+        0 => "0".to_owned(),
+        _ => format!("0.{places}"),
+    };
+    Format::new().set_num_format(pattern)
+}
+
+/// Workbooks can only be written whole, so the workbook is built in memory first.
+fn save<W: Write>(mut workbook: Workbook, mut writer: W) -> Result<(), Box<dyn Error>> {
+    writer.write_all(&workbook.save_to_buffer()?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        records::TxType,
+        testing::Scenario,
+        transaction::{ClientId, RejectionReason, TxId},
+    };
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn reports_have_a_sheet_per_table() {
+        let engine = Scenario::new()
+            .deposit(1, 1, 10)
+            .deposit(2, 2, 5)
+            .dispute(2, 2)
+            .into_engine();
+        let open_disputes = engine.open_disputes();
+        let mut accounts: Vec<&Account> = engine.accounts_iter().collect();
+        accounts.sort_by_key(|account| account.client);
+        let reject = Reject {
+            line: 4,
+            reason: RejectionReason::InsufficientFunds,
+            r#type: Some(TxType::Withdrawal),
+            client: Some(ClientId(1)),
+            tx: Some(TxId(3)),
+            amount: Some(Money::new(50.0)),
+            detail: "needs 50 more".to_owned(),
+        };
+
+        let mut out = Vec::new();
+        let run_date = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        write_report(
+            &mut out,
+            &accounts,
+            Precision::Fixed(2),
+            None,
+            &[reject],
+            &open_disputes,
+            run_date,
+        )
+        .unwrap();
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(out)).unwrap();
+        let mut file = |name: &str| {
+            let mut text = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        };
+        let workbook = file("xl/workbook.xml");
+        for sheet in ["Accounts", "Rejects", "Open disputes"] {
+            assert!(workbook.contains(&format!("name=\"{sheet}\"")));
+        }
+        // The header and a row per account, dispute and reject.
+        let rows = |sheet: &str| sheet.matches("<row ").count();
+        assert_eq!(rows(&file("xl/worksheets/sheet1.xml")), 3);
+        assert_eq!(rows(&file("xl/worksheets/sheet2.xml")), 2);
+        assert_eq!(rows(&file("xl/worksheets/sheet3.xml")), 2);
+        assert!(file("xl/sharedStrings.xml").contains("needs 50 more"));
+        assert!(file("xl/styles.xml").contains("formatCode=\"0.00\""));
+    }
+}